@@ -1,18 +1,97 @@
 use teloxide::prelude::*;
-use teloxide_plugins::{dispatch, PluginContext};
+use teloxide::types::{
+    BusinessConnection, ChatBoostRemoved, ChatBoostUpdated, Poll, PollAnswer, PreCheckoutQuery,
+    UpdateKind,
+};
+use teloxide_plugins::{DispatchQueue, PluginContext};
 mod plugs;
 
-async fn handle_message(bot: Bot, msg: Message) -> ResponseResult<()> {
+async fn handle_message(bot: Bot, msg: Message, queue: DispatchQueue) -> ResponseResult<()> {
     println!("message: {:?}", msg);
-    let ctx = PluginContext::new(bot.clone(), Some(msg.clone()), None);
-    let _ = dispatch(ctx).await;
+    queue.push(PluginContext::new(bot, Some(msg), None));
     Ok(())
 }
 
-async fn handle_callback_query(bot: Bot, cq: CallbackQuery) -> ResponseResult<()> {
+async fn handle_edited_message(bot: Bot, msg: Message, queue: DispatchQueue) -> ResponseResult<()> {
+    queue.push(PluginContext::from_edited_message(bot, msg));
+    Ok(())
+}
+
+async fn handle_callback_query(
+    bot: Bot,
+    cq: CallbackQuery,
+    queue: DispatchQueue,
+) -> ResponseResult<()> {
     println!("callback query: {:?}", cq);
-    let ctx = PluginContext::new(bot.clone(), None, Some(cq.clone()));
-    let _ = dispatch(ctx).await;
+    queue.push(PluginContext::new(bot, None, Some(cq)));
+    Ok(())
+}
+
+async fn handle_inline_query(
+    bot: Bot,
+    query: InlineQuery,
+    queue: DispatchQueue,
+) -> ResponseResult<()> {
+    queue.push(PluginContext::from_inline_query(bot, query));
+    Ok(())
+}
+
+async fn handle_business_connection(
+    bot: Bot,
+    connection: BusinessConnection,
+    queue: DispatchQueue,
+) -> ResponseResult<()> {
+    queue.push(PluginContext::from_business_connection(bot, connection));
+    Ok(())
+}
+
+async fn handle_chat_boost(
+    bot: Bot,
+    boost: ChatBoostUpdated,
+    queue: DispatchQueue,
+) -> ResponseResult<()> {
+    queue.push(PluginContext::from_chat_boost(bot, boost));
+    Ok(())
+}
+
+async fn handle_removed_chat_boost(
+    bot: Bot,
+    boost: ChatBoostRemoved,
+    queue: DispatchQueue,
+) -> ResponseResult<()> {
+    queue.push(PluginContext::from_removed_chat_boost(bot, boost));
+    Ok(())
+}
+
+async fn handle_pre_checkout_query(
+    bot: Bot,
+    query: PreCheckoutQuery,
+    queue: DispatchQueue,
+) -> ResponseResult<()> {
+    queue.push(PluginContext::from_pre_checkout_query(bot, query));
+    Ok(())
+}
+
+async fn handle_poll(bot: Bot, poll: Poll, queue: DispatchQueue) -> ResponseResult<()> {
+    queue.push(PluginContext::from_poll(bot, poll));
+    Ok(())
+}
+
+async fn handle_poll_answer(
+    bot: Bot,
+    answer: PollAnswer,
+    queue: DispatchQueue,
+) -> ResponseResult<()> {
+    queue.push(PluginContext::from_poll_answer(bot, answer));
+    Ok(())
+}
+
+async fn handle_raw_update(
+    bot: Bot,
+    update: serde_json::Value,
+    queue: DispatchQueue,
+) -> ResponseResult<()> {
+    queue.push(PluginContext::from_raw_update(bot, update));
     Ok(())
 }
 
@@ -21,12 +100,31 @@ async fn main() {
     println!("Starting bot...");
 
     let bot = Bot::new(std::env::var("TELOXIDE_BOT_TOKEN").unwrap());
+    let queue = DispatchQueue::new(4);
 
     let handler = dptree::entry()
         .branch(Update::filter_message().endpoint(handle_message))
-        .branch(Update::filter_callback_query().endpoint(handle_callback_query));
+        .branch(Update::filter_edited_message().endpoint(handle_edited_message))
+        .branch(Update::filter_callback_query().endpoint(handle_callback_query))
+        .branch(Update::filter_inline_query().endpoint(handle_inline_query))
+        .branch(Update::filter_business_message().endpoint(handle_message))
+        .branch(Update::filter_edited_business_message().endpoint(handle_edited_message))
+        .branch(Update::filter_business_connection().endpoint(handle_business_connection))
+        .branch(Update::filter_chat_boost().endpoint(handle_chat_boost))
+        .branch(Update::filter_removed_chat_boost().endpoint(handle_removed_chat_boost))
+        .branch(Update::filter_pre_checkout_query().endpoint(handle_pre_checkout_query))
+        .branch(Update::filter_poll().endpoint(handle_poll))
+        .branch(Update::filter_poll_answer().endpoint(handle_poll_answer))
+        .branch(
+            dptree::filter_map(|update: Update| match update.kind {
+                UpdateKind::Error(raw) => Some(raw),
+                _ => None,
+            })
+            .endpoint(handle_raw_update),
+        );
 
     Dispatcher::builder(bot, handler)
+        .dependencies(dptree::deps![queue])
         .enable_ctrlc_handler()
         .build()
         .dispatch()
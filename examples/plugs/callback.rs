@@ -1,8 +1,8 @@
 use teloxide::prelude::*;
-use teloxide_plugins::TeloxidePlugin;
+use teloxide_plugins::{PluginBot, TeloxidePlugin};
 
 #[TeloxidePlugin(callback = ["callback"])]
-async fn callback(bot: Bot, cq: CallbackQuery) {
+async fn callback(bot: PluginBot, cq: CallbackQuery) {
     if let Some(message) = cq.message {
         bot.send_message(message.chat().id, "Callback received!")
             .await
@@ -0,0 +1,15 @@
+use futures::{stream, Stream};
+use std::time::Duration;
+use teloxide::prelude::*;
+use teloxide_plugins::TeloxidePlugin;
+
+#[TeloxidePlugin(commands = ["countdown"], prefixes = ["/"])]
+async fn countdown(_bot: Bot, _msg: Message) -> impl Stream<Item = String> {
+    stream::unfold(5u32, |n| async move {
+        if n == 0 {
+            return None;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        Some((format!("{}...", n), n - 1))
+    })
+}
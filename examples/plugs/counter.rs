@@ -0,0 +1,16 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use teloxide::prelude::*;
+use teloxide_plugins::{PluginBot, TeloxidePlugin};
+
+#[derive(Default)]
+pub struct Counter {
+    count: AtomicU32,
+}
+
+#[TeloxidePlugin(commands = ["count"], prefixes = ["/"], state = Counter::default())]
+async fn counter(bot: PluginBot, msg: Message, state: &'static Counter) {
+    let current = state.count.fetch_add(1, Ordering::SeqCst) + 1;
+    bot.send_message(msg.chat.id, format!("Count: {}", current))
+        .await
+        .unwrap();
+}
@@ -1,8 +1,8 @@
 use teloxide::prelude::*;
-use teloxide_plugins::TeloxidePlugin;
+use teloxide_plugins::{PluginBot, TeloxidePlugin};
 
 #[TeloxidePlugin(commands = ["echo"], prefixes = ["/"])]
-async fn echo(bot: Bot, msg: Message) {
+async fn echo(bot: PluginBot, msg: Message) {
     if let Some(text) = msg.text() {
         bot.send_message(msg.chat.id, text).await.unwrap();
     }
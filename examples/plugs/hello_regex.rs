@@ -1,7 +1,7 @@
 use teloxide::prelude::*;
-use teloxide_plugins::TeloxidePlugin;
+use teloxide_plugins::{PluginBot, TeloxidePlugin};
 
 #[TeloxidePlugin(regex = ["(?i)hello"])]
-async fn hello_regex(bot: Bot, msg: Message) {
+async fn hello_regex(bot: PluginBot, msg: Message) {
     bot.send_message(msg.chat.id, "Hi there 👋").await.unwrap();
 }
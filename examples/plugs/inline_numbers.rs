@@ -0,0 +1,16 @@
+use teloxide::types::InlineQuery;
+use teloxide_plugins::{inline, InlineAnswer, PluginBot, TeloxidePlugin};
+
+#[TeloxidePlugin(inline = ["^\\d*$"])]
+async fn inline_numbers(bot: PluginBot, query: InlineQuery) {
+    let results = (1..=500)
+        .map(|n| n.to_string())
+        .filter(|n| n.starts_with(&query.query))
+        .map(|n| inline::article(n.clone(), format!("Number {}", n), n));
+
+    let _ = InlineAnswer::new()
+        .page_size(20)
+        .cache_time(60)
+        .send(&bot, &query, results)
+        .await;
+}
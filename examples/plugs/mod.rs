@@ -1,4 +1,7 @@
 pub mod callback;
+pub mod countdown;
+pub mod counter;
 pub mod echo;
 pub mod hello_regex;
+pub mod inline_numbers;
 pub mod ping;
@@ -2,6 +2,6 @@ use teloxide::prelude::*;
 use teloxide_plugins::TeloxidePlugin;
 
 #[TeloxidePlugin(commands = ["ping", "p"], prefixes = ["/", "!"])]
-async fn ping(bot: Bot, msg: Message) {
-    bot.send_message(msg.chat.id, "pong!").await.unwrap();
+async fn ping(_bot: Bot, _msg: Message) -> &'static str {
+    "pong!"
 }
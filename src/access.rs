@@ -0,0 +1,290 @@
+use crate::context::PluginContext;
+use crate::storage::{self, StorageError};
+use once_cell::sync::Lazy;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::RwLock;
+use teloxide::types::{ChatId, UserId};
+
+const CHAT_PREFIX: &str = "allow:chat:";
+const USER_PREFIX: &str = "allow:user:";
+
+#[derive(Default)]
+struct Allowlist {
+    chats: HashSet<ChatId>,
+    users: HashSet<UserId>,
+}
+
+static OWNERS: Lazy<RwLock<HashSet<UserId>>> = Lazy::new(|| RwLock::new(HashSet::new()));
+static WHITELIST: AtomicBool = AtomicBool::new(false);
+static UNAUTHORIZED_MESSAGE: RwLock<Option<String>> = RwLock::new(None);
+static ALLOWED: RwLock<Option<Allowlist>> = RwLock::new(None);
+// Bumped, under the `ALLOWED` lock, by every change to the lists and every
+// `invalidate`.
+static CHANGES: AtomicU64 = AtomicU64::new(0);
+
+pub fn set_owners(owners: impl IntoIterator<Item = UserId>) {
+    *OWNERS.write().unwrap() = owners.into_iter().collect();
+}
+
+pub fn owners() -> Vec<UserId> {
+    OWNERS.read().unwrap().iter().copied().collect()
+}
+
+pub fn is_owner(user: UserId) -> bool {
+    OWNERS.read().unwrap().contains(&user)
+}
+
+pub fn set_whitelist_mode(enabled: bool) {
+    WHITELIST.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_whitelist_mode() -> bool {
+    WHITELIST.load(Ordering::Relaxed)
+}
+
+pub fn set_unauthorized_message(text: Option<impl Into<String>>) {
+    *UNAUTHORIZED_MESSAGE.write().unwrap() = text.map(Into::into);
+}
+
+pub(crate) fn unauthorized_message() -> Option<String> {
+    UNAUTHORIZED_MESSAGE.read().unwrap().clone()
+}
+
+pub(crate) fn invalidate() {
+    let mut allowed = ALLOWED.write().unwrap();
+    CHANGES.fetch_add(1, Ordering::SeqCst);
+    *allowed = None;
+}
+
+// Reads the lists from storage unless they're already in memory. A load that
+// overlapped a change or an `invalidate` may have read storage too early, so it
+// throws its result away and reads again.
+async fn ensure_loaded() -> Result<(), StorageError> {
+    loop {
+        if ALLOWED.read().unwrap().is_some() {
+            return Ok(());
+        }
+        let started = CHANGES.load(Ordering::SeqCst);
+        let storage = storage::storage();
+        let chats = storage
+            .keys(CHAT_PREFIX)
+            .await?
+            .iter()
+            .filter_map(|key| key.strip_prefix(CHAT_PREFIX)?.parse().ok())
+            .map(ChatId)
+            .collect();
+        let users = storage
+            .keys(USER_PREFIX)
+            .await?
+            .iter()
+            .filter_map(|key| key.strip_prefix(USER_PREFIX)?.parse().ok())
+            .map(UserId)
+            .collect();
+        let mut allowed = ALLOWED.write().unwrap();
+        if allowed.is_some() {
+            return Ok(());
+        }
+        if CHANGES.load(Ordering::SeqCst) == started {
+            *allowed = Some(Allowlist { chats, users });
+            return Ok(());
+        }
+    }
+}
+
+async fn read_allowlist<R>(f: impl Fn(&Allowlist) -> R) -> Result<R, StorageError> {
+    loop {
+        ensure_loaded().await?;
+        if let Some(list) = ALLOWED.read().unwrap().as_ref() {
+            return Ok(f(list));
+        }
+    }
+}
+
+// Applies a change that's already in storage. When the lists aren't loaded there's
+// nothing to update: the next load reads the change back.
+fn change_allowlist(f: impl FnOnce(&mut Allowlist)) {
+    let mut allowed = ALLOWED.write().unwrap();
+    CHANGES.fetch_add(1, Ordering::SeqCst);
+    if let Some(list) = allowed.as_mut() {
+        f(list);
+    }
+}
+
+pub async fn allow_chat(chat: ChatId) -> Result<(), StorageError> {
+    storage::storage()
+        .set(&format!("{}{}", CHAT_PREFIX, chat), String::from("1"))
+        .await?;
+    change_allowlist(|list| {
+        list.chats.insert(chat);
+    });
+    Ok(())
+}
+
+pub async fn allow_user(user: UserId) -> Result<(), StorageError> {
+    storage::storage()
+        .set(&format!("{}{}", USER_PREFIX, user), String::from("1"))
+        .await?;
+    change_allowlist(|list| {
+        list.users.insert(user);
+    });
+    Ok(())
+}
+
+pub async fn disallow_chat(chat: ChatId) -> Result<(), StorageError> {
+    storage::delete(&format!("{}{}", CHAT_PREFIX, chat)).await?;
+    change_allowlist(|list| {
+        list.chats.remove(&chat);
+    });
+    Ok(())
+}
+
+pub async fn disallow_user(user: UserId) -> Result<(), StorageError> {
+    storage::delete(&format!("{}{}", USER_PREFIX, user)).await?;
+    change_allowlist(|list| {
+        list.users.remove(&user);
+    });
+    Ok(())
+}
+
+pub async fn allowed_chats() -> Result<Vec<ChatId>, StorageError> {
+    read_allowlist(|list| list.chats.iter().copied().collect()).await
+}
+
+pub async fn allowed_users() -> Result<Vec<UserId>, StorageError> {
+    read_allowlist(|list| list.users.iter().copied().collect()).await
+}
+
+pub async fn is_allowed(chat: Option<ChatId>, user: Option<UserId>) -> Result<bool, StorageError> {
+    if user.is_some_and(is_owner) {
+        return Ok(true);
+    }
+    read_allowlist(|list| {
+        chat.is_some_and(|chat| list.chats.contains(&chat))
+            || user.is_some_and(|user| list.users.contains(&user))
+    })
+    .await
+}
+
+pub(crate) async fn admits(ctx: &PluginContext) -> bool {
+    if !is_whitelist_mode() {
+        return true;
+    }
+    is_allowed(ctx.chat_id(), ctx.sender().map(|u| u.id))
+        .await
+        .unwrap_or(false)
+}
+
+#[cfg(feature = "whitelist-commands")]
+fn whitelist_target(ctx: &PluginContext) -> Option<Result<ChatId, UserId>> {
+    use crate::context::TargetUser;
+
+    if let Some(id) = ctx.args().first().and_then(|arg| arg.parse::<i64>().ok()) {
+        return Some(if id < 0 {
+            Ok(ChatId(id))
+        } else {
+            Err(UserId(id as u64))
+        });
+    }
+    match ctx.target_user() {
+        Some(TargetUser::User(user)) => Some(Err(user.id)),
+        Some(TargetUser::Id(id)) => Some(Err(id)),
+        Some(TargetUser::Username(_)) => None,
+        None => ctx.chat_id().map(Ok),
+    }
+}
+
+#[cfg(feature = "whitelist-commands")]
+#[crate::TeloxidePlugin(commands = ["allow"], prefixes = ["/"])]
+async fn allow_command(ctx: PluginContext) -> Option<String> {
+    if !is_owner(ctx.sender()?.id) {
+        return None;
+    }
+    let reply = match whitelist_target(&ctx) {
+        Some(Ok(chat)) => allow_chat(chat)
+            .await
+            .map(|_| format!("Chat {} is now allowed.", chat)),
+        Some(Err(user)) => allow_user(user)
+            .await
+            .map(|_| format!("User {} is now allowed.", user)),
+        None => return Some(String::from("Reply to the user or pass their id.")),
+    };
+    Some(reply.unwrap_or_else(|err| format!("Couldn't update the allowlist: {}", err)))
+}
+
+#[cfg(feature = "whitelist-commands")]
+#[crate::TeloxidePlugin(commands = ["disallow"], prefixes = ["/"])]
+async fn disallow_command(ctx: PluginContext) -> Option<String> {
+    if !is_owner(ctx.sender()?.id) {
+        return None;
+    }
+    let reply = match whitelist_target(&ctx) {
+        Some(Ok(chat)) => disallow_chat(chat)
+            .await
+            .map(|_| format!("Chat {} is no longer allowed.", chat)),
+        Some(Err(user)) => disallow_user(user)
+            .await
+            .map(|_| format!("User {} is no longer allowed.", user)),
+        None => return Some(String::from("Reply to the user or pass their id.")),
+    };
+    Some(reply.unwrap_or_else(|err| format!("Couldn't update the allowlist: {}", err)))
+}
+
+#[cfg(feature = "whitelist-commands")]
+#[crate::TeloxidePlugin(commands = ["allowlist"], prefixes = ["/"])]
+async fn allowlist_command(ctx: PluginContext) -> Option<String> {
+    if !is_owner(ctx.sender()?.id) {
+        return None;
+    }
+    let chats = allowed_chats().await.ok()?;
+    let users = allowed_users().await.ok()?;
+    let list = |ids: Vec<String>| {
+        if ids.is_empty() {
+            String::from("none")
+        } else {
+            ids.join(", ")
+        }
+    };
+    Some(format!(
+        "Whitelist mode: {}\nChats: {}\nUsers: {}",
+        if is_whitelist_mode() { "on" } else { "off" },
+        list(chats.iter().map(ToString::to_string).collect()),
+        list(users.iter().map(ToString::to_string).collect()),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn a_change_while_unloaded_keeps_the_rest_of_the_list() {
+        allow_chat(ChatId(-501)).await.unwrap();
+        allow_user(UserId(501)).await.unwrap();
+        assert!(is_allowed(Some(ChatId(-501)), None).await.unwrap());
+
+        // What `allow_chat` does when a storage swap lands between its write and
+        // its update of the in-memory lists.
+        invalidate();
+        storage::storage()
+            .set(&format!("{}{}", CHAT_PREFIX, -502), String::from("1"))
+            .await
+            .unwrap();
+        change_allowlist(|list| {
+            list.chats.insert(ChatId(-502));
+        });
+
+        let chats = allowed_chats().await.unwrap();
+        assert!(chats.contains(&ChatId(-501)) && chats.contains(&ChatId(-502)));
+        assert!(is_allowed(None, Some(UserId(501))).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn disallowing_takes_effect_right_away() {
+        allow_user(UserId(503)).await.unwrap();
+        assert!(is_allowed(None, Some(UserId(503))).await.unwrap());
+        disallow_user(UserId(503)).await.unwrap();
+        assert!(!is_allowed(None, Some(UserId(503))).await.unwrap());
+        assert!(!allowed_users().await.unwrap().contains(&UserId(503)));
+    }
+}
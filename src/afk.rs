@@ -0,0 +1,194 @@
+use crate::args::HumanDuration;
+use crate::context::{reply_of, split_command, PluginContext};
+use crate::response::SendMessageParams;
+use crate::storage::{self, StorageError};
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::RwLock;
+use teloxide::prelude::*;
+use teloxide::types::{MessageEntityKind, ParseMode, User};
+use teloxide::utils::html;
+
+const PREFIX: &str = "afk:";
+
+// Everyone who is AFK, loaded from storage on first use. Every message is checked
+// against it, so it has to be cheap.
+static AWAY: Lazy<RwLock<Option<HashMap<UserId, Afk>>>> = Lazy::new(|| RwLock::new(None));
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Afk {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub since: DateTime<Utc>,
+}
+
+fn key(user: UserId) -> String {
+    format!("{}{}", PREFIX, user)
+}
+
+pub(crate) fn invalidate() {
+    *AWAY.write().unwrap() = None;
+}
+
+async fn away() -> Result<HashMap<UserId, Afk>, StorageError> {
+    if let Some(away) = AWAY.read().unwrap().as_ref() {
+        return Ok(away.clone());
+    }
+    let mut loaded = HashMap::new();
+    for key in storage::storage().keys(PREFIX).await? {
+        let Some(user) = key
+            .strip_prefix(PREFIX)
+            .and_then(|id| id.parse::<u64>().ok())
+        else {
+            continue;
+        };
+        if let Some(afk) = storage::get_json::<Afk>(&key).await? {
+            loaded.insert(UserId(user), afk);
+        }
+    }
+    *AWAY.write().unwrap() = Some(loaded.clone());
+    Ok(loaded)
+}
+
+pub async fn get(user: UserId) -> Result<Option<Afk>, StorageError> {
+    Ok(away().await?.remove(&user))
+}
+
+pub async fn set(user: &User, reason: Option<String>) -> Result<Afk, StorageError> {
+    away().await?;
+    let afk = Afk {
+        name: user.first_name.clone(),
+        username: user.username.clone(),
+        reason,
+        since: Utc::now(),
+    };
+    storage::set_json(&key(user.id), &afk).await?;
+    if let Some(away) = AWAY.write().unwrap().as_mut() {
+        away.insert(user.id, afk.clone());
+    }
+    Ok(afk)
+}
+
+pub async fn clear(user: UserId) -> Result<Option<Afk>, StorageError> {
+    let Some(afk) = get(user).await? else {
+        return Ok(None);
+    };
+    storage::delete(&key(user)).await?;
+    if let Some(away) = AWAY.write().unwrap().as_mut() {
+        away.remove(&user);
+    }
+    Ok(Some(afk))
+}
+
+fn ago(since: DateTime<Utc>) -> String {
+    HumanDuration((Utc::now() - since).to_std().unwrap_or_default()).to_string()
+}
+
+fn html_reply(text: String) -> SendMessageParams {
+    SendMessageParams::new(text)
+        .parse_mode(ParseMode::Html)
+        .reply()
+}
+
+fn notice(afk: &Afk) -> String {
+    let mut text = format!(
+        "{} is AFK (since {} ago)",
+        html::escape(&afk.name),
+        ago(afk.since)
+    );
+    if let Some(reason) = &afk.reason {
+        text.push_str(&format!(": {}", html::escape(reason)));
+    }
+    text
+}
+
+// The users a message points at: the author of the replied-to message, text mentions,
+// and @usernames of people who are AFK.
+fn addressed(msg: &Message, away: &HashMap<UserId, Afk>) -> Vec<UserId> {
+    let mut users = Vec::new();
+    if let Some(user) = reply_of(msg).and_then(|m| m.from.as_ref()) {
+        users.push(user.id);
+    }
+    for entity in msg
+        .parse_entities()
+        .into_iter()
+        .chain(msg.parse_caption_entities())
+        .flatten()
+    {
+        match entity.kind() {
+            MessageEntityKind::TextMention { user } => users.push(user.id),
+            MessageEntityKind::Mention => {
+                let username = entity.text().trim_start_matches('@');
+                users.extend(away.iter().filter_map(|(id, afk)| {
+                    afk.username
+                        .as_deref()
+                        .is_some_and(|name| name.eq_ignore_ascii_case(username))
+                        .then_some(*id)
+                }));
+            }
+            _ => {}
+        }
+    }
+    users.sort_by_key(|user| user.0);
+    users.dedup();
+    users
+}
+
+// Runs before routing and never stops it. Whoever speaks is back, unless they're the
+// ones going AFK right now, and mentioning or replying to someone away gets a notice.
+pub(crate) async fn track(ctx: &PluginContext) {
+    let Some(msg) = ctx.message.as_ref().filter(|_| !ctx.is_edited()) else {
+        return;
+    };
+    let Ok(away) = away().await else {
+        return;
+    };
+    if away.is_empty() {
+        return;
+    }
+
+    if let Some(user) = ctx.sender() {
+        let going_afk = ctx
+            .text_or_caption()
+            .is_some_and(|text| split_command(text).0 == "/afk");
+        if !going_afk && away.contains_key(&user.id) {
+            if let Ok(Some(afk)) = clear(user.id).await {
+                let text = format!(
+                    "{} is back (AFK for {}).",
+                    html::escape(&user.first_name),
+                    ago(afk.since)
+                );
+                let _ = ctx.send(html_reply(text)).await;
+            }
+        }
+    }
+
+    let sender = ctx.sender().map(|u| u.id);
+    let notices: Vec<String> = addressed(msg, &away)
+        .into_iter()
+        .filter(|user| Some(*user) != sender)
+        .filter_map(|user| away.get(&user).map(notice))
+        .collect();
+    if !notices.is_empty() {
+        let _ = ctx.send(html_reply(notices.join("\n"))).await;
+    }
+}
+
+#[crate::TeloxidePlugin(commands = ["afk"], prefixes = ["/"])]
+async fn afk_command(ctx: PluginContext) -> Option<String> {
+    let user = ctx.sender()?;
+    let reason = ctx.args().join(" ");
+    let reason = (!reason.is_empty()).then_some(reason);
+    Some(match set(user, reason).await {
+        Ok(afk) => match afk.reason {
+            Some(reason) => format!("{} is now AFK: {}", afk.name, reason),
+            None => format!("{} is now AFK.", afk.name),
+        },
+        Err(err) => format!("Couldn't set your status: {}", err),
+    })
+}
@@ -0,0 +1,340 @@
+use crate::budget;
+use crate::context::{reply_of, PluginContext};
+use crate::http::{self, HttpError, HttpRequest};
+use crate::logging::Outcome;
+use crate::plugin::Triggers;
+use crate::registry::{self, PluginMeta};
+use crate::response::{self, PluginResponse, SendMessageParams, StreamReply};
+use futures::stream::{self, BoxStream, StreamExt};
+use once_cell::sync::Lazy;
+use serde_json::{json, Value};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+const DEFAULT_MODEL: &str = "gpt-4o-mini";
+
+// Answers are streamed, so this bounds the whole answer rather than the first byte.
+const TIMEOUT: Duration = Duration::from_secs(120);
+
+static PROVIDER: RwLock<Option<Arc<dyn CompletionProvider>>> = RwLock::new(None);
+static FALLBACK: AtomicBool = AtomicBool::new(false);
+
+// Like filters, the fallback isn't in the registry: it only runs when nothing else
+// matched, but the plugin entry gives it the same checks, budgets and logs.
+static META: Lazy<&'static PluginMeta> = Lazy::new(|| {
+    Triggers::new()
+        .name("ai_fallback")
+        .regex(".")
+        .into_meta("ai_fallback", handle)
+        .expect("the ai fallback plugin has a valid trigger")
+});
+
+#[derive(Debug)]
+pub enum AiError {
+    NoProvider,
+    Http(HttpError),
+    Api { status: u16, message: String },
+    Decode(String),
+}
+
+impl fmt::Display for AiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AiError::NoProvider => write!(f, "no completion provider is configured"),
+            AiError::Http(err) => write!(f, "{}", err),
+            AiError::Api { status, message } => {
+                write!(f, "the provider answered {}: {}", status, message)
+            }
+            AiError::Decode(reason) => write!(f, "unexpected answer from the provider: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for AiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AiError::Http(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<HttpError> for AiError {
+    fn from(err: HttpError) -> Self {
+        AiError::Http(err)
+    }
+}
+
+impl From<reqwest::Error> for AiError {
+    fn from(err: reqwest::Error) -> Self {
+        AiError::Http(HttpError::Request(err))
+    }
+}
+
+// Completions arrive as a stream of text pieces, each one following the last.
+pub trait CompletionProvider: Send + Sync {
+    fn complete(&self, prompt: &str) -> BoxStream<'static, Result<String, AiError>>;
+}
+
+pub fn set_provider(provider: impl CompletionProvider + 'static) {
+    *PROVIDER.write().unwrap() = Some(Arc::new(provider));
+}
+
+// The installed provider, or one configured from the environment on first use.
+pub fn provider() -> Option<Arc<dyn CompletionProvider>> {
+    if let Some(provider) = PROVIDER.read().unwrap().clone() {
+        return Some(provider);
+    }
+    let provider: Arc<dyn CompletionProvider> = Arc::new(OpenAiProvider::from_env()?);
+    Some(PROVIDER.write().unwrap().get_or_insert(provider).clone())
+}
+
+pub fn complete(prompt: &str) -> BoxStream<'static, Result<String, AiError>> {
+    match provider() {
+        Some(provider) => provider.complete(prompt),
+        None => stream::once(async { Err(AiError::NoProvider) }).boxed(),
+    }
+}
+
+// Any server that speaks the OpenAI chat completions API, which includes most local
+// and hosted model servers.
+#[derive(Clone, Debug)]
+pub struct OpenAiProvider {
+    base_url: String,
+    api_key: Option<String>,
+    model: String,
+    system: Option<String>,
+}
+
+impl OpenAiProvider {
+    pub fn new(base_url: impl Into<String>, model: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            api_key: None,
+            model: model.into(),
+            system: None,
+        }
+    }
+
+    pub fn api_key(mut self, key: impl Into<String>) -> Self {
+        self.api_key = Some(key.into());
+        self
+    }
+
+    pub fn system(mut self, prompt: impl Into<String>) -> Self {
+        self.system = Some(prompt.into());
+        self
+    }
+
+    // Reads `OPENAI_API_KEY`, `OPENAI_BASE_URL`, `OPENAI_MODEL` and `AI_SYSTEM_PROMPT`.
+    // Needs a key or a base url, since local servers often don't take a key.
+    pub fn from_env() -> Option<Self> {
+        let var = |name| {
+            std::env::var(name)
+                .ok()
+                .filter(|v: &String| !v.trim().is_empty())
+        };
+        let api_key = var("OPENAI_API_KEY");
+        let base_url = var("OPENAI_BASE_URL");
+        if api_key.is_none() && base_url.is_none() {
+            return None;
+        }
+        let mut provider = Self::new(
+            base_url.as_deref().unwrap_or(DEFAULT_BASE_URL),
+            var("OPENAI_MODEL").as_deref().unwrap_or(DEFAULT_MODEL),
+        );
+        provider.api_key = api_key;
+        provider.system = var("AI_SYSTEM_PROMPT");
+        Some(provider)
+    }
+
+    fn request(&self, prompt: &str) -> HttpRequest {
+        let mut messages = Vec::new();
+        if let Some(system) = &self.system {
+            messages.push(json!({ "role": "system", "content": system }));
+        }
+        messages.push(json!({ "role": "user", "content": prompt }));
+        let mut request = http::http()
+            .post(format!("{}/chat/completions", self.base_url))
+            .json(&json!({ "model": self.model, "messages": messages, "stream": true }))
+            .timeout(TIMEOUT);
+        if let Some(key) = &self.api_key {
+            request = request.bearer_auth(key);
+        }
+        request
+    }
+}
+
+impl CompletionProvider for OpenAiProvider {
+    // The request is built here, so it's attributed to the plugin that asked.
+    fn complete(&self, prompt: &str) -> BoxStream<'static, Result<String, AiError>> {
+        let request = self.request(prompt);
+        stream::unfold(State::Start(request), next).boxed()
+    }
+}
+
+enum State {
+    Start(HttpRequest),
+    Reading(reqwest::Response, Vec<u8>),
+    Done,
+}
+
+enum Event {
+    Delta(String),
+    Done,
+    Skip,
+}
+
+async fn open(request: HttpRequest) -> Result<reqwest::Response, AiError> {
+    let response = request.send().await?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    let message = serde_json::from_str::<Value>(&body)
+        .ok()
+        .and_then(|value| value["error"]["message"].as_str().map(str::to_string))
+        .unwrap_or(body);
+    Err(AiError::Api {
+        status: status.as_u16(),
+        message,
+    })
+}
+
+// Server-sent events: every `data:` line carries a JSON chunk, until `[DONE]`.
+fn event(line: &[u8]) -> Result<Event, AiError> {
+    let line = String::from_utf8_lossy(line);
+    let Some(data) = line.trim().strip_prefix("data:").map(str::trim) else {
+        return Ok(Event::Skip);
+    };
+    if data == "[DONE]" {
+        return Ok(Event::Done);
+    }
+    let value: Value =
+        serde_json::from_str(data).map_err(|err| AiError::Decode(err.to_string()))?;
+    if let Some(message) = value["error"]["message"].as_str() {
+        return Err(AiError::Api {
+            status: 200,
+            message: message.to_string(),
+        });
+    }
+    Ok(match value["choices"][0]["delta"]["content"].as_str() {
+        Some(text) if !text.is_empty() => Event::Delta(text.to_string()),
+        _ => Event::Skip,
+    })
+}
+
+async fn next(state: State) -> Option<(Result<String, AiError>, State)> {
+    let (mut response, mut buffer) = match state {
+        State::Done => return None,
+        State::Start(request) => match open(request).await {
+            Ok(response) => (response, Vec::new()),
+            Err(err) => return Some((Err(err), State::Done)),
+        },
+        State::Reading(response, buffer) => (response, buffer),
+    };
+    loop {
+        while let Some(end) = buffer.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            match event(&line) {
+                Ok(Event::Delta(text)) => {
+                    return Some((Ok(text), State::Reading(response, buffer)))
+                }
+                Ok(Event::Done) => return None,
+                Ok(Event::Skip) => {}
+                Err(err) => return Some((Err(err), State::Done)),
+            }
+        }
+        match response.chunk().await {
+            Ok(Some(chunk)) => buffer.extend_from_slice(&chunk),
+            Ok(None) => return None,
+            Err(err) => return Some((Err(err.into()), State::Done)),
+        }
+    }
+}
+
+// Replies with the answer to `prompt`, edited in place as it arrives. A failure is
+// shown in the message instead of the answer, or after the part that made it.
+pub fn answer(prompt: &str) -> StreamReply {
+    let pieces = complete(prompt).scan(false, |started, piece| {
+        let text = match piece {
+            Ok(text) => text,
+            Err(err) if *started => format!("\n\n(The answer was cut off: {})", err),
+            Err(err) => format!("Couldn't get an answer: {}", err),
+        };
+        *started = true;
+        futures::future::ready(Some(text))
+    });
+    StreamReply::new(pieces)
+        .append()
+        .throttle(Duration::from_millis(1500))
+}
+
+impl PluginContext {
+    pub fn ask_ai(&self, prompt: &str) -> StreamReply {
+        answer(prompt)
+    }
+}
+
+// When enabled, private messages that no plugin answered go to the provider.
+pub fn set_fallback(enabled: bool) {
+    FALLBACK.store(enabled, Ordering::Relaxed);
+}
+
+pub fn fallback_enabled() -> bool {
+    FALLBACK.load(Ordering::Relaxed)
+}
+
+fn fallback_prompt(ctx: &PluginContext) -> Option<&str> {
+    if !fallback_enabled() || !ctx.is_private_chat() || ctx.is_edited() {
+        return None;
+    }
+    ctx.text_or_caption()
+        .map(str::trim)
+        .filter(|text| !text.is_empty() && !text.starts_with('/'))
+}
+
+pub(crate) async fn fallback(ctx: &PluginContext) -> Option<(&'static PluginMeta, Outcome)> {
+    fallback_prompt(ctx)?;
+    if !registry::passes_filters(*META, ctx) || budget::suspended_for(META.name).is_some() {
+        return None;
+    }
+    Some((*META, registry::run_plugin(*META, ctx).await))
+}
+
+fn handle(ctx: PluginContext) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+    Box::pin(async move {
+        let Some(prompt) = fallback_prompt(&ctx) else {
+            return;
+        };
+        let _ = response::respond(&ctx, ctx.ask_ai(prompt)).await;
+    })
+}
+
+// `/ask <question>`, optionally in reply to a message that the question is about.
+#[crate::TeloxidePlugin(commands = ["ask"], prefixes = ["/"])]
+async fn ask_command(ctx: PluginContext) -> Option<PluginResponse> {
+    let question = ctx.args().join(" ");
+    let replied = ctx
+        .message
+        .as_ref()
+        .and_then(reply_of)
+        .and_then(|msg| msg.text().or_else(|| msg.caption()));
+    let prompt = match (replied, question.is_empty()) {
+        (None, true) => {
+            return Some(PluginResponse::Message(SendMessageParams::new(
+                "Usage: /ask <question>, or reply to a message with /ask",
+            )))
+        }
+        (None, false) => question,
+        (Some(replied), true) => replied.to_string(),
+        (Some(replied), false) => format!("{}\n\n{}", replied, question),
+    };
+    Some(PluginResponse::Stream(ctx.ask_ai(&prompt)))
+}
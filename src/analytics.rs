@@ -0,0 +1,240 @@
+use crate::context::PluginContext;
+use crate::registry::PluginMeta;
+use crate::storage::{self, StorageError};
+use chrono::{Days, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use teloxide::types::{ChatId, UserId};
+
+const PREFIX: &str = "usage:";
+const CAS_ATTEMPTS: usize = 8;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DayUsage {
+    pub total: u64,
+    pub chats: BTreeMap<i64, u64>,
+    pub users: BTreeMap<u64, u64>,
+}
+
+impl DayUsage {
+    fn count(&self, chat: Option<ChatId>) -> u64 {
+        match chat {
+            Some(chat) => self.chats.get(&chat.0).copied().unwrap_or(0),
+            None => self.total,
+        }
+    }
+}
+
+pub fn enable(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+fn key(day: NaiveDate, command: &str) -> String {
+    format!("{}{}:{}", PREFIX, day, command)
+}
+
+fn last_days(days: u32) -> impl Iterator<Item = NaiveDate> {
+    let today = Utc::now().date_naive();
+    (0..days.max(1) as u64).filter_map(move |back| today.checked_sub_days(Days::new(back)))
+}
+
+pub(crate) async fn record(
+    ctx: &PluginContext,
+    plugin: &'static PluginMeta,
+) -> Result<(), StorageError> {
+    if !is_enabled() {
+        return Ok(());
+    }
+    let key = key(Utc::now().date_naive(), plugin.name);
+    let chat = ctx.chat_id();
+    let user = ctx.sender().map(|u| u.id);
+    let storage = storage::storage();
+
+    for _ in 0..CAS_ATTEMPTS {
+        let current = storage.get(&key).await?;
+        let mut usage: DayUsage = match &current {
+            Some(raw) => serde_json::from_str(raw)?,
+            None => DayUsage::default(),
+        };
+        usage.total += 1;
+        if let Some(chat) = chat {
+            *usage.chats.entry(chat.0).or_default() += 1;
+        }
+        if let Some(user) = user {
+            *usage.users.entry(user.0).or_default() += 1;
+        }
+        let value = serde_json::to_string(&usage)?;
+        if storage
+            .compare_and_swap(&key, current.as_deref(), value)
+            .await?
+        {
+            break;
+        }
+    }
+    Ok(())
+}
+
+pub async fn usage(day: NaiveDate, command: &str) -> Result<DayUsage, StorageError> {
+    Ok(storage::get_json(&key(day, command))
+        .await?
+        .unwrap_or_default())
+}
+
+pub async fn commands(day: NaiveDate) -> Result<Vec<String>, StorageError> {
+    let prefix = format!("{}{}:", PREFIX, day);
+    let keys = storage::storage().keys(&prefix).await?;
+    Ok(keys
+        .into_iter()
+        .filter_map(|key| key.strip_prefix(&prefix).map(str::to_string))
+        .collect())
+}
+
+pub async fn top_commands(
+    days: u32,
+    chat: Option<ChatId>,
+) -> Result<Vec<(String, u64)>, StorageError> {
+    let mut totals: HashMap<String, u64> = HashMap::new();
+    for day in last_days(days) {
+        for command in commands(day).await? {
+            let count = usage(day, &command).await?.count(chat);
+            if count > 0 {
+                *totals.entry(command).or_default() += count;
+            }
+        }
+    }
+    let mut totals: Vec<_> = totals.into_iter().collect();
+    totals.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    Ok(totals)
+}
+
+pub async fn daily_totals(
+    days: u32,
+    command: Option<&str>,
+    chat: Option<ChatId>,
+) -> Result<Vec<(NaiveDate, u64)>, StorageError> {
+    let mut totals = Vec::new();
+    for day in last_days(days) {
+        let count = match command {
+            Some(command) => usage(day, command).await?.count(chat),
+            None => {
+                let mut count = 0;
+                for command in commands(day).await? {
+                    count += usage(day, &command).await?.count(chat);
+                }
+                count
+            }
+        };
+        totals.push((day, count));
+    }
+    totals.reverse();
+    Ok(totals)
+}
+
+pub async fn top_users(
+    days: u32,
+    command: Option<&str>,
+) -> Result<Vec<(UserId, u64)>, StorageError> {
+    let mut totals: HashMap<u64, u64> = HashMap::new();
+    for day in last_days(days) {
+        let commands = match command {
+            Some(command) => vec![command.to_string()],
+            None => commands(day).await?,
+        };
+        for command in commands {
+            for (user, count) in usage(day, &command).await?.users {
+                *totals.entry(user).or_default() += count;
+            }
+        }
+    }
+    let mut totals: Vec<_> = totals
+        .into_iter()
+        .map(|(user, count)| (UserId(user), count))
+        .collect();
+    totals.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    Ok(totals)
+}
+
+pub async fn prune(keep_days: u32) -> Result<usize, StorageError> {
+    let Some(cutoff) = last_days(keep_days).last() else {
+        return Ok(0);
+    };
+    let storage = storage::storage();
+    let mut removed = 0;
+    for key in storage.keys(PREFIX).await? {
+        let day = key
+            .strip_prefix(PREFIX)
+            .and_then(|rest| rest.split_once(':'))
+            .and_then(|(day, _)| day.parse::<NaiveDate>().ok());
+        if day.is_some_and(|day| day < cutoff) {
+            storage.delete(&key).await?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+pub fn bar_chart(rows: &[(String, u64)], width: usize) -> String {
+    let max = rows
+        .iter()
+        .map(|(_, count)| *count)
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    let label_width = rows
+        .iter()
+        .map(|(label, _)| label.chars().count())
+        .max()
+        .unwrap_or(0);
+    let mut chart = String::new();
+    for (label, count) in rows {
+        let filled = (*count as usize * width).div_ceil(max as usize);
+        chart.push_str(&format!(
+            "{:<label_width$} {} {}\n",
+            label,
+            "█".repeat(filled),
+            count,
+        ));
+    }
+    chart
+}
+
+#[cfg(feature = "usage-command")]
+#[crate::TeloxidePlugin(commands = ["usage"], prefixes = ["/"], admin_only = true)]
+async fn usage_report(ctx: PluginContext) -> Option<crate::SendMessageParams> {
+    let chat = ctx.chat_id()?;
+    let days = ctx
+        .args()
+        .first()
+        .and_then(|arg| arg.parse::<u32>().ok())
+        .unwrap_or(7)
+        .clamp(1, 90);
+
+    let commands = top_commands(days, Some(chat)).await.ok()?;
+    if commands.is_empty() {
+        return Some(crate::SendMessageParams::new(format!(
+            "No usage recorded in the last {} days.",
+            days
+        )));
+    }
+    let per_day: Vec<_> = daily_totals(days, None, Some(chat))
+        .await
+        .ok()?
+        .into_iter()
+        .map(|(day, count)| (day.format("%m-%d").to_string(), count))
+        .collect();
+
+    let text = format!(
+        "<b>Usage in the last {} days</b>\n<pre>{}</pre>\n<b>Per day</b>\n<pre>{}</pre>",
+        days,
+        bar_chart(&commands, 16),
+        bar_chart(&per_day, 16),
+    );
+    Some(crate::SendMessageParams::new(text).parse_mode(teloxide::types::ParseMode::Html))
+}
@@ -0,0 +1,475 @@
+use crate::context::{split_command, PluginContext};
+use crate::extract::FromContext;
+use chrono::{DateTime, Datelike, NaiveDate, NaiveTime, TimeDelta, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::RwLock;
+use std::time::Duration;
+
+const DEFAULT_TIME: (u32, u32) = (9, 0);
+// Longer durations are almost certainly typos, and far past the end of chrono's
+// calendar they'd overflow.
+const MAX_DURATION: Duration = Duration::from_secs(10 * 366 * 86400);
+
+// Languages that write `1.234,5`; everything else is read as `1,234.5`.
+const COMMA_DECIMAL: &[&str] = &[
+    "az", "be", "bg", "cs", "da", "de", "el", "es", "et", "fi", "fr", "hr", "hu", "id", "it", "kk",
+    "lt", "lv", "nb", "nl", "no", "pl", "pt", "ro", "ru", "sk", "sl", "sr", "sv", "tr", "uk", "vi",
+];
+
+static DEFAULT_TZ: RwLock<Tz> = RwLock::new(Tz::UTC);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArgError {
+    pub kind: &'static str,
+    pub input: String,
+}
+
+impl ArgError {
+    fn new(kind: &'static str, input: &str) -> Self {
+        Self {
+            kind,
+            input: input.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ArgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "couldn't read `{}` as a {}", self.input, self.kind)
+    }
+}
+
+impl std::error::Error for ArgError {}
+
+pub fn set_default_timezone(tz: Tz) {
+    *DEFAULT_TZ.write().unwrap() = tz;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct HumanDuration(pub Duration);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct HumanDate(pub DateTime<Utc>);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct HumanSize(pub u64);
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct HumanNumber(pub f64);
+
+fn unit_secs(unit: &str) -> Option<f64> {
+    Some(match unit {
+        "s" | "sec" | "secs" | "second" | "seconds" => 1.0,
+        "m" | "min" | "mins" | "minute" | "minutes" => 60.0,
+        "h" | "hr" | "hrs" | "hour" | "hours" => 3600.0,
+        "d" | "day" | "days" => 86400.0,
+        "w" | "wk" | "week" | "weeks" => 604800.0,
+        _ => return None,
+    })
+}
+
+// Splits `2h30m` into `[(2, "h"), (30, "m")]`.
+fn number_unit_pairs(text: &str) -> Option<Vec<(f64, &str)>> {
+    let mut pairs = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let number_end = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let unit_end = rest[number_end..]
+            .find(|c: char| !c.is_alphabetic())
+            .map_or(rest.len(), |end| number_end + end);
+        if number_end == 0 {
+            return None;
+        }
+        pairs.push((
+            rest[..number_end].parse().ok()?,
+            &rest[number_end..unit_end],
+        ));
+        rest = &rest[unit_end..];
+    }
+    Some(pairs)
+}
+
+impl FromStr for HumanDuration {
+    type Err = ArgError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ArgError::new("duration", s);
+        let lower = s.trim().to_lowercase();
+        let compact: String = lower.split_whitespace().collect();
+        let pairs = number_unit_pairs(&compact).filter(|pairs| !pairs.is_empty());
+        let mut secs = 0.0;
+        for (value, unit) in pairs.ok_or_else(error)? {
+            secs += value * unit_secs(unit).ok_or_else(error)?;
+        }
+        Duration::try_from_secs_f64(secs)
+            .ok()
+            .filter(|duration| *duration <= MAX_DURATION)
+            .map(HumanDuration)
+            .ok_or_else(|| ArgError::new("duration of at most 10 years", s))
+    }
+}
+
+// The two largest units, like `2h 5m`, which is plenty for telling people how long.
+impl fmt::Display for HumanDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.0.as_secs();
+        match secs {
+            0..=59 => write!(f, "{}s", secs),
+            60..=3599 => write!(f, "{}m {}s", secs / 60, secs % 60),
+            3600..=86399 => write!(f, "{}h {}m", secs / 3600, secs % 3600 / 60),
+            _ => write!(f, "{}d {}h", secs / 86400, secs % 86400 / 3600),
+        }
+    }
+}
+
+impl FromStr for HumanSize {
+    type Err = ArgError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ArgError::new("size", s);
+        let lower = s.trim().to_lowercase();
+        let compact: String = lower.split_whitespace().collect();
+        let [(value, unit)] = number_unit_pairs(&compact).ok_or_else(error)?[..] else {
+            return Err(error());
+        };
+        let multiplier: u64 = match unit {
+            "" | "b" => 1,
+            "k" | "kb" | "kib" => 1 << 10,
+            "m" | "mb" | "mib" => 1 << 20,
+            "g" | "gb" | "gib" => 1 << 30,
+            "t" | "tb" | "tib" => 1 << 40,
+            _ => return Err(error()),
+        };
+        Ok(HumanSize((value * multiplier as f64).round() as u64))
+    }
+}
+
+impl HumanNumber {
+    pub fn parse_for(s: &str, lang: Option<&str>) -> Result<Self, ArgError> {
+        let primary = lang.map(|code| code.split(['-', '_']).next().unwrap_or(code));
+        let (decimal, group) = match primary {
+            Some(code) if COMMA_DECIMAL.iter().any(|l| l.eq_ignore_ascii_case(code)) => (',', '.'),
+            _ => ('.', ','),
+        };
+        let normalized: String = s
+            .trim()
+            .chars()
+            .filter(|c| *c != group && !matches!(c, ' ' | '\u{a0}' | '\u{202f}' | '\'' | '_'))
+            .map(|c| if c == decimal { '.' } else { c })
+            .collect();
+        normalized
+            .parse()
+            .map(HumanNumber)
+            .map_err(|_| ArgError::new("number", s))
+    }
+}
+
+impl FromStr for HumanNumber {
+    type Err = ArgError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        HumanNumber::parse_for(s, None)
+    }
+}
+
+fn parse_weekday(word: &str) -> Option<Weekday> {
+    let day = match word {
+        "mon" | "monday" => Weekday::Mon,
+        "tue" | "tues" | "tuesday" => Weekday::Tue,
+        "wed" | "wednesday" => Weekday::Wed,
+        "thu" | "thurs" | "thursday" => Weekday::Thu,
+        "fri" | "friday" => Weekday::Fri,
+        "sat" | "saturday" => Weekday::Sat,
+        "sun" | "sunday" => Weekday::Sun,
+        _ => return None,
+    };
+    Some(day)
+}
+
+fn parse_day(word: &str, today: NaiveDate) -> Option<NaiveDate> {
+    match word {
+        "today" => Some(today),
+        "tomorrow" | "tmrw" => today.succ_opt(),
+        _ => {
+            if let Some(weekday) = parse_weekday(word) {
+                let ahead = (weekday.num_days_from_monday() + 7
+                    - today.weekday().num_days_from_monday())
+                    % 7;
+                return Some(today + TimeDelta::days(if ahead == 0 { 7 } else { ahead as i64 }));
+            }
+            NaiveDate::parse_from_str(word, "%Y-%m-%d").ok()
+        }
+    }
+}
+
+fn parse_time(word: &str) -> Option<NaiveTime> {
+    let (clock, offset) = match (word.strip_suffix("am"), word.strip_suffix("pm")) {
+        (Some(clock), _) => (clock, Some(0)),
+        (_, Some(clock)) => (clock, Some(12)),
+        _ => (word, None),
+    };
+    let (hour, minute) = match clock.split_once(':') {
+        Some((hour, minute)) => (hour.parse::<u32>().ok()?, minute.parse::<u32>().ok()?),
+        None if offset.is_some() => (clock.parse::<u32>().ok()?, 0),
+        None => return None,
+    };
+    let hour = match offset {
+        Some(offset) if (1..=12).contains(&hour) => hour % 12 + offset,
+        Some(_) => return None,
+        None => hour,
+    };
+    NaiveTime::from_hms_opt(hour, minute, 0)
+}
+
+fn parse_relative(words: &[&str]) -> Option<(Duration, usize)> {
+    if let Ok(HumanDuration(duration)) = words.first()?.parse() {
+        return Some((duration, 1));
+    }
+    let pair = format!("{}{}", words.first()?, words.get(1)?);
+    let HumanDuration(duration) = pair.parse().ok()?;
+    Some((duration, 2))
+}
+
+impl HumanDate {
+    pub fn parse_prefix(text: &str, now: DateTime<Tz>) -> Option<(HumanDate, &str)> {
+        let lower = text.to_lowercase();
+        let words: Vec<&str> = lower.split_whitespace().collect();
+        let (date, used) = Self::parse_words(&words, now)?;
+        let mut rest = text.trim_start();
+        for _ in 0..used {
+            rest = rest
+                .split_once(char::is_whitespace)
+                .map_or("", |(_, rest)| rest.trim_start());
+        }
+        Some((HumanDate(date), rest))
+    }
+
+    pub fn parse_at(text: &str, now: DateTime<Tz>) -> Option<HumanDate> {
+        match Self::parse_prefix(text, now)? {
+            (date, "") => Some(date),
+            _ => None,
+        }
+    }
+
+    fn parse_words(words: &[&str], now: DateTime<Tz>) -> Option<(DateTime<Utc>, usize)> {
+        if words.first() == Some(&"in") {
+            let (duration, used) = parse_relative(&words[1..])?;
+            let duration = TimeDelta::from_std(duration).ok()?;
+            return Some((
+                now.with_timezone(&Utc).checked_add_signed(duration)?,
+                used + 1,
+            ));
+        }
+
+        let today = now.date_naive();
+        let mut used = 0;
+        let day = words.first().and_then(|word| parse_day(word, today));
+        if day.is_some() {
+            used += 1;
+        }
+        let at = usize::from(words.get(used) == Some(&"at"));
+        let time = words.get(used + at).and_then(|word| parse_time(word));
+        if time.is_some() {
+            used += at + 1;
+        }
+
+        let (date, time) = match (day, time) {
+            (None, None) => return None,
+            (Some(day), time) => (
+                day,
+                time.unwrap_or_else(|| {
+                    NaiveTime::from_hms_opt(DEFAULT_TIME.0, DEFAULT_TIME.1, 0).unwrap()
+                }),
+            ),
+            (None, Some(time)) if time > now.time() => (today, time),
+            (None, Some(time)) => (today.succ_opt()?, time),
+        };
+        let local = now
+            .timezone()
+            .from_local_datetime(&date.and_time(time))
+            .earliest()?;
+        Some((local.with_timezone(&Utc), used))
+    }
+}
+
+impl FromStr for HumanDate {
+    type Err = ArgError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let now = Utc::now().with_timezone(&*DEFAULT_TZ.read().unwrap());
+        HumanDate::parse_at(s, now).ok_or_else(|| ArgError::new("date", s))
+    }
+}
+
+fn plugin_timezone(ctx: &PluginContext) -> Tz {
+    ctx.plugin
+        .and_then(|plugin| plugin.tz)
+        .and_then(|tz| tz.parse().ok())
+        .unwrap_or_else(|| *DEFAULT_TZ.read().unwrap())
+}
+
+impl FromContext for HumanDuration {
+    fn from_context(ctx: &PluginContext) -> Option<Self> {
+        ctx.arg(0)
+    }
+}
+
+impl FromContext for HumanSize {
+    fn from_context(ctx: &PluginContext) -> Option<Self> {
+        ctx.arg(0)
+    }
+}
+
+impl FromContext for HumanNumber {
+    fn from_context(ctx: &PluginContext) -> Option<Self> {
+        ctx.number_arg(0)
+    }
+}
+
+impl FromContext for HumanDate {
+    fn from_context(ctx: &PluginContext) -> Option<Self> {
+        ctx.date_arg().map(|(date, _)| date)
+    }
+}
+
+impl PluginContext {
+    pub fn args_text(&self) -> &str {
+        self.text_or_caption()
+            .map(|text| split_command(text).1)
+            .unwrap_or("")
+    }
+
+    pub fn arg<T: FromStr>(&self, index: usize) -> Option<T> {
+        self.args().get(index)?.parse().ok()
+    }
+
+    pub fn number_arg(&self, index: usize) -> Option<HumanNumber> {
+        let lang = self.sender().and_then(|u| u.language_code.as_deref());
+        HumanNumber::parse_for(self.args().get(index)?, lang).ok()
+    }
+
+    pub fn date_arg(&self) -> Option<(HumanDate, &str)> {
+        let now = Utc::now().with_timezone(&plugin_timezone(self));
+        HumanDate::parse_prefix(self.args_text(), now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A Wednesday afternoon in Berlin, which is UTC+2 in May.
+    fn now() -> DateTime<Tz> {
+        chrono_tz::Europe::Berlin
+            .with_ymd_and_hms(2024, 5, 15, 14, 0, 0)
+            .unwrap()
+    }
+
+    fn utc(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, h, min, 0).unwrap()
+    }
+
+    fn date(text: &str) -> Option<DateTime<Utc>> {
+        HumanDate::parse_at(text, now()).map(|date| date.0)
+    }
+
+    fn duration(text: &str) -> Result<u64, ArgError> {
+        text.parse::<HumanDuration>()
+            .map(|duration| duration.0.as_secs())
+    }
+
+    #[test]
+    fn durations_add_up_their_units() {
+        assert_eq!(duration("2h30m"), Ok(9000));
+        assert_eq!(duration("2h 30m"), Ok(9000));
+        assert_eq!(duration("1.5h"), Ok(5400));
+        assert_eq!(duration("90 Minutes"), Ok(5400));
+        assert_eq!(duration("1w"), Ok(604800));
+    }
+
+    #[test]
+    fn bad_durations_are_refused() {
+        assert!(duration("").is_err());
+        assert!(duration("5").is_err());
+        assert!(duration("5x").is_err());
+        assert!(duration("h").is_err());
+        assert_eq!(
+            duration("4000d").unwrap_err().kind,
+            "duration of at most 10 years"
+        );
+    }
+
+    #[test]
+    fn durations_display_their_two_largest_units() {
+        let show = |secs| HumanDuration(Duration::from_secs(secs)).to_string();
+        assert_eq!(show(59), "59s");
+        assert_eq!(show(90), "1m 30s");
+        assert_eq!(show(9000), "2h 30m");
+        assert_eq!(show(90061), "1d 1h");
+    }
+
+    #[test]
+    fn sizes_use_binary_units() {
+        let size = |text: &str| text.parse::<HumanSize>().map(|size| size.0);
+        assert_eq!(size("512"), Ok(512));
+        assert_eq!(size("10MB"), Ok(10 << 20));
+        assert_eq!(size("1.5 k"), Ok(1536));
+        assert_eq!(size("2GiB"), Ok(2 << 30));
+        assert!(size("5zb").is_err());
+        assert!(size("1k2m").is_err());
+    }
+
+    #[test]
+    fn numbers_follow_the_language() {
+        let number = |text, lang| HumanNumber::parse_for(text, lang).map(|number| number.0);
+        assert_eq!(number("1,234.5", None), Ok(1234.5));
+        assert_eq!(number("1.234,5", Some("de")), Ok(1234.5));
+        assert_eq!(number("3,5", Some("pt-BR")), Ok(3.5));
+        assert_eq!(number("1 000", Some("fr")), Ok(1000.0));
+        assert_eq!(number("1'000", None), Ok(1000.0));
+        assert!(number("abc", None).is_err());
+    }
+
+    #[test]
+    fn relative_dates_count_from_now() {
+        assert_eq!(date("in 2h"), Some(utc(2024, 5, 15, 14, 0)));
+        assert_eq!(date("in 2 hours"), Some(utc(2024, 5, 15, 14, 0)));
+    }
+
+    #[test]
+    fn days_default_to_nine_in_the_morning() {
+        assert_eq!(date("tomorrow"), Some(utc(2024, 5, 16, 7, 0)));
+        assert_eq!(date("2024-06-01"), Some(utc(2024, 6, 1, 7, 0)));
+        // Today's weekday means next week's.
+        assert_eq!(date("wednesday"), Some(utc(2024, 5, 22, 7, 0)));
+    }
+
+    #[test]
+    fn times_without_a_day_are_the_next_one() {
+        assert_eq!(date("15:30"), Some(utc(2024, 5, 15, 13, 30)));
+        assert_eq!(date("10:00"), Some(utc(2024, 5, 16, 8, 0)));
+        assert_eq!(date("12am"), Some(utc(2024, 5, 15, 22, 0)));
+        assert_eq!(date("fri at 5pm"), Some(utc(2024, 5, 17, 15, 0)));
+        assert_eq!(date("13pm"), None);
+        assert_eq!(date("25:00"), None);
+    }
+
+    #[test]
+    fn times_that_dont_exist_locally_are_refused() {
+        // Clocks in Berlin skip from 2:00 to 3:00 that night.
+        assert_eq!(date("2024-03-31 at 2:30"), None);
+    }
+
+    #[test]
+    fn prefixes_keep_the_rest_as_written() {
+        let (found, rest) = HumanDate::parse_prefix("Friday at 5pm Buy Milk", now()).unwrap();
+        assert_eq!(found.0, utc(2024, 5, 17, 15, 0));
+        assert_eq!(rest, "Buy Milk");
+        assert_eq!(date("tomorrow buy milk"), None);
+        assert!(HumanDate::parse_prefix("buy milk", now()).is_none());
+    }
+}
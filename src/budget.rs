@@ -0,0 +1,182 @@
+use crate::registry::PluginMeta;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Budget {
+    pub time_per_minute: Option<Duration>,
+    pub sends_per_minute: Option<u32>,
+    pub suspend_for: Duration,
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Self {
+            time_per_minute: None,
+            sends_per_minute: None,
+            suspend_for: Duration::from_secs(300),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PluginUsage {
+    pub time: Duration,
+    pub sends: u32,
+    pub runs: u64,
+    pub suspensions: u64,
+    pub suspended_for: Option<Duration>,
+}
+
+struct Account {
+    window_start: Instant,
+    time: Duration,
+    sends: u32,
+    runs: u64,
+    suspensions: u64,
+    suspended_until: Option<Instant>,
+}
+
+impl Account {
+    fn new(now: Instant) -> Self {
+        Self {
+            window_start: now,
+            time: Duration::ZERO,
+            sends: 0,
+            runs: 0,
+            suspensions: 0,
+            suspended_until: None,
+        }
+    }
+
+    fn roll(&mut self, now: Instant) {
+        if now.duration_since(self.window_start) >= WINDOW {
+            self.window_start = now;
+            self.time = Duration::ZERO;
+            self.sends = 0;
+        }
+        if self.suspended_until.is_some_and(|until| until <= now) {
+            self.suspended_until = None;
+        }
+    }
+
+    fn check(&mut self, budget: &Budget, now: Instant) {
+        let over_time = budget.time_per_minute.is_some_and(|max| self.time > max);
+        let over_sends = budget.sends_per_minute.is_some_and(|max| self.sends > max);
+        if (over_time || over_sends) && self.suspended_until.is_none() {
+            self.suspended_until = Some(now + budget.suspend_for);
+            self.suspensions += 1;
+        }
+    }
+}
+
+static DEFAULT_BUDGET: Lazy<RwLock<Budget>> = Lazy::new(|| RwLock::new(Budget::default()));
+
+static BUDGETS: Lazy<RwLock<HashMap<&'static str, Budget>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+static ACCOUNTS: Lazy<RwLock<HashMap<&'static str, Account>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+pub fn set_default_budget(budget: Budget) {
+    *DEFAULT_BUDGET.write().unwrap() = budget;
+}
+
+pub fn set_budget(plugin: &'static str, budget: Budget) {
+    BUDGETS.write().unwrap().insert(plugin, budget);
+}
+
+fn budget_of(plugin: &str) -> Budget {
+    BUDGETS
+        .read()
+        .unwrap()
+        .get(plugin)
+        .copied()
+        .unwrap_or_else(|| *DEFAULT_BUDGET.read().unwrap())
+}
+
+fn with_account<R>(plugin: &'static str, f: impl FnOnce(&mut Account, Instant) -> R) -> R {
+    let now = Instant::now();
+    let mut accounts = ACCOUNTS.write().unwrap();
+    let account = accounts.entry(plugin).or_insert_with(|| Account::new(now));
+    account.roll(now);
+    f(account, now)
+}
+
+pub fn suspended_for(plugin: &'static str) -> Option<Duration> {
+    with_account(plugin, |account, now| {
+        account.suspended_until.map(|until| until - now)
+    })
+}
+
+pub fn resume(plugin: &'static str) {
+    with_account(plugin, |account, _| account.suspended_until = None);
+}
+
+// Runs `future` and adds up the time spent inside its `poll`s: the time the handler
+// kept a worker busy. Waiting on Telegram, timers or locks in between doesn't count.
+pub(crate) async fn metered<F: Future>(future: F) -> (F::Output, Duration) {
+    let mut future = std::pin::pin!(future);
+    let mut busy = Duration::ZERO;
+    let output = std::future::poll_fn(|cx| {
+        let started = Instant::now();
+        let poll = future.as_mut().poll(cx);
+        busy += started.elapsed();
+        poll
+    })
+    .await;
+    (output, busy)
+}
+
+pub(crate) fn record_run(plugin: &'static PluginMeta, elapsed: Duration) {
+    let budget = budget_of(plugin.name);
+    with_account(plugin.name, |account, now| {
+        account.runs += 1;
+        account.time += elapsed;
+        account.check(&budget, now);
+    });
+}
+
+pub(crate) fn record_send(plugin: &'static PluginMeta) -> Result<(), Duration> {
+    let budget = budget_of(plugin.name);
+    with_account(plugin.name, |account, now| {
+        if let Some(until) = account.suspended_until {
+            return Err(until - now);
+        }
+        account.sends += 1;
+        account.check(&budget, now);
+        match account.suspended_until {
+            Some(until) => Err(until - now),
+            None => Ok(()),
+        }
+    })
+}
+
+pub fn usage(plugin: &'static str) -> PluginUsage {
+    with_account(plugin, |account, now| PluginUsage {
+        time: account.time,
+        sends: account.sends,
+        runs: account.runs,
+        suspensions: account.suspensions,
+        suspended_for: account.suspended_until.map(|until| until - now),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn metered_leaves_out_time_spent_waiting() {
+        let ((), busy) = metered(tokio::time::sleep(Duration::from_millis(200))).await;
+        assert!(busy < Duration::from_millis(100), "{:?}", busy);
+
+        let ((), busy) = metered(async { std::thread::sleep(Duration::from_millis(50)) }).await;
+        assert!(busy >= Duration::from_millis(50), "{:?}", busy);
+    }
+}
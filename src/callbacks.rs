@@ -0,0 +1,499 @@
+use crate::context::{reply_of, PluginContext};
+use crate::extract::FromContext;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use once_cell::sync::Lazy;
+use sha2::Sha256;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+use teloxide::types::{ChatId, MessageId, UserId};
+
+const SEPARATOR: char = ':';
+const SIGNATURE: char = '~';
+const TAG_LEN: usize = 8;
+const TAG_CHARS: usize = 11;
+const REMEMBERED_OWNERS: usize = 10_000;
+// Telegram's limit on callback data, in bytes.
+pub const MAX_DATA_LEN: usize = 64;
+
+static SIGNING_KEY: Lazy<RwLock<Vec<u8>>> = Lazy::new(|| RwLock::new(random_key()));
+static REQUIRE_SIGNED: AtomicBool = AtomicBool::new(false);
+static SIGNED_NAMES: Lazy<RwLock<HashSet<String>>> = Lazy::new(|| RwLock::new(HashSet::new()));
+static OWNERS: Lazy<Mutex<KeyboardOwners>> = Lazy::new(|| Mutex::new(KeyboardOwners::default()));
+
+#[derive(Default)]
+struct KeyboardOwners {
+    owners: HashMap<(ChatId, MessageId), UserId>,
+    order: VecDeque<(ChatId, MessageId)>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallbackData {
+    pub name: String,
+    pub args: Vec<String>,
+    pub owner: Option<UserId>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CallbackError {
+    Unsigned,
+    Tampered,
+    Expired,
+    // From `encode_signed`: the data doesn't fit in a button.
+    TooLong(usize),
+}
+
+impl fmt::Display for CallbackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CallbackError::Unsigned => write!(f, "callback data isn't signed"),
+            CallbackError::Tampered => write!(f, "callback data signature doesn't match"),
+            CallbackError::Expired => write!(f, "callback data has expired"),
+            CallbackError::TooLong(len) => write!(
+                f,
+                "callback data is {} bytes, over Telegram's limit of {}",
+                len, MAX_DATA_LEN
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CallbackError {}
+
+// Falls back to a per-process key, so signed buttons stop working after a restart
+// unless a stable key is configured.
+fn random_key() -> Vec<u8> {
+    let mut key = vec![0; 32];
+    getrandom::fill(&mut key).expect("the OS random number generator is available");
+    key
+}
+
+pub fn set_signing_key(key: impl Into<Vec<u8>>) {
+    *SIGNING_KEY.write().unwrap() = key.into();
+}
+
+pub fn set_require_signed(required: bool) {
+    REQUIRE_SIGNED.store(required, Ordering::Relaxed);
+}
+
+pub fn is_signed_required() -> bool {
+    REQUIRE_SIGNED.load(Ordering::Relaxed)
+}
+
+// Unsigned data for this callback name is refused even while `set_require_signed` is
+// off. `signed = true` on a callback plugin calls this for its names.
+pub fn require_signed_for(name: impl Into<String>) {
+    SIGNED_NAMES.write().unwrap().insert(name.into());
+}
+
+fn needs_signature(data: &str) -> bool {
+    is_signed_required()
+        || SIGNED_NAMES
+            .read()
+            .unwrap()
+            .iter()
+            .any(|name| matches(name, data))
+}
+
+fn escape(part: &str) -> String {
+    part.replace('%', "%25")
+        .replace(SEPARATOR, "%3A")
+        .replace(SIGNATURE, "%7E")
+}
+
+fn unescape(part: &str) -> String {
+    part.replace("%3A", ":")
+        .replace("%7E", "~")
+        .replace("%25", "%")
+}
+
+fn mac() -> Hmac<Sha256> {
+    Hmac::new_from_slice(&SIGNING_KEY.read().unwrap()).expect("HMAC accepts keys of any length")
+}
+
+fn now() -> u64 {
+    chrono::Utc::now().timestamp().max(0) as u64
+}
+
+impl CallbackData {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            args: Vec::new(),
+            owner: None,
+        }
+    }
+
+    pub fn arg(mut self, value: impl ToString) -> Self {
+        self.args.push(value.to_string());
+        self
+    }
+
+    pub fn owner(mut self, user: UserId) -> Self {
+        self.owner = Some(user);
+        self
+    }
+
+    pub fn get<T: FromStr>(&self, index: usize) -> Option<T> {
+        self.args.get(index)?.parse().ok()
+    }
+
+    pub fn encode(&self) -> String {
+        std::iter::once(&self.name)
+            .chain(&self.args)
+            .map(|part| escape(part))
+            .collect::<Vec<_>>()
+            .join(":")
+    }
+
+    pub fn encode_signed(&self, ttl: Option<Duration>) -> Result<String, CallbackError> {
+        let expires = ttl.map_or(0, |ttl| now() + ttl.as_secs().max(1));
+        let meta = match self.owner {
+            Some(owner) => format!("{:x}.{:x}", expires, owner.0),
+            None => format!("{:x}", expires),
+        };
+        let signed = format!("{}{}{}", self.encode(), SIGNATURE, meta);
+        let mut mac = mac();
+        mac.update(signed.as_bytes());
+        let tag = mac.finalize().into_bytes();
+        let data = format!(
+            "{}{}{}",
+            signed,
+            SIGNATURE,
+            URL_SAFE_NO_PAD.encode(&tag[..TAG_LEN])
+        );
+        if data.len() > MAX_DATA_LEN {
+            return Err(CallbackError::TooLong(data.len()));
+        }
+        Ok(data)
+    }
+
+    pub fn decode(data: &str) -> Option<Self> {
+        let (data, owner) = match split_signature(data) {
+            Some((payload, meta, _)) => (payload, parse_meta(meta)?.1),
+            None => (data, None),
+        };
+        if data.is_empty() {
+            return None;
+        }
+        let mut parts = data.split(SEPARATOR).map(unescape);
+        Some(Self {
+            name: parts.next()?,
+            args: parts.collect(),
+            owner,
+        })
+    }
+}
+
+impl fmt::Display for CallbackData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.encode())
+    }
+}
+
+impl From<CallbackData> for String {
+    fn from(data: CallbackData) -> Self {
+        data.encode()
+    }
+}
+
+fn split_signature(data: &str) -> Option<(&str, &str, &str)> {
+    let (signed, tag) = data.rsplit_once(SIGNATURE)?;
+    let (payload, meta) = signed.rsplit_once(SIGNATURE)?;
+    let looks_signed = tag.len() == TAG_CHARS && parse_meta(meta).is_some();
+    looks_signed.then_some((payload, meta, tag))
+}
+
+fn parse_meta(meta: &str) -> Option<(u64, Option<UserId>)> {
+    let (expires, owner) = match meta.split_once('.') {
+        Some((expires, owner)) => (expires, Some(owner)),
+        None => (meta, None),
+    };
+    let hex = |part: &str| {
+        (!part.is_empty() && part.chars().all(|c| c.is_ascii_hexdigit()))
+            .then(|| u64::from_str_radix(part, 16).ok())
+            .flatten()
+    };
+    let owner = match owner {
+        Some(owner) => Some(UserId(hex(owner)?)),
+        None => None,
+    };
+    Some((hex(expires)?, owner))
+}
+
+pub fn verify(data: &str) -> Result<&str, CallbackError> {
+    verify_owned(data).map(|(payload, _)| payload)
+}
+
+fn verify_owned(data: &str) -> Result<(&str, Option<UserId>), CallbackError> {
+    let Some((payload, meta, tag)) = split_signature(data) else {
+        // `encode` escapes the marker, so it only shows up in data that was signed
+        // and then cut or edited.
+        if data.contains(SIGNATURE) {
+            return Err(CallbackError::Tampered);
+        }
+        return if needs_signature(data) {
+            Err(CallbackError::Unsigned)
+        } else {
+            Ok((data, None))
+        };
+    };
+    let tag = URL_SAFE_NO_PAD
+        .decode(tag)
+        .ok()
+        .filter(|tag| tag.len() == TAG_LEN)
+        .ok_or(CallbackError::Tampered)?;
+    let mut mac = mac();
+    mac.update(&data.as_bytes()[..data.len() - TAG_CHARS - 1]);
+    mac.verify_truncated_left(&tag)
+        .map_err(|_| CallbackError::Tampered)?;
+    let (expires, owner) = parse_meta(meta).ok_or(CallbackError::Tampered)?;
+    if expires != 0 && expires < now() {
+        return Err(CallbackError::Expired);
+    }
+    Ok((payload, owner))
+}
+
+pub(crate) fn remember_owner(chat: ChatId, message: MessageId, user: UserId) {
+    let mut remembered = OWNERS.lock().unwrap();
+    if remembered.owners.insert((chat, message), user).is_none() {
+        remembered.order.push_back((chat, message));
+    }
+    while remembered.order.len() > REMEMBERED_OWNERS {
+        if let Some(oldest) = remembered.order.pop_front() {
+            remembered.owners.remove(&oldest);
+        }
+    }
+}
+
+pub fn keyboard_owner(ctx: &PluginContext) -> Option<UserId> {
+    let cq = ctx.callback_query.as_ref()?;
+    if let Some(owner) = cq
+        .data
+        .as_deref()
+        .and_then(|data| verify_owned(data).ok())
+        .and_then(|(_, owner)| owner)
+    {
+        return Some(owner);
+    }
+    let message = cq.message.as_ref()?;
+    let remembered = OWNERS
+        .lock()
+        .unwrap()
+        .owners
+        .get(&(message.chat().id, message.id()))
+        .copied();
+    remembered.or_else(|| {
+        reply_of(message.regular_message()?)?
+            .from
+            .as_ref()
+            .map(|user| user.id)
+    })
+}
+
+// Fails closed: a keyboard whose owner can't be worked out, say one sent before a
+// restart without an owner in its signed data, can't be pressed by anyone.
+pub(crate) fn pressed_by_owner(ctx: &PluginContext) -> bool {
+    let Some(cq) = &ctx.callback_query else {
+        return true;
+    };
+    keyboard_owner(ctx).is_some_and(|owner| owner == cq.from.id)
+}
+
+pub(crate) fn rejection_notice(ctx: &PluginContext, err: CallbackError) -> String {
+    let texts = ctx.framework_texts();
+    match err {
+        CallbackError::Expired => texts.button_expired.clone(),
+        CallbackError::Unsigned | CallbackError::Tampered | CallbackError::TooLong(_) => {
+            texts.button_invalid.clone()
+        }
+    }
+}
+
+pub(crate) fn matches(filter: &str, data: &str) -> bool {
+    filter.split('|').any(|name| {
+        data == name
+            || data
+                .strip_prefix(name)
+                .is_some_and(|rest| rest.starts_with(SEPARATOR))
+    })
+}
+
+impl FromContext for CallbackData {
+    fn from_context(ctx: &PluginContext) -> Option<Self> {
+        CallbackData::decode(ctx.callback_query.as_ref()?.data.as_deref()?)
+    }
+}
+
+impl PluginContext {
+    pub fn callback_data(&self) -> Option<CallbackData> {
+        CallbackData::from_context(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use teloxide::Bot;
+
+    // A press by user `by` on message `message` in a group, with no reply to go by.
+    fn press(by: u64, message: i32, data: &str) -> PluginContext {
+        let query: teloxide::types::CallbackQuery = serde_json::from_value(json!({
+            "id": "1",
+            "from": {"id": by, "is_bot": false, "first_name": "Ann"},
+            "chat_instance": "1",
+            "data": data,
+            "message": keyboard_message(message),
+        }))
+        .unwrap();
+        PluginContext::new(Bot::new("1:test"), None, Some(query))
+    }
+
+    fn keyboard_message(id: i32) -> Value {
+        json!({
+            "message_id": id,
+            "date": 0,
+            "chat": {"id": -700, "type": "supergroup", "title": "Group"},
+            "from": {"id": 1, "is_bot": true, "first_name": "Bot"},
+            "text": "Pick one",
+        })
+    }
+
+    // What `encode_signed` would give for `meta`, however far in the past it expired.
+    fn signed_with_meta(data: &CallbackData, meta: &str) -> String {
+        let signed = format!("{}{}{}", data.encode(), SIGNATURE, meta);
+        let mut mac = mac();
+        mac.update(signed.as_bytes());
+        let tag = mac.finalize().into_bytes();
+        format!(
+            "{}{}{}",
+            signed,
+            SIGNATURE,
+            URL_SAFE_NO_PAD.encode(&tag[..TAG_LEN])
+        )
+    }
+
+    #[test]
+    fn encode_escapes_separators() {
+        let data = CallbackData::new("vote").arg("a:b").arg("50%").arg("x~y");
+        let encoded = data.encode();
+        assert_eq!(encoded, "vote:a%3Ab:50%25:x%7Ey");
+        assert_eq!(CallbackData::decode(&encoded), Some(data));
+    }
+
+    #[test]
+    fn signed_data_round_trips() {
+        let data = CallbackData::new("page").arg(3).owner(UserId(42));
+        let encoded = data.encode_signed(Some(Duration::from_secs(60))).unwrap();
+        assert_eq!(verify(&encoded), Ok("page:3"));
+        assert_eq!(verify_owned(&encoded).unwrap().1, Some(UserId(42)));
+        assert_eq!(CallbackData::decode(&encoded), Some(data));
+    }
+
+    #[test]
+    fn signed_data_without_ttl_never_expires() {
+        let data = CallbackData::new("menu");
+        let encoded = data.encode_signed(None).unwrap();
+        assert_eq!(verify(&encoded), Ok("menu"));
+    }
+
+    #[test]
+    fn edited_payload_is_tampered() {
+        let encoded = CallbackData::new("ban").arg(1).encode_signed(None).unwrap();
+        let edited = encoded.replacen("ban:1", "ban:2", 1);
+        assert_eq!(verify(&edited), Err(CallbackError::Tampered));
+    }
+
+    #[test]
+    fn edited_owner_is_tampered() {
+        let encoded = CallbackData::new("ban")
+            .owner(UserId(0x10))
+            .encode_signed(None)
+            .unwrap();
+        let edited = encoded.replacen(".10~", ".11~", 1);
+        assert_ne!(edited, encoded);
+        assert_eq!(verify(&edited), Err(CallbackError::Tampered));
+    }
+
+    #[test]
+    fn cut_signature_is_tampered() {
+        let encoded = CallbackData::new("ban").encode_signed(None).unwrap();
+        let cut = &encoded[..encoded.len() - 3];
+        assert_eq!(verify(cut), Err(CallbackError::Tampered));
+    }
+
+    #[test]
+    fn past_expiry_is_expired() {
+        let data = CallbackData::new("vote");
+        let encoded = signed_with_meta(&data, &format!("{:x}", now() - 1));
+        assert_eq!(verify(&encoded), Err(CallbackError::Expired));
+    }
+
+    #[test]
+    fn expiry_is_checked_after_the_signature() {
+        let data = CallbackData::new("vote");
+        let encoded = signed_with_meta(&data, &format!("{:x}", now() - 1));
+        let extended = encoded.replacen(
+            &format!("~{:x}~", now() - 1),
+            &format!("~{:x}~", now() + 3600),
+            1,
+        );
+        assert_eq!(verify(&extended), Err(CallbackError::Tampered));
+    }
+
+    #[test]
+    fn unsigned_data_for_a_signed_name_is_refused() {
+        require_signed_for("signed-only");
+        assert_eq!(verify("signed-only:1"), Err(CallbackError::Unsigned));
+        assert_eq!(verify("signed-only-not:1"), Ok("signed-only-not:1"));
+        assert_eq!(verify("plain:1"), Ok("plain:1"));
+    }
+
+    #[test]
+    fn data_over_64_bytes_is_too_long() {
+        let data = CallbackData::new("x").arg("y".repeat(60));
+        assert!(matches!(
+            data.encode_signed(None),
+            Err(CallbackError::TooLong(len)) if len > MAX_DATA_LEN
+        ));
+    }
+
+    #[test]
+    fn filters_match_whole_names() {
+        assert!(matches("page", "page"));
+        assert!(matches("page", "page:2"));
+        assert!(matches("next|page", "page:2"));
+        assert!(!matches("page", "pages:2"));
+    }
+
+    #[test]
+    fn the_signed_owner_may_press_and_nobody_else() {
+        let data = CallbackData::new("quiz")
+            .arg("a")
+            .owner(UserId(42))
+            .encode_signed(None)
+            .unwrap();
+        assert!(pressed_by_owner(&press(42, 1, &data)));
+        assert!(!pressed_by_owner(&press(43, 1, &data)));
+    }
+
+    #[test]
+    fn a_remembered_owner_may_press() {
+        remember_owner(ChatId(-700), MessageId(2), UserId(42));
+        assert!(pressed_by_owner(&press(42, 2, "quiz:a")));
+        assert!(!pressed_by_owner(&press(43, 2, "quiz:a")));
+    }
+
+    #[test]
+    fn nobody_may_press_when_the_owner_is_unknown() {
+        let ctx = press(42, 3, "quiz:a");
+        assert_eq!(keyboard_owner(&ctx), None);
+        assert!(!pressed_by_owner(&ctx));
+    }
+}
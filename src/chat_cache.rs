@@ -0,0 +1,638 @@
+use crate::storage::StorageError;
+use chrono::{DateTime, Duration, Utc};
+use futures::future::BoxFuture;
+use once_cell::sync::Lazy;
+#[cfg(feature = "redis-chat-cache")]
+use redis::aio::ConnectionManager;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use teloxide::prelude::*;
+use teloxide::types::{ChatMember, ChatMemberStatus, User};
+
+#[derive(Clone, Debug)]
+pub struct SeenMember {
+    pub user: User,
+    pub messages: u64,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub status: Option<ChatMemberStatus>,
+}
+
+// Where the cache keeps what it has seen. `MemoryMemberStore` is the default;
+// `RedisMemberStore` (with the `redis-chat-cache` feature) keeps members across
+// restarts and shares them between processes. Install one with `set_member_store`.
+pub trait MemberStore: Send + Sync {
+    // `user` sent a message at `at`.
+    fn record_message<'a>(
+        &'a self,
+        chat_id: ChatId,
+        user: &'a User,
+        at: DateTime<Utc>,
+    ) -> BoxFuture<'a, Result<(), StorageError>>;
+
+    // The bot looked `user` up and found `status`. A member it hadn't seen before is
+    // first and last seen `at`.
+    fn record_status<'a>(
+        &'a self,
+        chat_id: ChatId,
+        user: &'a User,
+        status: ChatMemberStatus,
+        at: DateTime<Utc>,
+    ) -> BoxFuture<'a, Result<(), StorageError>>;
+
+    fn get(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+    ) -> BoxFuture<'_, Result<Option<SeenMember>, StorageError>>;
+
+    fn members(&self, chat_id: ChatId) -> BoxFuture<'_, Result<Vec<SeenMember>, StorageError>>;
+
+    fn count(&self, chat_id: ChatId) -> BoxFuture<'_, Result<usize, StorageError>> {
+        Box::pin(async move { Ok(self.members(chat_id).await?.len()) })
+    }
+
+    fn clear(&self, chat_id: ChatId) -> BoxFuture<'_, Result<(), StorageError>>;
+
+    // A group became a supergroup: its members move to the new id.
+    fn migrate(&self, from: ChatId, to: ChatId) -> BoxFuture<'_, Result<(), StorageError>>;
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static CAPACITY: AtomicUsize = AtomicUsize::new(100_000);
+
+static MEMBERS: Lazy<RwLock<Members>> = Lazy::new(|| RwLock::new(Members::default()));
+
+static STORE: Lazy<RwLock<Arc<dyn MemberStore>>> =
+    Lazy::new(|| RwLock::new(Arc::new(MemoryMemberStore)));
+
+#[derive(Default)]
+struct Members {
+    chats: HashMap<ChatId, HashMap<UserId, SeenMember>>,
+    len: usize,
+}
+
+impl Members {
+    fn entry(&mut self, chat_id: ChatId, user: &User, now: DateTime<Utc>) -> &mut SeenMember {
+        let known = self
+            .chats
+            .get(&chat_id)
+            .is_some_and(|members| members.contains_key(&user.id));
+        if !known {
+            if self.len >= capacity() {
+                self.evict();
+            }
+            self.len += 1;
+        }
+        self.chats
+            .entry(chat_id)
+            .or_default()
+            .entry(user.id)
+            .or_insert_with(|| SeenMember {
+                user: user.clone(),
+                messages: 0,
+                first_seen: now,
+                last_seen: now,
+                status: None,
+            })
+    }
+
+    // Forgets the least recently seen tenth, so a full cache doesn't sort on every
+    // new member.
+    fn evict(&mut self) {
+        let mut seen: Vec<(DateTime<Utc>, ChatId, UserId)> = self
+            .chats
+            .iter()
+            .flat_map(|(chat, members)| {
+                members
+                    .iter()
+                    .map(|(user, member)| (member.last_seen, *chat, *user))
+            })
+            .collect();
+        let drop = (seen.len() / 10).max(1).min(seen.len());
+        if drop < seen.len() {
+            seen.select_nth_unstable(drop);
+        }
+        for (_, chat, user) in &seen[..drop] {
+            if let Some(members) = self.chats.get_mut(chat) {
+                members.remove(user);
+                if members.is_empty() {
+                    self.chats.remove(chat);
+                }
+            }
+        }
+        self.len -= drop;
+    }
+
+    fn recount(&mut self) {
+        self.len = self.chats.values().map(HashMap::len).sum();
+    }
+}
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn set_member_store(store: impl MemberStore + 'static) {
+    *STORE.write().unwrap() = Arc::new(store);
+}
+
+fn store() -> Arc<dyn MemberStore> {
+    Arc::clone(&STORE.read().unwrap())
+}
+
+// How many members `MemoryMemberStore` holds across all chats (100,000 by default).
+// Past that, the ones seen least recently are forgotten.
+pub fn set_capacity(members: usize) {
+    CAPACITY.store(members.max(1), Ordering::Relaxed);
+    let mut members = MEMBERS.write().unwrap();
+    while members.len > capacity() {
+        members.evict();
+    }
+}
+
+pub fn capacity() -> usize {
+    CAPACITY.load(Ordering::Relaxed)
+}
+
+pub(crate) async fn record_message(msg: &Message) -> Result<(), StorageError> {
+    if !is_enabled() || msg.chat.is_private() {
+        return Ok(());
+    }
+    let Some(user) = &msg.from else {
+        return Ok(());
+    };
+    store().record_message(msg.chat.id, user, msg.date).await
+}
+
+pub(crate) async fn record_member(
+    chat_id: ChatId,
+    member: &ChatMember,
+) -> Result<(), StorageError> {
+    if !is_enabled() {
+        return Ok(());
+    }
+    store()
+        .record_status(chat_id, &member.user, member.status(), Utc::now())
+        .await
+}
+
+pub(crate) async fn migrate(from: ChatId, to: ChatId) -> Result<(), StorageError> {
+    store().migrate(from, to).await
+}
+
+// Keeps members in this process, up to `capacity()` of them, and loses them on
+// restart.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MemoryMemberStore;
+
+impl MemberStore for MemoryMemberStore {
+    fn record_message<'a>(
+        &'a self,
+        chat_id: ChatId,
+        user: &'a User,
+        at: DateTime<Utc>,
+    ) -> BoxFuture<'a, Result<(), StorageError>> {
+        let mut members = MEMBERS.write().unwrap();
+        let seen = members.entry(chat_id, user, at);
+        seen.user = user.clone();
+        seen.messages += 1;
+        seen.last_seen = seen.last_seen.max(at);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn record_status<'a>(
+        &'a self,
+        chat_id: ChatId,
+        user: &'a User,
+        status: ChatMemberStatus,
+        at: DateTime<Utc>,
+    ) -> BoxFuture<'a, Result<(), StorageError>> {
+        let mut members = MEMBERS.write().unwrap();
+        let seen = members.entry(chat_id, user, at);
+        seen.user = user.clone();
+        seen.status = Some(status);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn get(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+    ) -> BoxFuture<'_, Result<Option<SeenMember>, StorageError>> {
+        let members = MEMBERS.read().unwrap();
+        let member = members
+            .chats
+            .get(&chat_id)
+            .and_then(|members| members.get(&user_id))
+            .cloned();
+        Box::pin(async { Ok(member) })
+    }
+
+    fn members(&self, chat_id: ChatId) -> BoxFuture<'_, Result<Vec<SeenMember>, StorageError>> {
+        let members = MEMBERS.read().unwrap();
+        let seen = members
+            .chats
+            .get(&chat_id)
+            .map(|members| members.values().cloned().collect())
+            .unwrap_or_default();
+        Box::pin(async { Ok(seen) })
+    }
+
+    fn count(&self, chat_id: ChatId) -> BoxFuture<'_, Result<usize, StorageError>> {
+        let members = MEMBERS.read().unwrap();
+        let count = members.chats.get(&chat_id).map_or(0, HashMap::len);
+        Box::pin(async move { Ok(count) })
+    }
+
+    fn clear(&self, chat_id: ChatId) -> BoxFuture<'_, Result<(), StorageError>> {
+        let mut members = MEMBERS.write().unwrap();
+        if let Some(removed) = members.chats.remove(&chat_id) {
+            members.len -= removed.len();
+        }
+        Box::pin(async { Ok(()) })
+    }
+
+    fn migrate(&self, from: ChatId, to: ChatId) -> BoxFuture<'_, Result<(), StorageError>> {
+        let mut members = MEMBERS.write().unwrap();
+        if let Some(moved) = members.chats.remove(&from) {
+            members.chats.entry(to).or_default().extend(moved);
+            members.recount();
+        }
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[cfg(feature = "redis-chat-cache")]
+fn backend(err: impl std::error::Error + Send + Sync + 'static) -> StorageError {
+    StorageError::Backend(Box::new(err))
+}
+
+// Five keys per chat under `<key>:<chat id>`: `users` and `status` hash user ids to
+// JSON, `messages` to a count and `first` to when the member was first seen in
+// milliseconds, and `seen` is a sorted set scored by when they were last seen. Every
+// change is one atomic pipeline of commands that merge, so processes sharing the
+// store don't overwrite each other's counts. There's no capacity: `clear` a chat to
+// forget it. Needs Redis 6.2 or newer.
+#[cfg(feature = "redis-chat-cache")]
+#[derive(Clone)]
+pub struct RedisMemberStore {
+    conn: ConnectionManager,
+    key: String,
+}
+
+#[cfg(feature = "redis-chat-cache")]
+impl RedisMemberStore {
+    // Takes a `redis://` URL. Keys start with `teloxide-plugins:members`.
+    pub async fn connect(url: &str) -> Result<Self, StorageError> {
+        let client = redis::Client::open(url).map_err(backend)?;
+        Ok(Self {
+            conn: ConnectionManager::new(client).await.map_err(backend)?,
+            key: String::from("teloxide-plugins:members"),
+        })
+    }
+
+    // For bots sharing one Redis.
+    pub fn key(mut self, key: impl Into<String>) -> Self {
+        self.key = key.into();
+        self
+    }
+
+    fn keys(&self, chat_id: ChatId) -> RedisKeys {
+        let base = format!("{}:{}", self.key, chat_id.0);
+        RedisKeys {
+            users: format!("{}:users", base),
+            status: format!("{}:status", base),
+            messages: format!("{}:messages", base),
+            first: format!("{}:first", base),
+            seen: format!("{}:seen", base),
+        }
+    }
+
+    async fn write(
+        &self,
+        chat_id: ChatId,
+        member: &SeenMember,
+        last_seen: &str,
+    ) -> Result<(), StorageError> {
+        let keys = self.keys(chat_id);
+        let id = member.user.id.0;
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .hset(&keys.users, id, serde_json::to_string(&member.user)?)
+            .ignore()
+            .hincr(&keys.messages, id, member.messages)
+            .ignore()
+            .hset_nx(&keys.first, id, member.first_seen.timestamp_millis())
+            .ignore()
+            .cmd("ZADD")
+            .arg(&keys.seen)
+            .arg(last_seen)
+            .arg(member.last_seen.timestamp_millis())
+            .arg(id)
+            .ignore();
+        if let Some(status) = member.status {
+            pipe.hset(&keys.status, id, serde_json::to_string(&status)?)
+                .ignore();
+        }
+        pipe.query_async::<()>(&mut self.conn.clone())
+            .await
+            .map_err(backend)
+    }
+}
+
+#[cfg(feature = "redis-chat-cache")]
+struct RedisKeys {
+    users: String,
+    status: String,
+    messages: String,
+    first: String,
+    seen: String,
+}
+
+#[cfg(feature = "redis-chat-cache")]
+impl RedisKeys {
+    fn all(&self) -> [&str; 5] {
+        [
+            &self.users,
+            &self.status,
+            &self.messages,
+            &self.first,
+            &self.seen,
+        ]
+    }
+}
+
+// One member's fields as Redis returns them: user, messages, first seen, last seen
+// and status.
+#[cfg(feature = "redis-chat-cache")]
+type MemberFields = (
+    Option<String>,
+    Option<u64>,
+    Option<i64>,
+    Option<f64>,
+    Option<String>,
+);
+
+// The same for a whole chat, keyed by user id.
+#[cfg(feature = "redis-chat-cache")]
+type ChatFields = (
+    HashMap<String, String>,
+    HashMap<String, u64>,
+    HashMap<String, i64>,
+    Vec<(String, f64)>,
+    HashMap<String, String>,
+);
+
+#[cfg(feature = "redis-chat-cache")]
+fn time_of(ms: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp_millis(ms).unwrap_or_default()
+}
+
+#[cfg(feature = "redis-chat-cache")]
+fn member_of(
+    user: &str,
+    messages: Option<u64>,
+    first: Option<i64>,
+    seen: Option<f64>,
+    status: Option<&str>,
+) -> Result<SeenMember, StorageError> {
+    let last_seen = time_of(seen.unwrap_or_default() as i64);
+    Ok(SeenMember {
+        user: serde_json::from_str(user)?,
+        messages: messages.unwrap_or_default(),
+        first_seen: first.map_or(last_seen, time_of),
+        last_seen,
+        status: status.map(serde_json::from_str).transpose()?,
+    })
+}
+
+#[cfg(feature = "redis-chat-cache")]
+impl MemberStore for RedisMemberStore {
+    fn record_message<'a>(
+        &'a self,
+        chat_id: ChatId,
+        user: &'a User,
+        at: DateTime<Utc>,
+    ) -> BoxFuture<'a, Result<(), StorageError>> {
+        Box::pin(async move {
+            let member = SeenMember {
+                user: user.clone(),
+                messages: 1,
+                first_seen: at,
+                last_seen: at,
+                status: None,
+            };
+            // Messages can arrive out of order; the latest one wins.
+            self.write(chat_id, &member, "GT").await
+        })
+    }
+
+    fn record_status<'a>(
+        &'a self,
+        chat_id: ChatId,
+        user: &'a User,
+        status: ChatMemberStatus,
+        at: DateTime<Utc>,
+    ) -> BoxFuture<'a, Result<(), StorageError>> {
+        Box::pin(async move {
+            let member = SeenMember {
+                user: user.clone(),
+                messages: 0,
+                first_seen: at,
+                last_seen: at,
+                status: Some(status),
+            };
+            // A lookup says nothing about when they were last active.
+            self.write(chat_id, &member, "NX").await
+        })
+    }
+
+    fn get(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+    ) -> BoxFuture<'_, Result<Option<SeenMember>, StorageError>> {
+        Box::pin(async move {
+            let keys = self.keys(chat_id);
+            let id = user_id.0;
+            let (user, messages, first, seen, status): MemberFields = redis::pipe()
+                .hget(&keys.users, id)
+                .hget(&keys.messages, id)
+                .hget(&keys.first, id)
+                .zscore(&keys.seen, id)
+                .hget(&keys.status, id)
+                .query_async(&mut self.conn.clone())
+                .await
+                .map_err(backend)?;
+            user.map(|user| member_of(&user, messages, first, seen, status.as_deref()))
+                .transpose()
+        })
+    }
+
+    fn members(&self, chat_id: ChatId) -> BoxFuture<'_, Result<Vec<SeenMember>, StorageError>> {
+        Box::pin(async move {
+            let keys = self.keys(chat_id);
+            let (users, messages, first, seen, status): ChatFields = redis::pipe()
+                .hgetall(&keys.users)
+                .hgetall(&keys.messages)
+                .hgetall(&keys.first)
+                .zrange_withscores(&keys.seen, 0, -1)
+                .hgetall(&keys.status)
+                .query_async(&mut self.conn.clone())
+                .await
+                .map_err(backend)?;
+            let seen: HashMap<String, f64> = seen.into_iter().collect();
+            users
+                .iter()
+                .map(|(id, user)| {
+                    member_of(
+                        user,
+                        messages.get(id).copied(),
+                        first.get(id).copied(),
+                        seen.get(id).copied(),
+                        status.get(id).map(String::as_str),
+                    )
+                })
+                .collect()
+        })
+    }
+
+    fn count(&self, chat_id: ChatId) -> BoxFuture<'_, Result<usize, StorageError>> {
+        Box::pin(async move {
+            redis::cmd("HLEN")
+                .arg(self.keys(chat_id).users)
+                .query_async(&mut self.conn.clone())
+                .await
+                .map_err(backend)
+        })
+    }
+
+    fn clear(&self, chat_id: ChatId) -> BoxFuture<'_, Result<(), StorageError>> {
+        Box::pin(async move {
+            redis::cmd("DEL")
+                .arg(&self.keys(chat_id).all()[..])
+                .query_async::<()>(&mut self.conn.clone())
+                .await
+                .map_err(backend)
+        })
+    }
+
+    fn migrate(&self, from: ChatId, to: ChatId) -> BoxFuture<'_, Result<(), StorageError>> {
+        Box::pin(async move {
+            for member in self.members(from).await? {
+                self.write(to, &member, "GT").await?;
+            }
+            self.clear(from).await
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ChatCache {
+    chat_id: ChatId,
+}
+
+impl ChatCache {
+    pub fn new(chat_id: ChatId) -> Self {
+        Self { chat_id }
+    }
+
+    pub async fn get(&self, user_id: UserId) -> Result<Option<SeenMember>, StorageError> {
+        store().get(self.chat_id, user_id).await
+    }
+
+    pub async fn members(&self) -> Result<Vec<SeenMember>, StorageError> {
+        store().members(self.chat_id).await
+    }
+
+    pub async fn count(&self) -> Result<usize, StorageError> {
+        store().count(self.chat_id).await
+    }
+
+    pub async fn admins(&self) -> Result<Vec<SeenMember>, StorageError> {
+        Ok(self
+            .members()
+            .await?
+            .into_iter()
+            .filter(|m| m.status.is_some_and(|status| status.is_privileged()))
+            .collect())
+    }
+
+    pub async fn active_since(&self, window: Duration) -> Result<Vec<SeenMember>, StorageError> {
+        let since = Utc::now() - window;
+        let mut active: Vec<_> = self
+            .members()
+            .await?
+            .into_iter()
+            .filter(|m| m.last_seen >= since)
+            .collect();
+        active.sort_by_key(|m| std::cmp::Reverse(m.last_seen));
+        Ok(active)
+    }
+
+    pub async fn joined_since(&self, window: Duration) -> Result<Vec<SeenMember>, StorageError> {
+        let since = Utc::now() - window;
+        Ok(self
+            .members()
+            .await?
+            .into_iter()
+            .filter(|m| m.first_seen >= since)
+            .collect())
+    }
+
+    // Recording is best effort, as it is for every update: a store that's down
+    // doesn't fail the refresh.
+    pub async fn refresh_admins(&self, bot: &Bot) -> Result<(), teloxide::RequestError> {
+        for member in bot.get_chat_administrators(self.chat_id).await? {
+            let _ = record_member(self.chat_id, &member).await;
+        }
+        Ok(())
+    }
+
+    pub async fn clear(&self) -> Result<(), StorageError> {
+        store().clear(self.chat_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(id: u64) -> User {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "is_bot": false,
+            "first_name": "Ann",
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn the_memory_store_counts_messages_and_keeps_statuses() {
+        let (chat, ann) = (ChatId(-4242), user(42));
+        let at = |secs| DateTime::from_timestamp(secs, 0).unwrap();
+        let store = MemoryMemberStore;
+        store.record_message(chat, &ann, at(100)).await.unwrap();
+        store.record_message(chat, &ann, at(50)).await.unwrap();
+        store
+            .record_status(chat, &ann, ChatMemberStatus::Administrator, at(500))
+            .await
+            .unwrap();
+
+        let cache = ChatCache::new(chat);
+        let seen = cache.get(ann.id).await.unwrap().unwrap();
+        assert_eq!(seen.messages, 2);
+        assert_eq!((seen.first_seen, seen.last_seen), (at(100), at(100)));
+        assert_eq!(cache.admins().await.unwrap().len(), 1);
+
+        store.migrate(chat, ChatId(-4243)).await.unwrap();
+        assert_eq!(cache.count().await.unwrap(), 0);
+        ChatCache::new(ChatId(-4243)).clear().await.unwrap();
+    }
+}
@@ -0,0 +1,79 @@
+use crate::context::PluginContext;
+use crate::traffic;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use teloxide::prelude::*;
+use teloxide::types::ChatFullInfo;
+
+static TTL: RwLock<Duration> = RwLock::new(Duration::from_secs(600));
+
+struct CachedInfo {
+    fetched: Instant,
+    info: ChatFullInfo,
+}
+
+static CACHE: Lazy<RwLock<HashMap<ChatId, CachedInfo>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+
+// How long a `getChat` answer is reused. Changes that show up as service messages
+// (title, photo, pinned message) refresh it sooner; the rest, like the description
+// or default permissions, can be this stale. Defaults to 10 minutes.
+pub fn set_ttl(ttl: Duration) {
+    *TTL.write().unwrap() = ttl;
+}
+
+pub async fn get(bot: &Bot, chat: ChatId) -> Result<ChatFullInfo, teloxide::RequestError> {
+    {
+        let ttl = *TTL.read().unwrap();
+        let cache = CACHE.read().unwrap();
+        if let Some(cached) = cache.get(&chat) {
+            if cached.fetched.elapsed() < ttl {
+                return Ok(cached.info.clone());
+            }
+        }
+    }
+
+    let info = traffic::send(bot.get_chat(chat)).await?;
+    CACHE.write().unwrap().insert(
+        chat,
+        CachedInfo {
+            fetched: Instant::now(),
+            info: info.clone(),
+        },
+    );
+    Ok(info)
+}
+
+// The cached copy, without asking Telegram, however old it is.
+pub fn cached(chat: ChatId) -> Option<ChatFullInfo> {
+    CACHE
+        .read()
+        .unwrap()
+        .get(&chat)
+        .map(|cached| cached.info.clone())
+}
+
+pub fn invalidate(chat: ChatId) {
+    CACHE.write().unwrap().remove(&chat);
+}
+
+pub(crate) fn track(msg: &Message) {
+    let changed = msg.new_chat_title().is_some()
+        || msg.new_chat_photo().is_some()
+        || msg.delete_chat_photo().is_some()
+        || msg.pinned_message().is_some()
+        || msg.chat_background_set().is_some();
+    if changed {
+        invalidate(msg.chat.id);
+    }
+}
+
+impl PluginContext {
+    pub async fn chat_info(&self) -> Result<ChatFullInfo, teloxide::RequestError> {
+        let chat = self.chat_id().ok_or(teloxide::RequestError::Api(
+            teloxide::ApiError::ChatNotFound,
+        ))?;
+        get(&self.bot, chat).await
+    }
+}
@@ -0,0 +1,217 @@
+use crate::permissions;
+use crate::settings;
+use crate::storage::{self, StorageError};
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use teloxide::types::{Chat, ChatId, ChatMemberUpdated, UserId};
+
+const PREFIX: &str = "known_chat:";
+
+type LeaveHook = Arc<dyn Fn(ChatId) -> BoxFuture<'static, ()> + Send + Sync>;
+
+static KNOWN: RwLock<Option<HashMap<ChatId, KnownChat>>> = RwLock::new(None);
+static LEAVE_HOOKS: Lazy<RwLock<Vec<LeaveHook>>> = Lazy::new(|| RwLock::new(Vec::new()));
+static CLEAR_SETTINGS: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct KnownChat {
+    pub id: ChatId,
+    pub kind: String,
+    pub title: Option<String>,
+    pub username: Option<String>,
+    pub added_by: Option<UserId>,
+    pub added_at: DateTime<Utc>,
+}
+
+impl KnownChat {
+    fn new(chat: &Chat, added_by: Option<UserId>, added_at: DateTime<Utc>) -> Self {
+        let kind = if chat.is_private() {
+            "private"
+        } else if chat.is_channel() {
+            "channel"
+        } else if chat.is_supergroup() {
+            "supergroup"
+        } else {
+            "group"
+        };
+        Self {
+            id: chat.id,
+            kind: kind.to_string(),
+            title: chat
+                .title()
+                .or_else(|| chat.first_name())
+                .map(str::to_string),
+            username: chat.username().map(str::to_string),
+            added_by,
+            added_at,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChatPage {
+    pub chats: Vec<KnownChat>,
+    pub page: usize,
+    pub pages: usize,
+    pub total: usize,
+}
+
+pub fn on_leave<F>(hook: F)
+where
+    F: Fn(ChatId) -> BoxFuture<'static, ()> + Send + Sync + 'static,
+{
+    LEAVE_HOOKS.write().unwrap().push(Arc::new(hook));
+}
+
+pub fn set_clear_settings_on_leave(enabled: bool) {
+    CLEAR_SETTINGS.store(enabled, Ordering::Relaxed);
+}
+
+fn key(chat: ChatId) -> String {
+    format!("{}{}", PREFIX, chat)
+}
+
+pub(crate) fn invalidate() {
+    *KNOWN.write().unwrap() = None;
+}
+
+async fn ensure_loaded() -> Result<(), StorageError> {
+    if KNOWN.read().unwrap().is_some() {
+        return Ok(());
+    }
+    let storage = storage::storage();
+    let mut known = HashMap::new();
+    for key in storage.keys(PREFIX).await? {
+        if let Some(raw) = storage.get(&key).await? {
+            let chat: KnownChat = serde_json::from_str(&raw)?;
+            known.insert(chat.id, chat);
+        }
+    }
+    let mut cache = KNOWN.write().unwrap();
+    if cache.is_none() {
+        *cache = Some(known);
+    }
+    Ok(())
+}
+
+fn cached(chat: ChatId) -> Option<KnownChat> {
+    KNOWN.read().unwrap().as_ref()?.get(&chat).cloned()
+}
+
+async fn save(chat: KnownChat) -> Result<(), StorageError> {
+    storage::set_json(&key(chat.id), &chat).await?;
+    if let Some(known) = KNOWN.write().unwrap().as_mut() {
+        known.insert(chat.id, chat);
+    }
+    Ok(())
+}
+
+pub(crate) async fn record_member_update(update: &ChatMemberUpdated) -> Result<(), StorageError> {
+    ensure_loaded().await?;
+    if !update.new_chat_member.is_present() {
+        return forget(update.chat.id).await;
+    }
+    let chat = match cached(update.chat.id) {
+        Some(known) => KnownChat::new(&update.chat, known.added_by, known.added_at),
+        None => KnownChat::new(&update.chat, Some(update.from.id), update.date),
+    };
+    if cached(chat.id).as_ref() != Some(&chat) {
+        save(chat).await?;
+    }
+    Ok(())
+}
+
+pub(crate) async fn record_seen(chat: &Chat) -> Result<(), StorageError> {
+    ensure_loaded().await?;
+    if cached(chat.id).is_none() {
+        save(KnownChat::new(chat, None, Utc::now())).await?;
+    }
+    Ok(())
+}
+
+pub async fn known_chats() -> Result<Vec<KnownChat>, StorageError> {
+    ensure_loaded().await?;
+    let mut chats: Vec<_> = KNOWN
+        .read()
+        .unwrap()
+        .iter()
+        .flat_map(|known| known.values().cloned())
+        .collect();
+    chats.sort_by(|a, b| {
+        a.added_at
+            .cmp(&b.added_at)
+            .then_with(|| a.id.0.cmp(&b.id.0))
+    });
+    Ok(chats)
+}
+
+pub async fn page(page: usize, per_page: usize) -> Result<ChatPage, StorageError> {
+    let chats = known_chats().await?;
+    let per_page = per_page.max(1);
+    let total = chats.len();
+    let pages = total.div_ceil(per_page).max(1);
+    let page = page.min(pages - 1);
+    Ok(ChatPage {
+        chats: chats
+            .into_iter()
+            .skip(page * per_page)
+            .take(per_page)
+            .collect(),
+        page,
+        pages,
+        total,
+    })
+}
+
+pub async fn get(chat: ChatId) -> Result<Option<KnownChat>, StorageError> {
+    ensure_loaded().await?;
+    Ok(cached(chat))
+}
+
+pub async fn count() -> Result<usize, StorageError> {
+    ensure_loaded().await?;
+    Ok(KNOWN.read().unwrap().as_ref().map_or(0, HashMap::len))
+}
+
+pub async fn forget(chat: ChatId) -> Result<(), StorageError> {
+    ensure_loaded().await?;
+    storage::delete(&key(chat)).await?;
+    let known = KNOWN
+        .write()
+        .unwrap()
+        .as_mut()
+        .and_then(|known| known.remove(&chat));
+    permissions::forget_chat(chat);
+    if CLEAR_SETTINGS.load(Ordering::Relaxed) {
+        settings::clear(chat).await?;
+    }
+    if known.is_some() {
+        let hooks = LEAVE_HOOKS.read().unwrap().clone();
+        for hook in hooks {
+            hook(chat).await;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) async fn migrate(from: ChatId, to: ChatId) -> Result<(), StorageError> {
+    ensure_loaded().await?;
+    let Some(known) = cached(from) else {
+        return Ok(());
+    };
+    storage::delete(&key(from)).await?;
+    if let Some(chats) = KNOWN.write().unwrap().as_mut() {
+        chats.remove(&from);
+    }
+    save(KnownChat {
+        id: to,
+        kind: String::from("supergroup"),
+        ..known
+    })
+    .await
+}
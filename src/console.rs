@@ -0,0 +1,348 @@
+use crate::access;
+use crate::analytics;
+use crate::budget;
+use crate::callbacks::CallbackData;
+use crate::chats;
+use crate::context::PluginContext;
+use crate::extract::Event;
+use crate::outbox;
+use crate::plugin;
+use crate::registry;
+use crate::reporting::{self, BoxError};
+use crate::response::SendMessageParams;
+use crate::supervisor;
+use crate::traffic;
+use futures::future::BoxFuture;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+use teloxide::{ApiError, RequestError};
+
+const NAME: &str = "console";
+const PAGE: usize = 8;
+const SHOWN_ERRORS: usize = 10;
+// Console keyboards stop working after a day; /console opens a fresh one.
+const BUTTON_TTL: Duration = Duration::from_secs(86400);
+// The console can't switch itself off.
+const OWN_PLUGINS: [&str; 2] = ["console_command", "broadcast_command"];
+
+type ReloadHook = Arc<dyn Fn() -> BoxFuture<'static, Result<(), BoxError>> + Send + Sync>;
+
+static RELOAD: RwLock<Option<ReloadHook>> = RwLock::new(None);
+// Broadcasts waiting for their owner to press Send, by owner.
+static PENDING: Lazy<Mutex<HashMap<UserId, String>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+// Runs when an owner presses Reload, before every installed plugin's `reload`. Put
+// whatever rereads the bot's own configuration here.
+pub fn set_reload_hook<F, Fut>(hook: F)
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<(), BoxError>> + Send + 'static,
+{
+    *RELOAD.write().unwrap() = Some(Arc::new(move || Box::pin(hook())));
+}
+
+// Runs the reload hook, then `plugin::reload`.
+pub async fn reload() -> Result<(), BoxError> {
+    let hook = RELOAD.read().unwrap().clone();
+    if let Some(hook) = hook {
+        hook().await?;
+    }
+    plugin::reload().await;
+    Ok(())
+}
+
+// Buttons are signed for the owner who opened the console, so nobody can forge a
+// press or use another owner's keyboard.
+fn data(owner: UserId, action: &str) -> CallbackData {
+    CallbackData::new(NAME).arg(action).owner(owner)
+}
+
+fn button(text: impl Into<String>, data: CallbackData) -> InlineKeyboardButton {
+    // Plugins go by their place in the list, so the longest data is well under
+    // Telegram's 64 bytes.
+    let data = data
+        .encode_signed(Some(BUTTON_TTL))
+        .expect("console buttons fit in callback data");
+    InlineKeyboardButton::callback(text, data)
+}
+
+fn back_row(owner: UserId) -> Vec<InlineKeyboardButton> {
+    vec![button("« Menu", data(owner, "menu"))]
+}
+
+fn menu(owner: UserId) -> (String, InlineKeyboardMarkup) {
+    let keyboard = InlineKeyboardMarkup::new([
+        vec![
+            button("Plugins", data(owner, "plugins").arg(0)),
+            button("Stats", data(owner, "stats")),
+        ],
+        vec![
+            button("Errors", data(owner, "errors")),
+            button("Reload", data(owner, "reload")),
+        ],
+        vec![button("Broadcast", data(owner, "broadcast"))],
+    ]);
+    (String::from("Console"), keyboard)
+}
+
+// Sorted, so a plugin's index is the same on every page and every press.
+fn switchable() -> Vec<&'static str> {
+    let mut plugins: Vec<&'static str> = registry::plugins()
+        .into_iter()
+        .map(|plugin| plugin.name)
+        .filter(|name| !OWN_PLUGINS.contains(name))
+        .collect();
+    plugins.sort();
+    plugins.dedup();
+    plugins
+}
+
+fn plugins_page(owner: UserId, page: usize) -> (String, InlineKeyboardMarkup) {
+    let plugins = switchable();
+    let pages = plugins.len().div_ceil(PAGE).max(1);
+    let page = page.min(pages - 1);
+
+    let text = format!(
+        "Plugins ({} disabled), page {} of {}. Press one to switch it on or off.",
+        registry::disabled().len(),
+        page + 1,
+        pages
+    );
+    let mut rows = Vec::new();
+    for (index, name) in plugins.iter().enumerate().skip(page * PAGE).take(PAGE) {
+        let enabled = registry::is_enabled(name);
+        let label = format!("{} {}", if enabled { "✅" } else { "⛔" }, name);
+        let toggle = data(owner, "toggle").arg(index).arg(page);
+        rows.push(vec![button(label, toggle)]);
+    }
+    let mut nav = Vec::new();
+    if page > 0 {
+        nav.push(button("‹ Prev", data(owner, "plugins").arg(page - 1)));
+    }
+    if page + 1 < pages {
+        nav.push(button("Next ›", data(owner, "plugins").arg(page + 1)));
+    }
+    if !nav.is_empty() {
+        rows.push(nav);
+    }
+    rows.push(back_row(owner));
+    (text, InlineKeyboardMarkup::new(rows))
+}
+
+async fn stats() -> String {
+    let plugins = registry::plugins();
+    let mut lines = vec![format!(
+        "Plugins: {} ({} disabled)",
+        plugins.len(),
+        registry::disabled().len()
+    )];
+    if let Ok(count) = chats::count().await {
+        lines.push(format!("Known chats: {}", count));
+    }
+    if let Ok(pending) = outbox::pending().await {
+        lines.push(format!("Outbox: {} waiting", pending.len()));
+    }
+    let tasks = supervisor::tasks();
+    lines.push(format!(
+        "Background tasks: {} ({} restarts)",
+        tasks.len(),
+        tasks.iter().map(|task| task.restarts).sum::<u32>()
+    ));
+
+    let suspended: Vec<&str> = plugins
+        .iter()
+        .map(|plugin| plugin.name)
+        .filter(|name| budget::suspended_for(name).is_some())
+        .collect();
+    if !suspended.is_empty() {
+        lines.push(format!("Over budget: {}", suspended.join(", ")));
+    }
+
+    let mut traffic = traffic::all();
+    traffic.sort_by_key(|(_, stats)| std::cmp::Reverse(stats.requests));
+    if !traffic.is_empty() {
+        lines.push(String::from("\nTelegram requests:"));
+        for (plugin, stats) in traffic.iter().take(5) {
+            lines.push(format!("{}: {}", plugin, stats.requests));
+        }
+    }
+
+    if analytics::is_enabled() {
+        if let Ok(top) = analytics::top_commands(1, None).await {
+            if !top.is_empty() {
+                lines.push(String::from("\nTop commands today:"));
+                for (command, count) in top.iter().take(5) {
+                    lines.push(format!("{}: {}", command, count));
+                }
+            }
+        }
+    }
+    lines.join("\n")
+}
+
+fn errors() -> String {
+    let recent = reporting::recent_errors();
+    if recent.is_empty() {
+        return String::from("No errors since the bot started.");
+    }
+    let mut text = format!(
+        "Last {} errors, newest first:",
+        recent.len().min(SHOWN_ERRORS)
+    );
+    for entry in recent.iter().take(SHOWN_ERRORS) {
+        let message: String = entry.error.message().chars().take(200).collect();
+        text.push_str(&format!(
+            "\n\n{} {} ({}): {}",
+            entry.at.format("%m-%d %H:%M:%S"),
+            entry.error.plugin.unwrap_or("-"),
+            entry.error.kind,
+            message
+        ));
+    }
+    text
+}
+
+fn broadcast_preview(owner: UserId, text: &str, chats: usize) -> (String, InlineKeyboardMarkup) {
+    let keyboard = InlineKeyboardMarkup::new([vec![
+        button("Send", data(owner, "send")),
+        button("Cancel", data(owner, "cancel")),
+    ]]);
+    (
+        format!("Broadcast to {} chats:\n\n{}", chats, text),
+        keyboard,
+    )
+}
+
+// Queues the pending broadcast in the outbox for every known chat, which takes care
+// of rate limits, retries and chats that blocked the bot.
+async fn send_broadcast(owner: UserId) -> String {
+    let Some(text) = PENDING.lock().unwrap().remove(&owner) else {
+        return String::from("There's no broadcast waiting.");
+    };
+    let chats = match chats::known_chats().await {
+        Ok(chats) => chats,
+        Err(err) => return format!("Couldn't list chats: {}", err),
+    };
+    let mut queued = 0;
+    for chat in &chats {
+        if outbox::send(chat.id, SendMessageParams::new(text.clone()))
+            .await
+            .is_ok()
+        {
+            queued += 1;
+        }
+    }
+    format!("Broadcast queued for {} of {} chats.", queued, chats.len())
+}
+
+async fn press(cq: &CallbackQuery) -> Option<(String, InlineKeyboardMarkup)> {
+    let data = CallbackData::decode(cq.data.as_deref()?)?;
+    let owner = cq.from.id;
+    let back = || InlineKeyboardMarkup::new([back_row(owner)]);
+    let screen = match data.args.first()?.as_str() {
+        "menu" => menu(owner),
+        "plugins" => plugins_page(owner, data.get(1).unwrap_or(0)),
+        "toggle" => {
+            let name = *switchable().get(data.get::<usize>(1)?)?;
+            registry::set_enabled(name, !registry::is_enabled(name));
+            plugins_page(owner, data.get(2).unwrap_or(0))
+        }
+        "stats" => (stats().await, back()),
+        "errors" => (errors(), back()),
+        "reload" => {
+            let text = match reload().await {
+                Ok(()) => String::from("Reloaded."),
+                Err(err) => format!("Reload failed: {}", err),
+            };
+            (text, back())
+        }
+        "broadcast" => {
+            let pending = PENDING.lock().unwrap().get(&owner).cloned();
+            match pending {
+                Some(text) => {
+                    let chats = chats::count().await.unwrap_or_default();
+                    broadcast_preview(owner, &text, chats)
+                }
+                None => (
+                    String::from("Send /broadcast followed by the message to prepare one."),
+                    back(),
+                ),
+            }
+        }
+        "send" => (send_broadcast(owner).await, back()),
+        "cancel" => {
+            PENDING.lock().unwrap().remove(&owner);
+            (String::from("Broadcast cancelled."), back())
+        }
+        _ => return None,
+    };
+    Some(screen)
+}
+
+#[crate::TeloxidePlugin(
+    commands = ["console"],
+    prefixes = ["/"],
+    callback = ["console"],
+    signed = true,
+    owner_only_callback = true
+)]
+async fn console_command(ctx: PluginContext, event: Event) -> Option<SendMessageParams> {
+    let cq = match event {
+        Event::Message(_) => {
+            let owner = ctx.sender()?.id;
+            if !access::is_owner(owner) {
+                return None;
+            }
+            let (text, keyboard) = menu(owner);
+            return Some(SendMessageParams::new(text).reply_markup(keyboard));
+        }
+        Event::Callback(cq) => cq,
+    };
+    let mut answer = ctx.bot.answer_callback_query(cq.id.clone());
+    if !access::is_owner(cq.from.id) {
+        let _ = traffic::send(answer.text("This console is for the bot's owners.")).await;
+        return None;
+    }
+    let screen = press(&cq).await;
+    let message = cq.regular_message();
+    if let (Some((text, keyboard)), Some(message)) = (screen, message) {
+        let edit = ctx
+            .bot
+            .edit_message_text(message.chat.id, message.id, text)
+            .reply_markup(keyboard);
+        match traffic::send(edit).await {
+            // Pressing the same button twice leaves nothing to edit.
+            Ok(_) | Err(RequestError::Api(ApiError::MessageNotModified)) => {}
+            Err(err) => answer = answer.text(err.to_string()),
+        }
+    }
+    let _ = traffic::send(answer).await;
+    None
+}
+
+#[crate::TeloxidePlugin(commands = ["broadcast"], prefixes = ["/"])]
+async fn broadcast_command(ctx: PluginContext) -> Option<SendMessageParams> {
+    let owner = ctx.sender()?.id;
+    if !access::is_owner(owner) {
+        return None;
+    }
+    let text = ctx
+        .text()?
+        .split_once(char::is_whitespace)
+        .map(|(_, text)| text.trim())
+        .unwrap_or_default();
+    if text.is_empty() {
+        return Some(SendMessageParams::new(
+            "Usage: /broadcast <message>. You'll get to check it before it goes out.",
+        ));
+    }
+    PENDING.lock().unwrap().insert(owner, text.to_string());
+    let chats = chats::count().await.unwrap_or_default();
+    let (text, keyboard) = broadcast_preview(owner, text, chats);
+    Some(SendMessageParams::new(text).reply_markup(keyboard))
+}
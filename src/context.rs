@@ -1,11 +1,78 @@
+use crate::chat_cache::ChatCache;
+use crate::extensions::Extensions;
+use crate::external::ExternalEvent;
+use crate::registry::PluginMeta;
+use crate::session::SessionSlot;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
 use teloxide::prelude::*;
-use teloxide::types::{CallbackQuery, Message};
+use teloxide::types::{
+    BusinessConnection, BusinessConnectionId, CallbackQuery, Chat, ChatBoostRemoved,
+    ChatBoostUpdated, ChatId, ChatMemberUpdated, InlineQuery, Message, MessageEntityKind,
+    MessageKind, Poll, PollAnswer, PreCheckoutQuery, User,
+};
 
 #[derive(Clone)]
 pub struct PluginContext {
     pub bot: Bot,
     pub message: Option<Message>,
     pub callback_query: Option<CallbackQuery>,
+    pub inline_query: Option<InlineQuery>,
+    pub business_connection: Option<BusinessConnection>,
+    pub chat_boost: Option<ChatBoostUpdated>,
+    pub removed_chat_boost: Option<ChatBoostRemoved>,
+    pub pre_checkout_query: Option<PreCheckoutQuery>,
+    pub my_chat_member: Option<ChatMemberUpdated>,
+    pub poll: Option<Poll>,
+    pub poll_answer: Option<PollAnswer>,
+    pub raw_update: Option<serde_json::Value>,
+    pub external: Option<ExternalEvent>,
+    pub edited: bool,
+    pub plugin: Option<&'static PluginMeta>,
+    pub extensions: Extensions,
+    pub(crate) session: SessionSlot,
+    pub(crate) failed: Arc<AtomicBool>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TargetUser {
+    User(User),
+    Id(UserId),
+    Username(String),
+}
+
+impl TargetUser {
+    pub fn id(&self) -> Option<UserId> {
+        match self {
+            TargetUser::User(user) => Some(user.id),
+            TargetUser::Id(id) => Some(*id),
+            TargetUser::Username(_) => None,
+        }
+    }
+}
+
+pub(crate) fn split_command(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    let (token, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    let command = token.split_once('@').map_or(token, |(command, _)| command);
+    (command, rest.trim_start())
+}
+
+// The bot a command is addressed to, as in `/start@SomeBot`.
+pub(crate) fn command_mention(text: &str) -> Option<&str> {
+    let token = text.trim_start().split(char::is_whitespace).next()?;
+    token.split_once('@').map(|(_, bot)| bot)
+}
+
+// The message `msg` replies to. In a forum topic, Telegram puts the topic's creation
+// message in `reply_to_message` even when the user didn't reply to anything, so that
+// one doesn't count.
+pub(crate) fn reply_of(msg: &Message) -> Option<&Message> {
+    msg.reply_to_message().filter(|replied| {
+        let is_root = replied.forum_topic_created().is_some()
+            || (msg.is_topic_message && msg.thread_id.is_some_and(|t| t.0 == replied.id));
+        !is_root
+    })
 }
 
 impl PluginContext {
@@ -14,6 +81,330 @@ impl PluginContext {
             bot,
             message,
             callback_query,
+            inline_query: None,
+            business_connection: None,
+            chat_boost: None,
+            removed_chat_boost: None,
+            pre_checkout_query: None,
+            my_chat_member: None,
+            poll: None,
+            poll_answer: None,
+            raw_update: None,
+            external: None,
+            edited: false,
+            plugin: None,
+            extensions: Extensions::default(),
+            session: SessionSlot::default(),
+            failed: Arc::default(),
         }
     }
+
+    pub fn from_edited_message(bot: Bot, message: Message) -> Self {
+        Self {
+            edited: true,
+            ..Self::new(bot, Some(message), None)
+        }
+    }
+
+    pub fn from_inline_query(bot: Bot, query: InlineQuery) -> Self {
+        Self {
+            inline_query: Some(query),
+            ..Self::new(bot, None, None)
+        }
+    }
+
+    pub fn from_business_connection(bot: Bot, connection: BusinessConnection) -> Self {
+        Self {
+            business_connection: Some(connection),
+            ..Self::new(bot, None, None)
+        }
+    }
+
+    pub fn from_chat_boost(bot: Bot, boost: ChatBoostUpdated) -> Self {
+        Self {
+            chat_boost: Some(boost),
+            ..Self::new(bot, None, None)
+        }
+    }
+
+    pub fn from_removed_chat_boost(bot: Bot, boost: ChatBoostRemoved) -> Self {
+        Self {
+            removed_chat_boost: Some(boost),
+            ..Self::new(bot, None, None)
+        }
+    }
+
+    pub fn from_pre_checkout_query(bot: Bot, query: PreCheckoutQuery) -> Self {
+        Self {
+            pre_checkout_query: Some(query),
+            ..Self::new(bot, None, None)
+        }
+    }
+
+    pub fn from_my_chat_member(bot: Bot, update: ChatMemberUpdated) -> Self {
+        Self {
+            my_chat_member: Some(update),
+            ..Self::new(bot, None, None)
+        }
+    }
+
+    pub fn from_poll(bot: Bot, poll: Poll) -> Self {
+        Self {
+            poll: Some(poll),
+            ..Self::new(bot, None, None)
+        }
+    }
+
+    pub fn from_poll_answer(bot: Bot, answer: PollAnswer) -> Self {
+        Self {
+            poll_answer: Some(answer),
+            ..Self::new(bot, None, None)
+        }
+    }
+
+    pub fn from_raw_update(bot: Bot, update: serde_json::Value) -> Self {
+        Self {
+            raw_update: Some(update),
+            ..Self::new(bot, None, None)
+        }
+    }
+
+    pub fn from_external(bot: Bot, event: ExternalEvent) -> Self {
+        Self {
+            external: Some(event),
+            ..Self::new(bot, None, None)
+        }
+    }
+
+    pub fn update_kind(&self) -> Option<&'static str> {
+        if self.business_connection.is_some() {
+            return Some("business_connection");
+        }
+        if self.chat_boost.is_some() {
+            return Some("chat_boost");
+        }
+        if self.removed_chat_boost.is_some() {
+            return Some("removed_chat_boost");
+        }
+        if self.pre_checkout_query.is_some() {
+            return Some("pre_checkout_query");
+        }
+        if self.my_chat_member.is_some() {
+            return Some("my_chat_member");
+        }
+        if self.poll.is_some() {
+            return Some("poll");
+        }
+        if self.poll_answer.is_some() {
+            return Some("poll_answer");
+        }
+        match &self.message.as_ref()?.kind {
+            MessageKind::Giveaway(_) => Some("giveaway"),
+            MessageKind::GiveawayCreated(_) => Some("giveaway_created"),
+            MessageKind::GiveawayWinners(_) => Some("giveaway_winners"),
+            MessageKind::GiveawayCompleted(_) => Some("giveaway_completed"),
+            MessageKind::SuccessfulPayment(_) => Some("successful_payment"),
+            MessageKind::RefundedPayment(_) => Some("refunded_payment"),
+            MessageKind::VideoChatScheduled(_) => Some("video_chat_scheduled"),
+            MessageKind::VideoChatStarted(_) => Some("video_chat_started"),
+            MessageKind::VideoChatEnded(_) => Some("video_chat_ended"),
+            MessageKind::VideoChatParticipantsInvited(_) => Some("video_chat_participants_invited"),
+            _ => None,
+        }
+    }
+
+    pub fn is_business(&self) -> bool {
+        self.business_connection_id().is_some()
+    }
+
+    pub fn business_connection_id(&self) -> Option<&BusinessConnectionId> {
+        self.message
+            .as_ref()
+            .and_then(|m| match &m.kind {
+                MessageKind::Common(common) => common.business_connection_id.as_ref(),
+                _ => None,
+            })
+            .or_else(|| self.business_connection.as_ref().map(|c| &c.id))
+    }
+
+    pub fn is_edited(&self) -> bool {
+        self.edited
+    }
+
+    pub fn chat_id(&self) -> Option<ChatId> {
+        self.message.as_ref().map(|m| m.chat.id).or_else(|| {
+            self.callback_query
+                .as_ref()
+                .and_then(|c| c.message.as_ref())
+                .map(|m| m.chat().id)
+                .or_else(|| self.chat_boost.as_ref().map(|b| b.chat.id))
+                .or_else(|| self.removed_chat_boost.as_ref().map(|b| b.chat.id))
+                .or_else(|| self.my_chat_member.as_ref().map(|u| u.chat.id))
+        })
+    }
+
+    pub fn chat_cache(&self) -> Option<ChatCache> {
+        self.chat_id().map(ChatCache::new)
+    }
+
+    pub fn sender(&self) -> Option<&User> {
+        self.message
+            .as_ref()
+            .and_then(|m| m.from.as_ref())
+            .or_else(|| self.callback_query.as_ref().map(|c| &c.from))
+            .or_else(|| self.inline_query.as_ref().map(|q| &q.from))
+            .or_else(|| self.business_connection.as_ref().map(|c| &c.user))
+            .or_else(|| self.pre_checkout_query.as_ref().map(|q| &q.from))
+            .or_else(|| self.my_chat_member.as_ref().map(|u| &u.from))
+            .or_else(|| self.poll_answer.as_ref().and_then(|a| a.voter.user()))
+            .or_else(|| self.chat_boost.as_ref().and_then(|b| b.boost.source.user()))
+            .or_else(|| {
+                self.removed_chat_boost
+                    .as_ref()
+                    .and_then(|b| b.source.user())
+            })
+    }
+
+    pub fn sender_chat(&self) -> Option<&Chat> {
+        self.message.as_ref().and_then(|m| m.sender_chat.as_ref())
+    }
+
+    pub fn is_anonymous_admin(&self) -> bool {
+        self.message
+            .as_ref()
+            .and_then(|m| m.sender_chat.as_ref().map(|sender| sender.id == m.chat.id))
+            .unwrap_or(false)
+    }
+
+    pub fn is_on_behalf_of_channel(&self) -> bool {
+        self.message
+            .as_ref()
+            .and_then(|m| {
+                m.sender_chat
+                    .as_ref()
+                    .map(|sender| sender.id != m.chat.id && sender.is_channel())
+            })
+            .unwrap_or(false)
+    }
+
+    pub fn is_private_chat(&self) -> bool {
+        self.message.as_ref().is_some_and(|m| m.chat.is_private())
+            || self
+                .callback_query
+                .as_ref()
+                .and_then(|c| c.message.as_ref())
+                .is_some_and(|m| m.chat().is_private())
+    }
+
+    pub fn text(&self) -> Option<&str> {
+        self.message.as_ref().and_then(|m| m.text())
+    }
+
+    pub fn text_or_caption(&self) -> Option<&str> {
+        self.message
+            .as_ref()
+            .and_then(|m| m.text().or_else(|| m.caption()))
+    }
+
+    pub fn args(&self) -> Vec<&str> {
+        self.text_or_caption()
+            .map(|text| split_command(text).1.split_whitespace().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn replied_message(&self) -> Option<&Message> {
+        self.message.as_ref().and_then(reply_of)
+    }
+
+    pub fn target_user(&self) -> Option<TargetUser> {
+        if let Some(user) = self.replied_message().and_then(|m| m.from.as_ref()) {
+            return Some(TargetUser::User(user.clone()));
+        }
+
+        let msg = self.message.as_ref()?;
+        if let Some(entities) = msg.parse_entities() {
+            for entity in entities {
+                match entity.kind() {
+                    MessageEntityKind::TextMention { user } => {
+                        return Some(TargetUser::User(user.clone()));
+                    }
+                    MessageEntityKind::Mention => {
+                        let username = entity.text().trim_start_matches('@');
+                        return Some(TargetUser::Username(username.to_string()));
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        self.args()
+            .first()
+            .and_then(|arg| arg.parse::<u64>().ok())
+            .map(|id| TargetUser::Id(UserId(id)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    // A message in topic 10 of a forum, from user 7 to whatever `reply` says.
+    fn topic_message(text: &str, reply: Value) -> PluginContext {
+        let chat = json!({"id": -100, "type": "supergroup", "title": "Forum", "is_forum": true});
+        let message: Message = serde_json::from_value(json!({
+            "message_id": 12,
+            "message_thread_id": 10,
+            "is_topic_message": true,
+            "date": 0,
+            "chat": chat,
+            "from": {"id": 7, "is_bot": false, "first_name": "Ann"},
+            "text": text,
+            "reply_to_message": reply,
+        }))
+        .unwrap();
+        PluginContext::new(Bot::new("1:test"), Some(message), None)
+    }
+
+    fn topic_root() -> Value {
+        json!({
+            "message_id": 10,
+            "date": 0,
+            "chat": {"id": -100, "type": "supergroup", "title": "Forum", "is_forum": true},
+            "from": {"id": 1, "is_bot": false, "first_name": "Creator"},
+            "forum_topic_created": {"name": "Support", "icon_color": 7322096},
+        })
+    }
+
+    fn target_id(target: Option<TargetUser>) -> Option<UserId> {
+        match target? {
+            TargetUser::User(user) => Some(user.id),
+            TargetUser::Id(id) => Some(id),
+            TargetUser::Username(_) => None,
+        }
+    }
+
+    #[test]
+    fn the_topic_root_is_not_a_reply() {
+        let ctx = topic_message("/warn 42 spam", topic_root());
+        assert!(ctx.replied_message().is_none());
+        assert_eq!(target_id(ctx.target_user()), Some(UserId(42)));
+        assert_eq!(ctx.args(), ["42", "spam"]);
+    }
+
+    #[test]
+    fn a_real_reply_inside_a_topic_still_counts() {
+        let replied = json!({
+            "message_id": 11,
+            "message_thread_id": 10,
+            "is_topic_message": true,
+            "date": 0,
+            "chat": {"id": -100, "type": "supergroup", "title": "Forum", "is_forum": true},
+            "from": {"id": 5, "is_bot": false, "first_name": "Bob"},
+            "text": "buy cheap followers",
+        });
+        let ctx = topic_message("/warn spam", replied);
+        assert_eq!(ctx.replied_message().map(|m| m.id.0), Some(11));
+        assert_eq!(target_id(ctx.target_user()), Some(UserId(5)));
+    }
 }
@@ -0,0 +1,368 @@
+use crate::context::PluginContext;
+use crate::registry;
+use crate::texts;
+use futures::future::BoxFuture;
+use once_cell::sync::Lazy;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use teloxide::prelude::*;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(600);
+const DEFAULT_GRACE: Duration = Duration::from_secs(300);
+
+type Key = (ChatId, UserId);
+
+#[derive(Clone, Debug)]
+pub struct ConversationTimeout {
+    pub bot: Bot,
+    pub chat_id: ChatId,
+    pub user_id: UserId,
+    pub conversation: &'static str,
+    pub step: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ConversationRecord {
+    pub chat_id: ChatId,
+    pub user_id: UserId,
+    pub conversation: String,
+    pub step: String,
+    pub data: HashMap<String, serde_json::Value>,
+}
+
+type TimeoutHook = Arc<dyn Fn(ConversationTimeout) -> BoxFuture<'static, ()> + Send + Sync>;
+
+#[derive(Clone)]
+enum ExpiryMessage {
+    Framework,
+    Custom(String),
+    Off,
+}
+
+#[derive(Clone)]
+pub struct ConversationBuilder {
+    name: &'static str,
+    timeout: Duration,
+    step_timeouts: HashMap<String, Duration>,
+    grace: Duration,
+    expiry_message: ExpiryMessage,
+    on_timeout: Option<TimeoutHook>,
+    on_expire: Option<TimeoutHook>,
+}
+
+impl ConversationBuilder {
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            timeout: DEFAULT_TIMEOUT,
+            step_timeouts: HashMap::new(),
+            grace: DEFAULT_GRACE,
+            expiry_message: ExpiryMessage::Framework,
+            on_timeout: None,
+            on_expire: None,
+        }
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn step_timeout(mut self, step: impl Into<String>, timeout: Duration) -> Self {
+        self.step_timeouts.insert(step.into(), timeout);
+        self
+    }
+
+    pub fn on_timeout<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(ConversationTimeout) -> BoxFuture<'static, ()> + Send + Sync + 'static,
+    {
+        self.on_timeout = Some(Arc::new(hook));
+        self
+    }
+
+    pub fn grace(mut self, grace: Duration) -> Self {
+        self.grace = grace;
+        self
+    }
+
+    pub fn on_expire<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(ConversationTimeout) -> BoxFuture<'static, ()> + Send + Sync + 'static,
+    {
+        self.on_expire = Some(Arc::new(hook));
+        self
+    }
+
+    pub fn expiry_message(mut self, text: impl Into<String>) -> Self {
+        self.expiry_message = ExpiryMessage::Custom(text.into());
+        self
+    }
+
+    pub fn silent_expiry(mut self) -> Self {
+        self.expiry_message = ExpiryMessage::Off;
+        self
+    }
+
+    pub fn register(self) {
+        SPECS.lock().unwrap().insert(self.name, self);
+    }
+}
+
+struct Active {
+    name: &'static str,
+    step: String,
+    data: HashMap<String, serde_json::Value>,
+    generation: u64,
+}
+
+static SPECS: Lazy<Mutex<HashMap<&'static str, ConversationBuilder>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+static ACTIVE: Lazy<Mutex<HashMap<Key, Active>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn spec_of(name: &'static str) -> ConversationBuilder {
+    SPECS
+        .lock()
+        .unwrap()
+        .get(name)
+        .cloned()
+        .unwrap_or_else(|| ConversationBuilder::new(name))
+}
+
+pub(crate) fn current(ctx: &PluginContext) -> Option<(&'static str, String)> {
+    let key = key_of(ctx)?;
+    let active = ACTIVE.lock().unwrap();
+    active.get(&key).map(|a| (a.name, a.step.clone()))
+}
+
+fn key_of(ctx: &PluginContext) -> Option<Key> {
+    Some((ctx.chat_id()?, ctx.sender()?.id))
+}
+
+fn is_current(key: Key, name: &'static str, generation: u64) -> Option<String> {
+    let active = ACTIVE.lock().unwrap();
+    active
+        .get(&key)
+        .filter(|a| a.name == name && a.generation == generation)
+        .map(|a| a.step.clone())
+}
+
+fn arm_timer(bot: Bot, key: Key, name: &'static str, generation: u64) {
+    let spec = spec_of(name);
+    tokio::spawn(async move {
+        let Some(step) = is_current(key, name, generation) else {
+            return;
+        };
+        let timeout = spec
+            .step_timeouts
+            .get(&step)
+            .copied()
+            .unwrap_or(spec.timeout);
+        tokio::time::sleep(timeout).await;
+
+        let Some(step) = is_current(key, name, generation) else {
+            return;
+        };
+        let info = ConversationTimeout {
+            bot: bot.clone(),
+            chat_id: key.0,
+            user_id: key.1,
+            conversation: name,
+            step,
+        };
+        if let Some(hook) = &spec.on_timeout {
+            hook(info.clone()).await;
+            tokio::time::sleep(spec.grace).await;
+        }
+
+        let expired = {
+            let mut active = ACTIVE.lock().unwrap();
+            let current = active
+                .get(&key)
+                .is_some_and(|a| a.name == name && a.generation == generation);
+            if current {
+                active.remove(&key);
+            }
+            current
+        };
+        if !expired {
+            return;
+        }
+        let text = match spec.expiry_message {
+            ExpiryMessage::Framework => Some(texts::texts().conversation_expired.clone()),
+            ExpiryMessage::Custom(text) => Some(text),
+            ExpiryMessage::Off => None,
+        };
+        if let Some(text) = text {
+            let _ = bot.send_message(key.0, text).await;
+        }
+        if let Some(hook) = &spec.on_expire {
+            hook(info).await;
+        }
+    });
+}
+
+fn static_name(name: &str) -> Option<&'static str> {
+    if let Some(name) = SPECS.lock().unwrap().keys().find(|known| **known == name) {
+        return Some(*name);
+    }
+    registry::plugins()
+        .into_iter()
+        .filter_map(|plugin| plugin.conversation)
+        .find(|known| *known == name)
+}
+
+pub(crate) fn snapshot() -> Vec<ConversationRecord> {
+    ACTIVE
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|((chat_id, user_id), active)| ConversationRecord {
+            chat_id: *chat_id,
+            user_id: *user_id,
+            conversation: active.name.to_string(),
+            step: active.step.clone(),
+            data: active.data.clone(),
+        })
+        .collect()
+}
+
+// Restored conversations get their timer back the next time the user answers a step.
+pub(crate) fn restore(records: Vec<ConversationRecord>) -> usize {
+    let mut active = ACTIVE.lock().unwrap();
+    let mut restored = 0;
+    for record in records {
+        let Some(name) = static_name(&record.conversation) else {
+            continue;
+        };
+        let key = (record.chat_id, record.user_id);
+        let generation = active.get(&key).map_or(0, |a| a.generation + 1);
+        active.insert(
+            key,
+            Active {
+                name,
+                step: record.step,
+                data: record.data,
+                generation,
+            },
+        );
+        restored += 1;
+    }
+    restored
+}
+
+pub(crate) fn touch(ctx: &PluginContext) {
+    ctx.conversation().rearm();
+}
+
+pub struct Conversation<'a> {
+    ctx: &'a PluginContext,
+}
+
+impl Conversation<'_> {
+    pub fn start(&self, name: &'static str, step: impl Into<String>) {
+        let Some(key) = key_of(self.ctx) else {
+            return;
+        };
+        let generation = {
+            let mut active = ACTIVE.lock().unwrap();
+            let generation = active.get(&key).map_or(0, |a| a.generation + 1);
+            active.insert(
+                key,
+                Active {
+                    name,
+                    step: step.into(),
+                    data: HashMap::new(),
+                    generation,
+                },
+            );
+            generation
+        };
+        arm_timer(self.ctx.bot.clone(), key, name, generation);
+    }
+
+    pub fn goto(&self, step: impl Into<String>) {
+        self.update(|a| a.step = step.into());
+    }
+
+    fn rearm(&self) {
+        self.update(|_| {});
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Active)) {
+        let Some(key) = key_of(self.ctx) else {
+            return;
+        };
+        let armed = {
+            let mut active = ACTIVE.lock().unwrap();
+            active.get_mut(&key).map(|a| {
+                f(a);
+                a.generation += 1;
+                (a.name, a.generation)
+            })
+        };
+        if let Some((name, generation)) = armed {
+            arm_timer(self.ctx.bot.clone(), key, name, generation);
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.name().is_some()
+    }
+
+    pub fn name(&self) -> Option<&'static str> {
+        current(self.ctx).map(|(name, _)| name)
+    }
+
+    pub fn step(&self) -> Option<String> {
+        current(self.ctx).map(|(_, step)| step)
+    }
+
+    pub fn set<T: Serialize>(&self, field: &str, value: &T) {
+        let Some(key) = key_of(self.ctx) else {
+            return;
+        };
+        let Ok(value) = serde_json::to_value(value) else {
+            return;
+        };
+        if let Some(active) = ACTIVE.lock().unwrap().get_mut(&key) {
+            active.data.insert(field.to_string(), value);
+        }
+    }
+
+    pub fn get<T: DeserializeOwned>(&self, field: &str) -> Option<T> {
+        let key = key_of(self.ctx)?;
+        let active = ACTIVE.lock().unwrap();
+        let value = active.get(&key)?.data.get(field)?.clone();
+        serde_json::from_value(value).ok()
+    }
+
+    pub fn finish(&self) {
+        self.abort();
+    }
+
+    pub fn abort(&self) -> Option<&'static str> {
+        let key = key_of(self.ctx)?;
+        ACTIVE.lock().unwrap().remove(&key).map(|a| a.name)
+    }
+}
+
+impl PluginContext {
+    pub fn conversation(&self) -> Conversation<'_> {
+        Conversation { ctx: self }
+    }
+}
+
+#[cfg(feature = "cancel-command")]
+#[crate::TeloxidePlugin(commands = ["cancel"], prefixes = ["/"])]
+async fn cancel_conversation(ctx: PluginContext) -> String {
+    let texts = ctx.framework_texts();
+    match ctx.conversation().abort() {
+        Some(_) => texts.cancelled.clone(),
+        None => texts.nothing_to_cancel.clone(),
+    }
+}
@@ -0,0 +1,207 @@
+use crate::context::PluginContext;
+use crate::extract::FromContext;
+use crate::permissions;
+use crate::traffic;
+use once_cell::sync::Lazy;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::RwLock;
+use std::time::Duration;
+use teloxide::prelude::*;
+use teloxide::types::{MessageId, UserId};
+
+// Hashes kept per chat, however long the window is.
+const REMEMBERED: usize = 500;
+
+// Off until `set_policy` is called.
+static POLICY: RwLock<Option<DedupPolicy>> = RwLock::new(None);
+
+static RECENT: Lazy<RwLock<HashMap<ChatId, VecDeque<Seen>>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+struct Seen {
+    hash: u64,
+    id: MessageId,
+    sender: Option<UserId>,
+    at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DedupPolicy {
+    pub window: Duration,
+    pub min_length: usize,
+    pub delete_after: Option<usize>,
+}
+
+impl DedupPolicy {
+    // Texts counted as copies when they're posted again within `window`.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            min_length: 10,
+            delete_after: None,
+        }
+    }
+
+    // Shorter texts like "ok" or "+1" repeat all the time and are never counted.
+    pub fn min_length(mut self, chars: usize) -> Self {
+        self.min_length = chars;
+        self
+    }
+
+    // Deletes the `copies`th copy and every one after it, from anyone but admins.
+    pub fn delete_after(mut self, copies: usize) -> Self {
+        self.delete_after = Some(copies.max(2));
+        self
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DuplicateInfo {
+    pub hash: u64,
+    // Copies within the window, this one included.
+    pub count: usize,
+    pub senders: usize,
+    pub first: MessageId,
+    pub deleted: bool,
+}
+
+pub fn set_policy(policy: Option<DedupPolicy>) {
+    let off = policy.is_none();
+    *POLICY.write().unwrap() = policy;
+    if off {
+        RECENT.write().unwrap().clear();
+    }
+}
+
+pub fn policy() -> Option<DedupPolicy> {
+    POLICY.read().unwrap().clone()
+}
+
+// Case, spacing, punctuation and invisible characters are ignored, so small tweaks
+// to a pasted message still hash the same. Media is hashed by its file too.
+pub fn content_hash(msg: &Message, min_length: usize) -> Option<u64> {
+    let text: String = msg
+        .text()
+        .or_else(|| msg.caption())
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect();
+    let file = media_id(msg);
+    if file.is_none() && text.chars().count() < min_length {
+        return None;
+    }
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    file.hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+fn media_id(msg: &Message) -> Option<&str> {
+    let file = if let Some(photo) = msg.photo().and_then(|sizes| sizes.last()) {
+        &photo.file
+    } else if let Some(video) = msg.video() {
+        &video.file
+    } else if let Some(animation) = msg.animation() {
+        &animation.file
+    } else if let Some(document) = msg.document() {
+        &document.file
+    } else if let Some(sticker) = msg.sticker() {
+        &sticker.file
+    } else {
+        return None;
+    };
+    Some(&file.unique_id.0)
+}
+
+fn observe(msg: &Message, hash: u64, window: Duration) -> (usize, usize, MessageId) {
+    let mut chats = RECENT.write().unwrap();
+    let seen = chats.entry(msg.chat.id).or_default();
+    let since = chrono::Duration::from_std(window)
+        .ok()
+        .and_then(|window| msg.date.checked_sub_signed(window));
+    seen.retain(|s| since.is_none_or(|since| s.at > since));
+    if seen.len() >= REMEMBERED {
+        seen.pop_front();
+    }
+    seen.push_back(Seen {
+        hash,
+        id: msg.id,
+        sender: msg.from.as_ref().map(|user| user.id),
+        at: msg.date,
+    });
+    let copies: Vec<&Seen> = seen.iter().filter(|s| s.hash == hash).collect();
+    let mut senders: Vec<Option<UserId>> = copies.iter().map(|s| s.sender).collect();
+    senders.sort();
+    senders.dedup();
+    (copies.len(), senders.len(), copies[0].id)
+}
+
+pub(crate) fn migrate(from: ChatId, to: ChatId) {
+    let mut chats = RECENT.write().unwrap();
+    if let Some(seen) = chats.remove(&from) {
+        chats.insert(to, seen);
+    }
+}
+
+async fn is_exempt(ctx: &PluginContext, chat: ChatId) -> bool {
+    if ctx.is_anonymous_admin() {
+        return true;
+    }
+    let Some(user) = ctx.sender() else {
+        return false;
+    };
+    permissions::chat_member(&ctx.bot, chat, user.id)
+        .await
+        .is_ok_and(|member| member.status().is_privileged())
+}
+
+// Runs before routing. Returns `true` when the message was deleted, so no plugin
+// sees it; otherwise repeated messages carry a `DuplicateInfo` for plugins to act on.
+pub(crate) async fn check(ctx: &PluginContext) -> bool {
+    let Some(policy) = policy() else {
+        return false;
+    };
+    let Some(msg) = ctx
+        .message
+        .as_ref()
+        .filter(|msg| !ctx.is_edited() && !msg.chat.is_private())
+    else {
+        return false;
+    };
+    let Some(hash) = content_hash(msg, policy.min_length) else {
+        return false;
+    };
+    let (count, senders, first) = observe(msg, hash, policy.window);
+    if count < 2 {
+        return false;
+    }
+    let deleted = policy.delete_after.is_some_and(|after| count >= after)
+        && !is_exempt(ctx, msg.chat.id).await
+        && traffic::send(ctx.bot.delete_message(msg.chat.id, msg.id))
+            .await
+            .is_ok();
+    ctx.extensions.insert(DuplicateInfo {
+        hash,
+        count,
+        senders,
+        first,
+        deleted,
+    });
+    deleted
+}
+
+impl FromContext for DuplicateInfo {
+    fn from_context(ctx: &PluginContext) -> Option<Self> {
+        ctx.duplicate()
+    }
+}
+
+impl PluginContext {
+    pub fn duplicate(&self) -> Option<DuplicateInfo> {
+        self.extensions.get()
+    }
+}
@@ -0,0 +1,502 @@
+use crate::context::PluginContext;
+#[cfg(feature = "webhooks")]
+use crate::external;
+use crate::files;
+#[cfg(feature = "health")]
+use crate::health;
+use crate::outbox;
+use crate::permissions;
+use crate::plugin::{self, InstallError, Plugin, PluginSlot};
+use crate::pool::{self, BotPool};
+use crate::queue::{DispatchQueue, QueueConfig};
+use crate::registry::{self, PluginMeta};
+use crate::scheduler;
+use crate::source::{self, EventSource};
+use crate::spam;
+#[cfg(feature = "redis-streams")]
+use crate::streams::{self, RedisStream};
+use crate::supervisor;
+use crate::transport::{self, UpdateTransport};
+use futures::future::BoxFuture;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use teloxide::dispatching::UpdateHandler;
+use teloxide::prelude::*;
+use teloxide::stop::StopToken;
+use teloxide::types::{AllowedUpdate, UpdateKind};
+use teloxide::update_listeners::{AsUpdateStream, Polling, PollingStream, UpdateListener};
+use url::Url;
+
+pub fn context_of(bot: Bot, update: Update) -> Option<PluginContext> {
+    let ctx = match update.kind {
+        UpdateKind::Message(msg) | UpdateKind::BusinessMessage(msg) => {
+            PluginContext::new(bot, Some(msg), None)
+        }
+        UpdateKind::EditedMessage(msg) | UpdateKind::EditedBusinessMessage(msg) => {
+            PluginContext::from_edited_message(bot, msg)
+        }
+        UpdateKind::CallbackQuery(cq) => PluginContext::new(bot, None, Some(cq)),
+        UpdateKind::InlineQuery(query) => PluginContext::from_inline_query(bot, query),
+        UpdateKind::BusinessConnection(connection) => {
+            PluginContext::from_business_connection(bot, connection)
+        }
+        UpdateKind::ChatBoost(boost) => PluginContext::from_chat_boost(bot, boost),
+        UpdateKind::RemovedChatBoost(boost) => PluginContext::from_removed_chat_boost(bot, boost),
+        UpdateKind::PreCheckoutQuery(query) => PluginContext::from_pre_checkout_query(bot, query),
+        UpdateKind::MyChatMember(update) => PluginContext::from_my_chat_member(bot, update),
+        UpdateKind::Poll(poll) => PluginContext::from_poll(bot, poll),
+        UpdateKind::PollAnswer(answer) => PluginContext::from_poll_answer(bot, answer),
+        UpdateKind::Error(raw) => PluginContext::from_raw_update(bot, raw),
+        // Not dispatched to plugins: the spam heuristics note joins, and the member's
+        // cached rights are dropped so admin checks see the change.
+        UpdateKind::ChatMember(update) => {
+            spam::record_member_update(&update);
+            permissions::invalidate_member(update.chat.id, update.new_chat_member.user.id);
+            return None;
+        }
+        _ => return None,
+    };
+    Some(ctx)
+}
+
+pub fn handler() -> UpdateHandler<teloxide::RequestError> {
+    dptree::endpoint(
+        |bot: Bot, update: Update, queue: DispatchQueue| async move {
+            transport::mirror(&update);
+            if let Some(ctx) = context_of(bot, update) {
+                queue.push(ctx);
+            }
+            Ok(())
+        },
+    )
+}
+
+#[derive(Clone, Debug)]
+pub struct PollingConfig {
+    // How long each `getUpdates` call waits for something to arrive.
+    pub timeout: Duration,
+    // Updates per `getUpdates` call, 1 to 100; Telegram's default is 100.
+    pub limit: Option<u8>,
+    // Skip whatever piled up while the bot was offline.
+    pub drop_pending_updates: bool,
+}
+
+impl Default for PollingConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            limit: None,
+            drop_pending_updates: false,
+        }
+    }
+}
+
+static ALLOWED_UPDATES: RwLock<Option<Vec<AllowedUpdate>>> = RwLock::new(None);
+static LISTENING: Mutex<Option<Listening>> = Mutex::new(None);
+static RESTART: AtomicBool = AtomicBool::new(false);
+
+struct Listening {
+    stop: StopToken,
+    allowed: Option<Vec<AllowedUpdate>>,
+}
+
+// `None`, the default, asks Telegram for every kind of update. Changing it while the
+// dispatcher runs restarts polling with the new list. Nothing is lost on the way: the
+// old listener confirms what it got, and the new one picks up from there.
+pub fn set_allowed_updates(kinds: Option<Vec<AllowedUpdate>>) {
+    *ALLOWED_UPDATES.write().unwrap() = kinds;
+    refresh_allowed_updates();
+}
+
+// The list polling asks for: the one from `set_allowed_updates`, plus what the
+// registered plugins need, so a plugin installed later still gets its updates.
+pub fn allowed_updates() -> Option<Vec<AllowedUpdate>> {
+    let mut kinds = ALLOWED_UPDATES.read().unwrap().clone()?;
+    for plugin in registry::plugins() {
+        for kind in required_updates(plugin) {
+            if !kinds.contains(&kind) {
+                kinds.push(kind);
+            }
+        }
+    }
+    Some(kinds)
+}
+
+fn required_updates(plugin: &PluginMeta) -> Vec<AllowedUpdate> {
+    let mut kinds = Vec::new();
+    let reads_messages = !plugin.commands.is_empty()
+        || plugin.regex.is_some()
+        || !plugin.entities.is_empty()
+        || !plugin.media.is_empty();
+    if plugin.on_new && reads_messages {
+        kinds.push(AllowedUpdate::Message);
+    }
+    if plugin.on_edited && reads_messages {
+        kinds.push(AllowedUpdate::EditedMessage);
+    }
+    if plugin.on_business && reads_messages {
+        kinds.push(AllowedUpdate::BusinessMessage);
+    }
+    if plugin.on_edited_business && reads_messages {
+        kinds.push(AllowedUpdate::EditedBusinessMessage);
+    }
+    if plugin.callback_filter.is_some() || !plugin.games.is_empty() {
+        kinds.push(AllowedUpdate::CallbackQuery);
+    }
+    if plugin.inline.is_some() {
+        kinds.push(AllowedUpdate::InlineQuery);
+    }
+    for kind in plugin.updates {
+        kinds.push(match *kind {
+            "business_connection" => AllowedUpdate::BusinessConnection,
+            "chat_boost" => AllowedUpdate::ChatBoost,
+            "removed_chat_boost" => AllowedUpdate::RemovedChatBoost,
+            "pre_checkout_query" => AllowedUpdate::PreCheckoutQuery,
+            "my_chat_member" => AllowedUpdate::MyChatMember,
+            "poll" => AllowedUpdate::Poll,
+            "poll_answer" => AllowedUpdate::PollAnswer,
+            // Giveaways, payments and video chats arrive as service messages.
+            _ => AllowedUpdate::Message,
+        });
+    }
+    kinds
+}
+
+pub(crate) fn refresh_allowed_updates() {
+    let listening = LISTENING.lock().unwrap();
+    if let Some(listening) = listening.as_ref() {
+        if listening.allowed != allowed_updates() {
+            RESTART.store(true, Ordering::SeqCst);
+            listening.stop.stop();
+        }
+    }
+}
+
+// Polling that keeps its own allowed updates, instead of the full set the dispatcher
+// hints for a catch-all handler.
+struct FixedUpdates(Polling<Bot>);
+
+impl UpdateListener for FixedUpdates {
+    type Err = teloxide::RequestError;
+
+    fn stop_token(&mut self) -> StopToken {
+        self.0.stop_token()
+    }
+
+    fn hint_allowed_updates(&mut self, _hint: &mut dyn Iterator<Item = AllowedUpdate>) {}
+}
+
+impl<'a> AsUpdateStream<'a> for FixedUpdates {
+    type StreamErr = teloxide::RequestError;
+    type Stream = PollingStream<'a, Bot>;
+
+    fn as_stream(&'a mut self) -> Self::Stream {
+        self.0.as_stream()
+    }
+}
+
+pub struct PluginDispatcher {
+    token: String,
+    api_url: Option<Url>,
+    client: Option<reqwest::Client>,
+    proxy: Option<reqwest::Proxy>,
+    timeout: Option<Duration>,
+    queue: QueueConfig,
+    polling: PollingConfig,
+    local_mode: bool,
+    pool_tokens: Vec<String>,
+    sources: Vec<Box<dyn EventSource>>,
+    mirrors: Vec<Arc<dyn UpdateTransport>>,
+    plugins: Vec<PendingInstall>,
+    #[cfg(feature = "health")]
+    health: Option<std::net::SocketAddr>,
+    #[cfg(feature = "webhooks")]
+    webhooks: Option<std::net::SocketAddr>,
+    #[cfg(feature = "redis-streams")]
+    stream: Option<StreamRole>,
+}
+
+#[cfg(feature = "redis-streams")]
+enum StreamRole {
+    Publish(RedisStream),
+    Consume(RedisStream),
+}
+
+type PendingInstall = Box<dyn FnOnce() -> BoxFuture<'static, Result<(), InstallError>> + Send>;
+
+impl PluginDispatcher {
+    pub fn new(token: impl Into<String>) -> Self {
+        Self {
+            token: token.into(),
+            api_url: None,
+            client: None,
+            proxy: None,
+            timeout: None,
+            queue: QueueConfig::default(),
+            polling: PollingConfig::default(),
+            local_mode: false,
+            pool_tokens: Vec::new(),
+            sources: Vec::new(),
+            mirrors: Vec::new(),
+            plugins: Vec::new(),
+            #[cfg(feature = "health")]
+            health: None,
+            #[cfg(feature = "webhooks")]
+            webhooks: None,
+            #[cfg(feature = "redis-streams")]
+            stream: None,
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let mut dispatcher = Self::new(
+            std::env::var("TELOXIDE_TOKEN").expect("TELOXIDE_TOKEN env variable missing"),
+        );
+        if let Ok(url) = std::env::var("TELOXIDE_API_URL") {
+            dispatcher =
+                dispatcher.api_url(Url::parse(&url).expect("TELOXIDE_API_URL is not a valid URL"));
+        }
+        if let Ok(proxy) = std::env::var("TELOXIDE_PROXY") {
+            dispatcher = dispatcher.proxy(
+                reqwest::Proxy::all(proxy).expect("TELOXIDE_PROXY is not a valid proxy URL"),
+            );
+        }
+        dispatcher
+    }
+
+    pub fn api_url(mut self, url: Url) -> Self {
+        self.api_url = Some(url);
+        self
+    }
+
+    pub fn local_server(mut self, url: Url) -> Self {
+        self.local_mode = true;
+        self.api_url(url)
+    }
+
+    pub fn client(mut self, client: reqwest::Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    pub fn proxy(mut self, proxy: reqwest::Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn pool_tokens(mut self, tokens: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.pool_tokens = tokens.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn queue(mut self, config: QueueConfig) -> Self {
+        self.queue = config;
+        self
+    }
+
+    pub fn polling(mut self, config: PollingConfig) -> Self {
+        self.polling = config;
+        self
+    }
+
+    pub fn source(mut self, source: impl EventSource + 'static) -> Self {
+        self.sources.push(Box::new(source));
+        self
+    }
+
+    // Copies every update received from Telegram to the transport as well. To run
+    // plugins for updates from a transport, add `transport::source(..)` with `source`.
+    pub fn mirror_to(mut self, transport: impl UpdateTransport + 'static) -> Self {
+        self.mirrors.push(Arc::new(transport));
+        self
+    }
+
+    pub fn plugin<P: Plugin + PluginSlot>(mut self, instance: P) -> Self {
+        self.plugins
+            .push(Box::new(move || Box::pin(plugin::install(instance))));
+        self
+    }
+
+    #[cfg(feature = "health")]
+    pub fn health(mut self, addr: std::net::SocketAddr) -> Self {
+        self.health = Some(addr);
+        self
+    }
+
+    #[cfg(feature = "webhooks")]
+    pub fn webhooks(mut self, addr: std::net::SocketAddr) -> Self {
+        self.webhooks = Some(addr);
+        self
+    }
+
+    // Receive updates here and hand them to worker processes through a Redis stream,
+    // instead of running plugins in this process.
+    #[cfg(feature = "redis-streams")]
+    pub fn publish_to(mut self, stream: RedisStream) -> Self {
+        self.stream = Some(StreamRole::Publish(stream));
+        self
+    }
+
+    // Run as a worker: take updates from the stream instead of polling Telegram.
+    #[cfg(feature = "redis-streams")]
+    pub fn consume_from(mut self, stream: RedisStream) -> Self {
+        self.stream = Some(StreamRole::Consume(stream));
+        self
+    }
+
+    pub fn bot(&self) -> Bot {
+        self.bot_for(&self.token)
+    }
+
+    fn bot_for(&self, token: &str) -> Bot {
+        let client = self.client.clone().unwrap_or_else(|| {
+            let mut builder = teloxide::net::default_reqwest_settings();
+            if let Some(proxy) = self.proxy.clone() {
+                builder = builder.proxy(proxy);
+            }
+            if let Some(timeout) = self.timeout {
+                builder = builder.timeout(timeout);
+            }
+            builder.build().expect("Client creation failed")
+        });
+
+        let bot = Bot::with_client(token, client);
+        match &self.api_url {
+            Some(url) => bot.set_api_url(url.clone()),
+            None => bot,
+        }
+    }
+
+    pub async fn dispatch(mut self) {
+        files::set_local_mode(self.local_mode);
+        let bot = self.bot();
+        if !self.pool_tokens.is_empty() {
+            let bots = std::iter::once(bot.clone())
+                .chain(self.pool_tokens.iter().map(|token| self.bot_for(token)));
+            pool::set_pool(BotPool::new(bots));
+        }
+        for install in self.plugins.drain(..) {
+            install().await.expect("failed to install plugin");
+        }
+        let queue = DispatchQueue::with_config(self.queue);
+        #[cfg(feature = "redis-streams")]
+        let consumer = matches!(self.stream, Some(StreamRole::Consume(_)));
+        #[cfg(not(feature = "redis-streams"))]
+        let consumer = false;
+        // Cron plugins, scheduled jobs and the outbox run in the process that receives
+        // updates, not once per worker.
+        if !consumer {
+            scheduler::start(bot.clone())
+                .await
+                .expect("failed to start the scheduler");
+            outbox::start(bot.clone());
+        }
+        for mirror in self.mirrors.drain(..) {
+            transport::start_mirror(mirror);
+        }
+        for source in self.sources.drain(..) {
+            tokio::spawn(source::run_source(bot.clone(), source, queue.clone()));
+        }
+        #[cfg(feature = "webhooks")]
+        if let Some(addr) = self.webhooks {
+            let listener = tokio::net::TcpListener::bind(addr)
+                .await
+                .expect("failed to bind the webhook endpoint");
+            external::check_exposure(addr).expect("refusing to serve the webhook endpoint");
+            tokio::spawn(external::serve_listener(
+                listener,
+                bot.clone(),
+                queue.clone(),
+            ));
+        }
+
+        #[cfg(feature = "health")]
+        {
+            health::watch_queue(&queue);
+            if let Some(addr) = self.health {
+                let listener = tokio::net::TcpListener::bind(addr)
+                    .await
+                    .expect("failed to bind the health endpoint");
+                tokio::spawn(health::serve_listener(listener));
+            }
+            health::set_running(true);
+        }
+
+        #[cfg(feature = "redis-streams")]
+        let handler = match self.stream.take() {
+            Some(StreamRole::Publish(stream)) => streams::handler(stream),
+            Some(StreamRole::Consume(stream)) => {
+                tokio::select! {
+                    _ = streams::consume(bot.clone(), stream) => {}
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+                supervisor::shutdown().await;
+                plugin::shutdown().await;
+                #[cfg(feature = "health")]
+                health::set_running(false);
+                return;
+            }
+            None => handler(),
+        };
+        #[cfg(not(feature = "redis-streams"))]
+        let handler = handler();
+
+        let mut dispatcher = Dispatcher::builder(bot.clone(), handler)
+            .dependencies(dptree::deps![queue])
+            .enable_ctrlc_handler()
+            .build();
+        let errors = LoggingErrorHandler::with_custom_text("An error from the update listener");
+        let mut first = true;
+        loop {
+            let mut builder = Polling::builder(bot.clone()).timeout(self.polling.timeout);
+            if let Some(limit) = self.polling.limit {
+                builder = builder.limit(limit);
+            }
+            if first {
+                builder = builder.delete_webhook().await;
+                if self.polling.drop_pending_updates {
+                    builder = builder.drop_pending_updates();
+                }
+            }
+            let (polling, fixed) = {
+                let mut listening = LISTENING.lock().unwrap();
+                let allowed = allowed_updates();
+                if let Some(kinds) = allowed.clone() {
+                    builder = builder.allowed_updates(kinds);
+                }
+                let mut polling = builder.build();
+                *listening = Some(Listening {
+                    stop: polling.stop_token(),
+                    allowed: allowed.clone(),
+                });
+                (polling, allowed.is_some())
+            };
+            if fixed {
+                dispatcher
+                    .dispatch_with_listener(FixedUpdates(polling), errors.clone())
+                    .await;
+            } else {
+                dispatcher
+                    .dispatch_with_listener(polling, errors.clone())
+                    .await;
+            }
+            *LISTENING.lock().unwrap() = None;
+            if !RESTART.swap(false, Ordering::SeqCst) {
+                break;
+            }
+            first = false;
+        }
+
+        supervisor::shutdown().await;
+        plugin::shutdown().await;
+
+        #[cfg(feature = "health")]
+        health::set_running(false);
+    }
+}
@@ -0,0 +1,346 @@
+use crate::registry::{self, PluginMeta};
+use crate::roles;
+use crate::scheduler;
+use crate::storage;
+use crate::traffic;
+use crate::transcribe;
+use regex::Regex;
+use std::fmt;
+use teloxide::prelude::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Finding {
+    pub severity: Severity,
+    pub plugin: Option<&'static str>,
+    pub problem: String,
+    pub fix: String,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DoctorReport {
+    pub findings: Vec<Finding>,
+}
+
+impl DoctorReport {
+    // No errors; warnings are allowed.
+    pub fn is_ok(&self) -> bool {
+        self.errors().next().is_none()
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Finding> {
+        self.findings
+            .iter()
+            .filter(|finding| finding.severity == Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Finding> {
+        self.findings
+            .iter()
+            .filter(|finding| finding.severity == Severity::Warning)
+    }
+
+    fn error(&mut self, plugin: Option<&'static str>, problem: String, fix: impl Into<String>) {
+        self.push(Severity::Error, plugin, problem, fix.into());
+    }
+
+    fn warning(&mut self, plugin: Option<&'static str>, problem: String, fix: impl Into<String>) {
+        self.push(Severity::Warning, plugin, problem, fix.into());
+    }
+
+    fn push(
+        &mut self,
+        severity: Severity,
+        plugin: Option<&'static str>,
+        problem: String,
+        fix: String,
+    ) {
+        self.findings.push(Finding {
+            severity,
+            plugin,
+            problem,
+            fix,
+        });
+    }
+}
+
+impl fmt::Display for DoctorReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.findings.is_empty() {
+            return write!(f, "doctor: no problems found");
+        }
+        let errors = self.errors().count();
+        let warnings = self.findings.len() - errors;
+        write!(f, "doctor: {} error(s), {} warning(s)", errors, warnings)?;
+        for finding in self.errors().chain(self.warnings()) {
+            let label = match finding.severity {
+                Severity::Error => "error",
+                Severity::Warning => "warning",
+            };
+            match finding.plugin {
+                Some(plugin) => write!(f, "\n{} [{}]: {}", label, plugin, finding.problem)?,
+                None => write!(f, "\n{}: {}", label, finding.problem)?,
+            }
+            write!(f, "\n    fix: {}", finding.fix)?;
+        }
+        Ok(())
+    }
+}
+
+// Checks the bot's setup before it starts taking updates: the token, the webhook,
+// every registered plugin and the services they depend on. Nothing is changed; print
+// the report, or refuse to start when `is_ok()` is false.
+pub async fn doctor(bot: &Bot) -> DoctorReport {
+    let mut report = DoctorReport::default();
+    let plugins = registry::plugins();
+
+    check_account(bot, &plugins, &mut report).await;
+    for plugin in &plugins {
+        check_plugin(plugin, &mut report);
+    }
+    check_conflicts(&plugins, &mut report);
+    check_services(&plugins, &mut report);
+    report
+}
+
+async fn check_account(bot: &Bot, plugins: &[&'static PluginMeta], report: &mut DoctorReport) {
+    let me = match traffic::send(bot.get_me()).await {
+        Ok(me) => me,
+        Err(teloxide::RequestError::Api(teloxide::ApiError::InvalidToken)) => {
+            report.error(
+                None,
+                String::from("Telegram rejected the bot token"),
+                "check TELOXIDE_TOKEN, or get the current token from @BotFather with /token",
+            );
+            return;
+        }
+        Err(err) => {
+            report.error(
+                None,
+                format!("couldn't reach the Bot API: {}", err),
+                "check the network, the proxy and the API URL",
+            );
+            return;
+        }
+    };
+
+    let inline: Vec<&str> = plugins
+        .iter()
+        .filter(|plugin| plugin.inline.is_some())
+        .map(|plugin| plugin.name)
+        .collect();
+    if !inline.is_empty() && !me.supports_inline_queries {
+        report.error(
+            None,
+            format!(
+                "inline mode is off, so these plugins never run: {}",
+                inline.join(", ")
+            ),
+            "turn it on with /setinline in @BotFather",
+        );
+    }
+
+    let chatter: Vec<&str> = plugins
+        .iter()
+        .filter(|plugin| {
+            plugin.regex.is_some() || !plugin.entities.is_empty() || !plugin.media.is_empty()
+        })
+        .map(|plugin| plugin.name)
+        .collect();
+    if !chatter.is_empty() && !me.can_read_all_group_messages {
+        report.warning(
+            None,
+            format!(
+                "privacy mode is on, so in groups these plugins only see commands, replies \
+                 to the bot and mentions: {}",
+                chatter.join(", ")
+            ),
+            "disable it with /setprivacy in @BotFather, then re-add the bot to its groups",
+        );
+    }
+
+    match traffic::send(bot.get_webhook_info()).await {
+        Ok(info) => {
+            if let Some(url) = info.url {
+                report.warning(
+                    None,
+                    format!(
+                        "a webhook is set to {}; polling deletes it on start, so whatever \
+                         serves it stops getting updates",
+                        url
+                    ),
+                    "give each deployment its own token, or remove the webhook on purpose \
+                     with deleteWebhook",
+                );
+            }
+        }
+        Err(err) => report.warning(
+            None,
+            format!("couldn't read the webhook info: {}", err),
+            "run the doctor again once the Bot API is reachable",
+        ),
+    }
+}
+
+fn check_plugin(plugin: &'static PluginMeta, report: &mut DoctorReport) {
+    let name = Some(plugin.name);
+    if !plugin.commands.is_empty() && plugin.prefixes.is_empty() {
+        report.error(
+            name,
+            format!(
+                "commands {} can never match because the plugin has no prefixes",
+                plugin.commands.join(", ")
+            ),
+            "add `prefixes = [\"/\"]` to the attribute",
+        );
+    }
+
+    let patterns = [
+        ("regex", plugin.regex),
+        ("not_regex", plugin.not_regex),
+        ("inline", plugin.inline),
+    ];
+    for (attribute, pattern) in patterns {
+        let Some(pattern) = pattern else {
+            continue;
+        };
+        if let Err(err) = Regex::new(pattern) {
+            report.error(
+                name,
+                format!(
+                    "`{}` isn't a valid regex and never matches: {}",
+                    attribute, err
+                ),
+                format!("fix the `{}` pattern", attribute),
+            );
+        }
+    }
+
+    if let Some(pattern) = plugin.cron {
+        if let Err(err) = scheduler::parse_schedule(plugin, pattern) {
+            report.error(
+                name,
+                err.to_string(),
+                "fix the `cron` expression or `tz` name",
+            );
+        }
+    }
+
+    if let Some(role) = plugin.role {
+        if roles::rank(role).is_none() {
+            report.error(
+                name,
+                format!(
+                    "role `{}` isn't one of the roles ({}), so nobody can use the plugin",
+                    role,
+                    roles::roles().join(", ")
+                ),
+                "add it with `roles::set_roles` before running the doctor, or fix the name",
+            );
+        }
+    }
+
+    if plugin.step.is_some() && plugin.conversation.is_none() {
+        report.error(
+            name,
+            String::from("`step` is set without a `conversation`, so the plugin never runs"),
+            "add the `conversation` the step belongs to",
+        );
+    }
+}
+
+// Two plugins on the same command with the same routing filters: only the first one
+// the router reaches ever runs.
+fn check_conflicts(plugins: &[&'static PluginMeta], report: &mut DoctorReport) {
+    let same_route = |a: &PluginMeta, b: &PluginMeta| {
+        a.langs == b.langs
+            && a.topics == b.topics
+            && a.conversation == b.conversation
+            && a.step == b.step
+            && a.from_bot_only == b.from_bot_only
+            && a.regex.is_none()
+            && a.not_regex.is_none()
+    };
+    let mut seen: Vec<(String, &'static PluginMeta)> = Vec::new();
+    for plugin in plugins {
+        for prefix in plugin.prefixes {
+            for command in plugin.commands {
+                let key = format!("{}{}", prefix, command);
+                let winner = seen
+                    .iter()
+                    .find(|(known, other)| *known == key && same_route(other, plugin))
+                    .map(|(_, other)| other.name);
+                match winner {
+                    Some(winner) => report.warning(
+                        Some(plugin.name),
+                        format!("`{}` is shadowed by plugin `{}`", key, winner),
+                        "rename one of the commands, or tell them apart with `lang` or `topic`",
+                    ),
+                    None => seen.push((key, plugin)),
+                }
+            }
+        }
+    }
+}
+
+fn check_services(plugins: &[&'static PluginMeta], report: &mut DoctorReport) {
+    for plugin in plugins {
+        if plugin.transcribe && !transcribe::has_transcriber() {
+            report.error(
+                Some(plugin.name),
+                String::from("`transcribe = true` but no transcriber is installed"),
+                "call `transcribe::set_transcriber` at startup",
+            );
+        }
+    }
+
+    let conversations = plugins
+        .iter()
+        .filter(|plugin| plugin.conversation.is_some())
+        .map(|plugin| plugin.name)
+        .collect::<Vec<_>>();
+    let outboxes = plugins
+        .iter()
+        .filter(|plugin| plugin.outbox)
+        .map(|plugin| plugin.name)
+        .collect::<Vec<_>>();
+    if !outboxes.is_empty() && !storage::is_configured() {
+        report.warning(
+            None,
+            format!(
+                "storage is in memory, so outbox messages of these plugins that haven't \
+                 gone out yet are lost on restart: {}",
+                outboxes.join(", ")
+            ),
+            "install a persistent backend with `storage::set_storage`",
+        );
+    }
+
+    #[cfg(feature = "admin-console")]
+    if crate::access::owners().is_empty() {
+        report.warning(
+            None,
+            String::from(
+                "the admin console is built in but no owners are set, so nobody can open it",
+            ),
+            "call `access::set_owners` at startup",
+        );
+    }
+
+    if !conversations.is_empty() && !storage::is_configured() {
+        report.warning(
+            None,
+            format!(
+                "storage is in memory, so conversations of these plugins are lost on \
+                 restart: {}",
+                conversations.join(", ")
+            ),
+            "install a persistent backend with `storage::set_storage`",
+        );
+    }
+}
@@ -0,0 +1,88 @@
+use crate::context::PluginContext;
+use crate::extract::FromContext;
+use teloxide::types::{Message, MessageEntityKind, MessageEntityRef};
+
+pub const ENTITY_KINDS: &[&str] = &[
+    "mention",
+    "hashtag",
+    "cashtag",
+    "bot_command",
+    "url",
+    "email",
+    "phone_number",
+    "text_link",
+    "text_mention",
+    "custom_emoji",
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entity {
+    pub kind: &'static str,
+    pub value: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Entities(pub Vec<Entity>);
+
+impl Entities {
+    pub fn of_kind<'a>(&'a self, kind: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.0
+            .iter()
+            .filter(move |e| e.kind == kind)
+            .map(|e| e.value.as_str())
+    }
+}
+
+pub fn kind_name(kind: &MessageEntityKind) -> &'static str {
+    match kind {
+        MessageEntityKind::Mention => "mention",
+        MessageEntityKind::Hashtag => "hashtag",
+        MessageEntityKind::Cashtag => "cashtag",
+        MessageEntityKind::BotCommand => "bot_command",
+        MessageEntityKind::Url => "url",
+        MessageEntityKind::Email => "email",
+        MessageEntityKind::PhoneNumber => "phone_number",
+        MessageEntityKind::TextLink { .. } => "text_link",
+        MessageEntityKind::TextMention { .. } => "text_mention",
+        MessageEntityKind::CustomEmoji { .. } => "custom_emoji",
+        _ => "formatting",
+    }
+}
+
+fn to_entity(entity: MessageEntityRef<'_>) -> Entity {
+    let value = match entity.kind() {
+        MessageEntityKind::TextLink { url } => url.to_string(),
+        _ => entity.text().to_string(),
+    };
+    Entity {
+        kind: kind_name(entity.kind()),
+        value,
+    }
+}
+
+pub fn message_entities(msg: &Message) -> Vec<Entity> {
+    msg.parse_entities()
+        .into_iter()
+        .chain(msg.parse_caption_entities())
+        .flatten()
+        .map(to_entity)
+        .collect()
+}
+
+pub(crate) fn has_entity(msg: &Message, kinds: &[&str]) -> bool {
+    message_entities(msg)
+        .iter()
+        .any(|entity| kinds.contains(&entity.kind))
+}
+
+impl FromContext for Entities {
+    fn from_context(ctx: &PluginContext) -> Option<Self> {
+        let msg = ctx.message.as_ref()?;
+        let wanted = ctx.plugin.map(|p| p.entities).unwrap_or_default();
+        let entities = message_entities(msg)
+            .into_iter()
+            .filter(|entity| wanted.is_empty() || wanted.contains(&entity.kind))
+            .collect();
+        Some(Entities(entities))
+    }
+}
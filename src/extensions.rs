@@ -0,0 +1,32 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+type Values = HashMap<TypeId, Arc<dyn Any + Send + Sync>>;
+
+// Shared between clones of a context, so values stored before routing are visible
+// to the handler and to anything it spawns.
+#[derive(Clone, Default)]
+pub struct Extensions(Arc<RwLock<Values>>);
+
+impl Extensions {
+    pub fn insert<T: Any + Send + Sync>(&self, value: T) {
+        self.0
+            .write()
+            .unwrap()
+            .insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    pub fn get<T: Any + Send + Sync + Clone>(&self) -> Option<T> {
+        let values = self.0.read().unwrap();
+        values.get(&TypeId::of::<T>())?.downcast_ref().cloned()
+    }
+
+    pub fn contains<T: Any + Send + Sync>(&self) -> bool {
+        self.0.read().unwrap().contains_key(&TypeId::of::<T>())
+    }
+
+    pub fn remove<T: Any + Send + Sync>(&self) -> bool {
+        self.0.write().unwrap().remove(&TypeId::of::<T>()).is_some()
+    }
+}
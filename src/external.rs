@@ -0,0 +1,261 @@
+use crate::budget;
+use crate::context::PluginContext;
+#[cfg(feature = "webhooks")]
+use crate::queue::DispatchQueue;
+use crate::registry::{self, PluginMeta};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "webhooks")]
+use std::io;
+#[cfg(feature = "webhooks")]
+use std::net::SocketAddr;
+#[cfg(feature = "webhooks")]
+use std::sync::RwLock;
+#[cfg(any(feature = "webhooks", feature = "health"))]
+use std::time::Duration;
+use teloxide::Bot;
+#[cfg(any(feature = "webhooks", feature = "health"))]
+use tokio::io::AsyncReadExt;
+#[cfg(feature = "webhooks")]
+use tokio::io::AsyncWriteExt;
+#[cfg(any(feature = "webhooks", feature = "health"))]
+use tokio::net::TcpStream;
+#[cfg(feature = "webhooks")]
+use tokio::net::{TcpListener, ToSocketAddrs};
+
+#[cfg(any(feature = "webhooks", feature = "health"))]
+const MAX_HEAD: usize = 16 * 1024;
+#[cfg(any(feature = "webhooks", feature = "health"))]
+const MAX_BODY: usize = 1024 * 1024;
+#[cfg(any(feature = "webhooks", feature = "health"))]
+const BAD_REQUEST: &str = "400 Bad Request";
+// How long a client gets to send its whole request. The health endpoint reads its
+// requests the same way.
+#[cfg(any(feature = "webhooks", feature = "health"))]
+pub(crate) const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[cfg(feature = "webhooks")]
+static SECRET: RwLock<Option<String>> = RwLock::new(None);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExternalEvent {
+    pub name: String,
+    pub payload: serde_json::Value,
+}
+
+impl ExternalEvent {
+    pub fn new(name: impl Into<String>, payload: serde_json::Value) -> Self {
+        Self {
+            name: name.into(),
+            payload,
+        }
+    }
+}
+
+pub fn listeners(name: &str) -> Vec<&'static PluginMeta> {
+    registry::plugins()
+        .into_iter()
+        .filter(|plugin| plugin.external.contains(&name))
+        .collect()
+}
+
+// Events from the webhook endpoint go to the first listener, like any other update.
+// Published events reach every listener, so several plugins can react to one event.
+pub async fn publish(bot: &Bot, event: ExternalEvent) -> usize {
+    let plugins: Vec<&'static PluginMeta> = listeners(&event.name)
+        .into_iter()
+        .filter(|plugin| {
+            registry::is_enabled(plugin.name) && budget::suspended_for(plugin.name).is_none()
+        })
+        .collect();
+    let ctx = PluginContext::from_external(bot.clone(), event);
+    for plugin in &plugins {
+        registry::run_plugin(plugin, &ctx).await;
+    }
+    plugins.len()
+}
+
+impl PluginContext {
+    pub fn external_payload<T: DeserializeOwned>(&self) -> Option<T> {
+        serde_json::from_value(self.external.as_ref()?.payload.clone()).ok()
+    }
+}
+
+#[cfg(feature = "webhooks")]
+pub fn set_secret(secret: Option<impl Into<String>>) {
+    *SECRET.write().unwrap() = secret.map(Into::into);
+}
+
+// Anyone who can reach the endpoint can inject events, so without a secret it only
+// listens on loopback.
+#[cfg(feature = "webhooks")]
+pub fn check_exposure(addr: SocketAddr) -> io::Result<()> {
+    if SECRET.read().unwrap().is_none() && !addr.ip().is_loopback() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "refusing to accept external events on {} without a secret; call external::set_secret first",
+                addr
+            ),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "webhooks")]
+pub async fn serve(addr: impl ToSocketAddrs, bot: Bot, queue: DispatchQueue) -> io::Result<()> {
+    serve_listener(TcpListener::bind(addr).await?, bot, queue).await
+}
+
+#[cfg(feature = "webhooks")]
+pub async fn serve_listener(
+    listener: TcpListener,
+    bot: Bot,
+    queue: DispatchQueue,
+) -> io::Result<()> {
+    check_exposure(listener.local_addr()?)?;
+    loop {
+        let Ok((mut stream, _)) = listener.accept().await else {
+            continue;
+        };
+        let bot = bot.clone();
+        let queue = queue.clone();
+        tokio::spawn(async move {
+            let (status, body) =
+                match tokio::time::timeout(READ_TIMEOUT, read_request(&mut stream)).await {
+                    Ok(Ok(request)) => accept(request, bot, &queue),
+                    Ok(Err(status)) => (status, String::from("{}")),
+                    Err(_) => ("408 Request Timeout", String::from("{}")),
+                };
+            let response = format!(
+                "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+            let _ = stream.shutdown().await;
+        });
+    }
+}
+
+#[cfg(any(feature = "webhooks", feature = "health"))]
+#[cfg_attr(not(feature = "webhooks"), allow(dead_code))]
+pub(crate) struct HttpRequest {
+    pub(crate) method: String,
+    pub(crate) path: String,
+    secret: Option<String>,
+    body: Vec<u8>,
+}
+
+#[cfg(any(feature = "webhooks", feature = "health"))]
+pub(crate) async fn read_request(stream: &mut TcpStream) -> Result<HttpRequest, &'static str> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let head_end = loop {
+        if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end;
+        }
+        if buf.len() > MAX_HEAD {
+            return Err("431 Request Header Fields Too Large");
+        }
+        match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return Err(BAD_REQUEST),
+            Ok(read) => buf.extend_from_slice(&chunk[..read]),
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buf[..head_end]).into_owned();
+    let mut lines = head.split("\r\n");
+    let mut start = lines.next().unwrap_or_default().split_whitespace();
+    let method = start.next().unwrap_or_default().to_string();
+    let path = start.next().unwrap_or("/").to_string();
+    let mut length = 0;
+    let mut secret = None;
+    for (name, value) in lines.filter_map(|line| line.split_once(':')) {
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "content-length" => length = value.parse().map_err(|_| BAD_REQUEST)?,
+            "x-webhook-secret" => secret = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    if length > MAX_BODY {
+        return Err("413 Payload Too Large");
+    }
+
+    let mut body = buf.split_off(head_end + 4);
+    while body.len() < length {
+        match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return Err(BAD_REQUEST),
+            Ok(read) => body.extend_from_slice(&chunk[..read]),
+        }
+    }
+    body.truncate(length);
+
+    Ok(HttpRequest {
+        method,
+        path,
+        secret,
+        body,
+    })
+}
+
+#[cfg(feature = "webhooks")]
+fn accept(request: HttpRequest, bot: Bot, queue: &DispatchQueue) -> (&'static str, String) {
+    let path = request
+        .path
+        .split_once('?')
+        .map_or(request.path.as_str(), |(path, _)| path);
+    let Some(name) = path
+        .strip_prefix("/events/")
+        .filter(|name| !name.is_empty())
+    else {
+        return ("404 Not Found", String::from("{}"));
+    };
+    if request.method != "POST" {
+        return ("405 Method Not Allowed", String::from("{}"));
+    }
+    if let Some(expected) = SECRET.read().unwrap().as_deref() {
+        let given = request.secret.as_deref().unwrap_or_default();
+        if !secrets_match(expected.as_bytes(), given.as_bytes()) {
+            return ("401 Unauthorized", String::from("{}"));
+        }
+    }
+
+    let payload = if request.body.is_empty() {
+        serde_json::Value::Null
+    } else {
+        match serde_json::from_slice(&request.body) {
+            Ok(payload) => payload,
+            Err(err) => {
+                let body = serde_json::json!({ "error": err.to_string() });
+                return (BAD_REQUEST, body.to_string());
+            }
+        }
+    };
+    if listeners(name).is_empty() {
+        let body = serde_json::json!({ "error": "no plugin handles this event" });
+        return ("404 Not Found", body.to_string());
+    }
+
+    let ctx = PluginContext::from_external(bot, ExternalEvent::new(name, payload));
+    if queue.push(ctx) {
+        ("202 Accepted", String::from("{\"accepted\":true}"))
+    } else {
+        (
+            "503 Service Unavailable",
+            String::from("{\"accepted\":false}"),
+        )
+    }
+}
+
+#[cfg(feature = "webhooks")]
+fn secrets_match(expected: &[u8], given: &[u8]) -> bool {
+    expected.len() == given.len()
+        && expected
+            .iter()
+            .zip(given)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
@@ -0,0 +1,170 @@
+use crate::context::PluginContext;
+use teloxide::types::{
+    BusinessConnection, CallbackQuery, ChatBoostRemoved, ChatBoostUpdated, ChatId,
+    ChatMemberUpdated, Giveaway, GiveawayCompleted, GiveawayWinners, InlineQuery, Message,
+    MessageKind, Poll, PollAnswer, PreCheckoutQuery, RefundedPayment, VideoChatEnded,
+    VideoChatParticipantsInvited, VideoChatScheduled,
+};
+use teloxide::Bot;
+
+pub trait FromContext: Sized {
+    fn from_context(ctx: &PluginContext) -> Option<Self>;
+}
+
+impl FromContext for PluginContext {
+    fn from_context(ctx: &PluginContext) -> Option<Self> {
+        Some(ctx.clone())
+    }
+}
+
+impl FromContext for Bot {
+    fn from_context(ctx: &PluginContext) -> Option<Self> {
+        Some(ctx.bot.clone())
+    }
+}
+
+impl FromContext for Message {
+    fn from_context(ctx: &PluginContext) -> Option<Self> {
+        ctx.message.clone()
+    }
+}
+
+impl FromContext for CallbackQuery {
+    fn from_context(ctx: &PluginContext) -> Option<Self> {
+        ctx.callback_query.clone()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Event {
+    Message(Box<Message>),
+    Callback(Box<CallbackQuery>),
+}
+
+impl Event {
+    pub fn message(&self) -> Option<&Message> {
+        match self {
+            Event::Message(msg) => Some(msg),
+            Event::Callback(cq) => cq.regular_message(),
+        }
+    }
+
+    pub fn chat_id(&self) -> Option<ChatId> {
+        match self {
+            Event::Message(msg) => Some(msg.chat.id),
+            Event::Callback(cq) => cq.message.as_ref().map(|m| m.chat().id),
+        }
+    }
+}
+
+impl FromContext for Event {
+    fn from_context(ctx: &PluginContext) -> Option<Self> {
+        match (&ctx.callback_query, &ctx.message) {
+            (Some(cq), _) => Some(Event::Callback(Box::new(cq.clone()))),
+            (None, Some(msg)) => Some(Event::Message(Box::new(msg.clone()))),
+            (None, None) => None,
+        }
+    }
+}
+
+impl FromContext for InlineQuery {
+    fn from_context(ctx: &PluginContext) -> Option<Self> {
+        ctx.inline_query.clone()
+    }
+}
+
+impl FromContext for BusinessConnection {
+    fn from_context(ctx: &PluginContext) -> Option<Self> {
+        ctx.business_connection.clone()
+    }
+}
+
+impl FromContext for ChatBoostUpdated {
+    fn from_context(ctx: &PluginContext) -> Option<Self> {
+        ctx.chat_boost.clone()
+    }
+}
+
+impl FromContext for ChatBoostRemoved {
+    fn from_context(ctx: &PluginContext) -> Option<Self> {
+        ctx.removed_chat_boost.clone()
+    }
+}
+
+impl FromContext for ChatMemberUpdated {
+    fn from_context(ctx: &PluginContext) -> Option<Self> {
+        ctx.my_chat_member.clone()
+    }
+}
+
+impl FromContext for Poll {
+    fn from_context(ctx: &PluginContext) -> Option<Self> {
+        ctx.poll.clone()
+    }
+}
+
+impl FromContext for PollAnswer {
+    fn from_context(ctx: &PluginContext) -> Option<Self> {
+        ctx.poll_answer.clone()
+    }
+}
+
+impl FromContext for Giveaway {
+    fn from_context(ctx: &PluginContext) -> Option<Self> {
+        ctx.message.as_ref()?.giveaway().cloned()
+    }
+}
+
+impl FromContext for GiveawayWinners {
+    fn from_context(ctx: &PluginContext) -> Option<Self> {
+        ctx.message.as_ref()?.giveaway_winners().cloned()
+    }
+}
+
+impl FromContext for GiveawayCompleted {
+    fn from_context(ctx: &PluginContext) -> Option<Self> {
+        ctx.message.as_ref()?.giveaway_completed().cloned()
+    }
+}
+
+impl FromContext for VideoChatScheduled {
+    fn from_context(ctx: &PluginContext) -> Option<Self> {
+        ctx.message.as_ref()?.video_chat_scheduled().cloned()
+    }
+}
+
+impl FromContext for VideoChatEnded {
+    fn from_context(ctx: &PluginContext) -> Option<Self> {
+        ctx.message.as_ref()?.video_chat_ended().cloned()
+    }
+}
+
+impl FromContext for VideoChatParticipantsInvited {
+    fn from_context(ctx: &PluginContext) -> Option<Self> {
+        ctx.message
+            .as_ref()?
+            .video_chat_participants_invited()
+            .cloned()
+    }
+}
+
+impl FromContext for PreCheckoutQuery {
+    fn from_context(ctx: &PluginContext) -> Option<Self> {
+        ctx.pre_checkout_query.clone()
+    }
+}
+
+impl FromContext for RefundedPayment {
+    fn from_context(ctx: &PluginContext) -> Option<Self> {
+        match &ctx.message.as_ref()?.kind {
+            MessageKind::RefundedPayment(refund) => Some(refund.refunded_payment.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl FromContext for serde_json::Value {
+    fn from_context(ctx: &PluginContext) -> Option<Self> {
+        ctx.raw_update.clone()
+    }
+}
@@ -0,0 +1,569 @@
+use crate::context::PluginContext;
+use crate::storage::{self, StorageError};
+use crate::traffic;
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::BuildHasher;
+use std::sync::RwLock;
+use teloxide::prelude::*;
+
+const FED_PREFIX: &str = "fed:";
+const BAN_PREFIX: &str = "fedban:";
+const CHAT_PREFIX: &str = "fedchat:";
+
+static CHAT_FEDS: Lazy<RwLock<HashMap<ChatId, Option<String>>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Federation {
+    pub id: String,
+    pub name: String,
+    pub owner: UserId,
+    pub admins: Vec<UserId>,
+    pub chats: Vec<ChatId>,
+    pub subscriptions: Vec<String>,
+}
+
+impl Federation {
+    pub fn is_admin(&self, user: UserId) -> bool {
+        self.owner == user || self.admins.contains(&user)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FedBan {
+    pub federation: String,
+    pub user: UserId,
+    pub reason: Option<String>,
+    pub by: UserId,
+    pub at: DateTime<Utc>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FedBanReport {
+    pub chats: usize,
+    pub failed: usize,
+}
+
+#[derive(Debug)]
+pub enum FederationError {
+    NotFound(String),
+    SelfSubscription,
+    Storage(StorageError),
+}
+
+impl fmt::Display for FederationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FederationError::NotFound(id) => write!(f, "federation `{}` doesn't exist", id),
+            FederationError::SelfSubscription => {
+                write!(f, "a federation can't subscribe to itself")
+            }
+            FederationError::Storage(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for FederationError {}
+
+impl From<StorageError> for FederationError {
+    fn from(err: StorageError) -> Self {
+        FederationError::Storage(err)
+    }
+}
+
+fn fed_key(id: &str) -> String {
+    format!("{}{}", FED_PREFIX, id)
+}
+
+fn ban_key(id: &str, user: UserId) -> String {
+    format!("{}{}:{}", BAN_PREFIX, id, user)
+}
+
+fn chat_key(chat: ChatId) -> String {
+    format!("{}{}", CHAT_PREFIX, chat)
+}
+
+fn new_id() -> String {
+    let hash = RandomState::new().hash_one((std::process::id(), Utc::now()));
+    format!("{:016x}", hash)
+}
+
+pub(crate) fn invalidate() {
+    CHAT_FEDS.write().unwrap().clear();
+}
+
+pub async fn federation(id: &str) -> Result<Option<Federation>, StorageError> {
+    storage::get_json(&fed_key(id)).await
+}
+
+async fn existing(id: &str) -> Result<Federation, FederationError> {
+    federation(id)
+        .await?
+        .ok_or_else(|| FederationError::NotFound(id.to_string()))
+}
+
+async fn save(fed: &Federation) -> Result<(), StorageError> {
+    storage::set_json(&fed_key(&fed.id), fed).await
+}
+
+pub async fn federations() -> Result<Vec<Federation>, StorageError> {
+    let mut feds = Vec::new();
+    for key in storage::storage().keys(FED_PREFIX).await? {
+        if let Some(fed) = storage::get_json(&key).await? {
+            feds.push(fed);
+        }
+    }
+    Ok(feds)
+}
+
+pub async fn create(name: &str, owner: UserId) -> Result<Federation, StorageError> {
+    let fed = Federation {
+        id: new_id(),
+        name: name.to_string(),
+        owner,
+        admins: Vec::new(),
+        chats: Vec::new(),
+        subscriptions: Vec::new(),
+    };
+    save(&fed).await?;
+    Ok(fed)
+}
+
+pub async fn delete(id: &str) -> Result<(), FederationError> {
+    let fed = existing(id).await?;
+    let storage = storage::storage();
+    for chat in &fed.chats {
+        storage.delete(&chat_key(*chat)).await?;
+    }
+    for key in storage.keys(&format!("{}{}:", BAN_PREFIX, id)).await? {
+        storage.delete(&key).await?;
+    }
+    storage.delete(&fed_key(id)).await?;
+    invalidate();
+    Ok(())
+}
+
+pub async fn of_chat(chat: ChatId) -> Result<Option<Federation>, StorageError> {
+    let cached = CHAT_FEDS.read().unwrap().get(&chat).cloned();
+    let id = match cached {
+        Some(id) => id,
+        None => {
+            let id = storage::storage().get(&chat_key(chat)).await?;
+            CHAT_FEDS.write().unwrap().insert(chat, id.clone());
+            id
+        }
+    };
+    match id {
+        Some(id) => federation(&id).await,
+        None => Ok(None),
+    }
+}
+
+// A chat belongs to at most one federation, so joining another one leaves the old one.
+pub async fn join(chat: ChatId, id: &str) -> Result<Federation, FederationError> {
+    let mut fed = existing(id).await?;
+    leave(chat).await?;
+    if !fed.chats.contains(&chat) {
+        fed.chats.push(chat);
+    }
+    save(&fed).await?;
+    storage::storage()
+        .set(&chat_key(chat), fed.id.clone())
+        .await?;
+    CHAT_FEDS
+        .write()
+        .unwrap()
+        .insert(chat, Some(fed.id.clone()));
+    Ok(fed)
+}
+
+pub async fn leave(chat: ChatId) -> Result<Option<Federation>, FederationError> {
+    let Some(mut fed) = of_chat(chat).await? else {
+        return Ok(None);
+    };
+    fed.chats.retain(|joined| *joined != chat);
+    save(&fed).await?;
+    storage::delete(&chat_key(chat)).await?;
+    CHAT_FEDS.write().unwrap().insert(chat, None);
+    Ok(Some(fed))
+}
+
+pub async fn promote(id: &str, user: UserId) -> Result<Federation, FederationError> {
+    let mut fed = existing(id).await?;
+    if !fed.is_admin(user) {
+        fed.admins.push(user);
+        save(&fed).await?;
+    }
+    Ok(fed)
+}
+
+pub async fn demote(id: &str, user: UserId) -> Result<Federation, FederationError> {
+    let mut fed = existing(id).await?;
+    fed.admins.retain(|admin| *admin != user);
+    save(&fed).await?;
+    Ok(fed)
+}
+
+pub async fn subscribe(id: &str, to: &str) -> Result<Federation, FederationError> {
+    if id == to {
+        return Err(FederationError::SelfSubscription);
+    }
+    existing(to).await?;
+    let mut fed = existing(id).await?;
+    if !fed.subscriptions.iter().any(|sub| sub == to) {
+        fed.subscriptions.push(to.to_string());
+        save(&fed).await?;
+    }
+    Ok(fed)
+}
+
+pub async fn unsubscribe(id: &str, from: &str) -> Result<Federation, FederationError> {
+    let mut fed = existing(id).await?;
+    fed.subscriptions.retain(|sub| sub != from);
+    save(&fed).await?;
+    Ok(fed)
+}
+
+// Every chat that enforces `id`'s bans: its own chats, plus those of every federation
+// subscribed to it, directly or through other subscriptions.
+async fn enforcing_chats(id: &str) -> Result<Vec<ChatId>, StorageError> {
+    let feds = federations().await?;
+    let mut reached = HashSet::from([id.to_string()]);
+    let mut pending = vec![id.to_string()];
+    while let Some(current) = pending.pop() {
+        for fed in &feds {
+            if fed.subscriptions.contains(&current) && reached.insert(fed.id.clone()) {
+                pending.push(fed.id.clone());
+            }
+        }
+    }
+    let mut chats: Vec<ChatId> = feds
+        .iter()
+        .filter(|fed| reached.contains(&fed.id))
+        .flat_map(|fed| fed.chats.iter().copied())
+        .collect();
+    chats.sort_by_key(|chat| chat.0);
+    chats.dedup();
+    Ok(chats)
+}
+
+pub async fn ban(
+    bot: &Bot,
+    id: &str,
+    user: UserId,
+    reason: Option<String>,
+    by: UserId,
+) -> Result<FedBanReport, FederationError> {
+    existing(id).await?;
+    let record = FedBan {
+        federation: id.to_string(),
+        user,
+        reason,
+        by,
+        at: Utc::now(),
+    };
+    storage::set_json(&ban_key(id, user), &record).await?;
+    let mut report = FedBanReport::default();
+    for chat in enforcing_chats(id).await? {
+        match traffic::send(bot.ban_chat_member(chat, user)).await {
+            Ok(_) => report.chats += 1,
+            Err(_) => report.failed += 1,
+        }
+    }
+    Ok(report)
+}
+
+pub async fn unban(bot: &Bot, id: &str, user: UserId) -> Result<bool, FederationError> {
+    existing(id).await?;
+    let key = ban_key(id, user);
+    if storage::storage().get(&key).await?.is_none() {
+        return Ok(false);
+    }
+    storage::delete(&key).await?;
+    for chat in enforcing_chats(id).await? {
+        let _ = traffic::send(bot.unban_chat_member(chat, user).only_if_banned(true)).await;
+    }
+    Ok(true)
+}
+
+pub async fn bans(id: &str) -> Result<Vec<FedBan>, StorageError> {
+    let mut bans = Vec::new();
+    for key in storage::storage()
+        .keys(&format!("{}{}:", BAN_PREFIX, id))
+        .await?
+    {
+        if let Some(ban) = storage::get_json(&key).await? {
+            bans.push(ban);
+        }
+    }
+    Ok(bans)
+}
+
+// Checks the federation's own list first, then everything it subscribes to.
+pub async fn ban_of(id: &str, user: UserId) -> Result<Option<FedBan>, StorageError> {
+    let mut visited = HashSet::new();
+    let mut pending = vec![id.to_string()];
+    while let Some(current) = pending.pop() {
+        if !visited.insert(current.clone()) {
+            continue;
+        }
+        if let Some(ban) = storage::get_json(&ban_key(&current, user)).await? {
+            return Ok(Some(ban));
+        }
+        if let Some(fed) = federation(&current).await? {
+            pending.extend(fed.subscriptions);
+        }
+    }
+    Ok(None)
+}
+
+pub(crate) async fn migrate(from: ChatId, to: ChatId) -> Result<(), FederationError> {
+    let Some(fed) = of_chat(from).await? else {
+        return Ok(());
+    };
+    leave(from).await?;
+    join(to, &fed.id).await?;
+    Ok(())
+}
+
+// Runs before routing: fed-banned newcomers and senders are banned from the chat.
+// Returns `true` when the sender was banned, so their message isn't handled.
+pub(crate) async fn enforce(ctx: &PluginContext) -> bool {
+    let Some(msg) = ctx.message.as_ref().filter(|msg| !msg.chat.is_private()) else {
+        return false;
+    };
+    let Ok(Some(fed)) = of_chat(msg.chat.id).await else {
+        return false;
+    };
+    for member in msg.new_chat_members().unwrap_or_default() {
+        if let Ok(Some(_)) = ban_of(&fed.id, member.id).await {
+            let _ = traffic::send(ctx.bot.ban_chat_member(msg.chat.id, member.id)).await;
+        }
+    }
+    let Some(user) = ctx.sender().filter(|_| msg.new_chat_members().is_none()) else {
+        return false;
+    };
+    if !matches!(ban_of(&fed.id, user.id).await, Ok(Some(_))) {
+        return false;
+    }
+    let _ = traffic::send(ctx.bot.ban_chat_member(msg.chat.id, user.id)).await;
+    true
+}
+
+impl PluginContext {
+    pub async fn federation(&self) -> Option<Federation> {
+        of_chat(self.chat_id()?).await.ok().flatten()
+    }
+}
+
+#[cfg(feature = "federation-commands")]
+fn fed_target(ctx: &PluginContext) -> (Option<UserId>, Option<String>) {
+    let args = ctx.args();
+    let (target, rest) = match args.first().and_then(|arg| arg.parse::<u64>().ok()) {
+        Some(id) if ctx.replied_message().is_none() => (Some(UserId(id)), &args[1..]),
+        _ => (ctx.target_user().and_then(|t| t.id()), &args[..]),
+    };
+    let rest: Vec<&str> = rest
+        .iter()
+        .copied()
+        .filter(|arg| !arg.starts_with('@'))
+        .collect();
+    (target, (!rest.is_empty()).then(|| rest.join(" ")))
+}
+
+#[cfg(feature = "federation-commands")]
+async fn chat_federation(ctx: &PluginContext) -> Result<Federation, String> {
+    match ctx.federation().await {
+        Some(fed) => Ok(fed),
+        None => Err(String::from("This chat isn't part of a federation.")),
+    }
+}
+
+#[cfg(feature = "federation-commands")]
+#[crate::TeloxidePlugin(commands = ["newfed"], prefixes = ["/"])]
+async fn new_fed_command(ctx: PluginContext) -> Option<String> {
+    let owner = ctx.sender()?.id;
+    let name = ctx.args().join(" ");
+    if name.is_empty() {
+        return Some(String::from("Usage: /newfed <name>"));
+    }
+    Some(match create(&name, owner).await {
+        Ok(fed) => format!(
+            "Created federation {}. Its id is {}, use /joinfed {} in your groups.",
+            fed.name, fed.id, fed.id
+        ),
+        Err(err) => format!("Couldn't create the federation: {}", err),
+    })
+}
+
+#[cfg(feature = "federation-commands")]
+#[crate::TeloxidePlugin(commands = ["joinfed"], prefixes = ["/"], admin_only = true)]
+async fn join_fed_command(ctx: PluginContext) -> Option<String> {
+    let Some(id) = ctx.args().first().map(|id| id.to_string()) else {
+        return Some(String::from("Usage: /joinfed <federation id>"));
+    };
+    Some(match join(ctx.chat_id()?, &id).await {
+        Ok(fed) => format!("This chat is now part of {}.", fed.name),
+        Err(err) => format!("Couldn't join: {}", err),
+    })
+}
+
+#[cfg(feature = "federation-commands")]
+#[crate::TeloxidePlugin(commands = ["leavefed"], prefixes = ["/"], admin_only = true)]
+async fn leave_fed_command(ctx: PluginContext) -> Option<String> {
+    Some(match leave(ctx.chat_id()?).await {
+        Ok(Some(fed)) => format!("This chat has left {}.", fed.name),
+        Ok(None) => String::from("This chat isn't part of a federation."),
+        Err(err) => format!("Couldn't leave: {}", err),
+    })
+}
+
+#[cfg(feature = "federation-commands")]
+#[crate::TeloxidePlugin(commands = ["fedinfo"], prefixes = ["/"])]
+async fn fed_info_command(ctx: PluginContext) -> Option<String> {
+    let fed = match ctx.args().first() {
+        Some(id) => federation(id).await.ok().flatten(),
+        None => ctx.federation().await,
+    };
+    let Some(fed) = fed else {
+        return Some(String::from("No such federation."));
+    };
+    let banned = bans(&fed.id).await.map_or(0, |bans| bans.len());
+    Some(format!(
+        "{} ({})\nOwner: {}\nAdmins: {}\nChats: {}\nBans: {}\nSubscribed to: {}",
+        fed.name,
+        fed.id,
+        fed.owner,
+        fed.admins.len(),
+        fed.chats.len(),
+        banned,
+        if fed.subscriptions.is_empty() {
+            String::from("none")
+        } else {
+            fed.subscriptions.join(", ")
+        }
+    ))
+}
+
+#[cfg(feature = "federation-commands")]
+#[crate::TeloxidePlugin(commands = ["fban"], prefixes = ["/"])]
+async fn fban_command(ctx: PluginContext) -> Option<String> {
+    let fed = match chat_federation(&ctx).await {
+        Ok(fed) => fed,
+        Err(text) => return Some(text),
+    };
+    let by = ctx.sender()?.id;
+    if !fed.is_admin(by) {
+        return Some(String::from("Only federation admins can do that."));
+    }
+    let (Some(target), reason) = fed_target(&ctx) else {
+        return Some(String::from("Reply to the user or pass their id."));
+    };
+    if fed.is_admin(target) {
+        return Some(String::from("Federation admins can't be fbanned."));
+    }
+    Some(match ban(&ctx.bot, &fed.id, target, reason, by).await {
+        Ok(report) => format!(
+            "{} is now banned in {} ({} chats).",
+            target, fed.name, report.chats
+        ),
+        Err(err) => format!("Couldn't ban: {}", err),
+    })
+}
+
+#[cfg(feature = "federation-commands")]
+#[crate::TeloxidePlugin(commands = ["unfban"], prefixes = ["/"])]
+async fn unfban_command(ctx: PluginContext) -> Option<String> {
+    let fed = match chat_federation(&ctx).await {
+        Ok(fed) => fed,
+        Err(text) => return Some(text),
+    };
+    if !fed.is_admin(ctx.sender()?.id) {
+        return Some(String::from("Only federation admins can do that."));
+    }
+    let (Some(target), _) = fed_target(&ctx) else {
+        return Some(String::from("Reply to the user or pass their id."));
+    };
+    Some(match unban(&ctx.bot, &fed.id, target).await {
+        Ok(true) => format!("{} is no longer banned in {}.", target, fed.name),
+        Ok(false) => format!("{} isn't banned in {}.", target, fed.name),
+        Err(err) => format!("Couldn't unban: {}", err),
+    })
+}
+
+#[cfg(feature = "federation-commands")]
+async fn change_admins(ctx: PluginContext, promoting: bool) -> Option<String> {
+    let fed = match chat_federation(&ctx).await {
+        Ok(fed) => fed,
+        Err(text) => return Some(text),
+    };
+    if fed.owner != ctx.sender()?.id {
+        return Some(String::from("Only the federation owner can do that."));
+    }
+    let (Some(target), _) = fed_target(&ctx) else {
+        return Some(String::from("Reply to the user or pass their id."));
+    };
+    let result = if promoting {
+        promote(&fed.id, target).await
+    } else {
+        demote(&fed.id, target).await
+    };
+    Some(match result {
+        Ok(_) if promoting => format!("{} is now a federation admin.", target),
+        Ok(_) => format!("{} is no longer a federation admin.", target),
+        Err(err) => format!("Couldn't update the admins: {}", err),
+    })
+}
+
+#[cfg(feature = "federation-commands")]
+#[crate::TeloxidePlugin(commands = ["fpromote"], prefixes = ["/"])]
+async fn fpromote_command(ctx: PluginContext) -> Option<String> {
+    change_admins(ctx, true).await
+}
+
+#[cfg(feature = "federation-commands")]
+#[crate::TeloxidePlugin(commands = ["fdemote"], prefixes = ["/"])]
+async fn fdemote_command(ctx: PluginContext) -> Option<String> {
+    change_admins(ctx, false).await
+}
+
+#[cfg(feature = "federation-commands")]
+async fn change_subscription(ctx: PluginContext, subscribing: bool) -> Option<String> {
+    let fed = match chat_federation(&ctx).await {
+        Ok(fed) => fed,
+        Err(text) => return Some(text),
+    };
+    if fed.owner != ctx.sender()?.id {
+        return Some(String::from("Only the federation owner can do that."));
+    }
+    let Some(other) = ctx.args().first().map(|id| id.to_string()) else {
+        return Some(String::from("Pass the id of the other federation."));
+    };
+    let result = if subscribing {
+        subscribe(&fed.id, &other).await
+    } else {
+        unsubscribe(&fed.id, &other).await
+    };
+    Some(match result {
+        Ok(_) if subscribing => format!("{} now enforces bans from {}.", fed.name, other),
+        Ok(_) => format!("{} no longer enforces bans from {}.", fed.name, other),
+        Err(err) => format!("Couldn't update the subscription: {}", err),
+    })
+}
+
+#[cfg(feature = "federation-commands")]
+#[crate::TeloxidePlugin(commands = ["subfed"], prefixes = ["/"])]
+async fn subfed_command(ctx: PluginContext) -> Option<String> {
+    change_subscription(ctx, true).await
+}
+
+#[cfg(feature = "federation-commands")]
+#[crate::TeloxidePlugin(commands = ["unsubfed"], prefixes = ["/"])]
+async fn unsubfed_command(ctx: PluginContext) -> Option<String> {
+    change_subscription(ctx, false).await
+}
@@ -0,0 +1,664 @@
+use crate::context::PluginContext;
+use crate::response;
+use crate::storage::{self, StorageError};
+use crate::templates::{self, Template, TemplateError};
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::redirect::Policy;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+use url::Url;
+
+const PREFIX: &str = "feed:";
+const TEMPLATE: &str = "feed_post";
+const DEFAULT_TEMPLATE: &str = "<b>{feed}</b>\n<a href=\"{link}\">{title}</a>";
+// Enough to recognize everything a feed still lists, without growing forever.
+const SEEN_LIMIT: usize = 500;
+// A feed that suddenly lists many new entries posts the newest few instead of all.
+const POSTS_PER_CHECK: usize = 5;
+const SUMMARY_LEN: usize = 300;
+const MAX_BODY: usize = 5 * 1024 * 1024;
+const MAX_REDIRECTS: usize = 10;
+
+static INTERVAL: RwLock<Duration> = RwLock::new(Duration::from_secs(600));
+static ALLOW_PRIVATE: AtomicBool = AtomicBool::new(false);
+
+// Feed urls come from chat members, so the client only connects to public
+// addresses: every name is checked after it's resolved, and so is every redirect.
+static CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .user_agent(concat!("teloxide-plugins/", env!("CARGO_PKG_VERSION")))
+        .dns_resolver(Arc::new(PublicResolver))
+        .redirect(Policy::custom(|attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                attempt.error("too many redirects")
+            } else if check_url_host(attempt.url()).is_err() {
+                attempt.error("redirected to a private address")
+            } else {
+                attempt.follow()
+            }
+        }))
+        .build()
+        .unwrap_or_default()
+});
+
+static ENTRY: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?s)<(?:item|entry)[\s>].*?</(?:item|entry)>").unwrap());
+static ELEMENTS: Lazy<HashMap<&'static str, Regex>> = Lazy::new(|| {
+    [
+        "title",
+        "link",
+        "guid",
+        "id",
+        "description",
+        "summary",
+        "content",
+        "pubDate",
+        "published",
+        "updated",
+    ]
+    .into_iter()
+    .map(|name| {
+        let pattern = format!(r"(?s)<{}(?:\s[^>]*)?>(.*?)</{}>", name, name);
+        (name, Regex::new(&pattern).unwrap())
+    })
+    .collect()
+});
+static TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"<[^>]*>").unwrap());
+static ATOM_LINK: Lazy<Regex> = Lazy::new(|| Regex::new(r#"<link\b([^>]*?)/?>"#).unwrap());
+static HREF: Lazy<Regex> = Lazy::new(|| Regex::new(r#"href\s*=\s*["']([^"']*)["']"#).unwrap());
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Feed {
+    pub url: String,
+    pub title: String,
+    pub chats: Vec<ChatId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+    #[serde(default)]
+    pub seen: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_checked: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FeedEntry {
+    pub id: String,
+    pub title: String,
+    pub link: String,
+    pub summary: String,
+    pub published: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum FeedError {
+    InvalidUrl(String),
+    Fetch(reqwest::Error),
+    Status(StatusCode),
+    NotAFeed,
+    PrivateAddress(String),
+    TooLarge,
+    Storage(StorageError),
+}
+
+impl fmt::Display for FeedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeedError::InvalidUrl(url) => write!(f, "`{}` isn't an http or https url", url),
+            FeedError::Fetch(err) => write!(f, "{}", err),
+            FeedError::Status(status) => write!(f, "the server answered {}", status),
+            FeedError::NotAFeed => write!(f, "the page isn't an RSS or Atom feed"),
+            FeedError::PrivateAddress(host) => {
+                write!(f, "`{}` isn't a public address", host)
+            }
+            FeedError::TooLarge => write!(f, "the feed is larger than {} MB", MAX_BODY >> 20),
+            FeedError::Storage(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for FeedError {}
+
+impl From<reqwest::Error> for FeedError {
+    fn from(err: reqwest::Error) -> Self {
+        FeedError::Fetch(err)
+    }
+}
+
+impl From<StorageError> for FeedError {
+    fn from(err: StorageError) -> Self {
+        FeedError::Storage(err)
+    }
+}
+
+// How long a feed rests between fetches. The fetcher wakes every minute and only
+// fetches the feeds that are due.
+pub fn set_interval(interval: Duration) {
+    *INTERVAL.write().unwrap() = interval;
+}
+
+pub fn interval() -> Duration {
+    *INTERVAL.read().unwrap()
+}
+
+// Lets feeds on loopback, private and link-local addresses through, for bots that only
+// follow feeds on their own network.
+pub fn set_allow_private(allow: bool) {
+    ALLOW_PRIVATE.store(allow, Ordering::Relaxed);
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    if ALLOW_PRIVATE.load(Ordering::Relaxed) {
+        return true;
+    }
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                // Carrier-grade NAT, 100.64.0.0/10.
+                || (a == 100 && (64..128).contains(&b))
+                || a == 0)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(v4) => is_public(IpAddr::V4(v4)),
+            None => {
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+fn private_address(host: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!("{} isn't a public address", host),
+    )
+}
+
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(private_address(name.as_str()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+// Addresses written into the url never reach the resolver.
+fn check_url_host(url: &Url) -> Result<(), FeedError> {
+    let ip = match url.host() {
+        Some(url::Host::Ipv4(ip)) => IpAddr::V4(ip),
+        Some(url::Host::Ipv6(ip)) => IpAddr::V6(ip),
+        _ => return Ok(()),
+    };
+    if is_public(ip) {
+        Ok(())
+    } else {
+        Err(FeedError::PrivateAddress(ip.to_string()))
+    }
+}
+
+// Checks the resolved addresses up front too, so a subscription to a private host
+// fails with a clear error instead of a connection error.
+async fn check_host(url: &str) -> Result<(), FeedError> {
+    let parsed = Url::parse(url).map_err(|_| FeedError::InvalidUrl(url.to_string()))?;
+    check_url_host(&parsed)?;
+    let Some(url::Host::Domain(host)) = parsed.host() else {
+        return Ok(());
+    };
+    let private = || FeedError::PrivateAddress(host.to_string());
+    let port = parsed.port_or_known_default().unwrap_or(80);
+    let mut addrs = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|_| private())?
+        .peekable();
+    if addrs.peek().is_some() && addrs.all(|addr| !is_public(addr.ip())) {
+        return Err(private());
+    }
+    Ok(())
+}
+
+fn key(url: &str) -> String {
+    format!("{}{}", PREFIX, url)
+}
+
+pub fn normalize_url(url: &str) -> Result<String, FeedError> {
+    let invalid = || FeedError::InvalidUrl(url.to_string());
+    let parsed = Url::parse(url.trim()).map_err(|_| invalid())?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(invalid());
+    }
+    Ok(parsed.to_string())
+}
+
+fn unescape(text: &str) -> String {
+    let text = text.trim();
+    if let Some(raw) = text
+        .strip_prefix("<![CDATA[")
+        .and_then(|t| t.strip_suffix("]]>"))
+    {
+        return raw.to_string();
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let decoded = after.find(';').filter(|end| *end <= 8).and_then(|end| {
+            let entity = &after[..end];
+            let ch = match entity {
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "amp" => Some('&'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                _ => entity
+                    .strip_prefix("#x")
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                    .and_then(char::from_u32),
+            }?;
+            Some((end, ch))
+        });
+        match decoded {
+            Some((end, ch)) => {
+                out.push(ch);
+                rest = &after[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn tag(block: &str, name: &str) -> Option<String> {
+    let text = unescape(ELEMENTS.get(name)?.captures(block)?.get(1)?.as_str());
+    (!text.trim().is_empty()).then(|| text.trim().to_string())
+}
+
+fn collapse(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// Summaries are usually HTML; posts get them as plain text.
+fn plain(html: &str) -> String {
+    let text = collapse(&unescape(&TAG.replace_all(html, " ")));
+    match text.char_indices().nth(SUMMARY_LEN) {
+        Some((cut, _)) => format!("{}…", &text[..cut]),
+        None => text,
+    }
+}
+
+// Atom links are attributes; the `alternate` one (or one without `rel`) is the page.
+fn atom_link(block: &str) -> Option<String> {
+    ATOM_LINK
+        .captures_iter(block)
+        .map(|caps| caps[1].to_string())
+        .find(|attrs| !attrs.contains("rel=") || attrs.contains("alternate"))
+        .and_then(|attrs| HREF.captures(&attrs).map(|caps| unescape(&caps[1])))
+}
+
+// A small RSS 2.0 and Atom reader that pulls out what a post needs. It doesn't try to
+// be a full XML parser.
+pub fn parse(xml: &str) -> Result<(String, Vec<FeedEntry>), FeedError> {
+    if !xml.contains("<rss") && !xml.contains("<feed") && !xml.contains("<rdf:RDF") {
+        return Err(FeedError::NotAFeed);
+    }
+    let head = ENTRY.find(xml).map_or(xml, |first| &xml[..first.start()]);
+    let title = tag(head, "title").map(|t| collapse(&t)).unwrap_or_default();
+    let entries = ENTRY
+        .find_iter(xml)
+        .filter_map(|block| {
+            let block = block.as_str();
+            let link = tag(block, "link")
+                .filter(|link| !link.contains('<'))
+                .or_else(|| atom_link(block))
+                .unwrap_or_default();
+            let id = tag(block, "guid")
+                .or_else(|| tag(block, "id"))
+                .unwrap_or_else(|| link.clone());
+            let title = tag(block, "title")
+                .map(|t| collapse(&t))
+                .unwrap_or_default();
+            if id.is_empty() && title.is_empty() {
+                return None;
+            }
+            let summary = tag(block, "description")
+                .or_else(|| tag(block, "summary"))
+                .or_else(|| tag(block, "content"))
+                .map(|s| plain(&s))
+                .unwrap_or_default();
+            let published = tag(block, "pubDate")
+                .or_else(|| tag(block, "published"))
+                .or_else(|| tag(block, "updated"));
+            Some(FeedEntry {
+                id: if id.is_empty() { title.clone() } else { id },
+                title,
+                link,
+                summary,
+                published,
+            })
+        })
+        .collect();
+    Ok((title, entries))
+}
+
+// `None` when the server says nothing changed since the cached etag or date.
+async fn fetch(feed: &mut Feed) -> Result<Option<Vec<FeedEntry>>, FeedError> {
+    check_host(&feed.url).await?;
+    let mut request = CLIENT.get(&feed.url);
+    if let Some(etag) = &feed.etag {
+        request = request.header(IF_NONE_MATCH, etag);
+    }
+    if let Some(modified) = &feed.last_modified {
+        request = request.header(IF_MODIFIED_SINCE, modified);
+    }
+    let mut response = request.send().await?;
+    if response.status() == StatusCode::NOT_MODIFIED {
+        return Ok(None);
+    }
+    if !response.status().is_success() {
+        return Err(FeedError::Status(response.status()));
+    }
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value: &reqwest::header::HeaderValue| value.to_str().ok())
+            .map(str::to_string)
+    };
+    feed.etag = header(ETAG);
+    feed.last_modified = header(LAST_MODIFIED);
+    if response
+        .content_length()
+        .is_some_and(|length| length > MAX_BODY as u64)
+    {
+        return Err(FeedError::TooLarge);
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if body.len() + chunk.len() > MAX_BODY {
+            return Err(FeedError::TooLarge);
+        }
+        body.extend_from_slice(&chunk);
+    }
+    let (title, entries) = parse(&String::from_utf8_lossy(&body))?;
+    if !title.is_empty() {
+        feed.title = title;
+    }
+    Ok(Some(entries))
+}
+
+fn remember(feed: &mut Feed, ids: impl IntoIterator<Item = String>) {
+    for id in ids {
+        if !feed.seen.contains(&id) {
+            feed.seen.push(id);
+        }
+    }
+    let excess = feed.seen.len().saturating_sub(SEEN_LIMIT);
+    feed.seen.drain(..excess);
+}
+
+pub async fn feed(url: &str) -> Result<Option<Feed>, StorageError> {
+    storage::get_json(&key(url)).await
+}
+
+pub async fn feeds() -> Result<Vec<Feed>, StorageError> {
+    let mut feeds = Vec::new();
+    for key in storage::storage().keys(PREFIX).await? {
+        if let Some(feed) = storage::get_json::<Feed>(&key).await? {
+            feeds.push(feed);
+        }
+    }
+    feeds.sort_by(|a, b| a.url.cmp(&b.url));
+    Ok(feeds)
+}
+
+pub async fn subscriptions(chat: ChatId) -> Result<Vec<Feed>, StorageError> {
+    Ok(feeds()
+        .await?
+        .into_iter()
+        .filter(|feed| feed.chats.contains(&chat))
+        .collect())
+}
+
+// A new feed is fetched right away, so a bad url fails here, and what it already lists
+// counts as seen: subscribing doesn't flood the chat with old posts.
+pub async fn subscribe(chat: ChatId, url: &str) -> Result<Feed, FeedError> {
+    let url = normalize_url(url)?;
+    let mut feed = match feed(&url).await? {
+        Some(feed) => feed,
+        None => {
+            let mut feed = Feed {
+                url: url.clone(),
+                title: url.clone(),
+                chats: Vec::new(),
+                etag: None,
+                last_modified: None,
+                seen: Vec::new(),
+                last_checked: None,
+            };
+            let entries = fetch(&mut feed).await?.unwrap_or_default();
+            remember(&mut feed, entries.into_iter().rev().map(|entry| entry.id));
+            feed.last_checked = Some(Utc::now());
+            feed
+        }
+    };
+    if !feed.chats.contains(&chat) {
+        feed.chats.push(chat);
+    }
+    storage::set_json(&key(&url), &feed).await?;
+    Ok(feed)
+}
+
+pub async fn unsubscribe(chat: ChatId, url: &str) -> Result<bool, FeedError> {
+    let url = normalize_url(url)?;
+    let Some(mut feed) = feed(&url).await? else {
+        return Ok(false);
+    };
+    let before = feed.chats.len();
+    feed.chats.retain(|c| *c != chat);
+    if feed.chats.is_empty() {
+        storage::delete(&key(&url)).await?;
+    } else {
+        storage::set_json(&key(&url), &feed).await?;
+    }
+    Ok(feed.chats.len() < before)
+}
+
+async fn post_template(chat: ChatId) -> Result<Template, TemplateError> {
+    match templates::resolve(Some(chat), TEMPLATE).await {
+        Err(TemplateError::NotFound(_)) => {
+            Ok(Template::new(DEFAULT_TEMPLATE).parse_mode(ParseMode::Html))
+        }
+        resolved => resolved,
+    }
+}
+
+async fn post(ctx: &PluginContext, feed: &Feed, entry: &FeedEntry) {
+    for chat in &feed.chats {
+        let Ok(template) = post_template(*chat).await else {
+            continue;
+        };
+        let vars = [
+            ("feed", feed.title.clone()),
+            ("title", entry.title.clone()),
+            ("link", entry.link.clone()),
+            ("summary", entry.summary.clone()),
+            ("published", entry.published.clone().unwrap_or_default()),
+        ];
+        if let Ok(params) = template.render(vars) {
+            let _ = response::send_params(ctx, *chat, params).await;
+        }
+    }
+}
+
+async fn check(ctx: &PluginContext, mut feed: Feed) -> Result<usize, FeedError> {
+    feed.last_checked = Some(Utc::now());
+    let fetched = fetch(&mut feed).await;
+    let entries = match fetched {
+        Ok(entries) => entries.unwrap_or_default(),
+        Err(err) => {
+            storage::set_json(&key(&feed.url), &feed).await?;
+            return Err(err);
+        }
+    };
+    let fresh: Vec<&FeedEntry> = entries
+        .iter()
+        .filter(|entry| !feed.seen.contains(&entry.id))
+        .take(POSTS_PER_CHECK)
+        .collect();
+    for entry in fresh.iter().rev() {
+        post(ctx, &feed, entry).await;
+    }
+    let posted = fresh.len();
+    remember(&mut feed, entries.into_iter().rev().map(|entry| entry.id));
+    storage::set_json(&key(&feed.url), &feed).await?;
+    Ok(posted)
+}
+
+// Fetches every feed that's due and posts its new entries. Returns how many entries
+// were posted.
+pub async fn refresh(bot: &Bot) -> Result<usize, StorageError> {
+    let ctx = PluginContext::new(bot.clone(), None, None);
+    let interval = chrono::Duration::from_std(interval()).unwrap_or(chrono::Duration::MAX);
+    let now = Utc::now();
+    let mut posted = 0;
+    for feed in feeds().await? {
+        let due = feed
+            .last_checked
+            .is_none_or(|checked| checked + interval <= now);
+        if due {
+            posted += check(&ctx, feed).await.unwrap_or(0);
+        }
+    }
+    Ok(posted)
+}
+
+pub(crate) async fn migrate(from: ChatId, to: ChatId) -> Result<(), StorageError> {
+    for mut feed in subscriptions(from).await? {
+        for chat in feed.chats.iter_mut().filter(|chat| **chat == from) {
+            *chat = to;
+        }
+        storage::set_json(&key(&feed.url), &feed).await?;
+    }
+    Ok(())
+}
+
+#[crate::TeloxidePlugin(cron = "* * * * *")]
+async fn fetch_feeds(ctx: PluginContext) {
+    let _ = refresh(&ctx.bot).await;
+}
+
+#[crate::TeloxidePlugin(commands = ["sub"], prefixes = ["/"], admin_only = true)]
+async fn sub_command(ctx: PluginContext) -> Option<String> {
+    let Some(url) = ctx.args().first().map(|url| url.to_string()) else {
+        return Some(String::from("Usage: /sub <feed url>"));
+    };
+    Some(match subscribe(ctx.chat_id()?, &url).await {
+        Ok(feed) => format!("Subscribed to {}.", feed.title),
+        Err(err) => format!("Couldn't subscribe: {}", err),
+    })
+}
+
+#[crate::TeloxidePlugin(commands = ["unsub"], prefixes = ["/"], admin_only = true)]
+async fn unsub_command(ctx: PluginContext) -> Option<String> {
+    let chat = ctx.chat_id()?;
+    let Some(arg) = ctx.args().first().map(|arg| arg.to_string()) else {
+        return Some(String::from(
+            "Usage: /unsub <feed url or number from /feeds>",
+        ));
+    };
+    let url = match arg.parse::<usize>() {
+        Ok(number) => {
+            let feeds = subscriptions(chat).await.ok()?;
+            match number.checked_sub(1).and_then(|i| feeds.get(i)) {
+                Some(feed) => feed.url.clone(),
+                None => return Some(format!("There's no feed #{}.", number)),
+            }
+        }
+        Err(_) => arg,
+    };
+    Some(match unsubscribe(chat, &url).await {
+        Ok(true) => String::from("Unsubscribed."),
+        Ok(false) => String::from("This chat isn't subscribed to that feed."),
+        Err(err) => format!("Couldn't unsubscribe: {}", err),
+    })
+}
+
+#[crate::TeloxidePlugin(commands = ["feeds"], prefixes = ["/"])]
+async fn feeds_command(ctx: PluginContext) -> Option<String> {
+    let feeds = subscriptions(ctx.chat_id()?).await.ok()?;
+    if feeds.is_empty() {
+        return Some(String::from("This chat has no feed subscriptions."));
+    }
+    let list: Vec<String> = feeds
+        .iter()
+        .enumerate()
+        .map(|(i, feed)| format!("{}. {} ({})", i + 1, feed.title, feed.url))
+        .collect();
+    Some(format!("Feeds in this chat:\n{}", list.join("\n")))
+}
+
+#[crate::TeloxidePlugin(commands = ["feedtemplate"], prefixes = ["/"], admin_only = true)]
+async fn feed_template_command(ctx: PluginContext) -> Option<String> {
+    let chat = ctx.chat_id()?;
+    let text = crate::context::split_command(ctx.text_or_caption()?)
+        .1
+        .trim();
+    if text.is_empty() {
+        return Some(String::from(
+            "Usage: /feedtemplate <html>, with {feed}, {title}, {link}, {summary} and {published}, or /feedtemplate reset",
+        ));
+    }
+    if text == "reset" {
+        return Some(match templates::remove_override(chat, TEMPLATE).await {
+            Ok(()) => String::from("Feed posts use the default format again."),
+            Err(err) => format!("Couldn't reset the format: {}", err),
+        });
+    }
+    let template = Template::new(text).parse_mode(ParseMode::Html);
+    Some(
+        match templates::set_override(chat, TEMPLATE, &template).await {
+            Ok(()) => String::from("Saved the format for feed posts."),
+            Err(err) => format!("Couldn't save the format: {}", err),
+        },
+    )
+}
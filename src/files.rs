@@ -0,0 +1,139 @@
+use crate::context::PluginContext;
+use crate::traffic;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use teloxide::net::Download;
+use teloxide::prelude::*;
+use teloxide::types::{FileId, Message};
+
+pub const MEDIA_KINDS: &[&str] = &[
+    "photo",
+    "video",
+    "animation",
+    "audio",
+    "voice",
+    "video_note",
+    "document",
+    "sticker",
+];
+
+static LOCAL_MODE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_local_mode(enabled: bool) {
+    LOCAL_MODE.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_local_mode() -> bool {
+    LOCAL_MODE.load(Ordering::Relaxed)
+}
+
+#[derive(Debug)]
+pub enum FileError {
+    Request(teloxide::RequestError),
+    Download(teloxide::DownloadError),
+    Io(io::Error),
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileError::Request(err) => write!(f, "{}", err),
+            FileError::Download(err) => write!(f, "{}", err),
+            FileError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for FileError {}
+
+impl From<teloxide::RequestError> for FileError {
+    fn from(err: teloxide::RequestError) -> Self {
+        FileError::Request(err)
+    }
+}
+
+impl From<teloxide::DownloadError> for FileError {
+    fn from(err: teloxide::DownloadError) -> Self {
+        FileError::Download(err)
+    }
+}
+
+impl From<io::Error> for FileError {
+    fn from(err: io::Error) -> Self {
+        FileError::Io(err)
+    }
+}
+
+// The `media = [..]` kind of a message. Animations are also sent as documents, so
+// they're checked first.
+pub fn media_kind(msg: &Message) -> Option<&'static str> {
+    if msg.animation().is_some() {
+        Some("animation")
+    } else if msg.photo().is_some() {
+        Some("photo")
+    } else if msg.video().is_some() {
+        Some("video")
+    } else if msg.audio().is_some() {
+        Some("audio")
+    } else if msg.voice().is_some() {
+        Some("voice")
+    } else if msg.video_note().is_some() {
+        Some("video_note")
+    } else if msg.document().is_some() {
+        Some("document")
+    } else if msg.sticker().is_some() {
+        Some("sticker")
+    } else {
+        None
+    }
+}
+
+// A local Bot API server in `--local` mode hands out absolute paths on its own disk
+// instead of paths relative to the file download endpoint.
+fn local_path(path: &str) -> Option<&Path> {
+    let path = Path::new(path);
+    (is_local_mode() && path.is_absolute()).then_some(path)
+}
+
+pub async fn download(bot: &Bot, file_id: FileId) -> Result<Vec<u8>, FileError> {
+    let file = traffic::send(bot.get_file(file_id)).await?;
+    if let Some(path) = local_path(&file.path) {
+        return Ok(tokio::fs::read(path).await?);
+    }
+
+    let mut buf = Vec::with_capacity(file.meta.size as usize);
+    bot.download_file(&file.path, &mut buf).await?;
+    Ok(buf)
+}
+
+pub async fn download_to(
+    bot: &Bot,
+    file_id: FileId,
+    destination: impl AsRef<Path>,
+) -> Result<(), FileError> {
+    let file = traffic::send(bot.get_file(file_id)).await?;
+    if let Some(path) = local_path(&file.path) {
+        tokio::fs::copy(path, destination).await?;
+        return Ok(());
+    }
+
+    let mut dst = tokio::fs::File::create(destination).await?;
+    bot.download_file(&file.path, &mut dst).await?;
+    Ok(())
+}
+
+impl PluginContext {
+    pub async fn download_file(&self, file_id: FileId) -> Result<Vec<u8>, FileError> {
+        download(&self.bot, file_id).await
+    }
+
+    pub async fn download_file_to(
+        &self,
+        file_id: FileId,
+        destination: impl AsRef<Path>,
+    ) -> Result<(), FileError> {
+        download_to(&self.bot, file_id, destination).await
+    }
+}
@@ -0,0 +1,226 @@
+use crate::budget;
+use crate::context::{reply_of, split_command, PluginContext};
+use crate::logging::Outcome;
+use crate::plugin::Triggers;
+use crate::registry::{self, PluginMeta};
+use crate::response;
+use crate::settings;
+use crate::storage::StorageError;
+use crate::templates::Template;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, RwLock};
+use teloxide::prelude::*;
+use teloxide::types::{Message, ParseMode};
+use teloxide::utils::render::RenderMessageTextHelper;
+
+const PREFIX: &str = "filter:";
+
+static PRIORITY: AtomicI32 = AtomicI32::new(0);
+
+type Matchers = Arc<Vec<(String, Regex)>>;
+
+static MATCHERS: Lazy<RwLock<HashMap<ChatId, Matchers>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+// Filters aren't in the registry, since they match per chat at runtime, but running
+// them through a plugin entry gives them the same checks, budgets and logs.
+static META: Lazy<&'static PluginMeta> = Lazy::new(|| {
+    Triggers::new()
+        .name("filters")
+        .regex(".")
+        .into_meta("filters", handle)
+        .expect("the filters plugin has a valid trigger")
+});
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FilterMatch {
+    pub keyword: String,
+}
+
+// Filters answer after every plugin with at least this priority, and before the rest.
+pub fn set_priority(priority: i32) {
+    PRIORITY.store(priority, Ordering::Relaxed);
+}
+
+pub fn priority() -> i32 {
+    PRIORITY.load(Ordering::Relaxed)
+}
+
+fn setting_name(keyword: &str) -> String {
+    format!("{}{}", PREFIX, keyword)
+}
+
+pub fn normalize_keyword(keyword: &str) -> String {
+    keyword
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+fn matcher(keyword: &str) -> Option<Regex> {
+    Regex::new(&format!(
+        r"(?i)(?:^|\W){}(?:$|\W)",
+        regex::escape(keyword).replace(' ', r"\s+")
+    ))
+    .ok()
+}
+
+pub(crate) fn invalidate() {
+    MATCHERS.write().unwrap().clear();
+}
+
+pub async fn add(chat: ChatId, keyword: &str, reply: &Template) -> Result<(), StorageError> {
+    settings::set(chat, &setting_name(&normalize_keyword(keyword)), reply).await?;
+    MATCHERS.write().unwrap().remove(&chat);
+    Ok(())
+}
+
+pub async fn remove(chat: ChatId, keyword: &str) -> Result<bool, StorageError> {
+    let name = setting_name(&normalize_keyword(keyword));
+    let existed = settings::get::<Template>(chat, &name).await?.is_some();
+    settings::remove(chat, &name).await?;
+    MATCHERS.write().unwrap().remove(&chat);
+    Ok(existed)
+}
+
+pub async fn get(chat: ChatId, keyword: &str) -> Result<Option<Template>, StorageError> {
+    settings::get(chat, &setting_name(&normalize_keyword(keyword))).await
+}
+
+pub async fn keywords(chat: ChatId) -> Result<Vec<String>, StorageError> {
+    let mut keywords: Vec<String> = settings::names(chat)
+        .await?
+        .into_iter()
+        .filter_map(|name| name.strip_prefix(PREFIX).map(str::to_string))
+        .collect();
+    keywords.sort();
+    Ok(keywords)
+}
+
+// Longer keywords are tried first, so `good morning` wins over `morning`.
+async fn matchers(chat: ChatId) -> Result<Matchers, StorageError> {
+    if let Some(cached) = MATCHERS.read().unwrap().get(&chat) {
+        return Ok(cached.clone());
+    }
+    let mut compiled: Vec<(String, Regex)> = keywords(chat)
+        .await?
+        .into_iter()
+        .filter_map(|keyword| matcher(&keyword).map(|regex| (keyword, regex)))
+        .collect();
+    compiled.sort_by_key(|(keyword, _)| std::cmp::Reverse(keyword.len()));
+    let compiled = Arc::new(compiled);
+    MATCHERS.write().unwrap().insert(chat, compiled.clone());
+    Ok(compiled)
+}
+
+pub(crate) async fn route(ctx: &PluginContext) -> Option<(&'static PluginMeta, Outcome)> {
+    if !registry::passes_filters(*META, ctx) || budget::suspended_for(META.name).is_some() {
+        return None;
+    }
+    let chat = ctx.chat_id()?;
+    let text = ctx.text_or_caption()?;
+    let matchers = matchers(chat).await.ok()?;
+    let (keyword, _) = matchers.iter().find(|(_, regex)| regex.is_match(text))?;
+    ctx.extensions.insert(FilterMatch {
+        keyword: keyword.clone(),
+    });
+    Some((*META, registry::run_plugin(*META, ctx).await))
+}
+
+fn handle(ctx: PluginContext) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+    Box::pin(async move {
+        let (Some(chat), Some(matched)) = (ctx.chat_id(), ctx.extensions.get::<FilterMatch>())
+        else {
+            return;
+        };
+        let Ok(Some(reply)) = get(chat, &matched.keyword).await else {
+            return;
+        };
+        let first = ctx
+            .sender()
+            .map(|u| u.first_name.clone())
+            .unwrap_or_default();
+        let title = ctx
+            .message
+            .as_ref()
+            .and_then(|msg| msg.chat.title().map(str::to_string))
+            .unwrap_or_default();
+        let rendered = reply.render([("first", first), ("chat", title)]);
+        match rendered {
+            Ok(params) => {
+                let _ = response::respond(&ctx, params.reply()).await;
+            }
+            Err(err) => {
+                let _ = response::respond(&ctx, format!("This filter is broken: {}", err)).await;
+            }
+        }
+    })
+}
+
+fn unescape_html(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&amp;", "&")
+}
+
+// Splits `/filter "good morning" <b>Hi</b>` into the keyword and the HTML reply. A reply
+// to a message without its own reply text uses that message's text.
+fn parse_filter(msg: &Message) -> Option<(String, String)> {
+    let html = msg.html_text()?;
+    let (_, rest) = split_command(&html);
+    let (keyword, reply) = match rest.strip_prefix('"') {
+        Some(quoted) => quoted.split_once('"')?,
+        None => rest.split_once(char::is_whitespace).unwrap_or((rest, "")),
+    };
+    let keyword = normalize_keyword(&unescape_html(keyword));
+    let reply = match reply.trim() {
+        "" => reply_of(msg)
+            .and_then(|replied| replied.html_text().or_else(|| replied.html_caption()))?,
+        reply => reply.to_string(),
+    };
+    (!keyword.is_empty() && !reply.is_empty()).then_some((keyword, reply))
+}
+
+#[crate::TeloxidePlugin(commands = ["filter"], prefixes = ["/"], admin_only = true)]
+async fn filter_command(ctx: PluginContext) -> Option<String> {
+    let Some((keyword, reply)) = parse_filter(ctx.message.as_ref()?) else {
+        return Some(String::from(
+            "Usage: /filter <keyword> <reply>, or reply to a message with /filter <keyword>",
+        ));
+    };
+    let template = Template::new(reply).parse_mode(ParseMode::Html);
+    Some(match add(ctx.chat_id()?, &keyword, &template).await {
+        Ok(()) => format!("Saved the filter for \"{}\".", keyword),
+        Err(err) => format!("Couldn't save the filter: {}", err),
+    })
+}
+
+#[crate::TeloxidePlugin(commands = ["stop"], prefixes = ["/"], admin_only = true)]
+async fn stop_command(ctx: PluginContext) -> Option<String> {
+    let keyword = normalize_keyword(&ctx.args().join(" ").replace('"', ""));
+    if keyword.is_empty() {
+        return Some(String::from("Usage: /stop <keyword>"));
+    }
+    Some(match remove(ctx.chat_id()?, &keyword).await {
+        Ok(true) => format!("Stopped the filter for \"{}\".", keyword),
+        Ok(false) => format!("There's no filter for \"{}\".", keyword),
+        Err(err) => format!("Couldn't remove the filter: {}", err),
+    })
+}
+
+#[crate::TeloxidePlugin(commands = ["filters"], prefixes = ["/"])]
+async fn filters_command(ctx: PluginContext) -> Option<String> {
+    let keywords = keywords(ctx.chat_id()?).await.ok()?;
+    if keywords.is_empty() {
+        return Some(String::from("No filters in this chat."));
+    }
+    let list: Vec<String> = keywords.iter().map(|k| format!("- {}", k)).collect();
+    Some(format!("Filters in this chat:\n{}", list.join("\n")))
+}
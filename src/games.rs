@@ -0,0 +1,167 @@
+use crate::context::PluginContext;
+use crate::traffic;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::fmt;
+use std::sync::Arc;
+use teloxide::prelude::*;
+use teloxide::types::{GameHighScore, TargetMessage};
+use teloxide::ApiError;
+use url::Url;
+
+#[derive(Debug)]
+pub enum GameError {
+    NoGame,
+    Request(teloxide::RequestError),
+}
+
+impl fmt::Display for GameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GameError::NoGame => write!(f, "this update doesn't come from a game message"),
+            GameError::Request(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for GameError {}
+
+impl From<teloxide::RequestError> for GameError {
+    fn from(err: teloxide::RequestError) -> Self {
+        GameError::Request(err)
+    }
+}
+
+impl PluginContext {
+    pub fn game_short_name(&self) -> Option<&str> {
+        self.callback_query
+            .as_ref()
+            .and_then(|cq| cq.game_short_name.as_deref())
+    }
+
+    fn game_message(&self) -> Option<TargetMessage> {
+        let cq = self.callback_query.as_ref()?;
+        if let Some(id) = &cq.inline_message_id {
+            return Some(TargetMessage::Inline {
+                inline_message_id: id.clone(),
+            });
+        }
+        cq.message.as_ref().map(|m| TargetMessage::Common {
+            chat_id: m.chat().id.into(),
+            message_id: m.id(),
+        })
+    }
+
+    pub async fn open_game(&self, url: Url) -> Result<(), GameError> {
+        let cq = self.callback_query.as_ref().ok_or(GameError::NoGame)?;
+        self.call(self.bot.answer_callback_query(cq.id.clone()).url(url))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_game_score(&self, score: u64) -> Result<(), GameError> {
+        let user_id = self.sender().ok_or(GameError::NoGame)?.id;
+        self.set_game_score_for(user_id, score, false).await
+    }
+
+    pub async fn set_game_score_for(
+        &self,
+        user_id: UserId,
+        score: u64,
+        force: bool,
+    ) -> Result<(), GameError> {
+        let cq = self.callback_query.as_ref().ok_or(GameError::NoGame)?;
+        if let Some(inline_message_id) = &cq.inline_message_id {
+            self.call(
+                self.bot
+                    .set_game_score_inline(user_id, score, inline_message_id.clone())
+                    .force(force),
+            )
+            .await?;
+            return Ok(());
+        }
+
+        let msg = cq.message.as_ref().ok_or(GameError::NoGame)?;
+        // teloxide types `chat_id` as `u32` here, which leaves out every group, so this
+        // call is made by hand too.
+        let body = serde_json::json!({
+            "user_id": user_id,
+            "score": score,
+            "force": force,
+            "chat_id": msg.chat().id,
+            "message_id": msg.id().0,
+        });
+        traffic::record("SetGameScore");
+        raw_call::<serde_json::Value>(&self.bot, "setGameScore", body).await?;
+        Ok(())
+    }
+
+    pub async fn game_high_scores(&self) -> Result<Vec<GameHighScore>, GameError> {
+        let user_id = self.sender().ok_or(GameError::NoGame)?.id;
+        let target = self.game_message().ok_or(GameError::NoGame)?;
+        Ok(fetch_high_scores(&self.bot, user_id, target).await?)
+    }
+}
+
+#[derive(Deserialize)]
+struct RawResponse<T> {
+    ok: bool,
+    result: Option<T>,
+    description: Option<String>,
+}
+
+// teloxide types the result of getGameHighScores as `True`, so the call is made by hand.
+async fn fetch_high_scores(
+    bot: &Bot,
+    user_id: UserId,
+    target: TargetMessage,
+) -> Result<Vec<GameHighScore>, teloxide::RequestError> {
+    let mut body = serde_json::json!({ "user_id": user_id });
+    match target {
+        TargetMessage::Common {
+            chat_id,
+            message_id,
+        } => {
+            body["chat_id"] = serde_json::json!(chat_id);
+            body["message_id"] = serde_json::json!(message_id.0);
+        }
+        TargetMessage::Inline { inline_message_id } => {
+            body["inline_message_id"] = serde_json::json!(inline_message_id);
+        }
+    }
+    traffic::record("GetGameHighScores");
+    raw_call(bot, "getGameHighScores", body).await
+}
+
+async fn raw_call<T: DeserializeOwned>(
+    bot: &Bot,
+    method: &'static str,
+    body: serde_json::Value,
+) -> Result<T, teloxide::RequestError> {
+    // The method goes after the api url's own path, the way teloxide builds its urls.
+    let mut url = bot.api_url();
+    if let Ok(mut segments) = url.path_segments_mut() {
+        segments.pop_if_empty();
+        segments.push(&format!("bot{}", bot.token()));
+        segments.push(method);
+    }
+    let raw = bot
+        .client()
+        .post(url)
+        .header("Content-Type", "application/json")
+        .body(body.to_string())
+        .send()
+        .await?
+        .text()
+        .await?;
+
+    let response: RawResponse<T> =
+        serde_json::from_str(&raw).map_err(|err| teloxide::RequestError::InvalidJson {
+            source: Arc::new(err),
+            raw: raw.clone().into_boxed_str(),
+        })?;
+    match (response.ok, response.result) {
+        (true, Some(result)) => Ok(result),
+        _ => Err(ApiError::Unknown(response.description.unwrap_or_default()).into()),
+    }
+}
@@ -0,0 +1,34 @@
+use crate::context::PluginContext;
+use crate::texts::{self, FrameworkTexts};
+
+pub fn help(command: &str, subcommands: &[(&str, &str)]) -> String {
+    help_in(&texts::texts(), command, subcommands)
+}
+
+// `help` with the usage line from the given texts, as for the sender's language.
+pub fn help_in(texts: &FrameworkTexts, command: &str, subcommands: &[(&str, &str)]) -> String {
+    let names: Vec<&str> = subcommands.iter().map(|(name, _)| *name).collect();
+    let mut help = texts::fill(
+        &texts.usage,
+        &[("command", command), ("subcommands", &names.join("|"))],
+    );
+    for (name, description) in subcommands {
+        help.push('\n');
+        help.push_str(&format!("{} {}", command, name));
+        if !description.is_empty() {
+            help.push_str(" — ");
+            help.push_str(description);
+        }
+    }
+    help
+}
+
+impl PluginContext {
+    pub fn subcommand(&self) -> Option<&str> {
+        self.args().into_iter().next()
+    }
+
+    pub fn subcommand_args(&self) -> Vec<&str> {
+        self.args().into_iter().skip(1).collect()
+    }
+}
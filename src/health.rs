@@ -0,0 +1,183 @@
+use crate::budget;
+use crate::external::{read_request, READ_TIMEOUT};
+use crate::queue::{DispatchQueue, QueueMetrics};
+use crate::registry;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::sync::RwLock;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, ToSocketAddrs};
+
+type MetricsProbe = Box<dyn Fn() -> Option<QueueMetrics> + Send + Sync>;
+
+static LAST_UPDATE_MS: AtomicI64 = AtomicI64::new(0);
+static RUNNING: AtomicBool = AtomicBool::new(false);
+static QUEUE: RwLock<Option<MetricsProbe>> = RwLock::new(None);
+static STALE_AFTER: RwLock<Option<Duration>> = RwLock::new(None);
+
+#[derive(Clone, Debug, Serialize)]
+pub struct HealthReport {
+    pub healthy: bool,
+    pub running: bool,
+    pub last_update: Option<DateTime<Utc>>,
+    pub seconds_since_update: Option<i64>,
+    pub queue_depth: Option<usize>,
+    pub queue_dropped: Option<u64>,
+    pub plugins: Vec<PluginHealth>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PluginHealth {
+    pub name: &'static str,
+    pub enabled: bool,
+    pub suspended: bool,
+    pub retry_in: Option<u64>,
+}
+
+pub fn set_running(running: bool) {
+    RUNNING.store(running, Ordering::SeqCst);
+}
+
+pub fn watch_queue(queue: &DispatchQueue) {
+    *QUEUE.write().unwrap() = Some(Box::new(queue.metrics_probe()));
+}
+
+pub fn set_stale_after(limit: Option<Duration>) {
+    *STALE_AFTER.write().unwrap() = limit;
+}
+
+pub(crate) fn record_update() {
+    LAST_UPDATE_MS.store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+}
+
+pub fn last_update() -> Option<DateTime<Utc>> {
+    match LAST_UPDATE_MS.load(Ordering::Relaxed) {
+        0 => None,
+        ms => DateTime::from_timestamp_millis(ms),
+    }
+}
+
+pub fn report() -> HealthReport {
+    let running = RUNNING.load(Ordering::SeqCst);
+    let last_update = last_update();
+    let since = last_update.map(|at| (Utc::now() - at).num_seconds().max(0));
+    let stale = match (*STALE_AFTER.read().unwrap(), since) {
+        (Some(limit), Some(since)) => since as u64 > limit.as_secs(),
+        _ => false,
+    };
+    let metrics = QUEUE.read().unwrap().as_ref().and_then(|probe| probe());
+
+    let plugins = registry::plugins()
+        .into_iter()
+        .map(|plugin| {
+            let suspended = budget::suspended_for(plugin.name);
+            PluginHealth {
+                name: plugin.name,
+                enabled: registry::is_enabled(plugin.name),
+                suspended: suspended.is_some(),
+                retry_in: suspended.map(|left| left.as_secs()),
+            }
+        })
+        .collect();
+
+    HealthReport {
+        healthy: running && !stale,
+        running,
+        last_update,
+        seconds_since_update: since,
+        queue_depth: metrics.map(|m| m.depth),
+        queue_dropped: metrics.map(|m| m.dropped),
+        plugins,
+    }
+}
+
+fn respond(path: &str) -> (&'static str, String) {
+    let path = path.split_once('?').map_or(path, |(path, _)| path);
+    match path {
+        "/" | "/health" | "/healthz" => {
+            let report = report();
+            let status = if report.healthy {
+                "200 OK"
+            } else {
+                "503 Service Unavailable"
+            };
+            (status, serde_json::to_string(&report).unwrap_or_default())
+        }
+        _ => ("404 Not Found", String::from("{}")),
+    }
+}
+
+pub async fn serve(addr: impl ToSocketAddrs) -> std::io::Result<()> {
+    serve_listener(TcpListener::bind(addr).await?).await;
+    Ok(())
+}
+
+pub async fn serve_listener(listener: TcpListener) {
+    loop {
+        let Ok((mut stream, _)) = listener.accept().await else {
+            continue;
+        };
+        tokio::spawn(async move {
+            let (status, body) =
+                match tokio::time::timeout(READ_TIMEOUT, read_request(&mut stream)).await {
+                    Ok(Ok(request)) => respond(&request.path),
+                    Ok(Err(status)) => (status, String::from("{}")),
+                    Err(_) => ("408 Request Timeout", String::from("{}")),
+                };
+            let response = format!(
+                "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+            let _ = stream.shutdown().await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpStream;
+
+    async fn server() -> std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve_listener(listener));
+        addr
+    }
+
+    #[tokio::test]
+    async fn answers_a_request_that_arrives_in_pieces() {
+        let mut stream = TcpStream::connect(server().await).await.unwrap();
+        stream.write_all(b"GET /heal").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        stream.write_all(b"thz HTTP/1.1\r\n\r\n").await.unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let body = response.split("\r\n\r\n").nth(1).unwrap();
+        let report: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert!(report["plugins"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|plugin| plugin["suspended"].is_boolean()));
+    }
+
+    #[tokio::test]
+    async fn other_paths_are_not_found() {
+        let mut stream = TcpStream::connect(server().await).await.unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 404"));
+    }
+}
@@ -0,0 +1,130 @@
+use crate::context::PluginContext;
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::RwLock;
+use teloxide::prelude::*;
+
+// Off until `set_capacity` is called, since it keeps message contents in memory.
+static CAPACITY: AtomicUsize = AtomicUsize::new(0);
+
+static RECENT: Lazy<RwLock<HashMap<ChatId, VecDeque<Message>>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+// Keeps the last `capacity` messages of every chat; 0 turns it off and forgets them.
+pub fn set_capacity(capacity: usize) {
+    CAPACITY.store(capacity, Ordering::Relaxed);
+    let mut chats = RECENT.write().unwrap();
+    if capacity == 0 {
+        chats.clear();
+    }
+    for messages in chats.values_mut() {
+        while messages.len() > capacity {
+            messages.pop_front();
+        }
+    }
+}
+
+pub fn capacity() -> usize {
+    CAPACITY.load(Ordering::Relaxed)
+}
+
+// Edits replace the copy that's already kept, so the history shows the final text.
+pub(crate) fn record(msg: &Message) {
+    let capacity = capacity();
+    if capacity == 0 {
+        return;
+    }
+    let mut chats = RECENT.write().unwrap();
+    if msg.edit_date().is_some() {
+        if let Some(kept) = chats
+            .get_mut(&msg.chat.id)
+            .and_then(|messages| messages.iter_mut().find(|m| m.id == msg.id))
+        {
+            *kept = msg.clone();
+        }
+        return;
+    }
+    let messages = chats.entry(msg.chat.id).or_default();
+    if messages.len() >= capacity {
+        messages.pop_front();
+    }
+    messages.push_back(msg.clone());
+}
+
+pub(crate) fn migrate(from: ChatId, to: ChatId) {
+    let mut chats = RECENT.write().unwrap();
+    if let Some(messages) = chats.remove(&from) {
+        chats.insert(to, messages);
+    }
+}
+
+// The last `n` messages of a chat, oldest first.
+pub fn recent(chat: ChatId, n: usize) -> Vec<Message> {
+    let chats = RECENT.read().unwrap();
+    let Some(messages) = chats.get(&chat) else {
+        return Vec::new();
+    };
+    messages
+        .iter()
+        .skip(messages.len().saturating_sub(n))
+        .cloned()
+        .collect()
+}
+
+pub fn since(chat: ChatId, since: DateTime<Utc>) -> Vec<Message> {
+    let chats = RECENT.read().unwrap();
+    chats
+        .get(&chat)
+        .map(|messages| {
+            messages
+                .iter()
+                .filter(|m| m.date >= since)
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+pub fn clear(chat: ChatId) {
+    RECENT.write().unwrap().remove(&chat);
+}
+
+// One `[HH:MM] Name: text` line per message, for exports or for a summarizing model.
+// Media without a caption shows up as `[media]`.
+pub fn transcript(messages: &[Message]) -> String {
+    messages
+        .iter()
+        .map(|msg| {
+            let author = msg
+                .from
+                .as_ref()
+                .map(|user| user.full_name())
+                .or_else(|| {
+                    msg.sender_chat
+                        .as_ref()
+                        .and_then(|c| c.title().map(str::to_string))
+                })
+                .unwrap_or_else(|| String::from("unknown"));
+            let text = msg.text().or_else(|| msg.caption()).unwrap_or("[media]");
+            format!("[{}] {}: {}", msg.date.format("%H:%M"), author, text)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+impl PluginContext {
+    // The chat's recent messages before the one being handled, oldest first.
+    pub fn recent_messages(&self, n: usize) -> Vec<Message> {
+        let Some(chat) = self.chat_id() else {
+            return Vec::new();
+        };
+        let current = self.message.as_ref().map(|m| m.id);
+        let mut messages = recent(chat, n + 1);
+        messages.retain(|m| Some(m.id) != current);
+        let extra = messages.len().saturating_sub(n);
+        messages.drain(..extra);
+        messages
+    }
+}
@@ -0,0 +1,312 @@
+use crate::context::PluginContext;
+use crate::traffic;
+use once_cell::sync::Lazy;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(15);
+
+type HttpHook = Arc<dyn Fn(&HttpEvent) + Send + Sync>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HttpQuota {
+    pub requests: u32,
+    pub per: Duration,
+}
+
+impl HttpQuota {
+    pub fn new(requests: u32, per: Duration) -> Self {
+        Self { requests, per }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HttpStats {
+    pub requests: u64,
+    pub failures: u64,
+    pub throttled: u64,
+    pub time: Duration,
+}
+
+// What the hook sees for every request, including throttled ones (`status` and
+// `elapsed` are `None` for those, since nothing was sent).
+#[derive(Clone, Debug)]
+pub struct HttpEvent {
+    pub plugin: Option<&'static str>,
+    pub method: reqwest::Method,
+    pub url: String,
+    pub status: Option<reqwest::StatusCode>,
+    pub elapsed: Option<Duration>,
+}
+
+#[derive(Debug)]
+pub enum HttpError {
+    Throttled(Duration),
+    Request(reqwest::Error),
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpError::Throttled(wait) => {
+                write!(
+                    f,
+                    "http quota exhausted, try again in {}s",
+                    wait.as_secs() + 1
+                )
+            }
+            HttpError::Request(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for HttpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HttpError::Request(err) => Some(err),
+            HttpError::Throttled(_) => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for HttpError {
+    fn from(err: reqwest::Error) -> Self {
+        HttpError::Request(err)
+    }
+}
+
+static CLIENT: Lazy<RwLock<reqwest::Client>> = Lazy::new(|| RwLock::new(build(DEFAULT_TIMEOUT)));
+static DEFAULT_QUOTA: RwLock<Option<HttpQuota>> = RwLock::new(None);
+static QUOTAS: Lazy<RwLock<HashMap<&'static str, HttpQuota>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+static WINDOWS: Lazy<Mutex<HashMap<&'static str, VecDeque<Instant>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+static STATS: Lazy<Mutex<HashMap<Option<&'static str>, HttpStats>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+static HOOK: RwLock<Option<HttpHook>> = RwLock::new(None);
+
+fn build(timeout: Duration) -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(timeout)
+        .user_agent(concat!("teloxide-plugins/", env!("CARGO_PKG_VERSION")))
+        .build()
+        .unwrap_or_default()
+}
+
+pub fn client() -> reqwest::Client {
+    CLIENT.read().unwrap().clone()
+}
+
+// Replaces the shared client, for a proxy or custom TLS. Its own timeout applies.
+pub fn set_client(client: reqwest::Client) {
+    *CLIENT.write().unwrap() = client;
+}
+
+pub fn set_timeout(timeout: Duration) {
+    *CLIENT.write().unwrap() = build(timeout);
+}
+
+pub fn set_default_quota(quota: Option<HttpQuota>) {
+    *DEFAULT_QUOTA.write().unwrap() = quota;
+}
+
+pub fn set_quota(plugin: &'static str, quota: HttpQuota) {
+    QUOTAS.write().unwrap().insert(plugin, quota);
+}
+
+pub fn set_request_hook(hook: impl Fn(&HttpEvent) + Send + Sync + 'static) {
+    *HOOK.write().unwrap() = Some(Arc::new(hook));
+}
+
+pub fn stats(plugin: &'static str) -> HttpStats {
+    STATS
+        .lock()
+        .unwrap()
+        .get(&Some(plugin))
+        .cloned()
+        .unwrap_or_default()
+}
+
+pub fn all() -> Vec<(&'static str, HttpStats)> {
+    let mut all: Vec<_> = STATS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|(plugin, stats)| Some(((*plugin)?, stats.clone())))
+        .collect();
+    all.sort_by_key(|(plugin, _)| *plugin);
+    all
+}
+
+fn quota_of(plugin: &'static str) -> Option<HttpQuota> {
+    QUOTAS
+        .read()
+        .unwrap()
+        .get(plugin)
+        .copied()
+        .or(*DEFAULT_QUOTA.read().unwrap())
+}
+
+// A sliding window: a request is allowed when fewer than `requests` were sent during
+// the last `per`. Otherwise the error says when the oldest one leaves the window.
+fn acquire(plugin: &'static str) -> Result<(), Duration> {
+    let Some(quota) = quota_of(plugin) else {
+        return Ok(());
+    };
+    let now = Instant::now();
+    let mut windows = WINDOWS.lock().unwrap();
+    let window = windows.entry(plugin).or_default();
+    while window
+        .front()
+        .is_some_and(|sent| now.duration_since(*sent) >= quota.per)
+    {
+        window.pop_front();
+    }
+    if window.len() >= quota.requests as usize {
+        let oldest = window.front().copied().unwrap_or(now);
+        return Err(quota.per.saturating_sub(now.duration_since(oldest)));
+    }
+    window.push_back(now);
+    Ok(())
+}
+
+fn record(event: &HttpEvent, failed: bool) {
+    {
+        let mut stats = STATS.lock().unwrap();
+        let entry = stats.entry(event.plugin).or_default();
+        match event.elapsed {
+            Some(elapsed) => {
+                entry.requests += 1;
+                entry.time += elapsed;
+                entry.failures += u64::from(failed);
+            }
+            None => entry.throttled += 1,
+        }
+    }
+    if let Some(hook) = HOOK.read().unwrap().clone() {
+        hook(event);
+    }
+}
+
+pub struct HttpRequest {
+    plugin: Option<&'static str>,
+    builder: reqwest::RequestBuilder,
+}
+
+impl HttpRequest {
+    pub fn header(self, name: &str, value: &str) -> Self {
+        self.with(|builder| builder.header(name, value))
+    }
+
+    pub fn bearer_auth(self, token: impl fmt::Display) -> Self {
+        self.with(|builder| builder.bearer_auth(token))
+    }
+
+    pub fn query<T: Serialize + ?Sized>(self, query: &T) -> Self {
+        self.with(|builder| builder.query(query))
+    }
+
+    pub fn json<T: Serialize + ?Sized>(self, body: &T) -> Self {
+        self.with(|builder| builder.json(body))
+    }
+
+    pub fn form<T: Serialize + ?Sized>(self, form: &T) -> Self {
+        self.with(|builder| builder.form(form))
+    }
+
+    pub fn timeout(self, timeout: Duration) -> Self {
+        self.with(|builder| builder.timeout(timeout))
+    }
+
+    // For anything else reqwest supports.
+    pub fn with(
+        mut self,
+        f: impl FnOnce(reqwest::RequestBuilder) -> reqwest::RequestBuilder,
+    ) -> Self {
+        self.builder = f(self.builder);
+        self
+    }
+
+    pub async fn send(self) -> Result<reqwest::Response, HttpError> {
+        let (client, request) = self.builder.build_split();
+        let request = request?;
+        let mut event = HttpEvent {
+            plugin: self.plugin,
+            method: request.method().clone(),
+            url: request.url().to_string(),
+            status: None,
+            elapsed: None,
+        };
+        if let Some(plugin) = self.plugin {
+            if let Err(wait) = acquire(plugin) {
+                record(&event, true);
+                return Err(HttpError::Throttled(wait));
+            }
+        }
+        let started = Instant::now();
+        let response = client.execute(request).await;
+        event.elapsed = Some(started.elapsed());
+        event.status = response.as_ref().ok().map(|r| r.status());
+        let failed = !response.as_ref().is_ok_and(|r| r.status().is_success());
+        record(&event, failed);
+        Ok(response?)
+    }
+
+    // Sends the request and decodes a successful JSON answer; error statuses fail.
+    pub async fn json_response<T: DeserializeOwned>(self) -> Result<T, HttpError> {
+        Ok(self.send().await?.error_for_status()?.json().await?)
+    }
+
+    pub async fn text(self) -> Result<String, HttpError> {
+        Ok(self.send().await?.error_for_status()?.text().await?)
+    }
+}
+
+// The shared client, attributed to the plugin that's running.
+#[derive(Clone, Copy, Debug)]
+pub struct Http {
+    plugin: Option<&'static str>,
+}
+
+impl Http {
+    pub fn request(&self, method: reqwest::Method, url: impl reqwest::IntoUrl) -> HttpRequest {
+        HttpRequest {
+            plugin: self.plugin,
+            builder: client().request(method, url),
+        }
+    }
+
+    pub fn get(&self, url: impl reqwest::IntoUrl) -> HttpRequest {
+        self.request(reqwest::Method::GET, url)
+    }
+
+    pub fn post(&self, url: impl reqwest::IntoUrl) -> HttpRequest {
+        self.request(reqwest::Method::POST, url)
+    }
+
+    pub fn put(&self, url: impl reqwest::IntoUrl) -> HttpRequest {
+        self.request(reqwest::Method::PUT, url)
+    }
+
+    pub fn delete(&self, url: impl reqwest::IntoUrl) -> HttpRequest {
+        self.request(reqwest::Method::DELETE, url)
+    }
+}
+
+pub fn http() -> Http {
+    Http {
+        plugin: traffic::current_plugin(),
+    }
+}
+
+impl PluginContext {
+    pub fn http(&self) -> Http {
+        Http {
+            plugin: traffic::current_plugin().or(self.plugin.map(|p| p.name)),
+        }
+    }
+}
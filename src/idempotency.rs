@@ -0,0 +1,98 @@
+use crate::context::PluginContext;
+use crate::storage::{self, StorageError};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+const PREFIX: &str = "once:";
+
+static RETENTION_SECS: AtomicU64 = AtomicU64::new(24 * 60 * 60);
+static CLAIM_TIMEOUT_SECS: AtomicU64 = AtomicU64::new(10 * 60);
+
+#[derive(Serialize, Deserialize)]
+struct Record {
+    done: bool,
+    at: i64,
+}
+
+impl Record {
+    fn new(done: bool) -> Self {
+        Self {
+            done,
+            at: chrono::Utc::now().timestamp(),
+        }
+    }
+
+    fn is_live(&self) -> bool {
+        let limit = if self.done {
+            &RETENTION_SECS
+        } else {
+            &CLAIM_TIMEOUT_SECS
+        };
+        let age = chrono::Utc::now().timestamp().saturating_sub(self.at);
+        age < limit.load(Ordering::Relaxed) as i64
+    }
+}
+
+pub fn set_retention(retention: Duration) {
+    RETENTION_SECS.store(retention.as_secs(), Ordering::Relaxed);
+}
+
+pub fn set_claim_timeout(timeout: Duration) {
+    CLAIM_TIMEOUT_SECS.store(timeout.as_secs(), Ordering::Relaxed);
+}
+
+fn key(name: &str) -> String {
+    format!("{}{}", PREFIX, name)
+}
+
+// Unreadable records count as live, so a corrupted entry never lets a side-effect run twice.
+fn is_live(raw: &str) -> bool {
+    serde_json::from_str::<Record>(raw).map_or(true, |record| record.is_live())
+}
+
+async fn claim(key: &str) -> Result<bool, StorageError> {
+    let storage = storage::storage();
+    let existing = storage.get(key).await?;
+    if existing.as_deref().is_some_and(is_live) {
+        return Ok(false);
+    }
+    let claim = serde_json::to_string(&Record::new(false))?;
+    storage
+        .compare_and_swap(key, existing.as_deref(), claim)
+        .await
+}
+
+pub async fn once<F, Fut, T>(name: &str, f: F) -> Result<Option<T>, StorageError>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = T>,
+{
+    let key = key(name);
+    if !claim(&key).await? {
+        return Ok(None);
+    }
+    let value = f().await;
+    storage::set_json(&key, &Record::new(true)).await?;
+    Ok(Some(value))
+}
+
+pub async fn has_run(name: &str) -> Result<bool, StorageError> {
+    let record: Option<Record> = storage::get_json(&key(name)).await?;
+    Ok(record.is_some_and(|record| record.done && record.is_live()))
+}
+
+pub async fn forget(name: &str) -> Result<(), StorageError> {
+    storage::delete(&key(name)).await
+}
+
+impl PluginContext {
+    pub async fn once<F, Fut, T>(&self, key: &str, f: F) -> Result<Option<T>, StorageError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        once(key, f).await
+    }
+}
@@ -0,0 +1,125 @@
+use mime::Mime;
+use teloxide::prelude::*;
+use teloxide::types::{
+    InlineQuery, InlineQueryResult, InlineQueryResultArticle, InlineQueryResultDocument,
+    InlineQueryResultPhoto, InputMessageContent, InputMessageContentText,
+};
+use url::Url;
+
+const MAX_RESULTS: usize = 50;
+
+pub fn article(
+    id: impl Into<String>,
+    title: impl Into<String>,
+    text: impl Into<String>,
+) -> InlineQueryResultArticle {
+    InlineQueryResultArticle::new(
+        id,
+        title,
+        InputMessageContent::Text(InputMessageContentText::new(text)),
+    )
+}
+
+pub fn photo(id: impl Into<String>, photo_url: Url, thumbnail_url: Url) -> InlineQueryResultPhoto {
+    InlineQueryResultPhoto::new(id, photo_url, thumbnail_url)
+}
+
+pub fn document(
+    id: impl Into<String>,
+    title: impl Into<String>,
+    document_url: Url,
+    mime_type: Mime,
+) -> InlineQueryResultDocument {
+    InlineQueryResultDocument {
+        id: id.into(),
+        title: title.into(),
+        caption: None,
+        parse_mode: None,
+        caption_entities: None,
+        document_url,
+        mime_type,
+        description: None,
+        reply_markup: None,
+        input_message_content: None,
+        thumbnail_url: None,
+        thumbnail_width: None,
+        thumbnail_height: None,
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct InlineAnswer {
+    page_size: usize,
+    cache_time: Option<u32>,
+    is_personal: bool,
+}
+
+impl Default for InlineAnswer {
+    fn default() -> Self {
+        Self {
+            page_size: MAX_RESULTS,
+            cache_time: None,
+            is_personal: false,
+        }
+    }
+}
+
+impl InlineAnswer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn page_size(mut self, size: usize) -> Self {
+        self.page_size = size.clamp(1, MAX_RESULTS);
+        self
+    }
+
+    pub fn cache_time(mut self, seconds: u32) -> Self {
+        self.cache_time = Some(seconds);
+        self
+    }
+
+    pub fn personal(mut self) -> Self {
+        self.is_personal = true;
+        self
+    }
+
+    pub async fn send<B, I, R>(
+        &self,
+        bot: &B,
+        query: &InlineQuery,
+        results: I,
+    ) -> Result<(), teloxide::RequestError>
+    where
+        B: Requester<Err = teloxide::RequestError>,
+        I: IntoIterator<Item = R>,
+        R: Into<InlineQueryResult>,
+    {
+        let offset = query.offset.parse::<usize>().unwrap_or(0);
+        let mut page: Vec<InlineQueryResult> = results
+            .into_iter()
+            .skip(offset)
+            .take(self.page_size + 1)
+            .map(Into::into)
+            .collect();
+
+        let next_offset = if page.len() > self.page_size {
+            page.truncate(self.page_size);
+            (offset + self.page_size).to_string()
+        } else {
+            String::new()
+        };
+
+        let mut request = bot
+            .answer_inline_query(query.id.clone(), page)
+            .next_offset(next_offset);
+        if let Some(seconds) = self.cache_time {
+            request = request.cache_time(seconds);
+        }
+        if self.is_personal {
+            request = request.is_personal(true);
+        }
+        request.await?;
+        Ok(())
+    }
+}
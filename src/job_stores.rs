@@ -0,0 +1,223 @@
+// `JobStore`s that keep scheduled messages in a database of their own, for bots whose
+// `storage` backend is in memory. Install one with `scheduler::set_job_store` before
+// `scheduler::start`.
+use crate::scheduler::{Job, JobStore};
+use crate::storage::StorageError;
+use futures::future::BoxFuture;
+#[cfg(feature = "redis-jobs")]
+use redis::{aio::ConnectionManager, AsyncCommands};
+#[cfg(feature = "sqlite-jobs")]
+use rusqlite::{params, Connection};
+#[cfg(any(feature = "sled-jobs", feature = "sqlite-jobs"))]
+use std::path::Path;
+#[cfg(feature = "sqlite-jobs")]
+use std::sync::{Arc, Mutex};
+
+fn backend(err: impl std::error::Error + Send + Sync + 'static) -> StorageError {
+    StorageError::Backend(Box::new(err))
+}
+
+// One key per job id in a sled tree, flushed on every change.
+#[cfg(feature = "sled-jobs")]
+#[derive(Clone)]
+pub struct SledJobStore {
+    tree: sled::Tree,
+}
+
+#[cfg(feature = "sled-jobs")]
+impl SledJobStore {
+    // Opens (or creates) the database at `path` and uses its `jobs` tree.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        let db = sled::open(path).map_err(backend)?;
+        Ok(Self::with_tree(db.open_tree("jobs").map_err(backend)?))
+    }
+
+    // For a database the bot already has open.
+    pub fn with_tree(tree: sled::Tree) -> Self {
+        Self { tree }
+    }
+}
+
+#[cfg(feature = "sled-jobs")]
+impl JobStore for SledJobStore {
+    fn save<'a>(&'a self, job: &'a Job) -> BoxFuture<'a, Result<(), StorageError>> {
+        Box::pin(async move {
+            self.tree
+                .insert(job.id.as_bytes(), serde_json::to_vec(job)?)
+                .map_err(backend)?;
+            self.tree.flush_async().await.map_err(backend)?;
+            Ok(())
+        })
+    }
+
+    fn remove<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<(), StorageError>> {
+        Box::pin(async move {
+            self.tree.remove(id.as_bytes()).map_err(backend)?;
+            self.tree.flush_async().await.map_err(backend)?;
+            Ok(())
+        })
+    }
+
+    fn load_all(&self) -> BoxFuture<'_, Result<Vec<Job>, StorageError>> {
+        Box::pin(async move {
+            let mut jobs = Vec::new();
+            for value in self.tree.iter().values() {
+                jobs.push(serde_json::from_slice(&value.map_err(backend)?)?);
+            }
+            Ok(jobs)
+        })
+    }
+}
+
+#[cfg(feature = "sqlite-jobs")]
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS scheduled_jobs (
+    id TEXT PRIMARY KEY,
+    run_at INTEGER NOT NULL,
+    job TEXT NOT NULL
+)";
+
+// A `scheduled_jobs` table with the job as JSON. Queries run on the blocking
+// pool, so a slow disk doesn't stall the runtime.
+#[cfg(feature = "sqlite-jobs")]
+#[derive(Clone)]
+pub struct SqliteJobStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+#[cfg(feature = "sqlite-jobs")]
+impl SqliteJobStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        Self::with_connection(Connection::open(path).map_err(backend)?)
+    }
+
+    // Creates the table in a connection the bot already has, if it's missing.
+    pub fn with_connection(conn: Connection) -> Result<Self, StorageError> {
+        conn.execute(SCHEMA, []).map_err(backend)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    async fn run<T: Send + 'static>(
+        &self,
+        query: impl FnOnce(&Connection) -> rusqlite::Result<T> + Send + 'static,
+    ) -> Result<T, StorageError> {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || query(&conn.lock().unwrap()))
+            .await
+            .map_err(backend)?
+            .map_err(backend)
+    }
+}
+
+#[cfg(feature = "sqlite-jobs")]
+impl JobStore for SqliteJobStore {
+    fn save<'a>(&'a self, job: &'a Job) -> BoxFuture<'a, Result<(), StorageError>> {
+        Box::pin(async move {
+            let (id, run_at, json) = (
+                job.id.clone(),
+                job.run_at.timestamp(),
+                serde_json::to_string(job)?,
+            );
+            self.run(move |conn| {
+                conn.execute(
+                    "INSERT OR REPLACE INTO scheduled_jobs (id, run_at, job) VALUES (?1, ?2, ?3)",
+                    params![id, run_at, json],
+                )
+            })
+            .await?;
+            Ok(())
+        })
+    }
+
+    fn remove<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<(), StorageError>> {
+        Box::pin(async move {
+            let id = id.to_string();
+            self.run(move |conn| {
+                conn.execute("DELETE FROM scheduled_jobs WHERE id = ?1", params![id])
+            })
+            .await?;
+            Ok(())
+        })
+    }
+
+    fn load_all(&self) -> BoxFuture<'_, Result<Vec<Job>, StorageError>> {
+        Box::pin(async move {
+            let rows = self
+                .run(|conn| {
+                    let mut statement =
+                        conn.prepare("SELECT job FROM scheduled_jobs ORDER BY run_at")?;
+                    let rows = statement.query_map([], |row| row.get::<_, String>(0))?;
+                    rows.collect::<rusqlite::Result<Vec<String>>>()
+                })
+                .await?;
+            let mut jobs = Vec::with_capacity(rows.len());
+            for row in rows {
+                jobs.push(serde_json::from_str(&row)?);
+            }
+            Ok(jobs)
+        })
+    }
+}
+
+// One Redis hash, job id to JSON, so several processes can share the jobs. The
+// connection reconnects on its own after Redis restarts.
+#[cfg(feature = "redis-jobs")]
+#[derive(Clone)]
+pub struct RedisJobStore {
+    conn: ConnectionManager,
+    key: String,
+}
+
+#[cfg(feature = "redis-jobs")]
+impl RedisJobStore {
+    // Takes a `redis://` URL. The hash is `teloxide-plugins:jobs`.
+    pub async fn connect(url: &str) -> Result<Self, StorageError> {
+        let client = redis::Client::open(url).map_err(backend)?;
+        Ok(Self {
+            conn: ConnectionManager::new(client).await.map_err(backend)?,
+            key: String::from("teloxide-plugins:jobs"),
+        })
+    }
+
+    // For bots sharing one Redis.
+    pub fn key(mut self, key: impl Into<String>) -> Self {
+        self.key = key.into();
+        self
+    }
+}
+
+#[cfg(feature = "redis-jobs")]
+impl JobStore for RedisJobStore {
+    fn save<'a>(&'a self, job: &'a Job) -> BoxFuture<'a, Result<(), StorageError>> {
+        Box::pin(async move {
+            let json = serde_json::to_string(job)?;
+            self.conn
+                .clone()
+                .hset::<_, _, _, ()>(&self.key, &job.id, json)
+                .await
+                .map_err(backend)
+        })
+    }
+
+    fn remove<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<(), StorageError>> {
+        Box::pin(async move {
+            self.conn
+                .clone()
+                .hdel::<_, _, ()>(&self.key, id)
+                .await
+                .map_err(backend)
+        })
+    }
+
+    fn load_all(&self) -> BoxFuture<'_, Result<Vec<Job>, StorageError>> {
+        Box::pin(async move {
+            let values: Vec<String> = self.conn.clone().hvals(&self.key).await.map_err(backend)?;
+            let mut jobs = Vec::with_capacity(values.len());
+            for value in values {
+                jobs.push(serde_json::from_str(&value)?);
+            }
+            Ok(jobs)
+        })
+    }
+}
@@ -0,0 +1,222 @@
+use crate::http;
+use crate::transport::{self, TransportError, UpdateTransport};
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use reqwest::StatusCode;
+use serde_json::json;
+use std::time::{Duration, Instant};
+use teloxide::prelude::*;
+use tokio::sync::mpsc;
+use url::Url;
+
+const V2: &str = "application/vnd.kafka.v2+json";
+const JSON_V2: &str = "application/vnd.kafka.json.v2+json";
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+// Talks to Kafka through a Confluent-compatible REST Proxy (API v2) rather than the
+// broker protocol, so nothing beyond HTTP is needed.
+#[derive(Clone)]
+pub struct KafkaTransport {
+    proxy: Result<Url, String>,
+    topic: String,
+    group: String,
+    consumer: String,
+}
+
+impl KafkaTransport {
+    // `proxy` is the REST Proxy's base URL, like `http://localhost:8082`.
+    pub fn new(proxy: &str, topic: impl Into<String>) -> Self {
+        let host = std::env::var("HOSTNAME").unwrap_or_else(|_| String::from("worker"));
+        Self {
+            proxy: Url::parse(proxy)
+                .ok()
+                .filter(|url| matches!(url.scheme(), "http" | "https"))
+                .ok_or_else(|| proxy.to_string()),
+            topic: topic.into(),
+            group: String::from("teloxide-plugins"),
+            consumer: format!("{}-{}", host, std::process::id()),
+        }
+    }
+
+    // Subscribers in the same consumer group split the topic's partitions between
+    // them, and the group resumes from where it last stopped.
+    pub fn group(mut self, group: impl Into<String>) -> Self {
+        self.group = group.into();
+        self
+    }
+
+    // This process's name within the group. Defaults to `$HOSTNAME-pid`.
+    pub fn consumer(mut self, consumer: impl Into<String>) -> Self {
+        self.consumer = consumer.into();
+        self
+    }
+
+    fn url(&self, path: &[&str]) -> Result<Url, TransportError> {
+        let mut url = self.proxy.clone().map_err(TransportError::InvalidUrl)?;
+        // http(s) URLs always have a path to extend.
+        if let Ok(mut segments) = url.path_segments_mut() {
+            segments.pop_if_empty().extend(path);
+        }
+        Ok(url)
+    }
+
+    // Records are keyed by chat, so updates from one chat stay in one partition and
+    // in order.
+    pub async fn publish(&self, update: &Update) -> Result<(), TransportError> {
+        let key = update
+            .chat()
+            .map(|chat| chat.id.to_string())
+            .or_else(|| update.from().map(|user| user.id.to_string()));
+        let response = http::client()
+            .post(self.url(&["topics", &self.topic])?)
+            .header(CONTENT_TYPE, JSON_V2)
+            .header(ACCEPT, V2)
+            .json(&json!({ "records": [{ "key": key, "value": update }] }))
+            .send()
+            .await?;
+        let body: serde_json::Value = checked(response).await?.json().await?;
+        match body["offsets"][0]["error"].as_str() {
+            Some(error) => Err(TransportError::Remote(error.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    // Creates the consumer instance, or takes over the one left from an earlier run
+    // under the same name, and subscribes it to the topic.
+    async fn open_consumer(&self) -> Result<Url, TransportError> {
+        let response = http::client()
+            .post(self.url(&["consumers", &self.group])?)
+            .header(CONTENT_TYPE, V2)
+            .json(&json!({
+                "name": self.consumer,
+                "format": "json",
+                "auto.offset.reset": "latest",
+                "auto.commit.enable": "false",
+            }))
+            .send()
+            .await?;
+        if response.status() != StatusCode::CONFLICT {
+            checked(response).await?;
+        }
+        let response = http::client()
+            .post(self.instance_url(&["subscription"])?)
+            .header(CONTENT_TYPE, V2)
+            .json(&json!({ "topics": [self.topic] }))
+            .send()
+            .await?;
+        checked(response).await?;
+        self.instance_url(&[])
+    }
+
+    fn instance_url(&self, tail: &[&str]) -> Result<Url, TransportError> {
+        let mut path = vec!["consumers", &self.group, "instances", &self.consumer];
+        path.extend_from_slice(tail);
+        self.url(&path)
+    }
+
+    async fn subscribe_once(
+        &self,
+        instance: &mut Option<Url>,
+        tx: &mpsc::Sender<Result<Update, TransportError>>,
+    ) -> Result<(), TransportError> {
+        if instance.is_none() {
+            *instance = Some(self.open_consumer().await?);
+        }
+        let records = self.instance_url(&["records"])?;
+        let offsets = self.instance_url(&["offsets"])?;
+        loop {
+            let fetch = http::client()
+                .get(records.clone())
+                .header(ACCEPT, JSON_V2)
+                .send();
+            let response = tokio::select! {
+                response = fetch => response?,
+                _ = tx.closed() => return Ok(()),
+            };
+            if response.status() == StatusCode::NOT_FOUND {
+                // The proxy drops instances that stay idle for too long.
+                *instance = None;
+                return Err(TransportError::Remote(String::from(
+                    "the consumer instance expired",
+                )));
+            }
+            let batch: Vec<serde_json::Value> = checked(response).await?.json().await?;
+            if batch.is_empty() {
+                continue;
+            }
+            for record in batch {
+                // Through a string: updates don't deserialize from a `Value`.
+                let update = serde_json::from_str(&record["value"].to_string())
+                    .map_err(TransportError::from);
+                if tx.send(update).await.is_err() {
+                    return Ok(());
+                }
+            }
+            // Committed once the batch is handed over, so a restart doesn't repeat it.
+            let response = http::client()
+                .post(offsets.clone())
+                .header(CONTENT_TYPE, V2)
+                .send()
+                .await?;
+            checked(response).await?;
+        }
+    }
+
+    async fn run_subscriber(self, tx: mpsc::Sender<Result<Update, TransportError>>) {
+        let mut instance = None;
+        let mut backoff = FIRST_BACKOFF;
+        loop {
+            let started = Instant::now();
+            let result = self.subscribe_once(&mut instance, &tx).await;
+            if tx.is_closed() {
+                break;
+            }
+            if let Err(err) = result {
+                let fatal = matches!(err, TransportError::InvalidUrl(_));
+                if tx.send(Err(err)).await.is_err() || fatal {
+                    break;
+                }
+            }
+            if started.elapsed() > MAX_BACKOFF {
+                backoff = FIRST_BACKOFF;
+            }
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+        // Hands the partitions back to the rest of the group right away.
+        if let Some(instance) = instance {
+            let _ = http::client()
+                .delete(instance)
+                .header(CONTENT_TYPE, V2)
+                .send()
+                .await;
+        }
+    }
+}
+
+async fn checked(response: reqwest::Response) -> Result<reqwest::Response, TransportError> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+    let status = response.status();
+    let body: serde_json::Value = response.json().await.unwrap_or_default();
+    let message = body["message"]
+        .as_str()
+        .map(str::to_string)
+        .unwrap_or_else(|| status.to_string());
+    Err(TransportError::Remote(message))
+}
+
+impl UpdateTransport for KafkaTransport {
+    fn publish<'a>(&'a self, update: &'a Update) -> BoxFuture<'a, Result<(), TransportError>> {
+        Box::pin(KafkaTransport::publish(self, update))
+    }
+
+    fn subscribe(&self) -> BoxStream<'static, Result<Update, TransportError>> {
+        let (tx, rx) = mpsc::channel(64);
+        tokio::spawn(self.clone().run_subscriber(tx));
+        transport::channel_stream(rx)
+    }
+}
@@ -0,0 +1,397 @@
+use crate::context::PluginContext;
+use crate::extract::FromContext;
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use teloxide::types::ChatId;
+
+// A chat's language is the most common one among its last this many detections.
+const CHAT_WINDOW: usize = 50;
+
+// Latin-script languages told apart by their most frequent short words.
+const STOPWORDS: &[(&str, &[&str])] = &[
+    (
+        "en",
+        &[
+            "the", "and", "is", "you", "that", "it", "to", "of", "for", "with", "this", "are",
+            "have", "what", "was", "not",
+        ],
+    ),
+    (
+        "es",
+        &[
+            "el", "los", "las", "que", "y", "es", "por", "una", "con", "para", "pero", "muy",
+            "como", "del", "está", "qué",
+        ],
+    ),
+    (
+        "pt",
+        &[
+            "os", "não", "um", "uma", "com", "você", "é", "mas", "muito", "isso", "para", "está",
+            "do", "da", "se", "eu",
+        ],
+    ),
+    (
+        "fr",
+        &[
+            "le", "les", "et", "est", "une", "je", "pas", "vous", "il", "pour", "avec", "dans",
+            "ce", "qui", "sur", "des",
+        ],
+    ),
+    (
+        "de",
+        &[
+            "der", "die", "das", "und", "ist", "nicht", "ich", "ein", "eine", "mit", "zu", "auf",
+            "sie", "es", "den", "auch",
+        ],
+    ),
+    (
+        "it",
+        &[
+            "il", "di", "che", "è", "non", "per", "sono", "mi", "ma", "ho", "questo", "anche",
+            "gli", "della", "ci", "lo",
+        ],
+    ),
+    (
+        "nl",
+        &[
+            "het", "een", "en", "van", "ik", "niet", "dat", "je", "op", "te", "met", "zijn",
+            "maar", "wat", "ook", "er",
+        ],
+    ),
+    (
+        "tr",
+        &[
+            "ve", "bir", "bu", "da", "ne", "için", "ben", "çok", "mi", "var", "ama", "gibi", "sen",
+            "değil", "daha", "olarak",
+        ],
+    ),
+    (
+        "id",
+        &[
+            "yang", "dan", "di", "ini", "itu", "tidak", "saya", "ada", "aku", "dengan", "untuk",
+            "apa", "kamu", "bisa", "sudah", "juga",
+        ],
+    ),
+    (
+        "pl",
+        &[
+            "w", "nie", "się", "na", "że", "jest", "z", "do", "jak", "co", "ale", "mnie", "tak",
+            "czy", "jestem", "już",
+        ],
+    ),
+];
+
+// Letters that only a few of those languages use.
+const LETTERS: &[(&str, &str)] = &[
+    ("es", "ñ¿¡"),
+    ("pt", "ãõ"),
+    ("de", "ßäöü"),
+    ("pl", "łąęśżźń"),
+    ("tr", "ğşı"),
+    ("fr", "œêëîûù"),
+];
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+static ROUTING: AtomicBool = AtomicBool::new(false);
+static MIN_LENGTH: AtomicUsize = AtomicUsize::new(10);
+static DETECTOR: Lazy<RwLock<Arc<dyn LanguageDetector>>> =
+    Lazy::new(|| RwLock::new(Arc::new(WhatlangDetector)));
+static CHATS: Lazy<RwLock<HashMap<ChatId, VecDeque<String>>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DetectedLanguage {
+    // An ISO 639-1 code like `en`, or whatever the installed detector uses.
+    pub code: String,
+    pub confidence: f32,
+}
+
+impl DetectedLanguage {
+    pub fn new(code: impl Into<String>, confidence: f32) -> Self {
+        Self {
+            code: code.into(),
+            confidence: confidence.clamp(0.0, 1.0),
+        }
+    }
+}
+
+pub trait LanguageDetector: Send + Sync {
+    fn detect(&self, text: &str) -> Option<DetectedLanguage>;
+}
+
+impl<F> LanguageDetector for F
+where
+    F: Fn(&str) -> Option<DetectedLanguage> + Send + Sync,
+{
+    fn detect(&self, text: &str) -> Option<DetectedLanguage> {
+        self(text)
+    }
+}
+
+pub fn set_detector(detector: impl LanguageDetector + 'static) {
+    *DETECTOR.write().unwrap() = Arc::new(detector);
+}
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+// Shorter texts, like "ok" or "lol", are too short to tell and aren't tagged.
+pub fn set_min_length(chars: usize) {
+    MIN_LENGTH.store(chars, Ordering::Relaxed);
+}
+
+// When on, `lang = [..]` plugins also match messages written in one of their
+// languages, not just users whose Telegram app is set to one.
+pub fn set_routing(enabled: bool) {
+    ROUTING.store(enabled, Ordering::Relaxed);
+}
+
+pub fn detect(text: &str) -> Option<DetectedLanguage> {
+    if text.chars().filter(|c| c.is_alphabetic()).count() < MIN_LENGTH.load(Ordering::Relaxed) {
+        return None;
+    }
+    let detector = DETECTOR.read().unwrap().clone();
+    detector.detect(text)
+}
+
+// The most common language among the chat's recent messages.
+pub fn chat_language(chat: ChatId) -> Option<String> {
+    let chats = CHATS.read().unwrap();
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for code in chats.get(&chat)? {
+        *counts.entry(code).or_default() += 1;
+    }
+    counts
+        .into_iter()
+        .max_by_key(|(code, count)| (*count, std::cmp::Reverse(*code)))
+        .map(|(code, _)| code.to_string())
+}
+
+fn remember(chat: ChatId, code: &str) {
+    let mut chats = CHATS.write().unwrap();
+    let recent = chats.entry(chat).or_default();
+    if recent.len() >= CHAT_WINDOW {
+        recent.pop_front();
+    }
+    recent.push_back(code.to_string());
+}
+
+pub(crate) fn migrate(from: ChatId, to: ChatId) {
+    let mut chats = CHATS.write().unwrap();
+    if let Some(recent) = chats.remove(&from) {
+        chats.insert(to, recent);
+    }
+}
+
+// Runs before routing and tags the message; it never stops it.
+pub(crate) fn tag(ctx: &PluginContext) {
+    if !ENABLED.load(Ordering::Relaxed) || ctx.message.is_none() {
+        return;
+    }
+    let Some(detected) = ctx.text_or_caption().and_then(detect) else {
+        return;
+    };
+    if let Some(chat) = ctx.chat_id() {
+        remember(chat, &detected.code);
+    }
+    ctx.extensions.insert(detected);
+}
+
+pub(crate) fn routing_language(ctx: &PluginContext) -> Option<String> {
+    if !ROUTING.load(Ordering::Relaxed) {
+        return None;
+    }
+    ctx.detected_language().map(|detected| detected.code)
+}
+
+impl FromContext for DetectedLanguage {
+    fn from_context(ctx: &PluginContext) -> Option<Self> {
+        ctx.detected_language()
+    }
+}
+
+impl PluginContext {
+    pub fn detected_language(&self) -> Option<DetectedLanguage> {
+        self.extensions.get()
+    }
+
+    pub fn chat_language(&self) -> Option<String> {
+        chat_language(self.chat_id()?)
+    }
+}
+
+// The default detector, backed by whatlang's trigram models for 69 languages.
+pub struct WhatlangDetector;
+
+impl LanguageDetector for WhatlangDetector {
+    fn detect(&self, text: &str) -> Option<DetectedLanguage> {
+        let info = whatlang::detect(text)?;
+        Some(DetectedLanguage::new(
+            iso_639_1(info.lang().code()),
+            info.confidence() as f32,
+        ))
+    }
+}
+
+// whatlang names languages by ISO 639-3; Telegram's `language_code` and `lang = [..]`
+// use the two-letter codes.
+fn iso_639_1(code: &'static str) -> &'static str {
+    match code {
+        "epo" => "eo",
+        "eng" => "en",
+        "rus" => "ru",
+        "cmn" => "zh",
+        "spa" => "es",
+        "por" => "pt",
+        "ita" => "it",
+        "ben" => "bn",
+        "fra" => "fr",
+        "deu" => "de",
+        "ukr" => "uk",
+        "kat" => "ka",
+        "ara" => "ar",
+        "hin" => "hi",
+        "jpn" => "ja",
+        "heb" => "he",
+        "yid" => "yi",
+        "pol" => "pl",
+        "amh" => "am",
+        "jav" => "jv",
+        "kor" => "ko",
+        "nob" => "nb",
+        "dan" => "da",
+        "swe" => "sv",
+        "fin" => "fi",
+        "tur" => "tr",
+        "nld" => "nl",
+        "hun" => "hu",
+        "ces" => "cs",
+        "ell" => "el",
+        "bul" => "bg",
+        "bel" => "be",
+        "mar" => "mr",
+        "kan" => "kn",
+        "ron" => "ro",
+        "slv" => "sl",
+        "hrv" => "hr",
+        "srp" => "sr",
+        "mkd" => "mk",
+        "lit" => "lt",
+        "lav" => "lv",
+        "est" => "et",
+        "tam" => "ta",
+        "vie" => "vi",
+        "urd" => "ur",
+        "tha" => "th",
+        "guj" => "gu",
+        "uzb" => "uz",
+        "pan" => "pa",
+        "aze" => "az",
+        "ind" => "id",
+        "tel" => "te",
+        "pes" => "fa",
+        "mal" => "ml",
+        "ori" => "or",
+        "mya" => "my",
+        "nep" => "ne",
+        "sin" => "si",
+        "khm" => "km",
+        "tuk" => "tk",
+        "aka" => "ak",
+        "zul" => "zu",
+        "sna" => "sn",
+        "afr" => "af",
+        "lat" => "la",
+        "slk" => "sk",
+        "cat" => "ca",
+        "tgl" => "tl",
+        "hye" => "hy",
+        other => other,
+    }
+}
+
+fn script_of(c: char) -> Option<&'static str> {
+    Some(match c as u32 {
+        0x0370..=0x03FF => "el",
+        0x0400..=0x04FF => "cyrillic",
+        0x0530..=0x058F => "hy",
+        0x0590..=0x05FF => "he",
+        0x0600..=0x06FF => "arabic",
+        0x0900..=0x097F => "hi",
+        0x0E00..=0x0E7F => "th",
+        0x10A0..=0x10FF => "ka",
+        0x3040..=0x30FF => "ja",
+        0x1100..=0x11FF | 0xAC00..=0xD7AF => "ko",
+        0x4E00..=0x9FFF => "zh",
+        _ if c.is_alphabetic() => "latin",
+        _ => return None,
+    })
+}
+
+// A lighter detector: the writing system settles most languages, letters and common
+// words tell apart the ones sharing an alphabet. It's small and fast, not thorough.
+pub struct ScriptDetector;
+
+impl LanguageDetector for ScriptDetector {
+    fn detect(&self, text: &str) -> Option<DetectedLanguage> {
+        let mut scripts: HashMap<&str, usize> = HashMap::new();
+        for c in text.chars() {
+            if let Some(script) = script_of(c) {
+                *scripts.entry(script).or_default() += 1;
+            }
+        }
+        let letters: usize = scripts.values().sum();
+        // Kana is what sets Japanese apart, since it's mixed with Chinese characters.
+        let (script, count) = if scripts.contains_key("ja") {
+            (
+                "ja",
+                scripts["ja"] + scripts.get("zh").copied().unwrap_or(0),
+            )
+        } else {
+            scripts.into_iter().max_by_key(|(_, count)| *count)?
+        };
+        let share = count as f32 / letters as f32;
+        let lower = text.to_lowercase();
+        let code = match script {
+            "latin" => {
+                return latin(&lower).map(|(code, c)| DetectedLanguage::new(code, c * share))
+            }
+            "cyrillic" if lower.contains(['і', 'ї', 'є', 'ґ']) => "uk",
+            "cyrillic" if lower.contains('ў') => "be",
+            "cyrillic" => "ru",
+            "arabic" if lower.contains(['پ', 'چ', 'ژ', 'گ', 'ک', 'ی']) => "fa",
+            "arabic" => "ar",
+            code => code,
+        };
+        Some(DetectedLanguage::new(code, share))
+    }
+}
+
+fn latin(lower: &str) -> Option<(&'static str, f32)> {
+    let words: Vec<&str> = lower
+        .split(|c: char| !c.is_alphabetic())
+        .filter(|word| !word.is_empty())
+        .collect();
+    let mut scores: Vec<(&'static str, usize)> = STOPWORDS
+        .iter()
+        .map(|(code, common)| {
+            let hits = words.iter().filter(|word| common.contains(word)).count();
+            let marks = LETTERS
+                .iter()
+                .filter(|(lang, _)| lang == code)
+                .map(|(_, letters)| lower.chars().filter(|c| letters.contains(*c)).count())
+                .sum::<usize>();
+            (*code, hits * 2 + marks)
+        })
+        .filter(|(_, score)| *score > 0)
+        .collect();
+    scores.sort_by_key(|(code, score)| (std::cmp::Reverse(*score), *code));
+    let (code, best) = *scores.first()?;
+    let total: usize = scores.iter().map(|(_, score)| score).sum();
+    // Few hits or a close second both make for a shaky guess.
+    let evidence = (best as f32 / 6.0).min(1.0);
+    Some((code, evidence * best as f32 / total as f32))
+}
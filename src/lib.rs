@@ -1,10 +1,206 @@
+extern crate self as teloxide_plugins;
+
+pub mod access;
+#[cfg(feature = "afk")]
+pub mod afk;
+#[cfg(feature = "ai")]
+pub mod ai;
+pub mod analytics;
+pub mod args;
+pub mod budget;
+pub mod callbacks;
+pub mod chat_cache;
+pub mod chat_info;
+pub mod chats;
+#[cfg(feature = "admin-console")]
+pub mod console;
 pub mod context;
+pub mod conversation;
+pub mod dedup;
+pub mod dispatcher;
+pub mod doctor;
+pub mod entities;
+pub mod extensions;
+pub mod external;
+pub mod extract;
+pub mod federation;
+#[cfg(feature = "feeds")]
+pub mod feeds;
+pub mod files;
+#[cfg(feature = "filters")]
+pub mod filters;
+pub mod games;
+pub mod groups;
+#[cfg(feature = "health")]
+pub mod health;
+pub mod history;
+pub mod http;
+pub mod idempotency;
+pub mod inline;
+#[cfg(any(feature = "sled-jobs", feature = "sqlite-jobs", feature = "redis-jobs"))]
+pub mod job_stores;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+#[cfg(feature = "lang-detect")]
+pub mod lang_detect;
+#[cfg(feature = "link-filter")]
+pub mod link_filter;
+pub mod locks;
+pub mod logging;
+#[cfg(feature = "media-utils")]
+pub mod media_utils;
+pub mod migration;
+pub mod moderation;
+#[cfg(feature = "nats")]
+pub mod nats;
+#[cfg(feature = "notes")]
+pub mod notes;
+pub mod outbox;
+pub mod payments;
+pub mod permissions;
+pub mod plugin;
+pub mod plugin_bot;
+pub mod polls;
+pub mod pool;
+pub mod queue;
+pub mod ratelimit;
+pub mod reactions;
 pub mod registry;
+#[cfg(feature = "reminders")]
+pub mod reminders;
+pub mod reporting;
+pub mod response;
+#[cfg(feature = "retry")]
+pub mod retry;
+pub mod roles;
+pub mod scheduler;
+pub mod session;
+pub mod settings;
+pub mod slowmode;
+pub mod source;
+pub mod spam;
+pub mod stickers;
+pub mod storage;
+#[cfg(feature = "redis-streams")]
+pub mod streams;
+pub mod supervisor;
+pub mod templates;
+pub mod texts;
+pub mod topics;
+pub mod traffic;
+pub mod transcribe;
+pub mod transport;
+pub mod usage_export;
+pub mod warnings;
 
-pub use crate::context::PluginContext;
+#[cfg(feature = "afk")]
+pub use crate::afk::Afk;
+#[cfg(feature = "ai")]
+pub use crate::ai::{AiError, CompletionProvider, OpenAiProvider};
+pub use crate::analytics::DayUsage;
+pub use crate::args::{ArgError, HumanDate, HumanDuration, HumanNumber, HumanSize};
+pub use crate::budget::{Budget, PluginUsage};
+pub use crate::callbacks::{CallbackData, CallbackError};
+#[cfg(feature = "redis-chat-cache")]
+pub use crate::chat_cache::RedisMemberStore;
+pub use crate::chat_cache::{ChatCache, MemberStore, MemoryMemberStore, SeenMember};
+pub use crate::chats::{ChatPage, KnownChat};
+pub use crate::context::{PluginContext, TargetUser};
+pub use crate::conversation::{
+    Conversation, ConversationBuilder, ConversationRecord, ConversationTimeout,
+};
+pub use crate::dedup::{DedupPolicy, DuplicateInfo};
+pub use crate::dispatcher::{PluginDispatcher, PollingConfig};
+pub use crate::doctor::{DoctorReport, Finding, Severity};
+pub use crate::entities::{Entities, Entity};
+pub use crate::extensions::Extensions;
+pub use crate::external::ExternalEvent;
+pub use crate::extract::{Event, FromContext};
+pub use crate::federation::{FedBan, FedBanReport, Federation, FederationError};
+#[cfg(feature = "feeds")]
+pub use crate::feeds::{Feed, FeedEntry, FeedError};
+pub use crate::files::FileError;
+#[cfg(feature = "filters")]
+pub use crate::filters::FilterMatch;
+pub use crate::games::GameError;
+#[cfg(feature = "health")]
+pub use crate::health::{HealthReport, PluginHealth};
+pub use crate::http::{Http, HttpError, HttpEvent, HttpQuota, HttpRequest, HttpStats};
+pub use crate::inline::InlineAnswer;
+#[cfg(feature = "redis-jobs")]
+pub use crate::job_stores::RedisJobStore;
+#[cfg(feature = "sled-jobs")]
+pub use crate::job_stores::SledJobStore;
+#[cfg(feature = "sqlite-jobs")]
+pub use crate::job_stores::SqliteJobStore;
+#[cfg(feature = "kafka")]
+pub use crate::kafka::KafkaTransport;
+#[cfg(feature = "lang-detect")]
+pub use crate::lang_detect::{
+    DetectedLanguage, LanguageDetector, ScriptDetector, WhatlangDetector,
+};
+#[cfg(feature = "link-filter")]
+pub use crate::link_filter::{LinkPolicy, LinkViolation};
+pub use crate::locks::ChatLockGuard;
+pub use crate::logging::{LogSink, Outcome, StdoutSink, UpdateRecord};
+#[cfg(feature = "media-utils")]
+pub use crate::media_utils::{Image, ImageFormat, ImageInfo, MediaError};
+pub use crate::migration::{register_migration, ChatIdMigration};
+pub use crate::moderation::{ModerationError, PurgeReport};
+#[cfg(feature = "nats")]
+pub use crate::nats::NatsTransport;
+#[cfg(feature = "notes")]
+pub use crate::notes::{MediaKind, Note, NoteError, NoteMedia};
+pub use crate::outbox::OutboxMessage;
+pub use crate::payments::{Checkout, Invoice, Payment, PaymentError};
+pub use crate::plugin::{InstallError, Plugin, Triggers};
+pub use crate::plugin_bot::{PluginBot, PluginRequest};
+pub use crate::polls::{ManagedPoll, OptionResult, PollError, PollRequest, PollResults};
+pub use crate::pool::{BotPool, TokenStats};
+pub use crate::queue::{DispatchQueue, OverflowPolicy, Priority, QueueConfig, QueueMetrics};
+pub use crate::reactions::ReactionError;
 pub use crate::registry::{dispatch, PluginMeta, PLUGIN_REGISTRY};
-pub use teloxide_plugins_macros::TeloxidePlugin;
+#[cfg(feature = "sentry")]
+pub use crate::reporting::SentryReporter;
+pub use crate::reporting::{
+    ErrorKind, ErrorReport, ErrorReporter, OrUser, PluginError, RecentError,
+};
+pub use crate::response::{
+    IntoPluginResponse, PluginResponse, ReplyOptions, SendError, SendMessageParams, StreamMode,
+    StreamReply,
+};
+pub use crate::scheduler::{CatchUp, Job, JobStore, SchedulerError};
+pub use crate::session::{ConflictStrategy, SessionGuard};
+pub use crate::source::{EventSource, IncomingEvent};
+pub use crate::spam::{HeuristicClassifier, SpamClassifier, SpamScore};
+pub use crate::stickers::{Kanged, NewSticker, StickerError};
+pub use crate::storage::{Archive, MemoryStorage, Storage, StorageError};
+#[cfg(feature = "redis-streams")]
+pub use crate::streams::{RedisStream, StreamError};
+pub use crate::supervisor::SupervisedTask;
+pub use crate::templates::{Template, TemplateButton, TemplateError};
+pub use crate::texts::FrameworkTexts;
+pub use crate::topics::Topic;
+pub use crate::traffic::TrafficStats;
+pub use crate::transcribe::{TranscribeError, Transcriber, Transcript};
+pub use crate::transport::{TransportError, UpdateTransport};
+pub use crate::usage_export::{ExportError, ExportTarget, UsageExporter, UsageRow};
+pub use crate::warnings::{WarnAction, WarnConfig, WarnError, WarnEvent, WarnOutcome, Warning};
+pub use teloxide_plugins_macros::{Plugin, TeloxidePlugin, TeloxidePluginGroup};
+
+#[doc(hidden)]
+pub use once_cell;
+
+// `#[derive(teloxide_plugins::derive::TeloxidePlugin)]` is `#[derive(Plugin)]` under
+// the attribute's name. It can't live next to the attribute at the crate root: a
+// derive and an attribute share one macro namespace.
+pub mod derive {
+    pub use teloxide_plugins_macros::Plugin as TeloxidePlugin;
+}
 
 pub mod prelude {
-    pub use crate::{dispatch, PluginContext, PluginMeta, TeloxidePlugin};
+    pub use crate::{
+        dispatch, DispatchQueue, IntoPluginResponse, PluginBot, PluginContext, PluginDispatcher,
+        PluginMeta, SendMessageParams, StreamReply, TeloxidePlugin,
+    };
 }
@@ -0,0 +1,244 @@
+use crate::context::PluginContext;
+use crate::entities;
+use crate::permissions;
+use crate::settings;
+use crate::storage::StorageError;
+use crate::traffic;
+use serde::{Deserialize, Serialize};
+use teloxide::prelude::*;
+use teloxide::types::Message;
+
+const SETTING: &str = "link_filter";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkPolicy {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+    pub delete: bool,
+    pub warn: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LinkViolation {
+    pub domains: Vec<String>,
+    pub deleted: bool,
+}
+
+// A listed domain also covers its subdomains, so `example.com` matches `docs.example.com`.
+fn covers(listed: &str, domain: &str) -> bool {
+    domain == listed
+        || domain
+            .strip_suffix(listed)
+            .is_some_and(|rest| rest.ends_with('.'))
+}
+
+impl LinkPolicy {
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    // Once anything is allowlisted, every other domain is blocked. Denied domains
+    // stay blocked even when a broader entry allows them.
+    pub fn blocks(&self, domain: &str) -> bool {
+        if self.deny.iter().any(|listed| covers(listed, domain)) {
+            return true;
+        }
+        !self.allow.is_empty() && !self.allow.iter().any(|listed| covers(listed, domain))
+    }
+}
+
+pub fn normalize_domain(input: &str) -> Option<String> {
+    let input = input.trim();
+    let with_scheme = if input.contains("://") {
+        input.to_string()
+    } else {
+        format!("http://{}", input)
+    };
+    let host = url::Url::parse(&with_scheme)
+        .ok()?
+        .host_str()?
+        .to_lowercase();
+    let host = host.trim_end_matches('.');
+    let host = host.strip_prefix("www.").unwrap_or(host);
+    // A bare word like `hello` parses as a host too, but it's never a link.
+    host.contains(['.', ':']).then(|| host.to_string())
+}
+
+pub fn domains(msg: &Message) -> Vec<String> {
+    let mut domains: Vec<String> = entities::message_entities(msg)
+        .iter()
+        .filter(|entity| matches!(entity.kind, "url" | "text_link"))
+        .filter_map(|entity| normalize_domain(&entity.value))
+        .collect();
+    domains.sort();
+    domains.dedup();
+    domains
+}
+
+pub async fn policy(chat: ChatId) -> Result<LinkPolicy, StorageError> {
+    Ok(settings::get(chat, SETTING).await?.unwrap_or_default())
+}
+
+pub async fn set_policy(chat: ChatId, policy: &LinkPolicy) -> Result<(), StorageError> {
+    settings::set(chat, SETTING, policy).await
+}
+
+async fn is_exempt(ctx: &PluginContext, chat: ChatId, user: UserId) -> bool {
+    if ctx.is_anonymous_admin() {
+        return true;
+    }
+    permissions::chat_member(&ctx.bot, chat, user)
+        .await
+        .is_ok_and(|member| member.status().is_privileged())
+}
+
+// Runs before routing. Returns `true` when the message was deleted, so no plugin
+// sees it; otherwise a violation is left in the extensions for plugins to act on.
+pub(crate) async fn enforce(ctx: &PluginContext) -> bool {
+    let Some(msg) = ctx.message.as_ref().filter(|msg| !msg.chat.is_private()) else {
+        return false;
+    };
+    let Some(user) = ctx.sender() else {
+        return false;
+    };
+    let found = domains(msg);
+    if found.is_empty() {
+        return false;
+    }
+    let Ok(policy) = policy(msg.chat.id).await else {
+        return false;
+    };
+    let blocked: Vec<String> = found
+        .into_iter()
+        .filter(|domain| policy.blocks(domain))
+        .collect();
+    if blocked.is_empty() || is_exempt(ctx, msg.chat.id, user.id).await {
+        return false;
+    }
+
+    let deleted = policy.delete
+        && traffic::send(ctx.bot.delete_message(msg.chat.id, msg.id))
+            .await
+            .is_ok();
+    if policy.warn {
+        let text = format!(
+            "{}, links to {} aren't allowed here.",
+            user.mention().unwrap_or_else(|| user.full_name()),
+            blocked.join(", ")
+        );
+        let _ = traffic::send(ctx.bot.send_message(msg.chat.id, text)).await;
+    }
+    ctx.extensions.insert(LinkViolation {
+        domains: blocked,
+        deleted,
+    });
+    deleted
+}
+
+impl PluginContext {
+    pub fn link_violation(&self) -> Option<LinkViolation> {
+        self.extensions.get()
+    }
+}
+
+fn describe(policy: &LinkPolicy) -> String {
+    let list = |domains: &[String]| {
+        if domains.is_empty() {
+            String::from("none")
+        } else {
+            domains.join(", ")
+        }
+    };
+    let action = match (policy.delete, policy.warn) {
+        (true, true) => "delete and warn",
+        (true, false) => "delete",
+        (false, true) => "warn",
+        (false, false) => "off",
+    };
+    format!(
+        "Allowed: {}\nDenied: {}\nAction: {}",
+        list(&policy.allow),
+        list(&policy.deny),
+        action
+    )
+}
+
+async fn update(
+    ctx: &PluginContext,
+    change: impl FnOnce(&mut LinkPolicy, Vec<String>),
+) -> Option<String> {
+    let chat = ctx.chat_id()?;
+    let domains: Vec<String> = ctx
+        .args()
+        .iter()
+        .filter_map(|arg| normalize_domain(arg))
+        .collect();
+    if domains.is_empty() {
+        return Some(String::from("Pass one or more domains."));
+    }
+    let mut policy = policy(chat).await.ok()?;
+    change(&mut policy, domains);
+    Some(match set_policy(chat, &policy).await {
+        Ok(()) => describe(&policy),
+        Err(err) => format!("Couldn't save the link filter: {}", err),
+    })
+}
+
+fn add_all(list: &mut Vec<String>, domains: &[String]) {
+    for domain in domains {
+        if !list.contains(domain) {
+            list.push(domain.clone());
+        }
+    }
+}
+
+#[crate::TeloxidePlugin(commands = ["allowdomain"], prefixes = ["/"], admin_only = true)]
+async fn allow_domain_command(ctx: PluginContext) -> Option<String> {
+    update(&ctx, |policy, domains| {
+        policy.deny.retain(|listed| !domains.contains(listed));
+        add_all(&mut policy.allow, &domains);
+    })
+    .await
+}
+
+#[crate::TeloxidePlugin(commands = ["denydomain"], prefixes = ["/"], admin_only = true)]
+async fn deny_domain_command(ctx: PluginContext) -> Option<String> {
+    update(&ctx, |policy, domains| {
+        policy.allow.retain(|listed| !domains.contains(listed));
+        add_all(&mut policy.deny, &domains);
+    })
+    .await
+}
+
+#[crate::TeloxidePlugin(commands = ["removedomain"], prefixes = ["/"], admin_only = true)]
+async fn remove_domain_command(ctx: PluginContext) -> Option<String> {
+    update(&ctx, |policy, domains| {
+        policy.allow.retain(|listed| !domains.contains(listed));
+        policy.deny.retain(|listed| !domains.contains(listed));
+    })
+    .await
+}
+
+#[crate::TeloxidePlugin(commands = ["linkaction"], prefixes = ["/"], admin_only = true)]
+async fn link_action_command(ctx: PluginContext) -> Option<String> {
+    let chat = ctx.chat_id()?;
+    let (delete, warn) = match ctx.args().first().map(|arg| arg.to_lowercase()).as_deref() {
+        Some("delete") => (true, false),
+        Some("warn") => (false, true),
+        Some("both") => (true, true),
+        Some("off") => (false, false),
+        _ => return Some(String::from("Usage: /linkaction delete|warn|both|off")),
+    };
+    let mut policy = policy(chat).await.ok()?;
+    policy.delete = delete;
+    policy.warn = warn;
+    Some(match set_policy(chat, &policy).await {
+        Ok(()) => describe(&policy),
+        Err(err) => format!("Couldn't save the link filter: {}", err),
+    })
+}
+
+#[crate::TeloxidePlugin(commands = ["linkfilter"], prefixes = ["/"], admin_only = true)]
+async fn link_filter_command(ctx: PluginContext) -> Option<String> {
+    Some(describe(&policy(ctx.chat_id()?).await.ok()?))
+}
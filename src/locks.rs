@@ -0,0 +1,39 @@
+use crate::context::PluginContext;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use teloxide::types::ChatId;
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+static CHAT_LOCKS: Lazy<Mutex<HashMap<ChatId, Arc<AsyncMutex<()>>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+pub struct ChatLockGuard {
+    chat_id: ChatId,
+    _guard: OwnedMutexGuard<()>,
+}
+
+impl ChatLockGuard {
+    pub fn chat_id(&self) -> ChatId {
+        self.chat_id
+    }
+}
+
+pub async fn chat_lock(chat_id: ChatId) -> ChatLockGuard {
+    let lock = {
+        let mut locks = CHAT_LOCKS.lock().unwrap();
+        // Nobody holds or waits on a lock whose only reference is the map's own.
+        locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+        locks.entry(chat_id).or_default().clone()
+    };
+    ChatLockGuard {
+        chat_id,
+        _guard: lock.lock_owned().await,
+    }
+}
+
+impl PluginContext {
+    pub async fn chat_lock(&self) -> Option<ChatLockGuard> {
+        Some(chat_lock(self.chat_id()?).await)
+    }
+}
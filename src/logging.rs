@@ -0,0 +1,107 @@
+use crate::context::PluginContext;
+use crate::registry::PluginMeta;
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::io::Write;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Ok,
+    Error,
+    Panic,
+    Denied,
+    Cooldown,
+    Unmatched,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct UpdateRecord {
+    pub timestamp: DateTime<Utc>,
+    pub update: &'static str,
+    pub chat_id: Option<i64>,
+    pub user_id: Option<u64>,
+    pub plugin: Option<&'static str>,
+    pub outcome: Outcome,
+    pub latency_ms: u64,
+}
+
+impl UpdateRecord {
+    pub fn to_json_line(&self) -> String {
+        let mut line = serde_json::to_string(self).unwrap_or_default();
+        line.push('\n');
+        line
+    }
+}
+
+pub trait LogSink: Send + Sync {
+    fn emit<'a>(&'a self, record: &'a UpdateRecord) -> BoxFuture<'a, ()>;
+}
+
+pub struct StdoutSink;
+
+impl LogSink for StdoutSink {
+    fn emit<'a>(&'a self, record: &'a UpdateRecord) -> BoxFuture<'a, ()> {
+        let line = record.to_json_line();
+        Box::pin(async move {
+            let _ = std::io::stdout().lock().write_all(line.as_bytes());
+        })
+    }
+}
+
+static SINK: Lazy<RwLock<Option<Arc<dyn LogSink>>>> = Lazy::new(|| RwLock::new(None));
+
+pub fn set_log_sink(sink: impl LogSink + 'static) {
+    *SINK.write().unwrap() = Some(Arc::new(sink));
+}
+
+pub fn clear_log_sink() {
+    *SINK.write().unwrap() = None;
+}
+
+pub(crate) async fn emit(
+    ctx: &PluginContext,
+    matched: Option<(&'static PluginMeta, Outcome)>,
+    latency: Duration,
+) {
+    let Some(sink) = SINK.read().unwrap().clone() else {
+        return;
+    };
+    let record = UpdateRecord {
+        timestamp: Utc::now(),
+        update: update_name(ctx),
+        chat_id: ctx.chat_id().map(|id| id.0),
+        user_id: ctx.sender().map(|user| user.id.0),
+        plugin: matched.map(|(plugin, _)| plugin.name),
+        outcome: matched.map_or(Outcome::Unmatched, |(_, outcome)| outcome),
+        latency_ms: latency.as_millis() as u64,
+    };
+    sink.emit(&record).await;
+}
+
+fn update_name(ctx: &PluginContext) -> &'static str {
+    if let Some(kind) = ctx.update_kind() {
+        return kind;
+    }
+    if ctx.message.is_some() {
+        if ctx.is_edited() {
+            "edited_message"
+        } else {
+            "message"
+        }
+    } else if ctx.callback_query.is_some() {
+        "callback_query"
+    } else if ctx.inline_query.is_some() {
+        "inline_query"
+    } else if ctx.raw_update.is_some() {
+        "raw"
+    } else if ctx.external.is_some() {
+        "external"
+    } else {
+        "unknown"
+    }
+}
@@ -0,0 +1,600 @@
+use crate::context::PluginContext;
+use crate::files::FileError;
+use image::ImageEncoder;
+use std::fmt;
+use teloxide::types::FileId;
+
+// Telegram's limits for static stickers: one side exactly 512px, the other at most
+// that, and at most 512 KB.
+pub const STICKER_SIDE: u32 = 512;
+pub const STICKER_MAX_BYTES: usize = 512 * 1024;
+
+// Decoding stops at this many pixels, so a tiny file can't claim to be a huge image.
+const MAX_PIXELS: u64 = 40_000_000;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    Webp,
+    Gif,
+}
+
+impl fmt::Display for ImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageFormat::Png => write!(f, "PNG"),
+            ImageFormat::Jpeg => write!(f, "JPEG"),
+            ImageFormat::Webp => write!(f, "WebP"),
+            ImageFormat::Gif => write!(f, "GIF"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImageInfo {
+    pub format: ImageFormat,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug)]
+pub enum MediaError {
+    UnknownFormat,
+    Unsupported(&'static str),
+    Corrupt(&'static str),
+    TooLarge { size: usize, limit: usize },
+    WrongSize { width: u32, height: u32 },
+    Download(FileError),
+}
+
+impl fmt::Display for MediaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MediaError::UnknownFormat => write!(f, "the file isn't a PNG, JPEG, WebP or GIF image"),
+            MediaError::Unsupported(what) => write!(f, "{} isn't supported", what),
+            MediaError::Corrupt(reason) => write!(f, "the image is damaged: {}", reason),
+            MediaError::TooLarge { size, limit } => {
+                write!(
+                    f,
+                    "the image is {} KB, over the {} KB limit",
+                    size / 1024,
+                    limit / 1024
+                )
+            }
+            MediaError::WrongSize { width, height } => write!(
+                f,
+                "stickers need one side of {}px and the other at most that, not {}x{}",
+                STICKER_SIDE, width, height
+            ),
+            MediaError::Download(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for MediaError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MediaError::Download(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<FileError> for MediaError {
+    fn from(err: FileError) -> Self {
+        MediaError::Download(err)
+    }
+}
+
+fn be16(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u16::from_be_bytes(bytes.get(at..at + 2)?.try_into().ok()?) as u32)
+}
+
+fn be32(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn le16(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?) as u32)
+}
+
+fn le24(bytes: &[u8], at: usize) -> Option<u32> {
+    let b = bytes.get(at..at + 3)?;
+    Some(b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16)
+}
+
+fn le32(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+pub fn format_of(bytes: &[u8]) -> Option<ImageFormat> {
+    if bytes.starts_with(PNG_SIGNATURE) {
+        Some(ImageFormat::Png)
+    } else if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some(ImageFormat::Jpeg)
+    } else if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        Some(ImageFormat::Webp)
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        Some(ImageFormat::Gif)
+    } else {
+        None
+    }
+}
+
+// Reads the format and dimensions from the headers, without decoding anything.
+pub fn probe(bytes: &[u8]) -> Result<ImageInfo, MediaError> {
+    let format = format_of(bytes).ok_or(MediaError::UnknownFormat)?;
+    let size = match format {
+        ImageFormat::Png => be32(bytes, 16).zip(be32(bytes, 20)),
+        ImageFormat::Gif => le16(bytes, 6).zip(le16(bytes, 8)),
+        ImageFormat::Jpeg => jpeg_size(bytes),
+        ImageFormat::Webp => webp_size(bytes),
+    };
+    let (width, height) = size.ok_or(MediaError::Corrupt("no image size in the header"))?;
+    Ok(ImageInfo {
+        format,
+        width,
+        height,
+    })
+}
+
+// The size is in the first start-of-frame segment, wherever that is.
+fn jpeg_size(bytes: &[u8]) -> Option<(u32, u32)> {
+    let mut at = 2;
+    loop {
+        if *bytes.get(at)? != 0xFF {
+            return None;
+        }
+        let marker = *bytes.get(at + 1)?;
+        if marker == 0xFF {
+            at += 1;
+            continue;
+        }
+        let length = be16(bytes, at + 2)? as usize;
+        let is_frame = matches!(marker, 0xC0..=0xCF) && !matches!(marker, 0xC4 | 0xC8 | 0xCC);
+        if is_frame {
+            return Some((be16(bytes, at + 7)?, be16(bytes, at + 5)?));
+        }
+        at += 2 + length;
+    }
+}
+
+fn webp_size(bytes: &[u8]) -> Option<(u32, u32)> {
+    match bytes.get(12..16)? {
+        b"VP8 " => Some((le16(bytes, 26)? & 0x3FFF, le16(bytes, 28)? & 0x3FFF)),
+        b"VP8L" => {
+            let bits = le32(bytes, 21)?;
+            Some(((bits & 0x3FFF) + 1, ((bits >> 14) & 0x3FFF) + 1))
+        }
+        b"VP8X" => Some((le24(bytes, 24)? + 1, le24(bytes, 27)? + 1)),
+        _ => None,
+    }
+}
+
+// Removes EXIF, XMP and text metadata, which can carry the camera, the time and the
+// place a picture was taken. Pixels and color profiles stay as they are. GIFs have
+// nowhere to keep camera data, so they come back unchanged.
+pub fn strip_metadata(bytes: &[u8]) -> Result<Vec<u8>, MediaError> {
+    match format_of(bytes).ok_or(MediaError::UnknownFormat)? {
+        ImageFormat::Jpeg => strip_jpeg(bytes).ok_or(MediaError::Corrupt("broken JPEG segments")),
+        ImageFormat::Png => strip_png(bytes).ok_or(MediaError::Corrupt("broken PNG chunks")),
+        ImageFormat::Webp => strip_webp(bytes).ok_or(MediaError::Corrupt("broken WebP chunks")),
+        ImageFormat::Gif => Ok(bytes.to_vec()),
+    }
+}
+
+// APP1 holds EXIF and XMP, APP13 holds Photoshop's IPTC block, COM is a free comment.
+fn strip_jpeg(bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut out = bytes[..2].to_vec();
+    let mut at = 2;
+    loop {
+        if *bytes.get(at)? != 0xFF {
+            return None;
+        }
+        let marker = *bytes.get(at + 1)?;
+        // Start of scan: the rest is image data.
+        if marker == 0xDA {
+            out.extend_from_slice(&bytes[at..]);
+            return Some(out);
+        }
+        let end = at + 2 + be16(bytes, at + 2)? as usize;
+        let segment = bytes.get(at..end)?;
+        if !matches!(marker, 0xE1 | 0xED | 0xFE) {
+            out.extend_from_slice(segment);
+        }
+        at = end;
+    }
+}
+
+fn png_chunks(bytes: &[u8]) -> Option<Vec<(&[u8], &[u8])>> {
+    let mut chunks = Vec::new();
+    let mut at = PNG_SIGNATURE.len();
+    while at < bytes.len() {
+        let length = be32(bytes, at)? as usize;
+        let kind = bytes.get(at + 4..at + 8)?;
+        let whole = bytes.get(at..at + 12 + length)?;
+        chunks.push((kind, whole));
+        at += 12 + length;
+        if kind == b"IEND" {
+            break;
+        }
+    }
+    Some(chunks)
+}
+
+fn strip_png(bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut out = PNG_SIGNATURE.to_vec();
+    for (kind, chunk) in png_chunks(bytes)? {
+        if !matches!(kind, b"eXIf" | b"tEXt" | b"zTXt" | b"iTXt" | b"tIME") {
+            out.extend_from_slice(chunk);
+        }
+    }
+    Some(out)
+}
+
+fn strip_webp(bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut out = bytes.get(..12)?.to_vec();
+    let mut at = 12;
+    while at + 8 <= bytes.len() {
+        let kind = &bytes[at..at + 4];
+        let length = le32(bytes, at + 4)? as usize;
+        let end = (at + 8 + length + length % 2).min(bytes.len());
+        let chunk = bytes.get(at..end)?;
+        match kind {
+            b"EXIF" | b"XMP " => {}
+            b"VP8X" => {
+                let start = out.len();
+                out.extend_from_slice(chunk);
+                // The flags say which optional chunks follow.
+                out[start + 8] &= !0b1100;
+            }
+            _ => out.extend_from_slice(chunk),
+        }
+        at = end;
+    }
+    let riff_size = (out.len() - 8) as u32;
+    out[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Some(out)
+}
+
+// Decoded pixels, always 8-bit RGBA.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    // Decodes PNG, JPEG, WebP and the first frame of a GIF. The size in the header is
+    // checked first, so nothing is allocated for a file that claims to be huge.
+    pub fn decode(bytes: &[u8]) -> Result<Image, MediaError> {
+        let info = probe(bytes)?;
+        if info.width == 0
+            || info.height == 0
+            || info.width as u64 * info.height as u64 > MAX_PIXELS
+        {
+            return Err(MediaError::Corrupt("impossible image size"));
+        }
+        let format = match info.format {
+            ImageFormat::Png => image::ImageFormat::Png,
+            ImageFormat::Jpeg => image::ImageFormat::Jpeg,
+            ImageFormat::Webp => image::ImageFormat::WebP,
+            ImageFormat::Gif => image::ImageFormat::Gif,
+        };
+        let mut limits = image::Limits::default();
+        limits.max_alloc = Some(MAX_PIXELS * 4);
+        let mut reader = image::ImageReader::with_format(std::io::Cursor::new(bytes), format);
+        reader.limits(limits);
+        let decoded = reader.decode().map_err(|err| match err {
+            image::ImageError::Limits(_) => MediaError::Corrupt("impossible image size"),
+            image::ImageError::Unsupported(_) => MediaError::Unsupported("this kind of image"),
+            _ => MediaError::Corrupt("the pixels don't decode"),
+        })?;
+        let pixels = decoded.to_rgba8();
+        Ok(Image {
+            width: pixels.width(),
+            height: pixels.height(),
+            pixels: pixels.into_raw(),
+        })
+    }
+
+    pub fn to_png(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        image::codecs::png::PngEncoder::new_with_quality(
+            &mut out,
+            image::codecs::png::CompressionType::Best,
+            image::codecs::png::FilterType::Adaptive,
+        )
+        .write_image(
+            &self.pixels,
+            self.width,
+            self.height,
+            image::ExtendedColorType::Rgba8,
+        )
+        // Only a pixel buffer of the wrong length fails, and `Image` always has the
+        // right one unless its fields were changed by hand.
+        .expect("the pixels don't match the image size");
+        out
+    }
+
+    // Averages the covered pixels when shrinking and repeats them when growing.
+    // Colors are weighted by alpha, so transparent pixels don't darken the edges.
+    pub fn resize(&self, width: u32, height: u32) -> Image {
+        let (width, height) = (width.max(1), height.max(1));
+        let (sw, sh) = (self.width as usize, self.height as usize);
+        let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+        for y in 0..height as usize {
+            let y0 = y * sh / height as usize;
+            let y1 = ((y + 1) * sh / height as usize).max(y0 + 1);
+            for x in 0..width as usize {
+                let x0 = x * sw / width as usize;
+                let x1 = ((x + 1) * sw / width as usize).max(x0 + 1);
+                let mut sum = [0u64; 4];
+                for sy in y0..y1 {
+                    for sx in x0..x1 {
+                        let p = &self.pixels[(sy * sw + sx) * 4..][..4];
+                        let alpha = p[3] as u64;
+                        for (total, channel) in sum.iter_mut().zip(&p[..3]) {
+                            *total += *channel as u64 * alpha;
+                        }
+                        sum[3] += alpha;
+                    }
+                }
+                let count = ((y1 - y0) * (x1 - x0)) as u64;
+                for total in &sum[..3] {
+                    pixels.push(total.checked_div(sum[3]).unwrap_or(0) as u8);
+                }
+                pixels.push((sum[3] / count) as u8);
+            }
+        }
+        Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    // Scales down to fit in the box, keeping the aspect ratio. Never scales up.
+    pub fn fit(&self, max_width: u32, max_height: u32) -> Image {
+        let (width, height) = fitted(self.width, self.height, max_width, max_height);
+        if (width, height) == (self.width, self.height) {
+            return self.clone();
+        }
+        self.resize(width, height)
+    }
+}
+
+fn fitted(width: u32, height: u32, max_width: u32, max_height: u32) -> (u32, u32) {
+    if width <= max_width && height <= max_height {
+        return (width, height);
+    }
+    let scale = f64::min(
+        max_width as f64 / width as f64,
+        max_height as f64 / height as f64,
+    );
+    (
+        ((width as f64 * scale).round() as u32).max(1),
+        ((height as f64 * scale).round() as u32).max(1),
+    )
+}
+
+// Checks a static sticker against Telegram's rules before uploading it.
+pub fn validate_sticker(bytes: &[u8]) -> Result<ImageInfo, MediaError> {
+    let info = probe(bytes)?;
+    if !matches!(info.format, ImageFormat::Png | ImageFormat::Webp) {
+        return Err(MediaError::Unsupported("a sticker that isn't PNG or WebP"));
+    }
+    if bytes.len() > STICKER_MAX_BYTES {
+        return Err(MediaError::TooLarge {
+            size: bytes.len(),
+            limit: STICKER_MAX_BYTES,
+        });
+    }
+    let longest = info.width.max(info.height);
+    if longest != STICKER_SIDE {
+        return Err(MediaError::WrongSize {
+            width: info.width,
+            height: info.height,
+        });
+    }
+    Ok(info)
+}
+
+// The size a picture needs to become a sticker: the longer side at 512px.
+pub fn sticker_size(width: u32, height: u32) -> (u32, u32) {
+    let longest = width.max(height).max(1) as u64;
+    let scale = |side: u32| ((side as u64 * STICKER_SIDE as u64 + longest / 2) / longest) as u32;
+    (scale(width).max(1), scale(height).max(1))
+}
+
+// Turns a PNG, JPEG, WebP or GIF into a valid static sticker: a PNG with its longer
+// side at 512px.
+// Stickers that already fit come back unchanged.
+pub fn make_sticker(bytes: &[u8]) -> Result<Vec<u8>, MediaError> {
+    if validate_sticker(bytes).is_ok() {
+        return Ok(bytes.to_vec());
+    }
+    let image = Image::decode(bytes)?;
+    let (width, height) = sticker_size(image.width, image.height);
+    let png = image.resize(width, height).to_png();
+    if png.len() > STICKER_MAX_BYTES {
+        return Err(MediaError::TooLarge {
+            size: png.len(),
+            limit: STICKER_MAX_BYTES,
+        });
+    }
+    Ok(png)
+}
+
+// A PNG preview of any decodable image that fits in `max_side`, like the 100px thumbnail of a sticker set.
+pub fn thumbnail(bytes: &[u8], max_side: u32) -> Result<Vec<u8>, MediaError> {
+    Ok(Image::decode(bytes)?.fit(max_side, max_side).to_png())
+}
+
+impl PluginContext {
+    // Downloads a file and checks that it's an image, so plugins can go straight to
+    // `strip_metadata`, `make_sticker` or `thumbnail`.
+    pub async fn download_image(
+        &self,
+        file_id: FileId,
+    ) -> Result<(ImageInfo, Vec<u8>), MediaError> {
+        let bytes = self.download_file(file_id).await?;
+        Ok((probe(&bytes)?, bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checkerboard(width: u32, height: u32) -> Image {
+        let mut pixels = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let on = (x + y) % 2 == 0;
+                pixels.extend_from_slice(if on {
+                    &[255, 0, 0, 255]
+                } else {
+                    &[0, 0, 255, 128]
+                });
+            }
+        }
+        Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    fn jpeg(width: u32, height: u32) -> Vec<u8> {
+        let pixels = vec![200u8; (width * height * 3) as usize];
+        let mut out = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new(&mut out)
+            .write_image(&pixels, width, height, image::ExtendedColorType::Rgb8)
+            .unwrap();
+        out
+    }
+
+    // A chunk right after IHDR. The CRC isn't checked on the way out.
+    fn with_png_chunk(png: &[u8], kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let ihdr_end = PNG_SIGNATURE.len() + 12 + 13;
+        let mut out = png[..ihdr_end].to_vec();
+        out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        out.extend_from_slice(kind);
+        out.extend_from_slice(data);
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&png[ihdr_end..]);
+        out
+    }
+
+    #[test]
+    fn png_round_trips() {
+        let image = checkerboard(5, 3);
+        let png = image.to_png();
+        assert_eq!(
+            probe(&png).unwrap(),
+            ImageInfo {
+                format: ImageFormat::Png,
+                width: 5,
+                height: 3
+            }
+        );
+        assert_eq!(Image::decode(&png).unwrap(), image);
+    }
+
+    #[test]
+    fn jpeg_size_comes_from_the_frame_header() {
+        let info = probe(&jpeg(40, 30)).unwrap();
+        assert_eq!(
+            (info.format, info.width, info.height),
+            (ImageFormat::Jpeg, 40, 30)
+        );
+        let decoded = Image::decode(&jpeg(40, 30)).unwrap();
+        assert_eq!((decoded.width, decoded.height), (40, 30));
+    }
+
+    #[test]
+    fn huge_headers_are_refused_before_decoding() {
+        let mut png = checkerboard(1, 1).to_png();
+        png[16..20].copy_from_slice(&100_000u32.to_be_bytes());
+        png[20..24].copy_from_slice(&100_000u32.to_be_bytes());
+        assert!(matches!(
+            Image::decode(&png),
+            Err(MediaError::Corrupt("impossible image size"))
+        ));
+    }
+
+    #[test]
+    fn unknown_and_truncated_files_are_errors() {
+        assert!(matches!(probe(b"hello"), Err(MediaError::UnknownFormat)));
+        let png = checkerboard(4, 4).to_png();
+        assert!(matches!(probe(&png[..12]), Err(MediaError::Corrupt(_))));
+        assert!(matches!(
+            Image::decode(&png[..png.len() / 2]),
+            Err(MediaError::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn png_text_chunks_are_stripped() {
+        let png = checkerboard(3, 3).to_png();
+        let tagged = with_png_chunk(&png, b"tEXt", b"Author\0someone");
+        let stripped = strip_metadata(&tagged).unwrap();
+        assert_eq!(stripped, png);
+    }
+
+    #[test]
+    fn jpeg_exif_is_stripped() {
+        let plain = jpeg(8, 8);
+        let mut tagged = plain[..2].to_vec();
+        let exif = b"Exif\0\0camera";
+        tagged.extend_from_slice(&[0xFF, 0xE1]);
+        tagged.extend_from_slice(&((exif.len() + 2) as u16).to_be_bytes());
+        tagged.extend_from_slice(exif);
+        tagged.extend_from_slice(&plain[2..]);
+        assert_eq!(strip_metadata(&tagged).unwrap(), plain);
+    }
+
+    #[test]
+    fn shrinking_weights_colors_by_alpha() {
+        let image = Image {
+            width: 2,
+            height: 1,
+            pixels: vec![255, 0, 0, 255, 0, 0, 0, 0],
+        };
+        assert_eq!(image.resize(1, 1).pixels, vec![255, 0, 0, 127]);
+    }
+
+    #[test]
+    fn fit_never_scales_up() {
+        let image = checkerboard(10, 4);
+        assert_eq!(image.fit(100, 100), image);
+        let fitted = image.fit(5, 5);
+        assert_eq!((fitted.width, fitted.height), (5, 2));
+    }
+
+    #[test]
+    fn stickers_get_a_512px_side() {
+        assert_eq!(sticker_size(1000, 500), (512, 256));
+        assert_eq!(sticker_size(10, 40), (128, 512));
+        let sticker = make_sticker(&jpeg(64, 32)).unwrap();
+        let info = validate_sticker(&sticker).unwrap();
+        assert_eq!(
+            (info.format, info.width, info.height),
+            (ImageFormat::Png, 512, 256)
+        );
+        assert_eq!(make_sticker(&sticker).unwrap(), sticker);
+    }
+
+    #[test]
+    fn jpeg_stickers_are_refused_as_they_are() {
+        assert!(matches!(
+            validate_sticker(&jpeg(512, 512)),
+            Err(MediaError::Unsupported(_))
+        ));
+    }
+}
@@ -0,0 +1,66 @@
+use crate::chat_cache;
+use crate::chat_info;
+use crate::chats;
+use crate::dedup;
+use crate::federation;
+use crate::history;
+use crate::permissions;
+use crate::settings;
+use crate::slowmode;
+use crate::topics;
+use crate::warnings;
+use futures::future::BoxFuture;
+use once_cell::sync::Lazy;
+use std::sync::{Arc, RwLock};
+use teloxide::types::{ChatId, Message};
+
+pub trait ChatIdMigration: Send + Sync {
+    fn migrate(&self, from: ChatId, to: ChatId) -> BoxFuture<'static, ()>;
+}
+
+impl<F> ChatIdMigration for F
+where
+    F: Fn(ChatId, ChatId) -> BoxFuture<'static, ()> + Send + Sync,
+{
+    fn migrate(&self, from: ChatId, to: ChatId) -> BoxFuture<'static, ()> {
+        self(from, to)
+    }
+}
+
+static HOOKS: Lazy<RwLock<Vec<Arc<dyn ChatIdMigration>>>> = Lazy::new(|| RwLock::new(Vec::new()));
+
+pub fn register_migration(hook: impl ChatIdMigration + 'static) {
+    HOOKS.write().unwrap().push(Arc::new(hook));
+}
+
+pub fn migration_of(msg: &Message) -> Option<(ChatId, ChatId)> {
+    if let Some(to) = msg.migrate_to_chat_id() {
+        return Some((msg.chat.id, *to));
+    }
+    None
+}
+
+pub async fn migrate(from: ChatId, to: ChatId) {
+    let _ = chat_cache::migrate(from, to).await;
+    history::migrate(from, to);
+    topics::migrate(from, to);
+    dedup::migrate(from, to);
+    #[cfg(feature = "lang-detect")]
+    crate::lang_detect::migrate(from, to);
+    permissions::forget_chat(from);
+    chat_info::invalidate(from);
+    slowmode::invalidate(from);
+    let _ = settings::migrate(from, to).await;
+    #[cfg(feature = "filters")]
+    crate::filters::invalidate();
+    let _ = chats::migrate(from, to).await;
+    let _ = federation::migrate(from, to).await;
+    let _ = warnings::migrate(from, to).await;
+    #[cfg(feature = "feeds")]
+    let _ = crate::feeds::migrate(from, to).await;
+
+    let hooks = HOOKS.read().unwrap().clone();
+    for hook in hooks {
+        hook.migrate(from, to).await;
+    }
+}
@@ -0,0 +1,165 @@
+use crate::context::PluginContext;
+use chrono::{DateTime, TimeDelta, Utc};
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::time::Duration;
+use teloxide::prelude::*;
+use teloxide::types::{ChatPermissions, MessageId};
+use teloxide::{ApiError, RequestError};
+
+// Telegram treats restrictions shorter than 30 seconds or longer than 366 days as
+// permanent. Durations are kept a little inside both limits, so request latency and
+// clock drift can't push them over.
+const SHORTEST: Duration = Duration::from_secs(35);
+const LONGEST: Duration = Duration::from_secs(366 * 24 * 60 * 60 - 3600);
+const DELETE_BATCH: i64 = 100;
+// About a day of a busy group. Anything bigger is more likely a wrong id than a purge.
+pub const MAX_PURGE: usize = 10_000;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PurgeReport {
+    pub requested: usize,
+    pub deleted: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+#[derive(Debug)]
+pub enum ModerationError {
+    NoChat,
+    NoSender,
+    RangeTooLarge(usize),
+    Request(RequestError),
+}
+
+impl fmt::Display for ModerationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModerationError::NoChat => write!(f, "the update has no chat to moderate"),
+            ModerationError::NoSender => write!(f, "the update has no sender to restrict"),
+            ModerationError::RangeTooLarge(requested) => write!(
+                f,
+                "{} messages are too many to purge at once, the limit is {}",
+                requested, MAX_PURGE
+            ),
+            ModerationError::Request(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for ModerationError {}
+
+impl From<RequestError> for ModerationError {
+    fn from(err: RequestError) -> Self {
+        ModerationError::Request(err)
+    }
+}
+
+pub fn until_date(duration: Option<Duration>) -> Option<DateTime<Utc>> {
+    // Only `None` means forever; a long duration is the longest Telegram keeps.
+    let duration = duration?.clamp(SHORTEST, LONGEST);
+    let delta = TimeDelta::from_std(duration).expect("a year fits in a TimeDelta");
+    Some(Utc::now() + delta)
+}
+
+impl PluginContext {
+    // Flood waits are left to `ctx.call`, which waits them out with the retry feature.
+    pub async fn purge_messages(
+        &self,
+        range: RangeInclusive<MessageId>,
+    ) -> Result<PurgeReport, ModerationError> {
+        let chat_id = self.chat_id().ok_or(ModerationError::NoChat)?;
+        let (first, last) = (range.start().0 as i64, range.end().0 as i64);
+        let requested = (last - first + 1).max(0) as usize;
+        if requested > MAX_PURGE {
+            return Err(ModerationError::RangeTooLarge(requested));
+        }
+        let mut report = PurgeReport {
+            requested,
+            ..PurgeReport::default()
+        };
+        let mut start = first;
+        while start <= last {
+            let end = last.min(start + DELETE_BATCH - 1);
+            let batch = (start..=end).map(|id| MessageId(id as i32));
+            start = end + 1;
+            match self
+                .call(self.bot.delete_messages(chat_id, batch.clone()))
+                .await
+            {
+                Ok(_) => {
+                    report.deleted += batch.count();
+                    continue;
+                }
+                Err(RequestError::Api(_)) => {}
+                Err(err) => return Err(err.into()),
+            }
+            // The batch call fails as a whole if any message in it can't be deleted,
+            // so retry one by one to delete the rest and find out which ones failed.
+            for id in batch {
+                match self.call(self.bot.delete_message(chat_id, id)).await {
+                    Ok(_) => report.deleted += 1,
+                    Err(RequestError::Api(
+                        ApiError::MessageToDeleteNotFound | ApiError::MessageIdInvalid,
+                    )) => report.skipped += 1,
+                    Err(RequestError::Api(_)) => report.failed += 1,
+                    Err(err) => return Err(err.into()),
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    pub async fn restrict(
+        &self,
+        user: UserId,
+        duration: Option<Duration>,
+        permissions: ChatPermissions,
+    ) -> Result<Option<DateTime<Utc>>, ModerationError> {
+        let chat_id = self.chat_id().ok_or(ModerationError::NoChat)?;
+        let until = until_date(duration);
+        let mut request = self
+            .bot
+            .restrict_chat_member(chat_id, user, permissions)
+            .use_independent_chat_permissions(true);
+        if let Some(until) = until {
+            request = request.until_date(until);
+        }
+        self.call(request).await?;
+        Ok(until)
+    }
+
+    pub async fn restrict_sender(
+        &self,
+        duration: Option<Duration>,
+        permissions: ChatPermissions,
+    ) -> Result<Option<DateTime<Utc>>, ModerationError> {
+        let user = self.sender().ok_or(ModerationError::NoSender)?.id;
+        self.restrict(user, duration, permissions).await
+    }
+
+    pub async fn mute(
+        &self,
+        user: UserId,
+        duration: Option<Duration>,
+    ) -> Result<Option<DateTime<Utc>>, ModerationError> {
+        self.restrict(user, duration, ChatPermissions::empty())
+            .await
+    }
+
+    pub async fn unrestrict(&self, user: UserId) -> Result<(), ModerationError> {
+        let chat_id = self.chat_id().ok_or(ModerationError::NoChat)?;
+        let defaults = self
+            .call(self.bot.get_chat(chat_id))
+            .await?
+            .permissions()
+            .unwrap_or_else(ChatPermissions::all);
+        self.call(
+            self.bot
+                .restrict_chat_member(chat_id, user, defaults)
+                .use_independent_chat_permissions(true),
+        )
+        .await?;
+        Ok(())
+    }
+}
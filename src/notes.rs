@@ -0,0 +1,439 @@
+use crate::context::{reply_of, split_command, PluginContext};
+use crate::entities::Entities;
+use crate::files;
+use crate::settings;
+use crate::storage::StorageError;
+use crate::templates::TemplateButton;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use teloxide::prelude::*;
+use teloxide::requests::HasPayload;
+use teloxide::types::{FileId, InlineKeyboardMarkup, InputFile, Message, ParseMode, ReplyMarkup};
+use teloxide::utils::render::RenderMessageTextHelper;
+
+const PREFIX: &str = "note:";
+const BUTTON_SCHEME: &str = "buttonurl://";
+const SAME_ROW: &str = ":same";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaKind {
+    Photo,
+    Video,
+    Animation,
+    Document,
+    Audio,
+    Voice,
+    Sticker,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NoteMedia {
+    pub kind: MediaKind,
+    pub file_id: String,
+}
+
+// `text` is HTML, so formatting from the saved message survives.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Note {
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media: Option<NoteMedia>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub buttons: Vec<Vec<TemplateButton>>,
+}
+
+#[derive(Debug)]
+pub enum NoteError {
+    NoChat,
+    InvalidUrl(String),
+    Request(teloxide::RequestError),
+    Storage(StorageError),
+}
+
+impl fmt::Display for NoteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NoteError::NoChat => write!(f, "the update has no chat to send the note to"),
+            NoteError::InvalidUrl(url) => write!(f, "invalid button url `{}`", url),
+            NoteError::Request(err) => write!(f, "{}", err),
+            NoteError::Storage(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for NoteError {}
+
+impl From<teloxide::RequestError> for NoteError {
+    fn from(err: teloxide::RequestError) -> Self {
+        NoteError::Request(err)
+    }
+}
+
+impl From<StorageError> for NoteError {
+    fn from(err: StorageError) -> Self {
+        NoteError::Storage(err)
+    }
+}
+
+fn setting_name(name: &str) -> String {
+    format!("{}{}", PREFIX, name)
+}
+
+pub fn normalize_name(name: &str) -> String {
+    name.trim().trim_start_matches('#').to_lowercase()
+}
+
+fn unescape_html(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&amp;", "&")
+}
+
+// Pulls `[Text](buttonurl://example.com)` out of the text. A `:same` suffix puts the
+// button on the previous button's row.
+pub fn parse_buttons(text: &str) -> (String, Vec<Vec<TemplateButton>>) {
+    let mut rows: Vec<Vec<TemplateButton>> = Vec::new();
+    let mut clean = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('[') {
+        let button = rest[start + 1..]
+            .split_once("](")
+            .and_then(|(label, after)| {
+                let target = after.strip_prefix(BUTTON_SCHEME)?;
+                let end = target.find(')')?;
+                Some((label, &target[..end], &target[end + 1..]))
+            });
+        let Some((label, target, after)) = button.filter(|(label, ..)| !label.contains('[')) else {
+            clean.push_str(&rest[..=start]);
+            rest = &rest[start + 1..];
+            continue;
+        };
+        clean.push_str(&rest[..start]);
+        let (target, same_row) = match target.strip_suffix(SAME_ROW) {
+            Some(target) => (target, true),
+            None => (target, false),
+        };
+        let target = unescape_html(target);
+        let url = if target.contains("://") {
+            target
+        } else {
+            format!("https://{}", target)
+        };
+        let button = TemplateButton::url(unescape_html(label), url);
+        match rows.last_mut() {
+            Some(row) if same_row => row.push(button),
+            _ => rows.push(vec![button]),
+        }
+        rest = after;
+    }
+    clean.push_str(rest);
+    let lines: Vec<&str> = clean.lines().map(str::trim_end).collect();
+    (lines.join("\n").trim().to_string(), rows)
+}
+
+fn media_of(msg: &Message) -> Option<NoteMedia> {
+    let (kind, file_id) = if let Some(photo) = msg.photo().and_then(|sizes| sizes.last()) {
+        (MediaKind::Photo, &photo.file.id)
+    } else if let Some(animation) = msg.animation() {
+        (MediaKind::Animation, &animation.file.id)
+    } else if let Some(video) = msg.video() {
+        (MediaKind::Video, &video.file.id)
+    } else if let Some(document) = msg.document() {
+        (MediaKind::Document, &document.file.id)
+    } else if let Some(audio) = msg.audio() {
+        (MediaKind::Audio, &audio.file.id)
+    } else if let Some(voice) = msg.voice() {
+        (MediaKind::Voice, &voice.file.id)
+    } else if let Some(sticker) = msg.sticker() {
+        (MediaKind::Sticker, &sticker.file.id)
+    } else {
+        return None;
+    };
+    Some(NoteMedia {
+        kind,
+        file_id: file_id.0.clone(),
+    })
+}
+
+fn html_of(msg: &Message) -> String {
+    msg.html_text()
+        .or_else(|| msg.html_caption())
+        .unwrap_or_default()
+}
+
+impl Note {
+    pub fn new(text: impl Into<String>) -> Self {
+        let (text, buttons) = parse_buttons(&text.into());
+        Self {
+            text,
+            media: None,
+            buttons,
+        }
+    }
+
+    // `/save name content`: the content comes from the command itself, and media
+    // (or the whole message, if there's no content) from the message it replies to.
+    pub fn from_command(msg: &Message) -> Option<(String, Note)> {
+        let html = html_of(msg);
+        let (_, rest) = split_command(&html);
+        let (name, content) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        let name = normalize_name(name);
+        if name.is_empty() {
+            return None;
+        }
+        let replied = reply_of(msg);
+        let content = match content.trim() {
+            "" => replied.map(html_of).unwrap_or_default(),
+            content => content.to_string(),
+        };
+        let mut note = Note::new(content);
+        note.media = replied.and_then(media_of);
+        (!note.text.is_empty() || note.media.is_some()).then_some((name, note))
+    }
+
+    fn markup(&self) -> Result<Option<ReplyMarkup>, NoteError> {
+        if self.buttons.is_empty() {
+            return Ok(None);
+        }
+        let mut rows = Vec::new();
+        for row in &self.buttons {
+            let mut buttons = Vec::new();
+            for button in row {
+                let url = button.url.clone().unwrap_or_default();
+                let parsed = url.parse().map_err(|_| NoteError::InvalidUrl(url))?;
+                buttons.push(teloxide::types::InlineKeyboardButton::url(
+                    button.text.clone(),
+                    parsed,
+                ));
+            }
+            rows.push(buttons);
+        }
+        Ok(Some(InlineKeyboardMarkup::new(rows).into()))
+    }
+}
+
+pub async fn get(chat: ChatId, name: &str) -> Result<Option<Note>, StorageError> {
+    settings::get(chat, &setting_name(&normalize_name(name))).await
+}
+
+pub async fn save(chat: ChatId, name: &str, note: &Note) -> Result<(), StorageError> {
+    settings::set(chat, &setting_name(&normalize_name(name)), note).await
+}
+
+pub async fn remove(chat: ChatId, name: &str) -> Result<bool, StorageError> {
+    let name = setting_name(&normalize_name(name));
+    let existed = settings::get::<Note>(chat, &name).await?.is_some();
+    settings::remove(chat, &name).await?;
+    Ok(existed)
+}
+
+pub async fn names(chat: ChatId) -> Result<Vec<String>, StorageError> {
+    let mut names: Vec<String> = settings::names(chat)
+        .await?
+        .into_iter()
+        .filter_map(|name| name.strip_prefix(PREFIX).map(str::to_string))
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+pub async fn export(chat: ChatId) -> Result<BTreeMap<String, Note>, StorageError> {
+    let mut notes = BTreeMap::new();
+    for name in names(chat).await? {
+        if let Some(note) = get(chat, &name).await? {
+            notes.insert(name, note);
+        }
+    }
+    Ok(notes)
+}
+
+pub async fn import(chat: ChatId, notes: BTreeMap<String, Note>) -> Result<usize, StorageError> {
+    let imported = notes.len();
+    for (name, note) in notes {
+        save(chat, &name, &note).await?;
+    }
+    Ok(imported)
+}
+
+impl PluginContext {
+    pub async fn send_note(&self, note: &Note) -> Result<(), NoteError> {
+        let chat = self.chat_id().ok_or(NoteError::NoChat)?;
+        let markup = note.markup()?;
+        let caption = (!note.text.is_empty()).then(|| note.text.clone());
+        let Some(media) = &note.media else {
+            let mut request = self.bot.send_message(chat, note.text.clone());
+            let payload = request.payload_mut();
+            payload.parse_mode = Some(ParseMode::Html);
+            payload.reply_markup = markup;
+            self.call(request).await?;
+            return Ok(());
+        };
+        let file = InputFile::file_id(FileId(media.file_id.clone()));
+        match media.kind {
+            MediaKind::Photo => {
+                let mut request = self.bot.send_photo(chat, file);
+                let payload = request.payload_mut();
+                payload.caption = caption;
+                payload.parse_mode = Some(ParseMode::Html);
+                payload.reply_markup = markup;
+                self.call(request).await?;
+            }
+            MediaKind::Video => {
+                let mut request = self.bot.send_video(chat, file);
+                let payload = request.payload_mut();
+                payload.caption = caption;
+                payload.parse_mode = Some(ParseMode::Html);
+                payload.reply_markup = markup;
+                self.call(request).await?;
+            }
+            MediaKind::Animation => {
+                let mut request = self.bot.send_animation(chat, file);
+                let payload = request.payload_mut();
+                payload.caption = caption;
+                payload.parse_mode = Some(ParseMode::Html);
+                payload.reply_markup = markup;
+                self.call(request).await?;
+            }
+            MediaKind::Document => {
+                let mut request = self.bot.send_document(chat, file);
+                let payload = request.payload_mut();
+                payload.caption = caption;
+                payload.parse_mode = Some(ParseMode::Html);
+                payload.reply_markup = markup;
+                self.call(request).await?;
+            }
+            MediaKind::Audio => {
+                let mut request = self.bot.send_audio(chat, file);
+                let payload = request.payload_mut();
+                payload.caption = caption;
+                payload.parse_mode = Some(ParseMode::Html);
+                payload.reply_markup = markup;
+                self.call(request).await?;
+            }
+            MediaKind::Voice => {
+                let mut request = self.bot.send_voice(chat, file);
+                let payload = request.payload_mut();
+                payload.caption = caption;
+                payload.parse_mode = Some(ParseMode::Html);
+                payload.reply_markup = markup;
+                self.call(request).await?;
+            }
+            // Stickers can't carry a caption, so the text follows as its own message.
+            MediaKind::Sticker => {
+                let mut request = self.bot.send_sticker(chat, file);
+                if caption.is_none() {
+                    request.payload_mut().reply_markup = markup;
+                    self.call(request).await?;
+                    return Ok(());
+                }
+                self.call(request).await?;
+                let mut request = self.bot.send_message(chat, note.text.clone());
+                let payload = request.payload_mut();
+                payload.parse_mode = Some(ParseMode::Html);
+                payload.reply_markup = markup;
+                self.call(request).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[crate::TeloxidePlugin(commands = ["save"], prefixes = ["/"], admin_only = true)]
+async fn save_command(ctx: PluginContext) -> Option<String> {
+    let Some((name, note)) = Note::from_command(ctx.message.as_ref()?) else {
+        return Some(String::from(
+            "Usage: /save <name> <content>, or reply to a message with /save <name>",
+        ));
+    };
+    Some(match save(ctx.chat_id()?, &name, &note).await {
+        Ok(()) => format!("Saved note #{}.", name),
+        Err(err) => format!("Couldn't save the note: {}", err),
+    })
+}
+
+#[crate::TeloxidePlugin(commands = ["get"], prefixes = ["/"])]
+async fn get_command(ctx: PluginContext) -> Option<String> {
+    let name = ctx.args().first()?.to_string();
+    let note = get(ctx.chat_id()?, &name).await.ok()?;
+    let Some(note) = note else {
+        return Some(format!(
+            "There's no note called #{}.",
+            normalize_name(&name)
+        ));
+    };
+    ctx.send_note(&note)
+        .await
+        .err()
+        .map(|err| format!("Couldn't send the note: {}", err))
+}
+
+// Below everything else by default, so bot-defined hashtag plugins aren't shadowed.
+#[crate::TeloxidePlugin(entity = ["hashtag"], priority = -100)]
+async fn hashtag_note(ctx: PluginContext, hashtags: Entities) {
+    let Some(chat) = ctx.chat_id() else {
+        return;
+    };
+    for hashtag in hashtags.of_kind("hashtag") {
+        if let Ok(Some(note)) = get(chat, hashtag).await {
+            let _ = ctx.send_note(&note).await;
+            return;
+        }
+    }
+}
+
+#[crate::TeloxidePlugin(commands = ["notes"], prefixes = ["/"])]
+async fn notes_command(ctx: PluginContext) -> Option<String> {
+    let names = names(ctx.chat_id()?).await.ok()?;
+    if names.is_empty() {
+        return Some(String::from("No notes in this chat."));
+    }
+    let list: Vec<String> = names.iter().map(|name| format!("#{}", name)).collect();
+    Some(format!("Notes in this chat:\n{}", list.join("\n")))
+}
+
+#[crate::TeloxidePlugin(commands = ["clear"], prefixes = ["/"], admin_only = true)]
+async fn clear_command(ctx: PluginContext) -> Option<String> {
+    let Some(name) = ctx.args().first().map(|name| normalize_name(name)) else {
+        return Some(String::from("Usage: /clear <name>"));
+    };
+    Some(match remove(ctx.chat_id()?, &name).await {
+        Ok(true) => format!("Removed note #{}.", name),
+        Ok(false) => format!("There's no note called #{}.", name),
+        Err(err) => format!("Couldn't remove the note: {}", err),
+    })
+}
+
+#[crate::TeloxidePlugin(commands = ["exportnotes"], prefixes = ["/"], admin_only = true)]
+async fn export_command(ctx: PluginContext) -> Option<String> {
+    let chat = ctx.chat_id()?;
+    let notes = match export(chat).await {
+        Ok(notes) => notes,
+        Err(err) => return Some(format!("Couldn't export the notes: {}", err)),
+    };
+    let json = serde_json::to_vec_pretty(&notes).ok()?;
+    let file = InputFile::memory(json).file_name("notes.json");
+    let sent = ctx.call(ctx.bot.send_document(chat, file)).await;
+    sent.err()
+        .map(|err| format!("Couldn't send the export: {}", err))
+}
+
+#[crate::TeloxidePlugin(commands = ["importnotes"], prefixes = ["/"], admin_only = true)]
+async fn import_command(ctx: PluginContext) -> Option<String> {
+    let Some(document) = ctx.replied_message().and_then(|msg| msg.document()) else {
+        return Some(String::from("Reply to a notes export with /importnotes."));
+    };
+    let raw = match files::download(&ctx.bot, document.file.id.clone()).await {
+        Ok(raw) => raw,
+        Err(err) => return Some(format!("Couldn't download the file: {}", err)),
+    };
+    let Ok(notes) = serde_json::from_slice::<BTreeMap<String, Note>>(&raw) else {
+        return Some(String::from("That file isn't a notes export."));
+    };
+    Some(match import(ctx.chat_id()?, notes).await {
+        Ok(count) => format!("Imported {} notes.", count),
+        Err(err) => format!("Couldn't import the notes: {}", err),
+    })
+}
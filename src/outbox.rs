@@ -0,0 +1,267 @@
+use crate::callbacks;
+use crate::context::PluginContext;
+use crate::registry;
+use crate::reporting::{self, ErrorKind};
+use crate::response::SendMessageParams;
+use crate::slowmode;
+use crate::storage::{self, StorageError};
+use crate::traffic;
+use chrono::{DateTime, Utc};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+use teloxide::prelude::*;
+use teloxide::types::{
+    BusinessConnectionId, ChatId, MessageId, ParseMode, ReplyMarkup, ReplyParameters,
+};
+use teloxide::RequestError;
+use tokio::sync::Notify;
+
+const PREFIX: &str = "outbox:";
+// Also how soon messages queued by other processes sharing the storage go out.
+const POLL: Duration = Duration::from_secs(5);
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(600);
+
+static BOT: OnceCell<Bot> = OnceCell::new();
+static WAKE: Notify = Notify::const_new();
+static NEXT_ID: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OutboxMessage {
+    pub id: String,
+    pub chat_id: ChatId,
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parse_mode: Option<ParseMode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_markup: Option<ReplyMarkup>,
+    #[serde(default)]
+    pub disable_notification: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<MessageId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub business_connection_id: Option<BusinessConnectionId>,
+    // Who may press the keyboard's buttons, as with a direct reply.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<UserId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub plugin: Option<String>,
+    pub created: DateTime<Utc>,
+    #[serde(default)]
+    pub attempts: u32,
+    pub next_attempt: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+impl OutboxMessage {
+    fn new(chat_id: ChatId, params: SendMessageParams) -> Self {
+        let now = Utc::now();
+        // Time first, so keys sort in the order messages were queued.
+        let id = format!(
+            "{:016x}-{:x}-{:x}",
+            now.timestamp_micros(),
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        );
+        let reply = params.reply.as_ref();
+        Self {
+            id,
+            chat_id: reply.and_then(|r| r.deliver_to).unwrap_or(chat_id),
+            text: params.text,
+            parse_mode: params.parse_mode,
+            reply_markup: params.reply_markup,
+            disable_notification: params.disable_notification,
+            reply_to: reply.and_then(|r| r.message_id),
+            business_connection_id: None,
+            owner: None,
+            plugin: None,
+            created: now,
+            attempts: 0,
+            next_attempt: now,
+            last_error: None,
+        }
+    }
+}
+
+fn key(id: &str) -> String {
+    format!("{}{}", PREFIX, id)
+}
+
+async fn save(message: &OutboxMessage) -> Result<(), StorageError> {
+    storage::set_json(&key(&message.id), message).await
+}
+
+// Queues a message for `chat_id` and returns its id. It's in storage before this
+// returns, and the flusher keeps trying until Telegram takes it or refuses it for
+// good.
+pub async fn send(chat_id: ChatId, params: SendMessageParams) -> Result<String, StorageError> {
+    queue(OutboxMessage::new(chat_id, params)).await
+}
+
+async fn queue(message: OutboxMessage) -> Result<String, StorageError> {
+    save(&message).await?;
+    WAKE.notify_one();
+    Ok(message.id)
+}
+
+// Messages waiting to go out, oldest first.
+pub async fn pending() -> Result<Vec<OutboxMessage>, StorageError> {
+    let mut messages = Vec::new();
+    for key in storage::storage().keys(PREFIX).await? {
+        if let Some(message) = storage::get_json::<OutboxMessage>(&key).await? {
+            messages.push(message);
+        }
+    }
+    messages.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(messages)
+}
+
+// Drops a queued message that hasn't gone out yet.
+pub async fn cancel(id: &str) -> Result<(), StorageError> {
+    storage::delete(&key(id)).await
+}
+
+// Starts the flusher. The dispatcher calls this in the process that receives updates;
+// call it once yourself when driving `registry::dispatch` without one.
+pub fn start(bot: Bot) {
+    if BOT.set(bot.clone()).is_ok() {
+        tokio::spawn(run(bot));
+    }
+}
+
+async fn run(bot: Bot) {
+    loop {
+        let wait = match flush(&bot).await {
+            Some(next) => (next - Utc::now()).to_std().unwrap_or_default().min(POLL),
+            None => POLL,
+        };
+        tokio::select! {
+            _ = WAKE.notified() => {}
+            _ = tokio::time::sleep(wait) => {}
+        }
+    }
+}
+
+// Sends everything that's due and returns when the next retry is. Messages to a chat
+// go out in order: while one waits for a retry, the ones after it wait too.
+async fn flush(bot: &Bot) -> Option<DateTime<Utc>> {
+    let messages = pending().await.ok()?;
+    let mut held = HashSet::new();
+    let mut next: Option<DateTime<Utc>> = None;
+    for mut message in messages {
+        if held.contains(&message.chat_id) {
+            continue;
+        }
+        if message.next_attempt > Utc::now() {
+            held.insert(message.chat_id);
+            next = Some(next.map_or(message.next_attempt, |at| at.min(message.next_attempt)));
+            continue;
+        }
+
+        let error = match deliver(bot, &message).await {
+            Ok(()) => {
+                let _ = cancel(&message.id).await;
+                continue;
+            }
+            Err(err) => err,
+        };
+        held.insert(message.chat_id);
+        let wait = match &error {
+            RequestError::MigrateToChatId(chat) => {
+                message.chat_id = *chat;
+                Duration::ZERO
+            }
+            RequestError::RetryAfter(wait) => wait.duration(),
+            RequestError::Network(_) | RequestError::Io(_) | RequestError::InvalidJson { .. } => {
+                (FIRST_BACKOFF * 2u32.saturating_pow(message.attempts)).min(MAX_BACKOFF)
+            }
+            // Telegram refused it, and will again: a blocked bot, a deleted chat, bad
+            // markup.
+            RequestError::Api(_) => {
+                let _ = cancel(&message.id).await;
+                let plugin = message.plugin.as_deref().and_then(plugin_named);
+                reporting::report_background(
+                    plugin,
+                    ErrorKind::Send,
+                    Some(message.chat_id),
+                    &error,
+                )
+                .await;
+                continue;
+            }
+        };
+        message.attempts += 1;
+        message.next_attempt = Utc::now() + wait;
+        message.last_error = Some(error.to_string());
+        let _ = save(&message).await;
+        next = Some(next.map_or(message.next_attempt, |at| at.min(message.next_attempt)));
+    }
+    next
+}
+
+async fn deliver(bot: &Bot, message: &OutboxMessage) -> Result<(), RequestError> {
+    slowmode::wait(bot, message.chat_id).await;
+    let mut request = match &message.business_connection_id {
+        Some(id) => bot
+            .send_message(message.chat_id, message.text.clone())
+            .business_connection_id(id.clone()),
+        None => bot.send_message(message.chat_id, message.text.clone()),
+    };
+    if let Some(mode) = message.parse_mode {
+        request = request.parse_mode(mode);
+    }
+    if let Some(markup) = message.reply_markup.clone() {
+        request = request.reply_markup(markup);
+    }
+    if message.disable_notification {
+        request = request.disable_notification(true);
+    }
+    if let Some(reply_to) = message.reply_to {
+        // The trigger may be gone by the time a retry goes out.
+        request =
+            request.reply_parameters(ReplyParameters::new(reply_to).allow_sending_without_reply());
+    }
+    let sent = match message.plugin.as_deref().and_then(plugin_named) {
+        Some(plugin) => traffic::scope(plugin, traffic::send(request)).await?,
+        None => traffic::send(request).await?,
+    };
+    let keyboard = matches!(message.reply_markup, Some(ReplyMarkup::InlineKeyboard(_)));
+    if let (true, Some(owner)) = (keyboard, message.owner) {
+        callbacks::remember_owner(sent.chat.id, sent.id, owner);
+    }
+    Ok(())
+}
+
+fn plugin_named(name: &str) -> Option<&'static str> {
+    registry::plugins()
+        .into_iter()
+        .find(|plugin| plugin.name == name)
+        .map(|plugin| plugin.name)
+}
+
+impl PluginContext {
+    // Like `send`, through the outbox: the message survives a crash or a Telegram
+    // outage and goes out once it can. Replies point at the triggering message.
+    pub async fn send_durable(&self, params: SendMessageParams) -> Result<String, StorageError> {
+        let chat_id = self
+            .chat_id()
+            .or_else(|| params.reply.as_ref().and_then(|r| r.deliver_to))
+            .ok_or_else(|| StorageError::Backend("no chat to send to".into()))?;
+        let replying = params.reply.is_some();
+        let mut message = OutboxMessage::new(chat_id, params);
+        // Only a reply in the same chat can point at the trigger.
+        if replying && Some(message.chat_id) == self.chat_id() {
+            message.reply_to = message
+                .reply_to
+                .or_else(|| self.message.as_ref().map(|m| m.id));
+        }
+        message.business_connection_id = self.business_connection_id().cloned();
+        message.owner = self.sender().map(|user| user.id);
+        message.plugin = self.plugin.map(|plugin| plugin.name.to_string());
+        queue(message).await
+    }
+}
@@ -0,0 +1,345 @@
+use crate::context::PluginContext;
+use crate::extract::FromContext;
+use crate::traffic;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt;
+use teloxide::prelude::*;
+use teloxide::types::{LabeledPrice, PreCheckoutQuery, SuccessfulPayment, TelegramTransactionId};
+use url::Url;
+
+pub const STARS_CURRENCY: &str = "XTR";
+
+const MAX_PAYLOAD_LEN: usize = 128;
+
+#[derive(Debug)]
+pub enum PaymentError {
+    PayloadTooLong(usize),
+    Payload(serde_json::Error),
+    NoChat,
+    NoPrices,
+    NoPayment,
+    NoCheckout,
+    Request(teloxide::RequestError),
+}
+
+impl fmt::Display for PaymentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaymentError::PayloadTooLong(len) => write!(
+                f,
+                "invoice payload is {} bytes, at most {} are allowed",
+                len, MAX_PAYLOAD_LEN
+            ),
+            PaymentError::Payload(err) => write!(f, "invalid invoice payload: {}", err),
+            PaymentError::NoChat => write!(f, "there is no chat to send the invoice to"),
+            PaymentError::NoPrices => write!(f, "an invoice needs at least one price"),
+            PaymentError::NoPayment => write!(f, "there is no payment to refund"),
+            PaymentError::NoCheckout => write!(f, "there is no pre-checkout query to answer"),
+            PaymentError::Request(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for PaymentError {}
+
+impl From<teloxide::RequestError> for PaymentError {
+    fn from(err: teloxide::RequestError) -> Self {
+        PaymentError::Request(err)
+    }
+}
+
+impl From<serde_json::Error> for PaymentError {
+    fn from(err: serde_json::Error) -> Self {
+        PaymentError::Payload(err)
+    }
+}
+
+// The money has already moved when this arrives, so a payload that doesn't decode
+// still reaches the handler (as an error) instead of skipping it.
+#[derive(Debug)]
+pub struct Payment<T> {
+    pub payload: Result<T, PaymentError>,
+    pub payment: SuccessfulPayment,
+}
+
+impl<T> Payment<T> {
+    pub fn amount(&self) -> u32 {
+        self.payment.total_amount
+    }
+
+    pub fn charge_id(&self) -> &TelegramTransactionId {
+        &self.payment.telegram_payment_charge_id
+    }
+}
+
+impl<T: DeserializeOwned> FromContext for Payment<T> {
+    fn from_context(ctx: &PluginContext) -> Option<Self> {
+        let payment = ctx.message.as_ref()?.successful_payment()?.clone();
+        let payload = serde_json::from_str(&payment.invoice_payload).map_err(PaymentError::from);
+        Some(Payment { payload, payment })
+    }
+}
+
+pub struct Checkout<T> {
+    pub payload: T,
+    pub query: PreCheckoutQuery,
+    bot: Bot,
+}
+
+impl<T> Checkout<T> {
+    pub fn amount(&self) -> u32 {
+        self.query.total_amount
+    }
+
+    pub async fn approve(&self) -> Result<(), PaymentError> {
+        self.answer(Ok::<(), String>(())).await
+    }
+
+    pub async fn reject(&self, reason: impl Into<String>) -> Result<(), PaymentError> {
+        self.answer(Err(reason)).await
+    }
+
+    pub async fn answer<R: Into<String>>(
+        &self,
+        verdict: Result<(), R>,
+    ) -> Result<(), PaymentError> {
+        let request = self
+            .bot
+            .answer_pre_checkout_query(self.query.id.clone(), verdict.is_ok());
+        match verdict {
+            Ok(()) => traffic::send(request).await?,
+            Err(reason) => traffic::send(request.error_message(reason)).await?,
+        };
+        Ok(())
+    }
+}
+
+impl<T: DeserializeOwned> FromContext for Checkout<T> {
+    fn from_context(ctx: &PluginContext) -> Option<Self> {
+        let query = ctx.pre_checkout_query.clone()?;
+        let payload = match serde_json::from_str(&query.invoice_payload) {
+            Ok(payload) => payload,
+            // Turned down right away, so the buyer isn't left waiting for a timeout.
+            Err(_) => {
+                let request = ctx
+                    .bot
+                    .answer_pre_checkout_query(query.id, false)
+                    .error_message(ctx.framework_texts().invalid_checkout.clone());
+                let plugin = traffic::current_plugin();
+                tokio::spawn(async move {
+                    match plugin {
+                        Some(plugin) => traffic::scope(plugin, traffic::send(request)).await,
+                        None => traffic::send(request).await,
+                    }
+                });
+                return None;
+            }
+        };
+        Some(Checkout {
+            payload,
+            query,
+            bot: ctx.bot.clone(),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Invoice {
+    title: String,
+    description: String,
+    payload: Result<String, PaymentError>,
+    currency: String,
+    provider_token: Option<String>,
+    prices: Vec<LabeledPrice>,
+    provider_data: Option<String>,
+    max_tip_amount: Option<u32>,
+    suggested_tip_amounts: Vec<u32>,
+    photo_url: Option<Url>,
+    need_name: bool,
+    need_phone_number: bool,
+    need_email: bool,
+    need_shipping_address: bool,
+    is_flexible: bool,
+}
+
+impl Invoice {
+    pub fn new<T: Serialize>(
+        title: impl Into<String>,
+        description: impl Into<String>,
+        payload: &T,
+    ) -> Self {
+        Self {
+            title: title.into(),
+            description: description.into(),
+            payload: encode_payload(payload),
+            currency: STARS_CURRENCY.to_string(),
+            provider_token: None,
+            prices: Vec::new(),
+            provider_data: None,
+            max_tip_amount: None,
+            suggested_tip_amounts: Vec::new(),
+            photo_url: None,
+            need_name: false,
+            need_phone_number: false,
+            need_email: false,
+            need_shipping_address: false,
+            is_flexible: false,
+        }
+    }
+
+    pub fn provider(mut self, token: impl Into<String>, currency: impl Into<String>) -> Self {
+        self.provider_token = Some(token.into());
+        self.currency = currency.into();
+        self
+    }
+
+    pub fn price(mut self, label: impl Into<String>, amount: u32) -> Self {
+        self.prices.push(LabeledPrice::new(label, amount));
+        self
+    }
+
+    pub fn provider_data<D: Serialize>(mut self, data: &D) -> Self {
+        self.provider_data = serde_json::to_string(data).ok();
+        self
+    }
+
+    pub fn tips(mut self, max: u32, suggested: impl IntoIterator<Item = u32>) -> Self {
+        self.max_tip_amount = Some(max);
+        self.suggested_tip_amounts = suggested.into_iter().collect();
+        self
+    }
+
+    pub fn photo(mut self, url: Url) -> Self {
+        self.photo_url = Some(url);
+        self
+    }
+
+    pub fn need_name(mut self) -> Self {
+        self.need_name = true;
+        self
+    }
+
+    pub fn need_phone_number(mut self) -> Self {
+        self.need_phone_number = true;
+        self
+    }
+
+    pub fn need_email(mut self) -> Self {
+        self.need_email = true;
+        self
+    }
+
+    pub fn need_shipping_address(mut self) -> Self {
+        self.need_shipping_address = true;
+        self.is_flexible = true;
+        self
+    }
+
+    pub async fn send(self, ctx: &PluginContext) -> Result<Message, PaymentError> {
+        let chat_id = ctx.chat_id().ok_or(PaymentError::NoChat)?;
+        let payload = self.payload?;
+        if self.prices.is_empty() {
+            return Err(PaymentError::NoPrices);
+        }
+
+        let mut request = ctx
+            .bot
+            .send_invoice(
+                chat_id,
+                self.title,
+                self.description,
+                payload,
+                self.currency,
+                self.prices,
+            )
+            .need_name(self.need_name)
+            .need_phone_number(self.need_phone_number)
+            .need_email(self.need_email)
+            .need_shipping_address(self.need_shipping_address)
+            .is_flexible(self.is_flexible);
+        if let Some(token) = self.provider_token {
+            request = request.provider_token(token);
+        }
+        if let Some(data) = self.provider_data {
+            request = request.provider_data(data);
+        }
+        if let Some(max) = self.max_tip_amount {
+            request = request
+                .max_tip_amount(max)
+                .suggested_tip_amounts(self.suggested_tip_amounts);
+        }
+        if let Some(url) = self.photo_url {
+            request = request.photo_url(url);
+        }
+        Ok(ctx.call(request).await?)
+    }
+}
+
+pub fn encode_payload<T: Serialize>(payload: &T) -> Result<String, PaymentError> {
+    let encoded = serde_json::to_string(payload)?;
+    if encoded.len() > MAX_PAYLOAD_LEN {
+        return Err(PaymentError::PayloadTooLong(encoded.len()));
+    }
+    Ok(encoded)
+}
+
+impl PluginContext {
+    pub async fn send_stars_invoice<T: Serialize>(
+        &self,
+        title: &str,
+        description: &str,
+        payload: &T,
+        amount: u32,
+    ) -> Result<Message, PaymentError> {
+        Invoice::new(title, description, payload)
+            .price(title, amount)
+            .send(self)
+            .await
+    }
+
+    pub async fn approve_checkout(&self) -> Result<(), PaymentError> {
+        let query = self
+            .pre_checkout_query
+            .as_ref()
+            .ok_or(PaymentError::NoCheckout)?;
+        self.call(self.bot.answer_pre_checkout_query(query.id.clone(), true))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn reject_checkout(&self, reason: &str) -> Result<(), PaymentError> {
+        let query = self
+            .pre_checkout_query
+            .as_ref()
+            .ok_or(PaymentError::NoCheckout)?;
+        self.call(
+            self.bot
+                .answer_pre_checkout_query(query.id.clone(), false)
+                .error_message(reason),
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn refund(&self) -> Result<(), PaymentError> {
+        let payment = self
+            .message
+            .as_ref()
+            .and_then(|m| m.successful_payment())
+            .ok_or(PaymentError::NoPayment)?;
+        let user = self.sender().ok_or(PaymentError::NoPayment)?;
+        self.refund_stars(user.id, payment.telegram_payment_charge_id.clone())
+            .await
+    }
+
+    pub async fn refund_stars(
+        &self,
+        user_id: UserId,
+        charge_id: TelegramTransactionId,
+    ) -> Result<(), PaymentError> {
+        self.call(self.bot.refund_star_payment(user_id, charge_id))
+            .await?;
+        Ok(())
+    }
+}
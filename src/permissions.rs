@@ -0,0 +1,357 @@
+use crate::chat_cache;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use teloxide::prelude::*;
+use teloxide::types::{ChatMember, ChatMemberKind};
+
+pub const RIGHTS: &[&str] = &[
+    "can_manage_chat",
+    "can_change_info",
+    "can_post_messages",
+    "can_edit_messages",
+    "can_delete_messages",
+    "can_post_stories",
+    "can_edit_stories",
+    "can_delete_stories",
+    "can_manage_video_chats",
+    "can_invite_users",
+    "can_restrict_members",
+    "can_pin_messages",
+    "can_manage_topics",
+    "can_promote_members",
+];
+
+const MEMBER_TTL: Duration = Duration::from_secs(300);
+// Past this many cached lookups, expired ones are dropped before anything new goes in,
+// and if that isn't enough, the oldest tenth.
+const CACHE_CAPACITY: usize = 10_000;
+
+struct CachedMember {
+    fetched: Instant,
+    member: ChatMember,
+}
+
+struct CachedAdmins {
+    fetched: Instant,
+    admins: Vec<ChatMember>,
+}
+
+static USERNAMES: Lazy<RwLock<HashMap<UserId, String>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+static MEMBER_CACHE: Lazy<RwLock<HashMap<(ChatId, UserId), CachedMember>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+static ADMIN_CACHE: Lazy<RwLock<HashMap<ChatId, CachedAdmins>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+// Makes room in a cache that's full, going by when each entry was fetched.
+fn make_room<K: Copy + Eq + std::hash::Hash, V>(
+    cache: &mut HashMap<K, V>,
+    fetched: impl Fn(&V) -> Instant,
+) {
+    if cache.len() < CACHE_CAPACITY {
+        return;
+    }
+    cache.retain(|_, entry| fetched(entry).elapsed() < MEMBER_TTL);
+    if cache.len() < CACHE_CAPACITY {
+        return;
+    }
+    let mut ages: Vec<(Instant, K)> = cache.iter().map(|(key, v)| (fetched(v), *key)).collect();
+    let drop = (ages.len() / 10).max(1);
+    ages.select_nth_unstable_by_key(drop - 1, |(fetched, _)| *fetched);
+    for (_, key) in &ages[..drop] {
+        cache.remove(key);
+    }
+}
+
+pub async fn chat_member(
+    bot: &Bot,
+    chat_id: ChatId,
+    user_id: UserId,
+) -> Result<ChatMember, teloxide::RequestError> {
+    {
+        let cache = MEMBER_CACHE.read().unwrap();
+        if let Some(cached) = cache.get(&(chat_id, user_id)) {
+            if cached.fetched.elapsed() < MEMBER_TTL {
+                return Ok(cached.member.clone());
+            }
+        }
+    }
+
+    let member = crate::traffic::send(bot.get_chat_member(chat_id, user_id)).await?;
+    let _ = chat_cache::record_member(chat_id, &member).await;
+    let mut cache = MEMBER_CACHE.write().unwrap();
+    make_room(&mut cache, |cached| cached.fetched);
+    cache.insert(
+        (chat_id, user_id),
+        CachedMember {
+            fetched: Instant::now(),
+            member: member.clone(),
+        },
+    );
+    Ok(member)
+}
+
+pub async fn chat_administrators(
+    bot: &Bot,
+    chat_id: ChatId,
+) -> Result<Vec<ChatMember>, teloxide::RequestError> {
+    if let Some(cached) = ADMIN_CACHE.read().unwrap().get(&chat_id) {
+        if cached.fetched.elapsed() < MEMBER_TTL {
+            return Ok(cached.admins.clone());
+        }
+    }
+
+    let admins = crate::traffic::send(bot.get_chat_administrators(chat_id)).await?;
+    let mut cache = ADMIN_CACHE.write().unwrap();
+    make_room(&mut cache, |cached| cached.fetched);
+    cache.insert(
+        chat_id,
+        CachedAdmins {
+            fetched: Instant::now(),
+            admins: admins.clone(),
+        },
+    );
+    Ok(admins)
+}
+
+// Anonymous admins post as the group, so there's no telling which of them sent a
+// message. Returns the rights that at least one anonymous admin lacks: the message
+// only passes if whoever sent it had them. All of them when there's no anonymous admin
+// to go by.
+pub async fn anonymous_admins_missing(
+    bot: &Bot,
+    chat_id: ChatId,
+    rights: &[&'static str],
+) -> Result<Vec<&'static str>, teloxide::RequestError> {
+    let admins = chat_administrators(bot, chat_id).await?;
+    let anonymous: Vec<&ChatMember> = admins
+        .iter()
+        .filter(|admin| admin.kind.is_anonymous())
+        .collect();
+    if anonymous.is_empty() {
+        return Ok(rights.to_vec());
+    }
+    Ok(rights
+        .iter()
+        .copied()
+        .filter(|right| anonymous.iter().any(|admin| !has_right(admin, right)))
+        .collect())
+}
+
+pub async fn bot_user_id(bot: &Bot) -> Result<UserId, teloxide::RequestError> {
+    let from_token = bot
+        .token()
+        .split(':')
+        .next()
+        .and_then(|id| id.parse::<u64>().ok());
+    match from_token {
+        Some(id) => Ok(UserId(id)),
+        None => Ok(bot.get_me().await?.id),
+    }
+}
+
+// Without the `@`. Asked once per bot and remembered.
+pub async fn bot_username(bot: &Bot) -> Result<String, teloxide::RequestError> {
+    let id = bot_user_id(bot).await?;
+    if let Some(username) = USERNAMES.read().unwrap().get(&id) {
+        return Ok(username.clone());
+    }
+    let me = crate::traffic::send(bot.get_me()).await?;
+    let username = me.username.clone().unwrap_or_default();
+    USERNAMES.write().unwrap().insert(id, username.clone());
+    Ok(username)
+}
+
+pub async fn bot_member(bot: &Bot, chat_id: ChatId) -> Result<ChatMember, teloxide::RequestError> {
+    let bot_id = bot_user_id(bot).await?;
+    chat_member(bot, chat_id, bot_id).await
+}
+
+// Lets other modules' tests decide what a lookup finds without asking Telegram.
+#[cfg(test)]
+pub(crate) fn remember_member(chat_id: ChatId, member: ChatMember) {
+    MEMBER_CACHE.write().unwrap().insert(
+        (chat_id, member.user.id),
+        CachedMember {
+            fetched: Instant::now(),
+            member,
+        },
+    );
+}
+
+// The dispatcher calls this for `chat_member` and `my_chat_member` updates, so a
+// promotion, demotion or ban takes effect right away rather than once the cached
+// lookup expires.
+pub fn invalidate_member(chat_id: ChatId, user_id: UserId) {
+    MEMBER_CACHE.write().unwrap().remove(&(chat_id, user_id));
+    ADMIN_CACHE.write().unwrap().remove(&chat_id);
+}
+
+pub(crate) fn forget_chat(chat_id: ChatId) {
+    MEMBER_CACHE
+        .write()
+        .unwrap()
+        .retain(|(chat, _), _| *chat != chat_id);
+    ADMIN_CACHE.write().unwrap().remove(&chat_id);
+}
+
+pub fn has_right(member: &ChatMember, right: &str) -> bool {
+    let admin = match &member.kind {
+        ChatMemberKind::Owner(_) => return true,
+        ChatMemberKind::Administrator(admin) => admin,
+        _ => return false,
+    };
+
+    match right {
+        "can_manage_chat" => admin.can_manage_chat,
+        "can_change_info" => admin.can_change_info,
+        "can_post_messages" => admin.can_post_messages,
+        "can_edit_messages" => admin.can_edit_messages,
+        "can_delete_messages" => admin.can_delete_messages,
+        "can_post_stories" => admin.can_post_stories,
+        "can_edit_stories" => admin.can_edit_stories,
+        "can_delete_stories" => admin.can_delete_stories,
+        "can_manage_video_chats" => admin.can_manage_video_chats,
+        "can_invite_users" => admin.can_invite_users,
+        "can_restrict_members" => admin.can_restrict_members,
+        "can_pin_messages" => admin.can_pin_messages,
+        "can_manage_topics" => admin.can_manage_topics,
+        "can_promote_members" => admin.can_promote_members,
+        _ => false,
+    }
+}
+
+pub fn missing_rights(member: &ChatMember, rights: &[&'static str]) -> Vec<&'static str> {
+    rights
+        .iter()
+        .copied()
+        .filter(|right| !has_right(member, right))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn admin(id: u64, anonymous: bool, can_pin: bool) -> ChatMember {
+        serde_json::from_value(json!({
+            "status": "administrator",
+            "user": {"id": id, "is_bot": false, "first_name": "Admin"},
+            "can_be_edited": false,
+            "is_anonymous": anonymous,
+            "can_manage_chat": true,
+            "can_delete_messages": true,
+            "can_manage_video_chats": false,
+            "can_restrict_members": true,
+            "can_promote_members": false,
+            "can_change_info": false,
+            "can_invite_users": true,
+            "can_post_stories": false,
+            "can_edit_stories": false,
+            "can_delete_stories": false,
+            "can_pin_messages": can_pin,
+        }))
+        .unwrap()
+    }
+
+    fn member(id: u64) -> ChatMember {
+        serde_json::from_value(json!({
+            "status": "member",
+            "user": {"id": id, "is_bot": false, "first_name": "Ann"},
+        }))
+        .unwrap()
+    }
+
+    fn cache_member(chat: ChatId, member: ChatMember, fetched: Instant) {
+        MEMBER_CACHE
+            .write()
+            .unwrap()
+            .insert((chat, member.user.id), CachedMember { fetched, member });
+    }
+
+    #[test]
+    fn rights_come_from_the_admin_record() {
+        let pinner = admin(1, false, true);
+        assert!(has_right(&pinner, "can_pin_messages"));
+        assert!(!has_right(&member(2), "can_delete_messages"));
+        assert_eq!(
+            missing_rights(
+                &admin(3, false, false),
+                &["can_pin_messages", "can_invite_users"]
+            ),
+            ["can_pin_messages"]
+        );
+    }
+
+    #[tokio::test]
+    async fn serves_fresh_lookups_from_the_cache_until_invalidated() {
+        let bot = Bot::new("1:test");
+        let chat = ChatId(-1001);
+        cache_member(chat, admin(5, false, true), Instant::now());
+
+        let cached = chat_member(&bot, chat, UserId(5)).await.unwrap();
+        assert!(cached.is_privileged());
+
+        invalidate_member(chat, UserId(5));
+        assert!(!MEMBER_CACHE
+            .read()
+            .unwrap()
+            .contains_key(&(chat, UserId(5))));
+    }
+
+    #[tokio::test]
+    async fn anonymous_admins_must_all_hold_a_right() {
+        let bot = Bot::new("1:test");
+        let chat = ChatId(-1002);
+        let admins = vec![
+            admin(1, true, true),
+            admin(2, true, false),
+            admin(3, false, false),
+        ];
+        ADMIN_CACHE.write().unwrap().insert(
+            chat,
+            CachedAdmins {
+                fetched: Instant::now(),
+                admins,
+            },
+        );
+        let missing =
+            anonymous_admins_missing(&bot, chat, &["can_pin_messages", "can_invite_users"])
+                .await
+                .unwrap();
+        assert_eq!(missing, ["can_pin_messages"]);
+
+        forget_chat(chat);
+        assert!(!ADMIN_CACHE.read().unwrap().contains_key(&chat));
+    }
+
+    #[test]
+    fn a_full_cache_drops_expired_entries_first() {
+        let now = Instant::now();
+        let expired = now - MEMBER_TTL - Duration::from_secs(1);
+        let mut cache: HashMap<usize, Instant> = (0..CACHE_CAPACITY)
+            .map(|key| (key, if key % 2 == 0 { expired } else { now }))
+            .collect();
+        make_room(&mut cache, |fetched| *fetched);
+        assert_eq!(cache.len(), CACHE_CAPACITY / 2);
+        assert!(cache.keys().all(|key| key % 2 == 1));
+    }
+
+    #[test]
+    fn a_full_cache_of_fresh_entries_drops_the_oldest_tenth() {
+        let now = Instant::now();
+        let mut cache: HashMap<usize, Instant> = (0..CACHE_CAPACITY)
+            .map(|key| {
+                (
+                    key,
+                    now - Duration::from_millis((CACHE_CAPACITY - key) as u64),
+                )
+            })
+            .collect();
+        make_room(&mut cache, |fetched| *fetched);
+        assert_eq!(cache.len(), CACHE_CAPACITY - CACHE_CAPACITY / 10);
+        assert!((0..CACHE_CAPACITY / 10).all(|key| !cache.contains_key(&key)));
+    }
+}
@@ -0,0 +1,325 @@
+use crate::context::PluginContext;
+use crate::registry::{self, PluginMeta};
+use crate::response::{self, PluginResponse};
+use crate::scheduler;
+use futures::future::BoxFuture;
+use once_cell::sync::{Lazy, OnceCell};
+use regex::Regex;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+
+static INSTALLED: Lazy<Mutex<Vec<&'static dyn Plugin>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+pub trait Plugin: Send + Sync + 'static {
+    fn triggers(&self) -> Triggers;
+
+    fn handle(&self, ctx: PluginContext) -> BoxFuture<'_, PluginResponse>;
+
+    fn init(&self) -> BoxFuture<'_, ()> {
+        Box::pin(async {})
+    }
+
+    fn shutdown(&self) -> BoxFuture<'_, ()> {
+        Box::pin(async {})
+    }
+
+    // Called by `reload`, for plugins that read their configuration once and can
+    // read it again without a restart.
+    fn reload(&self) -> BoxFuture<'_, ()> {
+        Box::pin(async {})
+    }
+}
+
+// Implemented by `#[derive(Plugin)]`: one static slot per type, so the registry's
+// plain `fn` callbacks can reach the instance.
+#[doc(hidden)]
+pub trait PluginSlot: Sized + 'static {
+    const NAME: &'static str;
+
+    fn slot() -> &'static OnceCell<Self>;
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Triggers {
+    name: Option<String>,
+    priority: i32,
+    commands: Vec<String>,
+    prefixes: Vec<String>,
+    regex: Option<String>,
+    not_regex: Option<String>,
+    exclude_commands: Vec<String>,
+    callbacks: Vec<String>,
+    inline: Option<String>,
+    updates: Vec<String>,
+    cron: Option<String>,
+    tz: Option<String>,
+    external: Vec<String>,
+    requires_reply: bool,
+    admin_only: bool,
+    role: Option<String>,
+    cooldown: u64,
+}
+
+#[derive(Debug)]
+pub enum InstallError {
+    AlreadyInstalled(&'static str),
+    NoTriggers(&'static str),
+    MixedTriggers(&'static str),
+    InvalidRegex {
+        plugin: &'static str,
+        reason: String,
+    },
+    InvalidCron(scheduler::SchedulerError),
+}
+
+impl fmt::Display for InstallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InstallError::AlreadyInstalled(name) => {
+                write!(f, "plugin `{}` is already installed", name)
+            }
+            InstallError::NoTriggers(name) => write!(f, "plugin `{}` has no triggers", name),
+            InstallError::MixedTriggers(name) => write!(
+                f,
+                "plugin `{}` mixes message or callback triggers with inline, update, cron or external ones",
+                name
+            ),
+            InstallError::InvalidRegex { plugin, reason } => {
+                write!(f, "invalid regex for plugin `{}`: {}", plugin, reason)
+            }
+            InstallError::InvalidCron(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for InstallError {}
+
+impl Triggers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn commands(mut self, commands: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.commands.extend(commands.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn prefixes(mut self, prefixes: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.prefixes.extend(prefixes.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn regex(mut self, pattern: impl Into<String>) -> Self {
+        self.regex = Some(pattern.into());
+        self
+    }
+
+    pub fn not_regex(mut self, pattern: impl Into<String>) -> Self {
+        self.not_regex = Some(pattern.into());
+        self
+    }
+
+    pub fn exclude_commands(
+        mut self,
+        commands: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.exclude_commands
+            .extend(commands.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn callback(mut self, names: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.callbacks.extend(names.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn inline(mut self, pattern: impl Into<String>) -> Self {
+        self.inline = Some(pattern.into());
+        self
+    }
+
+    pub fn updates(mut self, kinds: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.updates.extend(kinds.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn cron(mut self, pattern: impl Into<String>) -> Self {
+        self.cron = Some(pattern.into());
+        self
+    }
+
+    pub fn tz(mut self, tz: impl Into<String>) -> Self {
+        self.tz = Some(tz.into());
+        self
+    }
+
+    pub fn external(mut self, names: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.external.extend(names.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn requires_reply(mut self) -> Self {
+        self.requires_reply = true;
+        self
+    }
+
+    pub fn admin_only(mut self) -> Self {
+        self.admin_only = true;
+        self
+    }
+
+    pub fn role(mut self, role: impl Into<String>) -> Self {
+        self.role = Some(role.into());
+        self
+    }
+
+    pub fn cooldown(mut self, seconds: u64) -> Self {
+        self.cooldown = seconds;
+        self
+    }
+
+    fn kinds(&self) -> usize {
+        [
+            !self.commands.is_empty()
+                || !self.prefixes.is_empty()
+                || self.regex.is_some()
+                || !self.callbacks.is_empty(),
+            self.inline.is_some(),
+            !self.updates.is_empty(),
+            self.cron.is_some(),
+            !self.external.is_empty(),
+        ]
+        .iter()
+        .filter(|present| **present)
+        .count()
+    }
+
+    // Struct plugins are installed once per type, so their strings are leaked to
+    // live as long as the registry's `&'static` metadata.
+    pub(crate) fn into_meta(
+        self,
+        default_name: &'static str,
+        callback: fn(PluginContext) -> Pin<Box<dyn Future<Output = ()> + Send>>,
+    ) -> Result<&'static PluginMeta, InstallError> {
+        let name = self.name.clone().map_or(default_name, leak);
+        match self.kinds() {
+            0 => return Err(InstallError::NoTriggers(name)),
+            1 => {}
+            _ => return Err(InstallError::MixedTriggers(name)),
+        }
+        for pattern in self.regex.iter().chain(&self.not_regex) {
+            Regex::new(pattern).map_err(|err| InstallError::InvalidRegex {
+                plugin: name,
+                reason: err.to_string(),
+            })?;
+        }
+        let callback_filter = (!self.callbacks.is_empty()).then(|| self.callbacks.join("|"));
+        let meta: &'static PluginMeta = Box::leak(Box::new(PluginMeta {
+            name,
+            priority: self.priority,
+            commands: leak_all(self.commands),
+            prefixes: leak_all(self.prefixes),
+            regex: self.regex.map(leak),
+            not_regex: self.not_regex.map(leak),
+            exclude_commands: leak_all(self.exclude_commands),
+            callback_filter: callback_filter.map(leak),
+            games: &[],
+            owner_only_callback: false,
+            signed: false,
+            requires_reply: self.requires_reply,
+            admin_only: self.admin_only,
+            role: self.role.map(leak),
+            requires: &[],
+            bot_requires: &[],
+            allow_anonymous_admin: false,
+            ignore_bots: None,
+            from_bot_only: false,
+            on_new: true,
+            on_edited: false,
+            on_business: false,
+            on_edited_business: false,
+            match_captions: None,
+            entities: &[],
+            media: &[],
+            transcribe: false,
+            outbox: false,
+            langs: &[],
+            topics: &[],
+            inline: self.inline.map(leak),
+            updates: leak_all(self.updates),
+            raw_json: false,
+            cron: self.cron.map(leak),
+            tz: self.tz.map(leak),
+            overlap: "skip",
+            jitter: 0,
+            external: leak_all(self.external),
+            conversation: None,
+            step: None,
+            cooldown: self.cooldown,
+            cooldown_message: None,
+            cooldown_silent: false,
+            callback,
+        }));
+        if let Some(pattern) = meta.cron {
+            scheduler::parse_schedule(meta, pattern).map_err(InstallError::InvalidCron)?;
+        }
+        Ok(meta)
+    }
+}
+
+fn leak(value: String) -> &'static str {
+    Box::leak(value.into_boxed_str())
+}
+
+fn leak_all(values: Vec<String>) -> &'static [&'static str] {
+    Box::leak(values.into_iter().map(leak).collect())
+}
+
+fn call<P: Plugin + PluginSlot>(ctx: PluginContext) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+    Box::pin(async move {
+        let Some(plugin) = P::slot().get() else {
+            return;
+        };
+        let response = plugin.handle(ctx.clone()).await;
+        let _ = response::respond(&ctx, response).await;
+    })
+}
+
+pub async fn install<P: Plugin + PluginSlot>(plugin: P) -> Result<(), InstallError> {
+    let meta = plugin.triggers().into_meta(P::NAME, call::<P>)?;
+    P::slot()
+        .set(plugin)
+        .map_err(|_| InstallError::AlreadyInstalled(meta.name))?;
+    let plugin = P::slot().get().expect("plugin slot was just filled");
+    plugin.init().await;
+    INSTALLED.lock().unwrap().push(plugin);
+    registry::register_plugin(meta);
+    Ok(())
+}
+
+// Asks every installed plugin to reload its configuration.
+pub async fn reload() {
+    let installed = INSTALLED.lock().unwrap().clone();
+    for plugin in installed {
+        plugin.reload().await;
+    }
+}
+
+pub async fn shutdown() {
+    let installed = std::mem::take(&mut *INSTALLED.lock().unwrap());
+    for plugin in installed.into_iter().rev() {
+        plugin.shutdown().await;
+    }
+}
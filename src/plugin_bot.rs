@@ -0,0 +1,475 @@
+use crate::budget;
+use crate::context::PluginContext;
+use crate::extract::FromContext;
+use crate::registry::PluginMeta;
+use crate::response::SendError;
+use crate::traffic;
+use std::future::{Future, IntoFuture};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use teloxide::requests::{HasPayload, Output, Payload, Request, Requester};
+use teloxide::types::*;
+use teloxide::{Bot, RequestError};
+use tokio::time::Sleep;
+use url::Url;
+
+// A `Requester` that counts every request towards the plugin it was made for, the
+// same way `ctx.call` does: traffic stats, the request hook and the plugin's send
+// budget all see it, and a `RetryAfter` is waited out when the retry feature is on.
+// Take one as a handler argument (`bot: PluginBot`) instead of `Bot`.
+#[derive(Clone)]
+pub struct PluginBot<B = Bot> {
+    inner: B,
+    plugin: Option<&'static PluginMeta>,
+}
+
+impl<B> PluginBot<B> {
+    pub fn new(inner: B, plugin: Option<&'static PluginMeta>) -> Self {
+        Self { inner, plugin }
+    }
+
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+
+    pub fn plugin(&self) -> Option<&'static PluginMeta> {
+        self.plugin
+    }
+
+    fn wrap<R>(&self, inner: R) -> PluginRequest<R> {
+        PluginRequest {
+            inner,
+            plugin: self.plugin,
+        }
+    }
+}
+
+#[must_use = "Requests are lazy and do nothing unless sent"]
+#[derive(Clone)]
+pub struct PluginRequest<R> {
+    inner: R,
+    plugin: Option<&'static PluginMeta>,
+}
+
+impl<R> PluginRequest<R>
+where
+    R: Request<Err = RequestError>,
+{
+    fn start(&self) -> PluginSend<R::SendRef> {
+        let name = <R::Payload as Payload>::NAME;
+        let plugin = self.plugin.or_else(|| {
+            let current = traffic::current_plugin()?;
+            crate::registry::plugins()
+                .into_iter()
+                .find(|plugin| plugin.name == current)
+        });
+        // Refused the same way `ctx.send` refuses it, and never sent.
+        if let Some(plugin) = plugin.filter(|_| counts_as_send(name)) {
+            if let Err(retry_in) = budget::record_send(plugin) {
+                let err = SendError::OverBudget {
+                    plugin: plugin.name,
+                    retry_in,
+                };
+                return PluginSend {
+                    plugin: Some(plugin.name),
+                    attempt: 0,
+                    state: State::Refused(Some(err.into())),
+                };
+            }
+        }
+        traffic::record_for(plugin.map(|plugin| plugin.name), name);
+        PluginSend {
+            plugin: plugin.map(|plugin| plugin.name),
+            attempt: 0,
+            state: State::Sending(Box::pin(self.inner.send_ref())),
+        }
+    }
+}
+
+pub(crate) fn counts_as_send(method: &str) -> bool {
+    (method.starts_with("Send") && method != "SendChatAction")
+        || method.starts_with("Forward")
+        || method.starts_with("Copy")
+}
+
+impl<R: HasPayload> HasPayload for PluginRequest<R> {
+    type Payload = R::Payload;
+
+    fn payload_mut(&mut self) -> &mut Self::Payload {
+        self.inner.payload_mut()
+    }
+
+    fn payload_ref(&self) -> &Self::Payload {
+        self.inner.payload_ref()
+    }
+}
+
+impl<R> Request for PluginRequest<R>
+where
+    R: Request<Err = RequestError> + Send,
+{
+    type Err = RequestError;
+    type Send = Resend<R>;
+    type SendRef = PluginSend<R::SendRef>;
+
+    fn send(self) -> Self::Send {
+        Resend {
+            send: self.start(),
+            request: self,
+        }
+    }
+
+    fn send_ref(&self) -> Self::SendRef {
+        self.start()
+    }
+}
+
+impl<R> IntoFuture for PluginRequest<R>
+where
+    R: Request<Err = RequestError> + Send,
+{
+    type Output = Result<Output<R>, RequestError>;
+    type IntoFuture = Resend<R>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        self.send()
+    }
+}
+
+enum State<F> {
+    Sending(Pin<Box<F>>),
+    Waiting(Pin<Box<Sleep>>),
+    Refused(Option<RequestError>),
+}
+
+// One attempt at a request; `send_ref` can't resend because it doesn't own the
+// request, so only `send` (and `.await`) retries.
+pub struct PluginSend<F> {
+    plugin: Option<&'static str>,
+    attempt: u32,
+    state: State<F>,
+}
+
+// Nothing in here is pinned in place: both futures are boxed.
+impl<F> Unpin for PluginSend<F> {}
+
+impl<F, T> Future for PluginSend<F>
+where
+    F: Future<Output = Result<T, RequestError>>,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match &mut self.get_mut().state {
+            State::Sending(send) => send.as_mut().poll(cx),
+            State::Waiting(_) => unreachable!("only `Resend` waits"),
+            State::Refused(err) => Poll::Ready(Err(err.take().expect("polled after completion"))),
+        }
+    }
+}
+
+pub struct Resend<R: Request> {
+    request: PluginRequest<R>,
+    send: PluginSend<R::SendRef>,
+}
+
+impl<R: Request> Unpin for Resend<R> {}
+
+impl<R> Future for Resend<R>
+where
+    R: Request<Err = RequestError>,
+{
+    type Output = Result<Output<R>, RequestError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        loop {
+            let send = &mut this.send;
+            match &mut send.state {
+                State::Sending(future) => match futures::ready!(future.as_mut().poll(cx)) {
+                    Err(RequestError::RetryAfter(wait))
+                        if should_retry(send.attempt, wait.duration()) =>
+                    {
+                        send.attempt += 1;
+                        send.state = State::Waiting(Box::pin(tokio::time::sleep(wait.duration())));
+                    }
+                    result => return Poll::Ready(result),
+                },
+                State::Waiting(sleep) => {
+                    futures::ready!(sleep.as_mut().poll(cx));
+                    traffic::record_for(send.plugin, <R::Payload as Payload>::NAME);
+                    send.state = State::Sending(Box::pin(this.request.inner.send_ref()));
+                }
+                State::Refused(err) => {
+                    return Poll::Ready(Err(err.take().expect("polled after completion")))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "retry")]
+fn should_retry(attempt: u32, wait: std::time::Duration) -> bool {
+    crate::retry::should_retry(attempt, wait)
+}
+
+#[cfg(not(feature = "retry"))]
+fn should_retry(_attempt: u32, _wait: std::time::Duration) -> bool {
+    false
+}
+
+macro_rules! forward {
+    ($B:ty { $($name:ident $T:ident [$($G:ident: $bound:path),*] ($($arg:ident: $A:ty),*);)* }) => {
+        impl Requester for PluginBot<$B> {
+            type Err = RequestError;
+
+            $(
+                type $T = PluginRequest<<$B as Requester>::$T>;
+
+                fn $name<$($G),*>(&self, $($arg: $A),*) -> Self::$T
+                where
+                    $($G: $bound),*
+                {
+                    self.wrap(self.inner.$name($($arg),*))
+                }
+            )*
+        }
+    };
+}
+
+macro_rules! requester {
+    ($B:ty) => {
+        forward!($B {
+            get_updates GetUpdates [] ();
+            set_webhook SetWebhook [] (url: Url);
+            delete_webhook DeleteWebhook [] ();
+            get_webhook_info GetWebhookInfo [] ();
+            get_me GetMe [] ();
+            log_out LogOut [] ();
+            close Close [] ();
+            send_message SendMessage [C: Into<Recipient>, T: Into<String>] (chat_id: C, text: T);
+            forward_message ForwardMessage [C: Into<Recipient>, F: Into<Recipient>] (chat_id: C, from_chat_id: F, message_id: MessageId);
+            forward_messages ForwardMessages [C: Into<Recipient>, F: Into<Recipient>, M: IntoIterator<Item = MessageId>] (chat_id: C, from_chat_id: F, message_ids: M);
+            copy_message CopyMessage [C: Into<Recipient>, F: Into<Recipient>] (chat_id: C, from_chat_id: F, message_id: MessageId);
+            copy_messages CopyMessages [C: Into<Recipient>, F: Into<Recipient>, M: IntoIterator<Item = MessageId>] (chat_id: C, from_chat_id: F, message_ids: M);
+            send_photo SendPhoto [C: Into<Recipient>] (chat_id: C, photo: InputFile);
+            send_audio SendAudio [C: Into<Recipient>] (chat_id: C, audio: InputFile);
+            send_document SendDocument [C: Into<Recipient>] (chat_id: C, document: InputFile);
+            send_video SendVideo [C: Into<Recipient>] (chat_id: C, video: InputFile);
+            send_animation SendAnimation [C: Into<Recipient>] (chat_id: C, animation: InputFile);
+            send_voice SendVoice [C: Into<Recipient>] (chat_id: C, voice: InputFile);
+            send_video_note SendVideoNote [C: Into<Recipient>] (chat_id: C, video_note: InputFile);
+            send_paid_media SendPaidMedia [C: Into<Recipient>, M: IntoIterator<Item = InputPaidMedia>] (chat_id: C, star_count: u32, media: M);
+            send_media_group SendMediaGroup [C: Into<Recipient>, M: IntoIterator<Item = InputMedia>] (chat_id: C, media: M);
+            send_location SendLocation [C: Into<Recipient>] (chat_id: C, latitude: f64, longitude: f64);
+            edit_message_live_location EditMessageLiveLocation [C: Into<Recipient>] (chat_id: C, message_id: MessageId, latitude: f64, longitude: f64);
+            edit_message_live_location_inline EditMessageLiveLocationInline [I: Into<String>] (inline_message_id: I, latitude: f64, longitude: f64);
+            stop_message_live_location StopMessageLiveLocation [C: Into<Recipient>] (chat_id: C, message_id: MessageId);
+            stop_message_live_location_inline StopMessageLiveLocationInline [I: Into<String>] (inline_message_id: I);
+            edit_message_checklist EditMessageChecklist [C: Into<ChatId>] (business_connection_id: BusinessConnectionId, chat_id: C, message_id: MessageId, checklist: InputChecklist);
+            send_venue SendVenue [C: Into<Recipient>, T: Into<String>, A: Into<String>] (chat_id: C, latitude: f64, longitude: f64, title: T, address: A);
+            send_contact SendContact [C: Into<Recipient>, P: Into<String>, F: Into<String>] (chat_id: C, phone_number: P, first_name: F);
+            send_poll SendPoll [C: Into<Recipient>, Q: Into<String>, O: IntoIterator<Item = InputPollOption>] (chat_id: C, question: Q, options: O);
+            send_checklist SendChecklist [C: Into<ChatId>] (business_connection_id: BusinessConnectionId, chat_id: C, checklist: InputChecklist);
+            send_dice SendDice [C: Into<Recipient>] (chat_id: C);
+            send_chat_action SendChatAction [C: Into<Recipient>] (chat_id: C, action: ChatAction);
+            set_message_reaction SetMessageReaction [C: Into<Recipient>] (chat_id: C, message_id: MessageId);
+            get_user_profile_photos GetUserProfilePhotos [] (user_id: UserId);
+            set_user_emoji_status SetUserEmojiStatus [] (user_id: UserId);
+            get_file GetFile [] (file_id: FileId);
+            ban_chat_member BanChatMember [C: Into<Recipient>] (chat_id: C, user_id: UserId);
+            kick_chat_member KickChatMember [C: Into<Recipient>] (chat_id: C, user_id: UserId);
+            unban_chat_member UnbanChatMember [C: Into<Recipient>] (chat_id: C, user_id: UserId);
+            restrict_chat_member RestrictChatMember [C: Into<Recipient>] (chat_id: C, user_id: UserId, permissions: ChatPermissions);
+            promote_chat_member PromoteChatMember [C: Into<Recipient>] (chat_id: C, user_id: UserId);
+            set_chat_administrator_custom_title SetChatAdministratorCustomTitle [Ch: Into<Recipient>, C: Into<String>] (chat_id: Ch, user_id: UserId, custom_title: C);
+            ban_chat_sender_chat BanChatSenderChat [C: Into<Recipient>, S: Into<ChatId>] (chat_id: C, sender_chat_id: S);
+            unban_chat_sender_chat UnbanChatSenderChat [C: Into<Recipient>, S: Into<ChatId>] (chat_id: C, sender_chat_id: S);
+            set_chat_permissions SetChatPermissions [C: Into<Recipient>] (chat_id: C, permissions: ChatPermissions);
+            export_chat_invite_link ExportChatInviteLink [C: Into<Recipient>] (chat_id: C);
+            create_chat_invite_link CreateChatInviteLink [C: Into<Recipient>] (chat_id: C);
+            edit_chat_invite_link EditChatInviteLink [C: Into<Recipient>, I: Into<String>] (chat_id: C, invite_link: I);
+            create_chat_subscription_invite_link CreateChatSubscriptionInviteLink [C: Into<Recipient>] (chat_id: C, subscription_period: Seconds, subscription_price: u32);
+            edit_chat_subscription_invite_link EditChatSubscriptionInviteLink [C: Into<Recipient>, I: Into<String>] (chat_id: C, invite_link: I);
+            revoke_chat_invite_link RevokeChatInviteLink [C: Into<Recipient>, I: Into<String>] (chat_id: C, invite_link: I);
+            approve_chat_join_request ApproveChatJoinRequest [C: Into<Recipient>] (chat_id: C, user_id: UserId);
+            decline_chat_join_request DeclineChatJoinRequest [C: Into<Recipient>] (chat_id: C, user_id: UserId);
+            set_chat_photo SetChatPhoto [C: Into<Recipient>] (chat_id: C, photo: InputFile);
+            delete_chat_photo DeleteChatPhoto [C: Into<Recipient>] (chat_id: C);
+            set_chat_title SetChatTitle [C: Into<Recipient>, T: Into<String>] (chat_id: C, title: T);
+            set_chat_description SetChatDescription [C: Into<Recipient>] (chat_id: C);
+            pin_chat_message PinChatMessage [C: Into<Recipient>] (chat_id: C, message_id: MessageId);
+            unpin_chat_message UnpinChatMessage [C: Into<Recipient>] (chat_id: C);
+            unpin_all_chat_messages UnpinAllChatMessages [C: Into<Recipient>] (chat_id: C);
+            leave_chat LeaveChat [C: Into<Recipient>] (chat_id: C);
+            get_chat GetChat [C: Into<Recipient>] (chat_id: C);
+            get_chat_administrators GetChatAdministrators [C: Into<Recipient>] (chat_id: C);
+            get_chat_member_count GetChatMemberCount [C: Into<Recipient>] (chat_id: C);
+            get_chat_members_count GetChatMembersCount [C: Into<Recipient>] (chat_id: C);
+            get_chat_member GetChatMember [C: Into<Recipient>] (chat_id: C, user_id: UserId);
+            set_chat_sticker_set SetChatStickerSet [C: Into<Recipient>, S: Into<String>] (chat_id: C, sticker_set_name: S);
+            delete_chat_sticker_set DeleteChatStickerSet [C: Into<Recipient>] (chat_id: C);
+            get_forum_topic_icon_stickers GetForumTopicIconStickers [] ();
+            create_forum_topic CreateForumTopic [C: Into<Recipient>, N: Into<String>] (chat_id: C, name: N);
+            edit_forum_topic EditForumTopic [C: Into<Recipient>] (chat_id: C, message_thread_id: ThreadId);
+            close_forum_topic CloseForumTopic [C: Into<Recipient>] (chat_id: C, message_thread_id: ThreadId);
+            reopen_forum_topic ReopenForumTopic [C: Into<Recipient>] (chat_id: C, message_thread_id: ThreadId);
+            delete_forum_topic DeleteForumTopic [C: Into<Recipient>] (chat_id: C, message_thread_id: ThreadId);
+            unpin_all_forum_topic_messages UnpinAllForumTopicMessages [C: Into<Recipient>] (chat_id: C, message_thread_id: ThreadId);
+            edit_general_forum_topic EditGeneralForumTopic [C: Into<Recipient>, N: Into<String>] (chat_id: C, name: N);
+            close_general_forum_topic CloseGeneralForumTopic [C: Into<Recipient>] (chat_id: C);
+            reopen_general_forum_topic ReopenGeneralForumTopic [C: Into<Recipient>] (chat_id: C);
+            hide_general_forum_topic HideGeneralForumTopic [C: Into<Recipient>] (chat_id: C);
+            unhide_general_forum_topic UnhideGeneralForumTopic [C: Into<Recipient>] (chat_id: C);
+            unpin_all_general_forum_topic_messages UnpinAllGeneralForumTopicMessages [C: Into<Recipient>] (chat_id: C);
+            answer_callback_query AnswerCallbackQuery [] (callback_query_id: CallbackQueryId);
+            get_user_chat_boosts GetUserChatBoosts [C: Into<Recipient>] (chat_id: C, user_id: UserId);
+            set_my_commands SetMyCommands [C: IntoIterator<Item = BotCommand>] (commands: C);
+            get_business_connection GetBusinessConnection [] (business_connection_id: BusinessConnectionId);
+            get_my_commands GetMyCommands [] ();
+            set_my_name SetMyName [] ();
+            get_my_name GetMyName [] ();
+            set_my_description SetMyDescription [] ();
+            get_my_description GetMyDescription [] ();
+            set_my_short_description SetMyShortDescription [] ();
+            get_my_short_description GetMyShortDescription [] ();
+            set_chat_menu_button SetChatMenuButton [] ();
+            get_chat_menu_button GetChatMenuButton [] ();
+            set_my_default_administrator_rights SetMyDefaultAdministratorRights [] ();
+            get_my_default_administrator_rights GetMyDefaultAdministratorRights [] ();
+            delete_my_commands DeleteMyCommands [] ();
+            answer_inline_query AnswerInlineQuery [R: IntoIterator<Item = InlineQueryResult>] (inline_query_id: InlineQueryId, results: R);
+            answer_web_app_query AnswerWebAppQuery [W: Into<String>] (web_app_query_id: W, result: InlineQueryResult);
+            save_prepared_inline_message SavePreparedInlineMessage [] (user_id: UserId, result: InlineQueryResult);
+            edit_message_text EditMessageText [C: Into<Recipient>, T: Into<String>] (chat_id: C, message_id: MessageId, text: T);
+            edit_message_text_inline EditMessageTextInline [I: Into<String>, T: Into<String>] (inline_message_id: I, text: T);
+            edit_message_caption EditMessageCaption [C: Into<Recipient>] (chat_id: C, message_id: MessageId);
+            edit_message_caption_inline EditMessageCaptionInline [I: Into<String>] (inline_message_id: I);
+            edit_message_media EditMessageMedia [C: Into<Recipient>] (chat_id: C, message_id: MessageId, media: InputMedia);
+            edit_message_media_inline EditMessageMediaInline [I: Into<String>] (inline_message_id: I, media: InputMedia);
+            edit_message_reply_markup EditMessageReplyMarkup [C: Into<Recipient>] (chat_id: C, message_id: MessageId);
+            edit_message_reply_markup_inline EditMessageReplyMarkupInline [I: Into<String>] (inline_message_id: I);
+            stop_poll StopPoll [C: Into<Recipient>] (chat_id: C, message_id: MessageId);
+            delete_message DeleteMessage [C: Into<Recipient>] (chat_id: C, message_id: MessageId);
+            delete_messages DeleteMessages [C: Into<Recipient>, M: IntoIterator<Item = MessageId>] (chat_id: C, message_ids: M);
+            send_sticker SendSticker [C: Into<Recipient>] (chat_id: C, sticker: InputFile);
+            get_sticker_set GetStickerSet [N: Into<String>] (name: N);
+            get_custom_emoji_stickers GetCustomEmojiStickers [C: IntoIterator<Item = CustomEmojiId>] (custom_emoji_ids: C);
+            upload_sticker_file UploadStickerFile [] (user_id: UserId, sticker: InputFile, sticker_format: StickerFormat);
+            create_new_sticker_set CreateNewStickerSet [N: Into<String>, T: Into<String>, S: IntoIterator<Item = InputSticker>] (user_id: UserId, name: N, title: T, stickers: S);
+            add_sticker_to_set AddStickerToSet [N: Into<String>] (user_id: UserId, name: N, sticker: InputSticker);
+            set_sticker_position_in_set SetStickerPositionInSet [S: Into<String>] (sticker: S, position: u32);
+            delete_sticker_from_set DeleteStickerFromSet [S: Into<String>] (sticker: S);
+            replace_sticker_in_set ReplaceStickerInSet [N: Into<String>, O: Into<String>] (user_id: UserId, name: N, old_sticker: O, sticker: InputSticker);
+            set_sticker_set_thumbnail SetStickerSetThumbnail [N: Into<String>] (name: N, user_id: UserId, format: StickerFormat);
+            set_custom_emoji_sticker_set_thumbnail SetCustomEmojiStickerSetThumbnail [N: Into<String>] (name: N);
+            set_sticker_set_title SetStickerSetTitle [N: Into<String>, T: Into<String>] (name: N, title: T);
+            delete_sticker_set DeleteStickerSet [N: Into<String>] (name: N);
+            set_sticker_emoji_list SetStickerEmojiList [S: Into<String>, E: IntoIterator<Item = String>] (sticker: S, emoji_list: E);
+            set_sticker_keywords SetStickerKeywords [S: Into<String>] (sticker: S);
+            set_sticker_mask_position SetStickerMaskPosition [S: Into<String>] (sticker: S);
+            get_available_gifts GetAvailableGifts [] ();
+            send_gift SendGift [] (user_id: UserId, gift_id: GiftId);
+            send_gift_chat SendGiftChat [C: Into<Recipient>] (chat_id: C, gift_id: GiftId);
+            gift_premium_subscription GiftPremiumSubscription [] (user_id: UserId, month_count: u8, star_count: u32);
+            verify_user VerifyUser [] (user_id: UserId);
+            verify_chat VerifyChat [C: Into<Recipient>] (chat_id: C);
+            remove_user_verification RemoveUserVerification [] (user_id: UserId);
+            remove_chat_verification RemoveChatVerification [C: Into<Recipient>] (chat_id: C);
+            read_business_message ReadBusinessMessage [C: Into<ChatId>] (business_connection_id: BusinessConnectionId, chat_id: C, message_id: MessageId);
+            delete_business_messages DeleteBusinessMessages [M: IntoIterator<Item = MessageId>] (business_connection_id: BusinessConnectionId, message_ids: M);
+            set_business_account_name SetBusinessAccountName [F: Into<String>] (business_connection_id: BusinessConnectionId, first_name: F);
+            set_business_account_username SetBusinessAccountUsername [] (business_connection_id: BusinessConnectionId);
+            set_business_account_bio SetBusinessAccountBio [] (business_connection_id: BusinessConnectionId);
+            set_business_account_profile_photo SetBusinessAccountProfilePhoto [] (business_connection_id: BusinessConnectionId, photo: InputProfilePhoto);
+            remove_business_account_profile_photo RemoveBusinessAccountProfilePhoto [] (business_connection_id: BusinessConnectionId);
+            set_business_account_gift_settings SetBusinessAccountGiftSettings [] (business_connection_id: BusinessConnectionId, show_gift_button: bool, accepted_gift_types: AcceptedGiftTypes);
+            get_business_account_star_balance GetBusinessAccountStarBalance [] (business_connection_id: BusinessConnectionId);
+            transfer_business_account_stars TransferBusinessAccountStars [] (business_connection_id: BusinessConnectionId, star_count: u32);
+            get_business_account_gifts GetBusinessAccountGifts [] (business_connection_id: BusinessConnectionId);
+            convert_gift_to_stars ConvertGiftToStars [] (business_connection_id: BusinessConnectionId, owned_gift_id: OwnedGiftId);
+            upgrade_gift UpgradeGift [] (business_connection_id: BusinessConnectionId, owned_gift_id: OwnedGiftId);
+            transfer_gift TransferGift [N: Into<ChatId>] (business_connection_id: BusinessConnectionId, owned_gift_id: OwnedGiftId, new_owner_chat_id: N);
+            post_story PostStory [] (business_connection_id: BusinessConnectionId, content: InputStoryContent, active_period: Seconds);
+            edit_story EditStory [] (business_connection_id: BusinessConnectionId, story_id: StoryId, content: InputStoryContent);
+            delete_story DeleteStory [] (business_connection_id: BusinessConnectionId, story_id: StoryId);
+            send_invoice SendInvoice [Ch: Into<Recipient>, T: Into<String>, D: Into<String>, Pa: Into<String>, C: Into<String>, P: IntoIterator<Item = LabeledPrice>] (chat_id: Ch, title: T, description: D, payload: Pa, currency: C, prices: P);
+            create_invoice_link CreateInvoiceLink [T: Into<String>, D: Into<String>, Pa: Into<String>, C: Into<String>, P: IntoIterator<Item = LabeledPrice>] (title: T, description: D, payload: Pa, currency: C, prices: P);
+            answer_shipping_query AnswerShippingQuery [] (shipping_query_id: ShippingQueryId, ok: bool);
+            answer_pre_checkout_query AnswerPreCheckoutQuery [] (pre_checkout_query_id: PreCheckoutQueryId, ok: bool);
+            get_my_star_balance GetMyStarBalance [] ();
+            get_star_transactions GetStarTransactions [] ();
+            refund_star_payment RefundStarPayment [] (user_id: UserId, telegram_payment_charge_id: TelegramTransactionId);
+            edit_user_star_subscription EditUserStarSubscription [] (user_id: UserId, telegram_payment_charge_id: TelegramTransactionId, is_canceled: bool);
+            set_passport_data_errors SetPassportDataErrors [E: IntoIterator<Item = PassportElementError>] (user_id: UserId, errors: E);
+            send_game SendGame [C: Into<ChatId>, G: Into<String>] (chat_id: C, game_short_name: G);
+            set_game_score SetGameScore [] (user_id: UserId, score: u64, chat_id: u32, message_id: MessageId);
+            set_game_score_inline SetGameScoreInline [I: Into<String>] (user_id: UserId, score: u64, inline_message_id: I);
+            get_game_high_scores GetGameHighScores [T: Into<TargetMessage>] (user_id: UserId, target: T);
+        });
+    };
+}
+
+requester!(Bot);
+#[cfg(feature = "throttle")]
+requester!(teloxide::adaptors::Throttle<Bot>);
+
+impl PluginContext {
+    // `ctx.bot`, but with every request counted towards this plugin.
+    pub fn plugin_bot(&self) -> PluginBot {
+        PluginBot::new(self.bot.clone(), self.plugin)
+    }
+}
+
+impl FromContext for PluginBot {
+    fn from_context(ctx: &PluginContext) -> Option<Self> {
+        Some(ctx.plugin_bot())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::budget::Budget;
+
+    #[crate::TeloxidePlugin(commands = ["chatty"], prefixes = ["/"])]
+    async fn chatty() {}
+
+    #[tokio::test]
+    async fn a_send_over_budget_is_refused_without_sending() {
+        let plugin = crate::registry::plugins()
+            .into_iter()
+            .find(|plugin| plugin.name == "chatty")
+            .unwrap();
+        budget::set_budget(
+            "chatty",
+            Budget {
+                sends_per_minute: Some(0),
+                ..Budget::default()
+            },
+        );
+        // Nothing listens there, so anything that got sent would fail differently.
+        let bot = Bot::new("1:test").set_api_url("http://127.0.0.1:1".parse().unwrap());
+        let bot = PluginBot::new(bot, Some(plugin));
+        for _ in 0..2 {
+            let Err(RequestError::Io(err)) = bot.send_message(ChatId(1), "hi").await else {
+                panic!("expected a refusal");
+            };
+            let refusal = err
+                .get_ref()
+                .and_then(|err| err.downcast_ref::<SendError>());
+            assert!(matches!(
+                refusal,
+                Some(SendError::OverBudget {
+                    plugin: "chatty",
+                    ..
+                })
+            ));
+        }
+        assert_eq!(budget::usage("chatty").sends, 1);
+    }
+}
@@ -0,0 +1,326 @@
+use crate::context::PluginContext;
+use crate::traffic;
+use futures::future::BoxFuture;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+use teloxide::prelude::*;
+use teloxide::types::{InputPollOption, MessageId, Poll, PollId, PollType};
+use tokio::task::JoinHandle;
+
+// Telegram shows a countdown for polls that close within this window.
+const OPEN_PERIOD: std::ops::RangeInclusive<u64> = 5..=600;
+
+type OnClose = Box<dyn FnOnce(PollResults) -> BoxFuture<'static, ()> + Send>;
+
+struct Managed {
+    chat_id: ChatId,
+    message_id: MessageId,
+    latest: Poll,
+    votes: HashMap<UserId, Vec<u8>>,
+    on_close: OnClose,
+    timer: Option<JoinHandle<()>>,
+}
+
+static MANAGED: Lazy<Mutex<HashMap<PollId, Managed>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Debug)]
+pub enum PollError {
+    NoChat,
+    TooFewOptions,
+    InvalidCorrectOption(u8),
+    NotManaged,
+    Request(teloxide::RequestError),
+}
+
+impl fmt::Display for PollError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PollError::NoChat => write!(f, "the update has no chat to send the poll to"),
+            PollError::TooFewOptions => write!(f, "a poll needs at least two options"),
+            PollError::InvalidCorrectOption(index) => {
+                write!(
+                    f,
+                    "option {} doesn't exist, so it can't be the correct one",
+                    index
+                )
+            }
+            PollError::NotManaged => write!(f, "the poll isn't managed or already closed"),
+            PollError::Request(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for PollError {}
+
+impl From<teloxide::RequestError> for PollError {
+    fn from(err: teloxide::RequestError) -> Self {
+        PollError::Request(err)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OptionResult {
+    pub text: String,
+    pub votes: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PollResults {
+    pub question: String,
+    pub options: Vec<OptionResult>,
+    pub total_voters: u32,
+    pub correct_option: Option<u8>,
+    // Only public polls tell the bot who voted for what.
+    pub votes: HashMap<UserId, Vec<u8>>,
+}
+
+impl PollResults {
+    fn new(poll: Poll, votes: HashMap<UserId, Vec<u8>>) -> Self {
+        Self {
+            question: poll.question,
+            options: poll
+                .options
+                .into_iter()
+                .map(|option| OptionResult {
+                    text: option.text,
+                    votes: option.voter_count,
+                })
+                .collect(),
+            total_voters: poll.total_voter_count,
+            correct_option: poll.correct_option_id,
+            votes,
+        }
+    }
+
+    // The options with the most votes; several on a tie, none when nobody voted.
+    pub fn winners(&self) -> Vec<&OptionResult> {
+        let most = self.options.iter().map(|o| o.votes).max().unwrap_or(0);
+        self.options
+            .iter()
+            .filter(|option| most > 0 && option.votes == most)
+            .collect()
+    }
+
+    // Voters who picked the correct answer of a quiz.
+    pub fn correct_voters(&self) -> Vec<UserId> {
+        let Some(correct) = self.correct_option else {
+            return Vec::new();
+        };
+        self.votes
+            .iter()
+            .filter(|(_, options)| options.contains(&correct))
+            .map(|(user, _)| *user)
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ManagedPoll {
+    pub poll_id: PollId,
+    pub chat_id: ChatId,
+    pub message_id: MessageId,
+}
+
+impl ManagedPoll {
+    pub async fn close(&self, bot: &Bot) -> Result<(), PollError> {
+        close(bot, &self.poll_id).await
+    }
+}
+
+pub struct PollRequest<'a> {
+    ctx: &'a PluginContext,
+    question: String,
+    options: Vec<String>,
+    correct_option: Option<u8>,
+    explanation: Option<String>,
+    multiple_answers: bool,
+    anonymous: bool,
+    close_after: Option<Duration>,
+}
+
+impl PollRequest<'_> {
+    pub fn quiz(mut self, correct_option: u8) -> Self {
+        self.correct_option = Some(correct_option);
+        self
+    }
+
+    pub fn explanation(mut self, text: impl Into<String>) -> Self {
+        self.explanation = Some(text.into());
+        self
+    }
+
+    pub fn multiple_answers(mut self) -> Self {
+        self.multiple_answers = true;
+        self
+    }
+
+    pub fn public(mut self) -> Self {
+        self.anonymous = false;
+        self
+    }
+
+    pub fn close_after(mut self, duration: Duration) -> Self {
+        self.close_after = Some(duration);
+        self
+    }
+
+    pub async fn send(self) -> Result<Message, PollError> {
+        let chat_id = self.ctx.chat_id().ok_or(PollError::NoChat)?;
+        if self.options.len() < 2 {
+            return Err(PollError::TooFewOptions);
+        }
+        let mut request = self
+            .ctx
+            .bot
+            .send_poll(
+                chat_id,
+                self.question,
+                self.options.into_iter().map(InputPollOption::new),
+            )
+            .is_anonymous(self.anonymous)
+            .allows_multiple_answers(self.multiple_answers);
+        if let Some(correct) = self.correct_option {
+            if correct as usize >= request.options.len() {
+                return Err(PollError::InvalidCorrectOption(correct));
+            }
+            request = request.type_(PollType::Quiz).correct_option_id(correct);
+        }
+        if let Some(explanation) = self.explanation {
+            request = request.explanation(explanation);
+        }
+        if let Some(secs) = self
+            .close_after
+            .map(|after| after.as_secs())
+            .filter(|secs| OPEN_PERIOD.contains(secs))
+        {
+            request = request.open_period(secs as u16);
+        }
+        Ok(self.ctx.call(request).await?)
+    }
+
+    // Sends the poll and calls `on_close` with the final tally once it closes, whether
+    // that's `close_after`, `ManagedPoll::close` or someone stopping it in Telegram.
+    // Managed polls live in memory, so a restart forgets them.
+    pub async fn managed<F, Fut>(self, on_close: F) -> Result<ManagedPoll, PollError>
+    where
+        F: FnOnce(PollResults) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let bot = self.ctx.bot.clone();
+        let close_after = self.close_after;
+        let msg = self.send().await?;
+        let Some(poll) = msg.poll().cloned() else {
+            return Err(PollError::NotManaged);
+        };
+        let managed = ManagedPoll {
+            poll_id: poll.id.clone(),
+            chat_id: msg.chat.id,
+            message_id: msg.id,
+        };
+        // Telegram closes short polls itself and tells us; the timer is only a fallback.
+        let timer = close_after.map(|after| {
+            let grace = if OPEN_PERIOD.contains(&after.as_secs()) {
+                Duration::from_secs(5)
+            } else {
+                Duration::ZERO
+            };
+            let poll_id = poll.id.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(after + grace).await;
+                let _ = close(&bot, &poll_id).await;
+            })
+        });
+        MANAGED.lock().unwrap().insert(
+            poll.id.clone(),
+            Managed {
+                chat_id: managed.chat_id,
+                message_id: managed.message_id,
+                latest: poll,
+                votes: HashMap::new(),
+                on_close: Box::new(move |results| Box::pin(on_close(results))),
+                timer,
+            },
+        );
+        Ok(managed)
+    }
+}
+
+// The callback gets its own task, so it can take its time and the close timer can be
+// aborted even when it's the one closing the poll.
+fn finish(poll_id: &PollId, poll: Option<Poll>) {
+    let Some(managed) = MANAGED.lock().unwrap().remove(poll_id) else {
+        return;
+    };
+    if let Some(timer) = &managed.timer {
+        timer.abort();
+    }
+    let poll = poll.unwrap_or(managed.latest);
+    tokio::spawn((managed.on_close)(PollResults::new(poll, managed.votes)));
+}
+
+// Stops the poll in Telegram. The final counts come back in the reply; if stopping
+// fails, for example because the message was deleted, the last known counts are used.
+pub async fn close(bot: &Bot, poll_id: &PollId) -> Result<(), PollError> {
+    let target = MANAGED
+        .lock()
+        .unwrap()
+        .get(poll_id)
+        .map(|managed| (managed.chat_id, managed.message_id));
+    let Some((chat_id, message_id)) = target else {
+        return Err(PollError::NotManaged);
+    };
+    let stopped = traffic::send(bot.stop_poll(chat_id, message_id)).await;
+    finish(poll_id, stopped.as_ref().ok().cloned());
+    stopped.map(|_| ()).map_err(PollError::from)
+}
+
+// Runs before routing and keeps managed polls up to date. Plugins still see the update.
+pub(crate) fn track(ctx: &PluginContext) {
+    if let Some(poll) = ctx.poll.as_ref() {
+        if poll.is_closed {
+            finish(&poll.id, Some(poll.clone()));
+            return;
+        }
+        if let Some(managed) = MANAGED.lock().unwrap().get_mut(&poll.id) {
+            managed.latest = poll.clone();
+        }
+    }
+    let Some(answer) = ctx.poll_answer.as_ref() else {
+        return;
+    };
+    let Some(user) = answer.voter.user() else {
+        return;
+    };
+    let mut polls = MANAGED.lock().unwrap();
+    let Some(managed) = polls.get_mut(&answer.poll_id) else {
+        return;
+    };
+    if answer.option_ids.is_empty() {
+        managed.votes.remove(&user.id);
+    } else {
+        managed.votes.insert(user.id, answer.option_ids.clone());
+    }
+}
+
+impl PluginContext {
+    pub fn send_poll(
+        &self,
+        question: impl Into<String>,
+        options: impl IntoIterator<Item = impl Into<String>>,
+    ) -> PollRequest<'_> {
+        PollRequest {
+            ctx: self,
+            question: question.into(),
+            options: options.into_iter().map(Into::into).collect(),
+            correct_option: None,
+            explanation: None,
+            multiple_answers: false,
+            anonymous: true,
+            close_after: None,
+        }
+    }
+}
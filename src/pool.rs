@@ -0,0 +1,192 @@
+use crate::plugin_bot::counts_as_send;
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use teloxide::prelude::*;
+use teloxide::requests::{HasPayload, JsonRequest, MultipartRequest, Payload, Request};
+use teloxide::RequestError;
+
+const WINDOW: Duration = Duration::from_secs(1);
+
+static POOL: OnceCell<BotPool> = OnceCell::new();
+
+tokio::task_local! {
+    static PINNED: ();
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TokenStats {
+    pub bot_id: Option<UserId>,
+    pub sent: u64,
+    pub in_window: usize,
+}
+
+struct Slot {
+    bot: Bot,
+    sent: u64,
+    recent: Vec<Instant>,
+}
+
+impl Slot {
+    fn prune(&mut self, now: Instant) {
+        self.recent.retain(|at| now.duration_since(*at) < WINDOW);
+    }
+}
+
+pub struct BotPool {
+    slots: Vec<Mutex<Slot>>,
+    next: AtomicUsize,
+    per_second: usize,
+}
+
+impl BotPool {
+    pub fn new(bots: impl IntoIterator<Item = Bot>) -> Self {
+        let slots: Vec<_> = bots
+            .into_iter()
+            .map(|bot| {
+                Mutex::new(Slot {
+                    bot,
+                    sent: 0,
+                    recent: Vec::new(),
+                })
+            })
+            .collect();
+        assert!(!slots.is_empty(), "a bot pool needs at least one token");
+        Self {
+            slots,
+            next: AtomicUsize::new(0),
+            per_second: 30,
+        }
+    }
+
+    pub fn from_tokens(tokens: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self::new(tokens.into_iter().map(Bot::new))
+    }
+
+    pub fn per_second(mut self, limit: usize) -> Self {
+        self.per_second = limit.max(1);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    pub fn next_bot(&self) -> Bot {
+        let now = Instant::now();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let mut quietest: Option<(usize, usize)> = None;
+
+        for offset in 0..self.slots.len() {
+            let index = (start + offset) % self.slots.len();
+            let mut slot = self.slots[index].lock().unwrap();
+            slot.prune(now);
+            if slot.recent.len() < self.per_second {
+                return take(&mut slot, now);
+            }
+            if quietest.is_none_or(|(_, load)| slot.recent.len() < load) {
+                quietest = Some((index, slot.recent.len()));
+            }
+        }
+
+        let (index, _) = quietest.unwrap_or((start % self.slots.len(), 0));
+        take(&mut self.slots[index].lock().unwrap(), now)
+    }
+
+    pub fn stats(&self) -> Vec<TokenStats> {
+        let now = Instant::now();
+        self.slots
+            .iter()
+            .map(|slot| {
+                let mut slot = slot.lock().unwrap();
+                slot.prune(now);
+                TokenStats {
+                    bot_id: token_id(slot.bot.token()),
+                    sent: slot.sent,
+                    in_window: slot.recent.len(),
+                }
+            })
+            .collect()
+    }
+}
+
+fn token_id(token: &str) -> Option<UserId> {
+    token.split(':').next()?.parse().ok().map(UserId)
+}
+
+fn take(slot: &mut Slot, now: Instant) -> Bot {
+    slot.sent += 1;
+    slot.recent.push(now);
+    slot.bot.clone()
+}
+
+pub fn set_pool(pool: BotPool) {
+    let _ = POOL.set(pool);
+}
+
+pub fn pool() -> Option<&'static BotPool> {
+    POOL.get()
+}
+
+// Requests that `ctx.call` can hand to another bot in the pool. Only sends move, and
+// not all of them: edits, callback answers and the like only work for the bot that got
+// the update, and polls, invoices, games and inline keyboards send their updates back
+// to the bot that sent them. So does anything sent on behalf of a business account.
+pub trait Poolable: Request<Err = RequestError> + Sized {
+    fn via(&self, bot: Bot) -> Self;
+}
+
+impl<P> Poolable for JsonRequest<P>
+where
+    JsonRequest<P>: Request<Err = RequestError> + HasPayload<Payload = P>,
+    P: Payload + Serialize + Clone,
+{
+    fn via(&self, bot: Bot) -> Self {
+        JsonRequest::new(bot, self.payload_ref().clone())
+    }
+}
+
+impl<P> Poolable for MultipartRequest<P>
+where
+    MultipartRequest<P>: Request<Err = RequestError> + HasPayload<Payload = P>,
+    P: Payload + Serialize + Clone,
+{
+    fn via(&self, bot: Bot) -> Self {
+        MultipartRequest::new(bot, self.payload_ref().clone())
+    }
+}
+
+const TIED_TO_SENDER: [&str; 3] = ["SendPoll", "SendInvoice", "SendGame"];
+
+fn can_move<P: Payload + Serialize>(payload: &P) -> bool {
+    counts_as_send(P::NAME)
+        && !TIED_TO_SENDER.contains(&P::NAME)
+        && PINNED.try_with(|_| ()).is_err()
+        && serde_json::to_value(payload).is_ok_and(|json| {
+            json.get("business_connection_id").is_none()
+                && json.pointer("/reply_markup/inline_keyboard").is_none()
+        })
+}
+
+// Runs `future` with every request going out through the bot that made it, for sends
+// whose message the plugin edits or deletes afterwards.
+pub async fn pinned<F: Future>(future: F) -> F::Output {
+    PINNED.scope((), future).await
+}
+
+// The request as the next bot in the pool would send it, or `None` to send it as is.
+pub(crate) fn route<R>(request: &R) -> Option<R>
+where
+    R: Poolable,
+    R::Payload: Serialize,
+{
+    let pool = pool()?;
+    can_move(request.payload_ref()).then(|| request.via(pool.next_bot()))
+}
@@ -0,0 +1,466 @@
+use crate::context::PluginContext;
+use crate::conversation;
+use crate::registry::dispatch;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Priority {
+    High,
+    Normal,
+}
+
+impl Priority {
+    // Button presses, inline queries and anything from a user in the middle of a
+    // conversation, so a wizard's next step doesn't wait behind a backlog.
+    pub fn of(ctx: &PluginContext) -> Self {
+        if ctx.callback_query.is_some()
+            || ctx.inline_query.is_some()
+            || conversation::current(ctx).is_some()
+        {
+            Priority::High
+        } else {
+            Priority::Normal
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    DropNewest,
+    DropOldest,
+}
+
+#[derive(Clone, Debug)]
+pub struct QueueConfig {
+    pub workers: usize,
+    pub capacity: usize,
+    pub overflow: OverflowPolicy,
+    // Give every worker its own queue and send each chat's updates to the same one,
+    // so a chat is handled one update at a time, in order, while chats run in
+    // parallel. High priority updates only skip ahead of other chats: one for a chat
+    // with normal updates still waiting goes behind them. `capacity` is split between
+    // the workers. On by default; without it any free worker takes the next update,
+    // so a chat's updates can be handled at the same time and finish out of order.
+    pub shard_by_chat: bool,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            workers: 4,
+            capacity: 10_000,
+            overflow: OverflowPolicy::DropOldest,
+            shard_by_chat: true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct QueueMetrics {
+    pub depth: usize,
+    pub high_depth: usize,
+    pub normal_depth: usize,
+    pub peak_depth: usize,
+    pub enqueued: u64,
+    pub dropped: u64,
+    pub processed: u64,
+}
+
+// Whose updates have to stay in order: the chat's, or the sender's without one.
+fn order_key(ctx: &PluginContext) -> Option<i64> {
+    ctx.chat_id()
+        .map(|chat| chat.0)
+        .or_else(|| ctx.sender().map(|user| user.id.0 as i64))
+}
+
+// Every update is queued with a sequence number, so the oldest one can be found
+// across both lanes.
+#[derive(Default)]
+struct Lanes {
+    high: VecDeque<(u64, PluginContext)>,
+    normal: VecDeque<(u64, PluginContext)>,
+    next: u64,
+    // Normal updates waiting per chat, kept while the shard keeps chats in order.
+    waiting: Option<HashMap<i64, usize>>,
+}
+
+impl Lanes {
+    fn ordered() -> Self {
+        Self {
+            waiting: Some(HashMap::new()),
+            ..Self::default()
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.high.len() + self.normal.len()
+    }
+
+    fn push(&mut self, ctx: PluginContext, priority: Priority) {
+        let seq = self.next;
+        self.next += 1;
+        let Some(waiting) = &mut self.waiting else {
+            match priority {
+                Priority::High => self.high.push_back((seq, ctx)),
+                Priority::Normal => self.normal.push_back((seq, ctx)),
+            }
+            return;
+        };
+        let key = order_key(&ctx);
+        let behind = key.is_some_and(|key| waiting.contains_key(&key));
+        if priority == Priority::High && !behind {
+            self.high.push_back((seq, ctx));
+            return;
+        }
+        if let Some(key) = key {
+            *waiting.entry(key).or_default() += 1;
+        }
+        self.normal.push_back((seq, ctx));
+    }
+
+    fn pop(&mut self) -> Option<PluginContext> {
+        if let Some((_, ctx)) = self.high.pop_front() {
+            return Some(ctx);
+        }
+        self.pop_normal()
+    }
+
+    // Whichever lane's head has waited longest.
+    fn evict_oldest(&mut self) -> Option<PluginContext> {
+        match (self.high.front(), self.normal.front()) {
+            (Some((high, _)), Some((normal, _))) if high < normal => {
+                self.high.pop_front().map(|(_, ctx)| ctx)
+            }
+            (Some(_), None) => self.high.pop_front().map(|(_, ctx)| ctx),
+            _ => self.pop_normal(),
+        }
+    }
+
+    fn pop_normal(&mut self) -> Option<PluginContext> {
+        let (_, ctx) = self.normal.pop_front()?;
+        if let (Some(waiting), Some(key)) = (&mut self.waiting, order_key(&ctx)) {
+            if let Some(count) = waiting.get_mut(&key) {
+                *count -= 1;
+                if *count == 0 {
+                    waiting.remove(&key);
+                }
+            }
+        }
+        Some(ctx)
+    }
+}
+
+#[derive(Default)]
+struct Shard {
+    lanes: Mutex<Lanes>,
+    notify: Notify,
+    peak_depth: AtomicUsize,
+    enqueued: AtomicU64,
+    dropped: AtomicU64,
+    processed: AtomicU64,
+}
+
+impl Shard {
+    fn pop(&self, depth: &AtomicUsize) -> Option<PluginContext> {
+        let ctx = self.lanes.lock().unwrap().pop()?;
+        depth.fetch_sub(1, Ordering::Relaxed);
+        Some(ctx)
+    }
+
+    fn metrics(&self) -> QueueMetrics {
+        let lanes = self.lanes.lock().unwrap();
+        QueueMetrics {
+            depth: lanes.len(),
+            high_depth: lanes.high.len(),
+            normal_depth: lanes.normal.len(),
+            peak_depth: self.peak_depth.load(Ordering::Relaxed),
+            enqueued: self.enqueued.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            processed: self.processed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+struct Shared {
+    config: QueueConfig,
+    // One shard all workers take from, or one per worker with `shard_by_chat`.
+    shards: Vec<Shard>,
+    capacity: usize,
+    depth: AtomicUsize,
+    peak_depth: AtomicUsize,
+    closed: AtomicBool,
+}
+
+impl Shared {
+    // Updates without a chat, like inline queries, go by sender instead.
+    fn shard_of(&self, ctx: &PluginContext) -> &Shard {
+        if self.shards.len() == 1 {
+            return &self.shards[0];
+        }
+        let mut hasher = DefaultHasher::new();
+        match ctx.chat_id() {
+            Some(chat) => chat.0.hash(&mut hasher),
+            None => ctx.sender().map(|user| user.id.0).hash(&mut hasher),
+        }
+        &self.shards[(hasher.finish() % self.shards.len() as u64) as usize]
+    }
+
+    fn metrics(&self) -> QueueMetrics {
+        let mut total = QueueMetrics {
+            peak_depth: self.peak_depth.load(Ordering::Relaxed),
+            ..QueueMetrics::default()
+        };
+        for shard in &self.shards {
+            let metrics = shard.metrics();
+            total.depth += metrics.depth;
+            total.high_depth += metrics.high_depth;
+            total.normal_depth += metrics.normal_depth;
+            total.enqueued += metrics.enqueued;
+            total.dropped += metrics.dropped;
+            total.processed += metrics.processed;
+        }
+        total
+    }
+}
+
+struct Closer(Arc<Shared>);
+
+impl Drop for Closer {
+    fn drop(&mut self) {
+        self.0.closed.store(true, Ordering::SeqCst);
+        for shard in &self.0.shards {
+            shard.notify.notify_waiters();
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct DispatchQueue {
+    shared: Arc<Shared>,
+    _closer: Arc<Closer>,
+}
+
+impl DispatchQueue {
+    pub fn new(workers: usize) -> Self {
+        Self::with_config(QueueConfig {
+            workers,
+            ..QueueConfig::default()
+        })
+    }
+
+    pub fn with_config(config: QueueConfig) -> Self {
+        let workers = config.workers.max(1);
+        let shards = if config.shard_by_chat { workers } else { 1 };
+        let ordered = config.shard_by_chat;
+        let shared = Arc::new(Shared {
+            capacity: config.capacity.div_ceil(shards).max(1),
+            config,
+            shards: (0..shards)
+                .map(|_| Shard {
+                    lanes: Mutex::new(if ordered {
+                        Lanes::ordered()
+                    } else {
+                        Lanes::default()
+                    }),
+                    ..Shard::default()
+                })
+                .collect(),
+            depth: AtomicUsize::new(0),
+            peak_depth: AtomicUsize::new(0),
+            closed: AtomicBool::new(false),
+        });
+
+        for worker in 0..workers {
+            let shared = Arc::clone(&shared);
+            tokio::spawn(async move {
+                let shard = &shared.shards[worker % shared.shards.len()];
+                loop {
+                    let notified = shard.notify.notified();
+                    if let Some(ctx) = shard.pop(&shared.depth) {
+                        let _ = dispatch(ctx).await;
+                        shard.processed.fetch_add(1, Ordering::Relaxed);
+                        continue;
+                    }
+                    if shared.closed.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    notified.await;
+                }
+            });
+        }
+
+        Self {
+            _closer: Arc::new(Closer(Arc::clone(&shared))),
+            shared,
+        }
+    }
+
+    pub fn push(&self, ctx: PluginContext) -> bool {
+        let priority = Priority::of(&ctx);
+        self.push_with_priority(ctx, priority)
+    }
+
+    pub fn push_with_priority(&self, ctx: PluginContext, priority: Priority) -> bool {
+        #[cfg(feature = "health")]
+        crate::health::record_update();
+        let shared = &self.shared;
+        let shard = shared.shard_of(&ctx);
+        let mut lanes = shard.lanes.lock().unwrap();
+
+        if lanes.len() >= shared.capacity {
+            shard.dropped.fetch_add(1, Ordering::Relaxed);
+            match shared.config.overflow {
+                OverflowPolicy::DropNewest => return false,
+                OverflowPolicy::DropOldest => {
+                    if lanes.evict_oldest().is_some() {
+                        shared.depth.fetch_sub(1, Ordering::Relaxed);
+                    }
+                }
+            }
+        }
+
+        lanes.push(ctx, priority);
+        shard.peak_depth.fetch_max(lanes.len(), Ordering::Relaxed);
+        shard.enqueued.fetch_add(1, Ordering::Relaxed);
+        let depth = shared.depth.fetch_add(1, Ordering::Relaxed) + 1;
+        shared.peak_depth.fetch_max(depth, Ordering::Relaxed);
+        drop(lanes);
+
+        shard.notify.notify_one();
+        true
+    }
+
+    pub fn metrics(&self) -> QueueMetrics {
+        self.shared.metrics()
+    }
+
+    // One entry per worker with `shard_by_chat`; otherwise the workers share a single
+    // queue and this has one entry, the same as `metrics()`.
+    pub fn worker_metrics(&self) -> Vec<QueueMetrics> {
+        self.shared.shards.iter().map(Shard::metrics).collect()
+    }
+
+    #[cfg(feature = "health")]
+    pub(crate) fn metrics_probe(&self) -> impl Fn() -> Option<QueueMetrics> + Send + Sync {
+        let shared = Arc::downgrade(&self.shared);
+        move || shared.upgrade().map(|shared| shared.metrics())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use teloxide::types::Message;
+    use teloxide::Bot;
+
+    fn ctx(chat: i64, id: i32) -> PluginContext {
+        let message: Message = serde_json::from_value(json!({
+            "message_id": id,
+            "date": 0,
+            "chat": {"id": chat, "type": "private", "first_name": "Ann"},
+            "from": {"id": chat, "is_bot": false, "first_name": "Ann"},
+            "text": "hi",
+        }))
+        .unwrap();
+        PluginContext::new(Bot::new("1:test"), Some(message), None)
+    }
+
+    fn id(ctx: Option<PluginContext>) -> Option<i32> {
+        ctx.and_then(|ctx| ctx.message).map(|message| message.id.0)
+    }
+
+    #[test]
+    fn evicts_the_update_that_waited_longest() {
+        let mut lanes = Lanes::default();
+        lanes.push(ctx(1, 1), Priority::High);
+        lanes.push(ctx(1, 2), Priority::Normal);
+        lanes.push(ctx(1, 3), Priority::High);
+        assert_eq!(id(lanes.evict_oldest()), Some(1));
+        assert_eq!(id(lanes.evict_oldest()), Some(2));
+        assert_eq!(id(lanes.evict_oldest()), Some(3));
+        assert_eq!(id(lanes.evict_oldest()), None);
+    }
+
+    #[test]
+    fn high_priority_goes_first() {
+        let mut lanes = Lanes::default();
+        lanes.push(ctx(1, 1), Priority::Normal);
+        lanes.push(ctx(1, 2), Priority::High);
+        assert_eq!(id(lanes.pop()), Some(2));
+        assert_eq!(id(lanes.pop()), Some(1));
+    }
+
+    #[test]
+    fn ordered_lanes_keep_a_chat_in_order() {
+        let mut lanes = Lanes::ordered();
+        lanes.push(ctx(1, 1), Priority::Normal);
+        lanes.push(ctx(1, 2), Priority::High);
+        lanes.push(ctx(2, 3), Priority::High);
+        assert_eq!(id(lanes.pop()), Some(3));
+        assert_eq!(id(lanes.pop()), Some(1));
+        assert_eq!(id(lanes.pop()), Some(2));
+
+        // Once the chat has nothing waiting, its presses skip ahead again.
+        lanes.push(ctx(2, 4), Priority::Normal);
+        lanes.push(ctx(1, 5), Priority::High);
+        assert_eq!(id(lanes.pop()), Some(5));
+    }
+
+    // The workers only get to run once the test awaits, so nothing is taken off the
+    // queue here.
+    #[tokio::test]
+    async fn chats_are_sharded_by_default() {
+        let queue = DispatchQueue::new(3);
+        assert_eq!(queue.worker_metrics().len(), 3);
+        for n in 0..4 {
+            queue.push(ctx(9, n));
+        }
+        let busy: Vec<usize> = queue
+            .worker_metrics()
+            .iter()
+            .map(|m| m.depth)
+            .filter(|&depth| depth > 0)
+            .collect();
+        assert_eq!(busy, [4]);
+    }
+
+    #[tokio::test]
+    async fn a_full_queue_drops_by_its_policy() {
+        let config = QueueConfig {
+            workers: 1,
+            capacity: 2,
+            overflow: OverflowPolicy::DropNewest,
+            shard_by_chat: false,
+        };
+        let queue = DispatchQueue::with_config(config.clone());
+        assert!(queue.push(ctx(1, 1)));
+        assert!(queue.push(ctx(1, 2)));
+        assert!(!queue.push(ctx(1, 3)));
+        let metrics = queue.metrics();
+        assert_eq!(
+            (metrics.depth, metrics.enqueued, metrics.dropped),
+            (2, 2, 1)
+        );
+
+        let queue = DispatchQueue::with_config(QueueConfig {
+            overflow: OverflowPolicy::DropOldest,
+            ..config
+        });
+        for n in 1..=3 {
+            assert!(queue.push(ctx(1, n)));
+        }
+        let metrics = queue.metrics();
+        assert_eq!(
+            (metrics.depth, metrics.peak_depth, metrics.dropped),
+            (2, 2, 1)
+        );
+        let shard = &queue.shared.shards[0];
+        assert_eq!(id(shard.pop(&queue.shared.depth)), Some(2));
+    }
+}
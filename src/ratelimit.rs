@@ -0,0 +1,81 @@
+use crate::context::PluginContext;
+use crate::registry::PluginMeta;
+use crate::texts;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use teloxide::types::{ChatId, UserId};
+
+type CooldownKey = (&'static str, Option<ChatId>, Option<UserId>);
+
+static BLOCKED_UNTIL: Lazy<Mutex<HashMap<CooldownKey, Instant>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+pub(crate) fn check(plugin: &'static PluginMeta, ctx: &PluginContext) -> Result<(), Duration> {
+    if plugin.cooldown == 0 {
+        return Ok(());
+    }
+
+    let cooldown = Duration::from_secs(plugin.cooldown);
+    let key = (plugin.name, ctx.chat_id(), ctx.sender().map(|u| u.id));
+    let now = Instant::now();
+    let mut blocked = BLOCKED_UNTIL.lock().unwrap();
+    blocked.retain(|_, until| *until > now);
+
+    if let Some(until) = blocked.get(&key) {
+        return Err(*until - now);
+    }
+    blocked.insert(key, now + cooldown);
+    Ok(())
+}
+
+type DebounceKey = (Option<&'static str>, String);
+
+static QUIET_UNTIL: Lazy<Mutex<HashMap<DebounceKey, Instant>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+// Keys are scoped to the calling plugin, so two plugins can both use `chat.to_string()`.
+fn pass(ctx: &PluginContext, key: String, window: Duration, extend: bool) -> bool {
+    let key = (ctx.plugin.map(|p| p.name), key);
+    let now = Instant::now();
+    let mut quiet = QUIET_UNTIL.lock().unwrap();
+    quiet.retain(|_, until| *until > now);
+
+    let proceed = !quiet.contains_key(&key);
+    if proceed || extend {
+        quiet.insert(key, now + window);
+    }
+    proceed
+}
+
+impl PluginContext {
+    // True for the first call of a burst; later calls with the same key return false
+    // until `window` goes by without any, so a steady stream only proceeds once.
+    pub fn debounce(&self, key: impl Into<String>, window: Duration) -> bool {
+        pass(self, key.into(), window, true)
+    }
+
+    // True at most once per `window` for the same key, however busy it is.
+    pub fn once_per(&self, key: impl Into<String>, window: Duration) -> bool {
+        pass(self, key.into(), window, false)
+    }
+}
+
+pub fn reset(plugin: &str, chat_id: Option<ChatId>, user_id: Option<UserId>) {
+    BLOCKED_UNTIL
+        .lock()
+        .unwrap()
+        .retain(|(name, chat, user), _| !(*name == plugin && *chat == chat_id && *user == user_id));
+}
+
+pub(crate) fn cooldown_notice(
+    plugin: &PluginMeta,
+    ctx: &PluginContext,
+    remaining: Duration,
+) -> String {
+    let seconds = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+    let texts = ctx.framework_texts();
+    let template = plugin.cooldown_message.unwrap_or(&texts.cooldown);
+    texts::fill(template, &[("remaining", &seconds.to_string())])
+}
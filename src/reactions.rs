@@ -0,0 +1,150 @@
+use crate::context::PluginContext;
+use std::fmt;
+use teloxide::prelude::*;
+use teloxide::types::{MessageId, ReactionType};
+
+pub const ALLOWED_REACTIONS: &[&str] = &[
+    "👍",
+    "👎",
+    "❤",
+    "🔥",
+    "🥰",
+    "👏",
+    "😁",
+    "🤔",
+    "🤯",
+    "😱",
+    "🤬",
+    "😢",
+    "🎉",
+    "🤩",
+    "🤮",
+    "💩",
+    "🙏",
+    "👌",
+    "🕊",
+    "🤡",
+    "🥱",
+    "🥴",
+    "😍",
+    "🐳",
+    "❤‍🔥",
+    "🌚",
+    "🌭",
+    "💯",
+    "🤣",
+    "⚡",
+    "🍌",
+    "🏆",
+    "💔",
+    "🤨",
+    "😐",
+    "🍓",
+    "🍾",
+    "💋",
+    "🖕",
+    "😈",
+    "😴",
+    "😭",
+    "🤓",
+    "👻",
+    "👨‍💻",
+    "👀",
+    "🎃",
+    "🙈",
+    "😇",
+    "😨",
+    "🤝",
+    "✍",
+    "🤗",
+    "🫡",
+    "🎅",
+    "🎄",
+    "☃",
+    "💅",
+    "🤪",
+    "🗿",
+    "🆒",
+    "💘",
+    "🙉",
+    "🦄",
+    "😘",
+    "💊",
+    "🙊",
+    "😎",
+    "👾",
+    "🤷‍♂",
+    "🤷",
+    "🤷‍♀",
+    "😡",
+];
+
+#[derive(Debug)]
+pub enum ReactionError {
+    NotAllowed(String),
+    NoMessage,
+    Request(teloxide::RequestError),
+}
+
+impl fmt::Display for ReactionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReactionError::NotAllowed(emoji) => {
+                write!(f, "{} can't be used as a reaction", emoji)
+            }
+            ReactionError::NoMessage => write!(f, "there is no message to react to"),
+            ReactionError::Request(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for ReactionError {}
+
+impl From<teloxide::RequestError> for ReactionError {
+    fn from(err: teloxide::RequestError) -> Self {
+        ReactionError::Request(err)
+    }
+}
+
+pub fn normalize_reaction(emoji: &str) -> Option<String> {
+    let normalized: String = emoji.trim().chars().filter(|c| *c != '\u{FE0F}').collect();
+    ALLOWED_REACTIONS
+        .contains(&normalized.as_str())
+        .then_some(normalized)
+}
+
+impl PluginContext {
+    pub async fn react(&self, emoji: &str) -> Result<(), ReactionError> {
+        let msg_id = self
+            .message
+            .as_ref()
+            .map(|m| m.id)
+            .ok_or(ReactionError::NoMessage)?;
+        self.set_reaction(msg_id, emoji).await
+    }
+
+    pub async fn set_reaction(&self, msg_id: MessageId, emoji: &str) -> Result<(), ReactionError> {
+        let emoji = normalize_reaction(emoji)
+            .ok_or_else(|| ReactionError::NotAllowed(emoji.to_string()))?;
+        let chat_id = self.chat_id().ok_or(ReactionError::NoMessage)?;
+
+        self.call(
+            self.bot
+                .set_message_reaction(chat_id, msg_id)
+                .reaction(vec![ReactionType::Emoji { emoji }]),
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn clear_reaction(&self, msg_id: MessageId) -> Result<(), ReactionError> {
+        let chat_id = self.chat_id().ok_or(ReactionError::NoMessage)?;
+        self.call(
+            self.bot
+                .set_message_reaction(chat_id, msg_id)
+                .reaction(Vec::<ReactionType>::new()),
+        )
+        .await?;
+        Ok(())
+    }
+}
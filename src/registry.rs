@@ -1,45 +1,613 @@
 #![allow(non_upper_case_globals)]
 
-use crate::context::PluginContext;
+use crate::access;
+use crate::analytics;
+use crate::budget;
+use crate::callbacks;
+use crate::chat_cache;
+use crate::chat_info;
+use crate::chats::{self, KnownChat};
+use crate::context::{command_mention, split_command, PluginContext};
+use crate::conversation;
+use crate::dedup;
+use crate::dispatcher;
+use crate::entities;
+use crate::external;
+use crate::federation;
+use crate::files;
+use crate::history;
+use crate::logging::{self, Outcome};
+use crate::migration;
+use crate::permissions;
+use crate::polls;
+use crate::ratelimit;
+use crate::reporting::{self, ErrorKind};
+use crate::session::{self, ConflictStrategy, SessionSlot, WriteBack};
+use crate::spam;
+use crate::storage::StorageError;
+use crate::supervisor;
+use crate::texts;
+use crate::topics;
+use crate::traffic;
+use crate::transcribe;
+use chrono::{DateTime, Utc};
+use futures::FutureExt;
 use once_cell::sync::Lazy;
 use regex::Regex;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::future::Future;
+use std::panic::AssertUnwindSafe;
 use std::pin::Pin;
-use std::sync::{Mutex, RwLock as StdRwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock as StdRwLock};
+use std::time::Instant;
+use teloxide::prelude::*;
 use tokio::sync::RwLock as AsyncRwLock;
 
+pub use crate::doctor::doctor;
+pub use crate::supervisor::spawn_supervised;
+
 pub struct PluginMeta {
     pub name: &'static str,
+    pub priority: i32,
     pub commands: &'static [&'static str],
     pub prefixes: &'static [&'static str],
     pub regex: Option<&'static str>,
+    pub not_regex: Option<&'static str>,
+    pub exclude_commands: &'static [&'static str],
     pub callback_filter: Option<&'static str>,
+    pub games: &'static [&'static str],
+    pub owner_only_callback: bool,
+    pub signed: bool,
+    pub requires_reply: bool,
+    pub admin_only: bool,
+    pub role: Option<&'static str>,
+    pub requires: &'static [&'static str],
+    pub bot_requires: &'static [&'static str],
+    pub allow_anonymous_admin: bool,
+    pub ignore_bots: Option<bool>,
+    pub from_bot_only: bool,
+    pub on_new: bool,
+    pub on_edited: bool,
+    pub on_business: bool,
+    pub on_edited_business: bool,
+    pub match_captions: Option<bool>,
+    pub entities: &'static [&'static str],
+    pub media: &'static [&'static str],
+    pub transcribe: bool,
+    pub outbox: bool,
+    pub langs: &'static [&'static str],
+    pub topics: &'static [&'static str],
+    pub inline: Option<&'static str>,
+    pub updates: &'static [&'static str],
+    pub raw_json: bool,
+    pub cron: Option<&'static str>,
+    pub tz: Option<&'static str>,
+    pub overlap: &'static str,
+    pub jitter: u64,
+    pub external: &'static [&'static str],
+    pub conversation: Option<&'static str>,
+    pub step: Option<&'static str>,
+    pub cooldown: u64,
+    pub cooldown_message: Option<&'static str>,
+    pub cooldown_silent: bool,
     pub callback: fn(PluginContext) -> Pin<Box<dyn Future<Output = ()> + Send>>,
 }
 
 pub static PLUGIN_REGISTRY: Lazy<Mutex<Vec<&'static PluginMeta>>> =
     Lazy::new(|| Mutex::new(Vec::new()));
 
-static REGEX_CACHE: Lazy<AsyncRwLock<HashMap<&'static str, Regex>>> =
+static REGEX_CACHE: Lazy<AsyncRwLock<HashMap<&'static str, Option<Regex>>>> =
     Lazy::new(|| AsyncRwLock::new(HashMap::new()));
 
-static COMMAND_MAP: Lazy<StdRwLock<HashMap<String, &'static PluginMeta>>> =
+static COMMAND_MAP: Lazy<StdRwLock<HashMap<String, Vec<&'static PluginMeta>>>> =
     Lazy::new(|| StdRwLock::new(HashMap::new()));
 
-fn find_command_plugin(text: &str) -> Option<&'static PluginMeta> {
+static IGNORE_BOTS: AtomicBool = AtomicBool::new(false);
+static MATCH_CAPTIONS: AtomicBool = AtomicBool::new(false);
+static MAINTENANCE: StdRwLock<Option<(String, DateTime<Utc>)>> = StdRwLock::new(None);
+static DISABLED: Lazy<StdRwLock<HashSet<&'static str>>> =
+    Lazy::new(|| StdRwLock::new(HashSet::new()));
+
+pub fn set_ignore_bots(ignore: bool) {
+    IGNORE_BOTS.store(ignore, Ordering::Relaxed);
+}
+
+pub fn set_match_captions(enabled: bool) {
+    MATCH_CAPTIONS.store(enabled, Ordering::Relaxed);
+}
+
+pub fn set_maintenance(enabled: bool, notice: impl Into<String>) {
+    let mut maintenance = MAINTENANCE.write().unwrap();
+    *maintenance = match (enabled, maintenance.take()) {
+        (false, _) => None,
+        (true, Some((_, since))) => Some((notice.into(), since)),
+        (true, None) => Some((notice.into(), Utc::now())),
+    };
+}
+
+pub fn maintenance() -> Option<String> {
+    MAINTENANCE
+        .read()
+        .unwrap()
+        .as_ref()
+        .map(|(notice, _)| notice.clone())
+}
+
+// Switches a plugin off or back on while the bot runs. A disabled plugin matches
+// nothing and its cron schedule skips; disabling also stops its supervised tasks,
+// which don't come back when it's enabled again. Returns false if no plugin has that
+// name. Not persisted, so every plugin starts enabled.
+pub fn set_enabled(plugin: &str, enabled: bool) -> bool {
+    let Some(plugin) = plugins().into_iter().find(|p| p.name == plugin) else {
+        return false;
+    };
+    if enabled {
+        DISABLED.write().unwrap().remove(plugin.name);
+    } else {
+        DISABLED.write().unwrap().insert(plugin.name);
+        supervisor::stop(plugin.name);
+    }
+    true
+}
+
+pub fn is_enabled(plugin: &str) -> bool {
+    !DISABLED.read().unwrap().contains(plugin)
+}
+
+pub fn disabled() -> Vec<&'static str> {
+    let mut disabled: Vec<_> = DISABLED.read().unwrap().iter().copied().collect();
+    disabled.sort();
+    disabled
+}
+
+fn maintenance_notice(ctx: &PluginContext) -> Option<String> {
+    let (notice, since) = MAINTENANCE.read().unwrap().clone()?;
+    let notice = ctx.framework_texts().maintenance.clone().unwrap_or(notice);
+    let name = ctx
+        .sender()
+        .map(|u| u.first_name.as_str())
+        .unwrap_or("there");
+    Some(
+        notice
+            .replace("{name}", name)
+            .replace("{since}", &since.format("%H:%M UTC").to_string())
+            .replace(
+                "{minutes}",
+                &(Utc::now() - since).num_minutes().max(0).to_string(),
+            ),
+    )
+}
+
+fn match_text<'a>(plugin: &PluginMeta, ctx: &'a PluginContext) -> Option<&'a str> {
+    let msg = ctx.message.as_ref()?;
+    msg.text().or_else(|| {
+        let captions = plugin
+            .match_captions
+            .unwrap_or_else(|| MATCH_CAPTIONS.load(Ordering::Relaxed));
+        if captions {
+            msg.caption()
+        } else {
+            None
+        }
+    })
+}
+
+pub(crate) fn passes_filters(plugin: &PluginMeta, ctx: &PluginContext) -> bool {
+    if !is_enabled(plugin.name) {
+        return false;
+    }
+
+    if let Some(step) = plugin.step {
+        let in_step = conversation::current(ctx)
+            .is_some_and(|(name, current)| Some(name) == plugin.conversation && current == step);
+        if !in_step {
+            return false;
+        }
+    }
+
+    if ctx.message.is_some() {
+        let wanted = match (ctx.is_business(), ctx.is_edited()) {
+            (false, false) => plugin.on_new,
+            (false, true) => plugin.on_edited,
+            (true, false) => plugin.on_business,
+            (true, true) => plugin.on_edited_business,
+        };
+        if !wanted {
+            return false;
+        }
+    }
+
+    let from_bot = ctx
+        .message
+        .as_ref()
+        .is_some_and(|m| m.from.as_ref().is_some_and(|user| user.is_bot) || m.via_bot.is_some());
+
+    if plugin.from_bot_only {
+        return from_bot;
+    }
+    if !plugin.langs.is_empty() {
+        let lang = ctx.sender().and_then(|u| u.language_code.as_deref());
+        let primary = lang.map(|code| code.split(['-', '_']).next().unwrap_or(code));
+        let wants = |code: &str| {
+            plugin
+                .langs
+                .iter()
+                .any(|wanted| wanted.eq_ignore_ascii_case(code))
+        };
+        let matches = primary.is_some_and(wants);
+        #[cfg(feature = "lang-detect")]
+        let matches =
+            matches || crate::lang_detect::routing_language(ctx).is_some_and(|code| wants(&code));
+        if !matches {
+            return false;
+        }
+    }
+
+    if !plugin.topics.is_empty() {
+        let name = topics::message_of(ctx).and_then(topics::name_of);
+        let matches = name.is_some_and(|name| {
+            plugin
+                .topics
+                .iter()
+                .any(|wanted| wanted.eq_ignore_ascii_case(&name))
+        });
+        if !matches {
+            return false;
+        }
+    }
+
+    let ignore_bots = plugin
+        .ignore_bots
+        .unwrap_or_else(|| IGNORE_BOTS.load(Ordering::Relaxed));
+    !(ignore_bots && from_bot)
+}
+
+async fn is_excluded(plugin: &PluginMeta, ctx: &PluginContext) -> bool {
+    let Some(text) = match_text(plugin, ctx) else {
+        return false;
+    };
+    if !plugin.exclude_commands.is_empty() {
+        let (command, _) = split_command(text);
+        let name = command.trim_start_matches(|c: char| !c.is_alphanumeric());
+        if name.len() < command.len()
+            && plugin.exclude_commands.iter().any(|excluded| {
+                excluded
+                    .trim_start_matches(|c: char| !c.is_alphanumeric())
+                    .eq_ignore_ascii_case(name)
+            })
+        {
+            return true;
+        }
+    }
+    match plugin.not_regex {
+        Some(pattern) => get_or_compile_regex(plugin, pattern)
+            .await
+            .is_some_and(|regex| regex.is_match(text)),
+        None => false,
+    }
+}
+
+fn has_triggers(plugin: &PluginMeta) -> bool {
+    !plugin.commands.is_empty()
+        || plugin.regex.is_some()
+        || !plugin.entities.is_empty()
+        || !plugin.media.is_empty()
+        || plugin.callback_filter.is_some()
+        || !plugin.games.is_empty()
+}
+
+// `/cmd@OtherBot` is for another bot in the group. If our own name can't be looked
+// up, addressed commands are ignored rather than risk answering for someone else.
+async fn addressed_elsewhere(ctx: &PluginContext, text: &str) -> bool {
+    let Some(mention) = command_mention(text) else {
+        return false;
+    };
+    permissions::bot_username(&ctx.bot)
+        .await
+        .map_or(true, |username| !username.eq_ignore_ascii_case(mention))
+}
+
+fn find_command_plugins(text: &str) -> Vec<&'static PluginMeta> {
+    let (command, _) = split_command(text);
     let map = COMMAND_MAP.read().unwrap();
-    map.get(text).copied()
+    map.get(command).cloned().unwrap_or_default()
+}
+
+async fn deny(ctx: &PluginContext, text: &str) {
+    if let Some(cq) = &ctx.callback_query {
+        let request = ctx
+            .bot
+            .answer_callback_query(cq.id.clone())
+            .text(text)
+            .show_alert(true);
+        let _ = traffic::send(request).await;
+    } else if let Some(chat_id) = ctx.chat_id() {
+        let _ = traffic::send(ctx.bot.send_message(chat_id, text)).await;
+    }
+}
+
+async fn check_sender_rights(plugin: &'static PluginMeta, ctx: &PluginContext) -> bool {
+    if !plugin.admin_only && plugin.requires.is_empty() {
+        return true;
+    }
+
+    let texts = ctx.framework_texts();
+    if ctx.is_private_chat() {
+        deny(ctx, &texts.groups_only).await;
+        return false;
+    }
+    if ctx.is_anonymous_admin() {
+        if !plugin.allow_anonymous_admin {
+            deny(ctx, &texts.anonymous_admin).await;
+            return false;
+        }
+        return check_anonymous_admin_rights(plugin, ctx).await;
+    }
+    if ctx.is_on_behalf_of_channel() {
+        deny(ctx, &texts.on_behalf_of_channel).await;
+        return false;
+    }
+
+    let (Some(chat_id), Some(user)) = (ctx.chat_id(), ctx.sender()) else {
+        return false;
+    };
+
+    let member = match permissions::chat_member(&ctx.bot, chat_id, user.id).await {
+        Ok(member) => member,
+        Err(_) => return false,
+    };
+
+    if !member.is_privileged() {
+        deny(ctx, &texts.admins_only).await;
+        return false;
+    }
+
+    let missing = permissions::missing_rights(&member, plugin.requires);
+    if !missing.is_empty() {
+        let rights = missing.join(", ");
+        deny(
+            ctx,
+            &texts::fill(&texts.missing_rights, &[("rights", &rights)]),
+        )
+        .await;
+        return false;
+    }
+
+    true
+}
+
+async fn check_anonymous_admin_rights(plugin: &'static PluginMeta, ctx: &PluginContext) -> bool {
+    if plugin.requires.is_empty() {
+        return true;
+    }
+    let Some(chat_id) = ctx.chat_id() else {
+        return false;
+    };
+    let missing =
+        match permissions::anonymous_admins_missing(&ctx.bot, chat_id, plugin.requires).await {
+            Ok(missing) => missing,
+            Err(_) => return false,
+        };
+    if !missing.is_empty() {
+        let rights = missing.join(", ");
+        let texts = ctx.framework_texts();
+        deny(
+            ctx,
+            &texts::fill(&texts.missing_rights, &[("rights", &rights)]),
+        )
+        .await;
+        return false;
+    }
+    true
+}
+
+async fn check_bot_rights(plugin: &'static PluginMeta, ctx: &PluginContext) -> bool {
+    if plugin.bot_requires.is_empty() || ctx.is_private_chat() {
+        return true;
+    }
+    let Some(chat_id) = ctx.chat_id() else {
+        return false;
+    };
+
+    let member = match permissions::bot_member(&ctx.bot, chat_id).await {
+        Ok(member) => member,
+        Err(_) => return false,
+    };
+
+    let missing = permissions::missing_rights(&member, plugin.bot_requires);
+    if !missing.is_empty() {
+        let rights = missing.join(", ");
+        let texts = ctx.framework_texts();
+        deny(
+            ctx,
+            &texts::fill(&texts.bot_missing_rights, &[("rights", &rights)]),
+        )
+        .await;
+        return false;
+    }
+
+    true
+}
+
+pub(crate) async fn run_plugin(plugin: &'static PluginMeta, ctx: &PluginContext) -> Outcome {
+    if plugin.owner_only_callback && !callbacks::pressed_by_owner(ctx) {
+        deny(ctx, &ctx.framework_texts().not_your_button).await;
+        return Outcome::Denied;
+    }
+
+    if plugin.requires_reply && ctx.replied_message().is_none() {
+        deny(ctx, &ctx.framework_texts().requires_reply).await;
+        return Outcome::Denied;
+    }
+
+    if !check_sender_rights(plugin, ctx).await || !check_bot_rights(plugin, ctx).await {
+        return Outcome::Denied;
+    }
+
+    if let Some(role) = plugin.role {
+        if !ctx.has_role(role).await {
+            let texts = ctx.framework_texts();
+            deny(ctx, &texts::fill(&texts.missing_role, &[("role", role)])).await;
+            return Outcome::Denied;
+        }
+    }
+
+    if let Err(remaining) = ratelimit::check(plugin, ctx) {
+        if !plugin.cooldown_silent {
+            deny(ctx, &ratelimit::cooldown_notice(plugin, ctx, remaining)).await;
+        }
+        return Outcome::Cooldown;
+    }
+
+    if plugin.step.is_some() {
+        conversation::touch(ctx);
+    }
+
+    let mut ctx = ctx.clone();
+    ctx.plugin = Some(plugin);
+    ctx.failed = Arc::default();
+    if plugin.transcribe {
+        if let Err(err) = traffic::scope(plugin.name, transcribe::prepare(&ctx)).await {
+            reporting::report(&ctx, ErrorKind::Handler, &err).await;
+        }
+    }
+    let attempts = match session::conflict_strategy() {
+        ConflictStrategy::RetryWithReload { attempts } => attempts.max(1),
+        _ => 1,
+    };
+    for _ in 0..attempts {
+        ctx.session = SessionSlot::default();
+        let (outcome, busy) = budget::metered(traffic::scope(
+            plugin.name,
+            AssertUnwindSafe((plugin.callback)(ctx.clone())).catch_unwind(),
+        ))
+        .await;
+        budget::record_run(plugin, busy);
+        if let Err(payload) = outcome {
+            reporting::report_panic(&ctx, payload).await;
+            return Outcome::Panic;
+        }
+        if !matches!(session::write_back(&ctx).await, Ok(WriteBack::Conflict)) {
+            break;
+        }
+    }
+
+    if ctx.failed.load(Ordering::Relaxed) {
+        Outcome::Error
+    } else {
+        Outcome::Ok
+    }
 }
 
 pub async fn dispatch(ctx: PluginContext) -> Result<(), teloxide::RequestError> {
-    let text = ctx.message.as_ref().and_then(|m| m.text());
-    let cb_data = ctx.callback_query.as_ref().and_then(|c| c.data.as_deref());
+    let started = Instant::now();
+    let matched = route(&ctx).await;
+    logging::emit(&ctx, matched, started.elapsed()).await;
+    if let Some((plugin, Outcome::Ok | Outcome::Error | Outcome::Panic)) = matched {
+        let _ = analytics::record(&ctx, plugin).await;
+    }
+    Ok(())
+}
+
+async fn route(ctx: &PluginContext) -> Option<(&'static PluginMeta, Outcome)> {
+    if let Some(msg) = &ctx.message {
+        if let Some((from, to)) = migration::migration_of(msg) {
+            migration::migrate(from, to).await;
+            return None;
+        }
+        if !ctx.is_edited() {
+            let _ = chat_cache::record_message(msg).await;
+            let _ = chats::record_seen(&msg.chat).await;
+        }
+        history::record(msg);
+        topics::track(msg);
+        chat_info::track(msg);
+    }
+    if let Some(update) = &ctx.my_chat_member {
+        chat_info::invalidate(update.chat.id);
+        permissions::invalidate_member(update.chat.id, update.new_chat_member.user.id);
+        let _ = chats::record_member_update(update).await;
+    }
+    if ctx.poll.is_some() || ctx.poll_answer.is_some() {
+        polls::track(ctx);
+    }
+
+    if let Some(event) = &ctx.external {
+        for plugin in external::listeners(&event.name) {
+            if is_enabled(plugin.name) && budget::suspended_for(plugin.name).is_none() {
+                return Some((plugin, run_plugin(plugin, ctx).await));
+            }
+        }
+        return None;
+    }
+
+    if !access::admits(ctx).await {
+        if let Some(text) = access::unauthorized_message() {
+            if ctx.callback_query.is_some() || ctx.is_private_chat() {
+                deny(ctx, &text).await;
+            }
+        }
+        return None;
+    }
+
+    if !ctx.sender().is_some_and(|u| access::is_owner(u.id)) {
+        if let Some(notice) = maintenance_notice(ctx) {
+            let is_command = ctx
+                .text_or_caption()
+                .is_some_and(|text| !find_command_plugins(text).is_empty());
+            if ctx.callback_query.is_some() || ctx.is_private_chat() || is_command {
+                deny(ctx, &notice).await;
+            }
+            return None;
+        }
+    }
+
+    if federation::enforce(ctx).await {
+        return None;
+    }
+
+    #[cfg(feature = "link-filter")]
+    if crate::link_filter::enforce(ctx).await {
+        return None;
+    }
+
+    if dedup::check(ctx).await {
+        return None;
+    }
+
+    spam::score(ctx).await;
 
+    #[cfg(feature = "lang-detect")]
+    crate::lang_detect::tag(ctx);
+
+    #[cfg(feature = "afk")]
+    crate::afk::track(ctx).await;
+
+    let cb_data = match ctx.callback_query.as_ref().and_then(|c| c.data.as_deref()) {
+        Some(data) => match callbacks::verify(data) {
+            Ok(payload) => Some(payload),
+            Err(err) => {
+                deny(ctx, &callbacks::rejection_notice(ctx, err)).await;
+                return None;
+            }
+        },
+        None => None,
+    };
+
+    let text = match ctx.text_or_caption() {
+        Some(text) if !addressed_elsewhere(ctx, text).await => Some(text),
+        _ => None,
+    };
     if let Some(text) = text {
-        if let Some(plugin) = find_command_plugin(text) {
-            (plugin.callback)(ctx.clone()).await;
-            return Ok(());
+        for plugin in find_command_plugins(text) {
+            if match_text(plugin, ctx).is_some()
+                && passes_filters(plugin, ctx)
+                && budget::suspended_for(plugin.name).is_none()
+                && !is_excluded(plugin, ctx).await
+            {
+                return Some((plugin, run_plugin(plugin, ctx).await));
+            }
         }
     }
 
@@ -48,33 +616,145 @@ pub async fn dispatch(ctx: PluginContext) -> Result<(), teloxide::RequestError>
         registry.clone()
     };
 
+    if ctx.message.is_some() {
+        for plugin in plugins
+            .iter()
+            .filter(|p| p.step.is_some() && !has_triggers(p))
+        {
+            if passes_filters(plugin, ctx)
+                && budget::suspended_for(plugin.name).is_none()
+                && !is_excluded(plugin, ctx).await
+            {
+                return Some((plugin, run_plugin(plugin, ctx).await));
+            }
+        }
+    }
+
+    #[cfg(feature = "filters")]
+    let mut filters_pending = ctx.message.is_some();
     for plugin in plugins {
-        if let Some(text) = text {
+        #[cfg(feature = "filters")]
+        if filters_pending && plugin.priority < crate::filters::priority() {
+            filters_pending = false;
+            if let Some(matched) = crate::filters::route(ctx).await {
+                return Some(matched);
+            }
+        }
+
+        if !passes_filters(plugin, ctx)
+            || budget::suspended_for(plugin.name).is_some()
+            || is_excluded(plugin, ctx).await
+        {
+            continue;
+        }
+
+        if let Some(text) = match_text(plugin, ctx) {
             if let Some(re) = plugin.regex {
-                let regex = get_or_compile_regex(re).await;
-                if regex.is_match(text) {
-                    (plugin.callback)(ctx.clone()).await;
-                    return Ok(());
+                let regex = get_or_compile_regex(plugin, re).await;
+                if regex.is_some_and(|regex| regex.is_match(text)) {
+                    return Some((plugin, run_plugin(plugin, ctx).await));
                 }
             }
         }
 
+        if !plugin.entities.is_empty() {
+            if let Some(msg) = &ctx.message {
+                if entities::has_entity(msg, plugin.entities) {
+                    return Some((plugin, run_plugin(plugin, ctx).await));
+                }
+            }
+        }
+
+        if !plugin.media.is_empty() {
+            let kind = ctx.message.as_ref().and_then(files::media_kind);
+            if kind.is_some_and(|kind| plugin.media.contains(&kind)) {
+                return Some((plugin, run_plugin(plugin, ctx).await));
+            }
+        }
+
+        if plugin.raw_json && ctx.raw_update.is_some() {
+            return Some((plugin, run_plugin(plugin, ctx).await));
+        }
+
+        if let Some(kind) = ctx.update_kind() {
+            if plugin.updates.contains(&kind) {
+                return Some((plugin, run_plugin(plugin, ctx).await));
+            }
+        }
+
+        if let (Some(query), Some(re)) = (&ctx.inline_query, plugin.inline) {
+            let regex = get_or_compile_regex(plugin, re).await;
+            if regex.is_some_and(|regex| regex.is_match(&query.query)) {
+                return Some((plugin, run_plugin(plugin, ctx).await));
+            }
+        }
+
+        if let Some(game) = ctx.game_short_name() {
+            if plugin.games.contains(&game) {
+                return Some((plugin, run_plugin(plugin, ctx).await));
+            }
+        }
+
         if let Some(cb) = cb_data {
             if let Some(filter) = plugin.callback_filter {
-                if cb == filter {
-                    (plugin.callback)(ctx.clone()).await;
-                    return Ok(());
+                if callbacks::matches(filter, cb) {
+                    return Some((plugin, run_plugin(plugin, ctx).await));
                 }
             }
         }
     }
 
-    Ok(())
+    #[cfg(feature = "filters")]
+    if filters_pending {
+        if let Some(matched) = crate::filters::route(ctx).await {
+            return Some(matched);
+        }
+    }
+
+    #[cfg(feature = "ai")]
+    if let Some(matched) = crate::ai::fallback(ctx).await {
+        return Some(matched);
+    }
+
+    None
+}
+
+pub async fn known_chats() -> Result<Vec<KnownChat>, StorageError> {
+    chats::known_chats().await
+}
+
+pub fn plugins() -> Vec<&'static PluginMeta> {
+    PLUGIN_REGISTRY.lock().unwrap().clone()
+}
+
+pub fn env_flag_enabled(name: &str) -> bool {
+    match std::env::var(name) {
+        Ok(value) => !matches!(
+            value.trim().to_ascii_lowercase().as_str(),
+            "" | "0" | "false" | "no" | "off"
+        ),
+        Err(_) => false,
+    }
+}
+
+// Constructors run in link order, which changes between builds, so the registry is kept
+// sorted instead of in registration order.
+fn routing_key(plugin: &PluginMeta) -> (std::cmp::Reverse<i32>, &'static str) {
+    (std::cmp::Reverse(plugin.priority), plugin.name)
 }
 
 pub fn register_plugin(plugin: &'static PluginMeta) {
-    let mut registry = PLUGIN_REGISTRY.lock().unwrap();
-    registry.push(plugin);
+    {
+        let mut registry = PLUGIN_REGISTRY.lock().unwrap();
+        registry.push(plugin);
+        registry.sort_by_key(|p| routing_key(p));
+    }
+
+    if let (true, Some(filter)) = (plugin.signed, plugin.callback_filter) {
+        for name in filter.split('|') {
+            callbacks::require_signed_for(name);
+        }
+    }
 
     if !plugin.prefixes.is_empty() && !plugin.commands.is_empty() {
         let mut map = COMMAND_MAP.write().unwrap();
@@ -83,13 +763,21 @@ pub fn register_plugin(plugin: &'static PluginMeta) {
                 let mut key = String::with_capacity(prefix.len() + cmd.len());
                 key.push_str(prefix);
                 key.push_str(cmd);
-                map.entry(key).or_insert(plugin);
+                let plugins = map.entry(key).or_default();
+                plugins.push(plugin);
+                plugins.sort_by_key(|p| (p.langs.is_empty(), routing_key(p)));
             }
         }
     }
+
+    // A plugin installed while polling may need updates it isn't asking for yet.
+    dispatcher::refresh_allowed_updates();
 }
 
-async fn get_or_compile_regex(pattern: &'static str) -> Regex {
+// A pattern that doesn't compile never matches. It can only come from a hand-built
+// `PluginMeta`, since the macro and `plugin::install` both refuse one, and it's
+// reported once, the first time it is needed.
+async fn get_or_compile_regex(plugin: &PluginMeta, pattern: &'static str) -> Option<Regex> {
     {
         let cache = REGEX_CACHE.read().await;
         if let Some(r) = cache.get(pattern) {
@@ -97,10 +785,168 @@ async fn get_or_compile_regex(pattern: &'static str) -> Regex {
         }
     }
 
-    let regex = Regex::new(pattern).unwrap_or_else(|_| Regex::new("(?!)").unwrap());
+    let regex = match Regex::new(pattern) {
+        Ok(regex) => Some(regex),
+        Err(err) => {
+            reporting::report_background(Some(plugin.name), ErrorKind::Handler, None, &err).await;
+            None
+        }
+    };
 
     let mut cache = REGEX_CACHE.write().await;
     cache.insert(pattern, regex.clone());
 
     regex
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use teloxide::types::ChatMember;
+
+    #[crate::TeloxidePlugin(commands = ["quote"], prefixes = ["/"], requires_reply = true)]
+    async fn quote() {}
+
+    #[crate::TeloxidePlugin(
+        commands = ["purge"],
+        prefixes = ["/"],
+        admin_only = true,
+        requires = ["can_delete_messages"]
+    )]
+    async fn purge() {}
+
+    // A message in topic 10 of a forum, replying to whatever `reply` says. Refusals
+    // go to an address nothing listens on.
+    fn message(text: &str, reply: Value) -> PluginContext {
+        let bot = Bot::new("1:test").set_api_url("http://127.0.0.1:1".parse().unwrap());
+        let message: Message = serde_json::from_value(json!({
+            "message_id": 12,
+            "message_thread_id": 10,
+            "is_topic_message": true,
+            "date": 0,
+            "chat": {"id": -100, "type": "supergroup", "title": "Forum", "is_forum": true},
+            "from": {"id": 7, "is_bot": false, "first_name": "Ann"},
+            "text": text,
+            "reply_to_message": reply,
+        }))
+        .unwrap();
+        PluginContext::new(bot, Some(message), None)
+    }
+
+    async fn outcome(ctx: PluginContext) -> Option<(&'static str, Outcome)> {
+        route(&ctx)
+            .await
+            .map(|(plugin, outcome)| (plugin.name, outcome))
+    }
+
+    #[tokio::test]
+    async fn requires_reply_refuses_messages_that_reply_to_nothing() {
+        let topic_root = json!({
+            "message_id": 10,
+            "date": 0,
+            "chat": {"id": -100, "type": "supergroup", "title": "Forum", "is_forum": true},
+            "from": {"id": 1, "is_bot": false, "first_name": "Creator"},
+            "forum_topic_created": {"name": "Support", "icon_color": 7322096},
+        });
+        assert_eq!(
+            outcome(message("/quote", Value::Null)).await,
+            Some(("quote", Outcome::Denied))
+        );
+        assert_eq!(
+            outcome(message("/quote", topic_root)).await,
+            Some(("quote", Outcome::Denied))
+        );
+
+        let reply = json!({
+            "message_id": 11,
+            "message_thread_id": 10,
+            "is_topic_message": true,
+            "date": 0,
+            "chat": {"id": -100, "type": "supergroup", "title": "Forum", "is_forum": true},
+            "from": {"id": 5, "is_bot": false, "first_name": "Bob"},
+            "text": "worth quoting",
+        });
+        assert_eq!(
+            outcome(message("/quote", reply)).await,
+            Some(("quote", Outcome::Ok))
+        );
+    }
+
+    // A command from user 7 in `chat`.
+    fn command(text: &str, chat: Value) -> PluginContext {
+        let bot = Bot::new("1:test").set_api_url("http://127.0.0.1:1".parse().unwrap());
+        let message: Message = serde_json::from_value(json!({
+            "message_id": 1,
+            "date": 0,
+            "chat": chat,
+            "from": {"id": 7, "is_bot": false, "first_name": "Ann"},
+            "text": text,
+        }))
+        .unwrap();
+        PluginContext::new(bot, Some(message), None)
+    }
+
+    fn group(id: i64) -> Value {
+        json!({"id": id, "type": "supergroup", "title": "Group"})
+    }
+
+    fn member(status: Value) -> ChatMember {
+        let mut member = json!({"user": {"id": 7, "is_bot": false, "first_name": "Ann"}});
+        member
+            .as_object_mut()
+            .unwrap()
+            .extend(status.as_object().unwrap().clone());
+        serde_json::from_value(member).unwrap()
+    }
+
+    fn admin(can_delete_messages: bool) -> ChatMember {
+        member(json!({
+            "status": "administrator",
+            "can_be_edited": false,
+            "is_anonymous": false,
+            "can_manage_chat": true,
+            "can_delete_messages": can_delete_messages,
+            "can_manage_video_chats": false,
+            "can_restrict_members": true,
+            "can_promote_members": false,
+            "can_change_info": false,
+            "can_invite_users": true,
+            "can_post_stories": false,
+            "can_edit_stories": false,
+            "can_delete_stories": false,
+        }))
+    }
+
+    #[tokio::test]
+    async fn admin_only_needs_an_admin_with_the_required_rights() {
+        let private = json!({"id": 7, "type": "private", "first_name": "Ann"});
+        assert_eq!(
+            outcome(command("/purge", private)).await,
+            Some(("purge", Outcome::Denied))
+        );
+
+        permissions::remember_member(ChatId(-201), admin(true));
+        assert_eq!(
+            outcome(command("/purge", group(-201))).await,
+            Some(("purge", Outcome::Ok))
+        );
+
+        permissions::remember_member(ChatId(-202), admin(false));
+        permissions::remember_member(ChatId(-203), member(json!({"status": "member"})));
+        for chat in [-202, -203] {
+            assert_eq!(
+                outcome(command("/purge", group(chat))).await,
+                Some(("purge", Outcome::Denied))
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn admin_only_refuses_when_the_lookup_fails() {
+        assert_eq!(
+            outcome(command("/purge", group(-204))).await,
+            Some(("purge", Outcome::Denied))
+        );
+    }
+}
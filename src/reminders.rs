@@ -0,0 +1,128 @@
+use crate::args::HumanDuration;
+use crate::context::PluginContext;
+use crate::scheduler::{self, Job, SchedulerError};
+use crate::storage::StorageError;
+use chrono::Utc;
+use std::time::Duration;
+use teloxide::prelude::*;
+use teloxide::types::User;
+
+// Durations can be written with spaces, like `1h 30m` or `2 hours`, so the longest
+// leading run of words that parses wins.
+fn split_duration(args: &[&str]) -> Option<(Duration, String)> {
+    (1..=args.len().min(3)).rev().find_map(|words| {
+        let duration = args[..words].join("").parse::<HumanDuration>().ok()?.0;
+        Some((duration, args[words..].join(" ")))
+    })
+}
+
+fn addressee(user: &User) -> String {
+    match &user.username {
+        Some(username) => format!("@{}", username),
+        None => user.first_name.clone(),
+    }
+}
+
+pub async fn remind(
+    chat: ChatId,
+    user: &User,
+    text: &str,
+    delay: Duration,
+) -> Result<Job, SchedulerError> {
+    let run_at = scheduler::run_at_after(delay)?;
+    let job = Job {
+        id: scheduler::new_id(),
+        chat_id: chat,
+        text: format!("Reminder for {}: {}", addressee(user), text),
+        run_at,
+        owner: Some(user.id),
+    };
+    scheduler::schedule(job.clone()).await?;
+    Ok(job)
+}
+
+// A user's reminders in one chat, or everywhere when `chat` is `None`, soonest first.
+pub async fn pending(user: UserId, chat: Option<ChatId>) -> Result<Vec<Job>, StorageError> {
+    Ok(scheduler::pending()
+        .await?
+        .into_iter()
+        .filter(|job| job.owner == Some(user) && chat.is_none_or(|chat| job.chat_id == chat))
+        .collect())
+}
+
+pub async fn cancel(user: UserId, id: &str) -> Result<bool, StorageError> {
+    let owned = pending(user, None).await?.iter().any(|job| job.id == id);
+    if owned {
+        scheduler::cancel(id).await?;
+    }
+    Ok(owned)
+}
+
+// Reminders set in a group are listed there; a private chat lists all of them.
+async fn listed(ctx: &PluginContext, user: UserId) -> Result<Vec<Job>, StorageError> {
+    let chat = ctx.chat_id().filter(|_| !ctx.is_private_chat());
+    pending(user, chat).await
+}
+
+#[crate::TeloxidePlugin(commands = ["remind"], prefixes = ["/"])]
+async fn remind_command(ctx: PluginContext) -> Option<String> {
+    let user = ctx.sender()?;
+    let parsed =
+        split_duration(&ctx.args()).filter(|(delay, text)| !delay.is_zero() && !text.is_empty());
+    let Some((delay, text)) = parsed else {
+        return Some(String::from(
+            "Usage: /remind <duration> <text>, like /remind 2h take a break",
+        ));
+    };
+    Some(match remind(ctx.chat_id()?, user, &text, delay).await {
+        Ok(_) => format!("I'll remind you in {}.", HumanDuration(delay)),
+        Err(SchedulerError::DelayTooLong(_)) => {
+            String::from("That's too far out; reminders can be at most ten years away.")
+        }
+        Err(err) => format!("Couldn't save the reminder: {}", err),
+    })
+}
+
+#[crate::TeloxidePlugin(commands = ["reminders"], prefixes = ["/"])]
+async fn reminders_command(ctx: PluginContext) -> Option<String> {
+    let jobs = listed(&ctx, ctx.sender()?.id).await.ok()?;
+    if jobs.is_empty() {
+        return Some(String::from("You have no pending reminders."));
+    }
+    let now = Utc::now();
+    let list: Vec<String> = jobs
+        .iter()
+        .enumerate()
+        .map(|(i, job)| {
+            let left = HumanDuration((job.run_at - now).to_std().unwrap_or_default());
+            let text = job
+                .text
+                .split_once(": ")
+                .map_or(job.text.as_str(), |(_, t)| t);
+            format!("{}. in {}: {}", i + 1, left, text)
+        })
+        .collect();
+    Some(format!(
+        "Your reminders:\n{}\nCancel one with /cancelreminder <number>",
+        list.join("\n")
+    ))
+}
+
+#[crate::TeloxidePlugin(commands = ["cancelreminder"], prefixes = ["/"])]
+async fn cancel_reminder_command(ctx: PluginContext) -> Option<String> {
+    let user = ctx.sender()?.id;
+    let Some(number) = ctx.args().first().and_then(|arg| arg.parse::<usize>().ok()) else {
+        return Some(String::from(
+            "Usage: /cancelreminder <number>, see /reminders",
+        ));
+    };
+    let jobs = listed(&ctx, user).await.ok()?;
+    let Some(job) = number.checked_sub(1).and_then(|i| jobs.get(i)) else {
+        return Some(format!("There's no reminder #{}.", number));
+    };
+    Some(match cancel(user, &job.id).await {
+        Ok(true) => format!("Canceled reminder #{}.", number),
+        Ok(false) => String::from("That reminder already went off."),
+        Err(err) => format!("Couldn't cancel the reminder: {}", err),
+    })
+}
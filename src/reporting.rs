@@ -0,0 +1,413 @@
+use crate::args::ArgError;
+use crate::context::PluginContext;
+use crate::http::HttpError;
+use crate::response::SendError;
+use crate::storage::StorageError;
+use crate::texts::{self, FrameworkTexts};
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use once_cell::sync::Lazy;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use teloxide::types::{ChatId, UserId};
+
+pub type BoxError = Box<dyn Error + Send + Sync>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    Handler,
+    Send,
+    Panic,
+    // Work the framework or a plugin runs on its own, like a scheduled export.
+    Background,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::Handler => write!(f, "handler error"),
+            ErrorKind::Send => write!(f, "send error"),
+            ErrorKind::Panic => write!(f, "panic"),
+            ErrorKind::Background => write!(f, "background error"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ErrorReport {
+    pub plugin: Option<&'static str>,
+    pub kind: ErrorKind,
+    pub chain: Vec<String>,
+    pub chat_id: Option<ChatId>,
+    pub user_id: Option<UserId>,
+    pub update: serde_json::Value,
+}
+
+impl ErrorReport {
+    pub fn message(&self) -> &str {
+        self.chain.first().map(String::as_str).unwrap_or_default()
+    }
+}
+
+impl fmt::Display for ErrorReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.plugin {
+            Some(plugin) => write!(f, "plugin `{}`: {}", plugin, self.chain.join(": ")),
+            None => write!(f, "{}", self.chain.join(": ")),
+        }
+    }
+}
+
+// What a handler can fail with. `UserError`s are the user's to fix, so their message
+// is sent back to the chat and nothing is reported. `PermissionDenied` and
+// `RateLimited` get a short notice in the chat and are reported too; `External` and
+// `Internal` are only reported.
+#[derive(Debug)]
+pub enum PluginError {
+    UserError(String),
+    PermissionDenied(Option<String>),
+    RateLimited(Option<Duration>),
+    External(BoxError),
+    Internal(BoxError),
+}
+
+impl PluginError {
+    pub fn user(message: impl Into<String>) -> Self {
+        PluginError::UserError(message.into())
+    }
+
+    pub fn denied(reason: impl Into<String>) -> Self {
+        PluginError::PermissionDenied(Some(reason.into()))
+    }
+
+    pub fn external(err: impl Into<BoxError>) -> Self {
+        PluginError::External(err.into())
+    }
+
+    pub fn internal(err: impl Into<BoxError>) -> Self {
+        PluginError::Internal(err.into())
+    }
+
+    // The text shown in the chat, if any. Handlers reply in the sender's language
+    // instead; this uses the default texts.
+    pub fn user_message(&self) -> Option<String> {
+        self.user_message_in(&texts::texts())
+    }
+
+    pub(crate) fn user_message_in(&self, texts: &FrameworkTexts) -> Option<String> {
+        match self {
+            PluginError::UserError(message) => Some(message.clone()),
+            PluginError::PermissionDenied(reason) => Some(
+                reason
+                    .clone()
+                    .unwrap_or_else(|| texts.permission_denied.clone()),
+            ),
+            PluginError::RateLimited(Some(wait)) => {
+                let seconds = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+                Some(texts::fill(
+                    &texts.cooldown,
+                    &[("remaining", &seconds.to_string())],
+                ))
+            }
+            PluginError::RateLimited(None) => Some(texts.rate_limited.clone()),
+            PluginError::External(_) | PluginError::Internal(_) => None,
+        }
+    }
+
+    pub fn is_reported(&self) -> bool {
+        !matches!(self, PluginError::UserError(_))
+    }
+}
+
+impl fmt::Display for PluginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PluginError::UserError(message) => write!(f, "{}", message),
+            PluginError::PermissionDenied(Some(reason)) => {
+                write!(f, "permission denied: {}", reason)
+            }
+            PluginError::PermissionDenied(None) => write!(f, "permission denied"),
+            PluginError::RateLimited(Some(wait)) => {
+                write!(f, "rate limited for {}s", wait.as_secs())
+            }
+            PluginError::RateLimited(None) => write!(f, "rate limited"),
+            // The cause follows in the report's chain, from `source()`.
+            PluginError::External(_) => write!(f, "external service failed"),
+            PluginError::Internal(_) => write!(f, "internal error"),
+        }
+    }
+}
+
+impl Error for PluginError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PluginError::External(err) | PluginError::Internal(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<teloxide::RequestError> for PluginError {
+    fn from(err: teloxide::RequestError) -> Self {
+        match err {
+            teloxide::RequestError::RetryAfter(wait) => {
+                PluginError::RateLimited(Some(wait.duration()))
+            }
+            err => PluginError::External(err.into()),
+        }
+    }
+}
+
+impl From<SendError> for PluginError {
+    fn from(err: SendError) -> Self {
+        match err {
+            SendError::Request(err) => err.into(),
+            SendError::OverBudget { retry_in, .. } => PluginError::RateLimited(Some(retry_in)),
+        }
+    }
+}
+
+impl From<HttpError> for PluginError {
+    fn from(err: HttpError) -> Self {
+        match err {
+            HttpError::Throttled(wait) => PluginError::RateLimited(Some(wait)),
+            err => PluginError::External(err.into()),
+        }
+    }
+}
+
+impl From<StorageError> for PluginError {
+    fn from(err: StorageError) -> Self {
+        PluginError::Internal(err.into())
+    }
+}
+
+// A bad argument is something the user typed, so it's shown to them.
+impl From<ArgError> for PluginError {
+    fn from(err: ArgError) -> Self {
+        let text = err.to_string();
+        let mut chars = text.chars();
+        let first = chars.next().map(|c| c.to_uppercase().collect::<String>());
+        PluginError::UserError(format!("{}{}.", first.unwrap_or_default(), chars.as_str()))
+    }
+}
+
+// Turns any failure, or a missing value, into a `UserError` with a friendlier text,
+// so `?` can be used on input parsing without leaking internals to the chat.
+pub trait OrUser<T> {
+    fn or_user(self, message: impl Into<String>) -> Result<T, PluginError>;
+}
+
+impl<T, E> OrUser<T> for Result<T, E> {
+    fn or_user(self, message: impl Into<String>) -> Result<T, PluginError> {
+        self.map_err(|_| PluginError::UserError(message.into()))
+    }
+}
+
+impl<T> OrUser<T> for Option<T> {
+    fn or_user(self, message: impl Into<String>) -> Result<T, PluginError> {
+        self.ok_or_else(|| PluginError::UserError(message.into()))
+    }
+}
+
+pub trait ErrorReporter: Send + Sync {
+    fn report<'a>(&'a self, error: &'a ErrorReport) -> BoxFuture<'a, ()>;
+}
+
+static REPORTER: Lazy<RwLock<Option<Arc<dyn ErrorReporter>>>> = Lazy::new(|| RwLock::new(None));
+
+const RECENT_LIMIT: usize = 20;
+
+static RECENT: Mutex<VecDeque<RecentError>> = Mutex::new(VecDeque::new());
+
+#[derive(Clone, Debug)]
+pub struct RecentError {
+    pub at: DateTime<Utc>,
+    pub error: ErrorReport,
+}
+
+pub fn set_error_reporter(reporter: impl ErrorReporter + 'static) {
+    *REPORTER.write().unwrap() = Some(Arc::new(reporter));
+}
+
+pub(crate) async fn report(
+    ctx: &PluginContext,
+    kind: ErrorKind,
+    error: &(dyn Error + Send + Sync + 'static),
+) {
+    report_chain(ctx, kind, chain_of(error)).await;
+}
+
+fn chain_of(error: &(dyn Error + 'static)) -> Vec<String> {
+    let mut chain = vec![error.to_string()];
+    let mut source = error.source();
+    while let Some(cause) = source {
+        chain.push(cause.to_string());
+        source = cause.source();
+    }
+    chain
+}
+
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| String::from("plugin panicked"))
+}
+
+pub(crate) async fn report_panic(ctx: &PluginContext, payload: Box<dyn std::any::Any + Send>) {
+    report_chain(ctx, ErrorKind::Panic, vec![panic_message(payload)]).await;
+}
+
+// Background tasks have no update, so there's no chat, user or payload to attach.
+pub(crate) async fn report_task_panic(
+    plugin: &'static str,
+    payload: Box<dyn std::any::Any + Send>,
+) {
+    let error = ErrorReport {
+        plugin: Some(plugin),
+        kind: ErrorKind::Panic,
+        chain: vec![panic_message(payload)],
+        chat_id: None,
+        user_id: None,
+        update: serde_json::Value::Null,
+    };
+    deliver(error).await;
+}
+
+// A failure in background work, like an outbox message Telegram refused long after
+// its update was handled, or an export that couldn't be written.
+pub(crate) async fn report_background(
+    plugin: Option<&'static str>,
+    kind: ErrorKind,
+    chat_id: Option<ChatId>,
+    error: &(dyn Error + Send + Sync + 'static),
+) {
+    let error = ErrorReport {
+        plugin,
+        kind,
+        chain: chain_of(error),
+        chat_id,
+        user_id: None,
+        update: serde_json::Value::Null,
+    };
+    deliver(error).await;
+}
+
+async fn report_chain(ctx: &PluginContext, kind: ErrorKind, chain: Vec<String>) {
+    ctx.failed.store(true, Ordering::Relaxed);
+    let error = ErrorReport {
+        plugin: ctx.plugin.map(|p| p.name),
+        kind,
+        chain,
+        chat_id: ctx.chat_id(),
+        user_id: ctx.sender().map(|u| u.id),
+        update: snapshot(ctx),
+    };
+    deliver(error).await;
+}
+
+async fn deliver(error: ErrorReport) {
+    {
+        let mut recent = RECENT.lock().unwrap();
+        if recent.len() == RECENT_LIMIT {
+            recent.pop_front();
+        }
+        recent.push_back(RecentError {
+            at: Utc::now(),
+            error: error.clone(),
+        });
+    }
+    let reporter = REPORTER.read().unwrap().clone();
+    if let Some(reporter) = reporter {
+        reporter.report(&error).await;
+    }
+}
+
+// The last errors, newest first, whether or not a reporter is installed. Kept in
+// memory only, so each process has its own.
+pub fn recent_errors() -> Vec<RecentError> {
+    RECENT.lock().unwrap().iter().rev().cloned().collect()
+}
+
+fn snapshot(ctx: &PluginContext) -> serde_json::Value {
+    if let Some(raw) = &ctx.raw_update {
+        return raw.clone();
+    }
+    let value = if let Some(msg) = &ctx.message {
+        serde_json::to_value(msg)
+    } else if let Some(cq) = &ctx.callback_query {
+        serde_json::to_value(cq)
+    } else if let Some(query) = &ctx.inline_query {
+        serde_json::to_value(query)
+    } else if let Some(query) = &ctx.pre_checkout_query {
+        serde_json::to_value(query)
+    } else if let Some(connection) = &ctx.business_connection {
+        serde_json::to_value(connection)
+    } else if let Some(boost) = &ctx.chat_boost {
+        serde_json::to_value(boost)
+    } else if let Some(boost) = &ctx.removed_chat_boost {
+        serde_json::to_value(boost)
+    } else if let Some(update) = &ctx.my_chat_member {
+        serde_json::to_value(update)
+    } else if let Some(poll) = &ctx.poll {
+        serde_json::to_value(poll)
+    } else if let Some(answer) = &ctx.poll_answer {
+        serde_json::to_value(answer)
+    } else if let Some(event) = &ctx.external {
+        serde_json::to_value(event)
+    } else {
+        Ok(serde_json::Value::Null)
+    };
+    value.unwrap_or_default()
+}
+
+#[cfg(feature = "sentry")]
+pub struct SentryReporter;
+
+#[cfg(feature = "sentry")]
+impl ErrorReporter for SentryReporter {
+    fn report<'a>(&'a self, error: &'a ErrorReport) -> BoxFuture<'a, ()> {
+        use sentry::protocol::{Event, Exception, Level, User};
+
+        Box::pin(async move {
+            let mut event = Event {
+                level: match error.kind {
+                    ErrorKind::Panic => Level::Fatal,
+                    _ => Level::Error,
+                },
+                message: Some(error.to_string()),
+                ..Default::default()
+            };
+            event.exception = error
+                .chain
+                .iter()
+                .rev()
+                .map(|message| Exception {
+                    ty: error.kind.to_string(),
+                    value: Some(message.clone()),
+                    ..Default::default()
+                })
+                .collect::<Vec<_>>()
+                .into();
+            if let Some(plugin) = error.plugin {
+                event.tags.insert("plugin".into(), plugin.into());
+            }
+            event.tags.insert("kind".into(), error.kind.to_string());
+            if let Some(chat_id) = error.chat_id {
+                event.tags.insert("chat_id".into(), chat_id.to_string());
+            }
+            event.user = error.user_id.map(|id| User {
+                id: Some(id.to_string()),
+                ..Default::default()
+            });
+            event.extra.insert("update".into(), error.update.clone());
+            sentry::capture_event(event);
+        })
+    }
+}
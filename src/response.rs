@@ -0,0 +1,582 @@
+use crate::budget;
+use crate::callbacks;
+use crate::context::PluginContext;
+use crate::pool;
+use crate::reporting::{self, BoxError, ErrorKind, PluginError};
+use crate::slowmode;
+use futures::{Stream, StreamExt};
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use teloxide::prelude::*;
+use teloxide::types::{ChatId, MessageId, ParseMode, ReplyMarkup, ReplyParameters};
+use teloxide::RequestError;
+
+// The longest text Telegram takes in one message.
+const MAX_TEXT: usize = 4096;
+
+// What sending a message from a plugin can fail with: Telegram's own errors, or the
+// plugin's send budget running out, in which case nothing was sent.
+#[derive(Debug)]
+pub enum SendError {
+    Request(RequestError),
+    OverBudget {
+        plugin: &'static str,
+        retry_in: Duration,
+    },
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Request(err) => write!(f, "{}", err),
+            SendError::OverBudget { plugin, retry_in } => write!(
+                f,
+                "plugin `{}` is over its send budget for another {}s",
+                plugin,
+                retry_in.as_secs().max(1)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SendError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SendError::Request(err) => Some(err),
+            SendError::OverBudget { .. } => None,
+        }
+    }
+}
+
+impl From<RequestError> for SendError {
+    fn from(err: RequestError) -> Self {
+        SendError::Request(err)
+    }
+}
+
+// For handlers that return `RequestError`. A refusal from the budget becomes an
+// `Io` error wrapping the `SendError`, never a `RetryAfter` that something would wait
+// out as if Telegram had asked for it.
+impl From<SendError> for RequestError {
+    fn from(err: SendError) -> Self {
+        match err {
+            SendError::Request(err) => err,
+            err => RequestError::Io(Arc::new(io::Error::other(err))),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ReplyOptions {
+    pub quote: Option<String>,
+    pub message_id: Option<MessageId>,
+    pub deliver_to: Option<ChatId>,
+    pub allow_sending_without_reply: bool,
+}
+
+impl ReplyOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn quote(mut self, text: impl Into<String>) -> Self {
+        self.quote = Some(text.into());
+        self
+    }
+
+    pub fn to_message(mut self, message_id: MessageId) -> Self {
+        self.message_id = Some(message_id);
+        self
+    }
+
+    pub fn deliver_to(mut self, chat_id: ChatId) -> Self {
+        self.deliver_to = Some(chat_id);
+        self
+    }
+
+    pub fn allow_sending_without_reply(mut self) -> Self {
+        self.allow_sending_without_reply = true;
+        self
+    }
+
+    fn parameters(&self, chat_id: ChatId, trigger: Option<MessageId>) -> Option<ReplyParameters> {
+        let mut params = ReplyParameters::new(self.message_id.or(trigger)?);
+        if self.deliver_to.is_some_and(|target| target != chat_id) {
+            params = params.chat_id(chat_id.into());
+        }
+        if self.allow_sending_without_reply {
+            params = params.allow_sending_without_reply();
+        }
+        if let Some(quote) = &self.quote {
+            params = params.quote(quote.clone());
+        }
+        Some(params)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SendMessageParams {
+    pub text: String,
+    pub parse_mode: Option<ParseMode>,
+    pub reply_markup: Option<ReplyMarkup>,
+    pub disable_notification: bool,
+    pub reply: Option<ReplyOptions>,
+}
+
+impl SendMessageParams {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn parse_mode(mut self, mode: ParseMode) -> Self {
+        self.parse_mode = Some(mode);
+        self
+    }
+
+    pub fn reply_markup(mut self, markup: impl Into<ReplyMarkup>) -> Self {
+        self.reply_markup = Some(markup.into());
+        self
+    }
+
+    pub fn silent(mut self) -> Self {
+        self.disable_notification = true;
+        self
+    }
+
+    pub fn reply(mut self) -> Self {
+        self.reply = Some(ReplyOptions::default());
+        self
+    }
+
+    pub fn reply_with(mut self, options: ReplyOptions) -> Self {
+        self.reply = Some(options);
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StreamMode {
+    Replace,
+    Append,
+}
+
+pub struct StreamReply {
+    stream: Pin<Box<dyn Stream<Item = String> + Send>>,
+    mode: StreamMode,
+    throttle: Duration,
+}
+
+impl StreamReply {
+    pub fn new<S>(stream: S) -> Self
+    where
+        S: Stream<Item = String> + Send + 'static,
+    {
+        Self {
+            stream: Box::pin(stream),
+            mode: StreamMode::Replace,
+            throttle: Duration::from_secs(1),
+        }
+    }
+
+    pub fn append(mut self) -> Self {
+        self.mode = StreamMode::Append;
+        self
+    }
+
+    pub fn throttle(mut self, interval: Duration) -> Self {
+        self.throttle = interval;
+        self
+    }
+}
+
+pub enum PluginResponse {
+    Nothing,
+    Message(SendMessageParams),
+    Stream(StreamReply),
+    Failed(BoxError),
+}
+
+pub trait IntoPluginResponse {
+    fn into_response(self) -> PluginResponse;
+}
+
+impl IntoPluginResponse for PluginResponse {
+    fn into_response(self) -> PluginResponse {
+        self
+    }
+}
+
+impl IntoPluginResponse for () {
+    fn into_response(self) -> PluginResponse {
+        PluginResponse::Nothing
+    }
+}
+
+impl IntoPluginResponse for String {
+    fn into_response(self) -> PluginResponse {
+        PluginResponse::Message(SendMessageParams::new(self))
+    }
+}
+
+impl IntoPluginResponse for &'static str {
+    fn into_response(self) -> PluginResponse {
+        PluginResponse::Message(SendMessageParams::new(self))
+    }
+}
+
+impl IntoPluginResponse for SendMessageParams {
+    fn into_response(self) -> PluginResponse {
+        PluginResponse::Message(self)
+    }
+}
+
+impl IntoPluginResponse for StreamReply {
+    fn into_response(self) -> PluginResponse {
+        PluginResponse::Stream(self)
+    }
+}
+
+impl<T: IntoPluginResponse, E: Into<BoxError>> IntoPluginResponse for Result<T, E> {
+    fn into_response(self) -> PluginResponse {
+        match self {
+            Ok(response) => response.into_response(),
+            Err(err) => PluginResponse::Failed(err.into()),
+        }
+    }
+}
+
+impl<T: IntoPluginResponse> IntoPluginResponse for Option<T> {
+    fn into_response(self) -> PluginResponse {
+        self.map(IntoPluginResponse::into_response)
+            .unwrap_or(PluginResponse::Nothing)
+    }
+}
+
+// Lets `#[TeloxidePlugin]` handlers return `Result<_, E>` for any `E: Into<PluginError>`,
+// not just errors that box. Which conversion applies is picked by autoref at the macro's
+// call site, where `E` is known: `ViaPluginError` matches `Returned` itself and wins.
+#[doc(hidden)]
+pub mod __private {
+    use super::{IntoPluginResponse, PluginResponse};
+    use crate::reporting::PluginError;
+    use std::cell::Cell;
+
+    pub struct Returned<R>(Cell<Option<R>>);
+
+    impl<R> Returned<R> {
+        pub fn new(response: R) -> Self {
+            Self(Cell::new(Some(response)))
+        }
+
+        fn take(&self) -> R {
+            self.0
+                .take()
+                .expect("a handler's response is converted once")
+        }
+    }
+
+    pub trait ViaPluginError {
+        fn take_response(&self) -> PluginResponse;
+    }
+
+    impl<T: IntoPluginResponse, E: Into<PluginError>> ViaPluginError for Returned<Result<T, E>> {
+        fn take_response(&self) -> PluginResponse {
+            match self.take() {
+                Ok(response) => response.into_response(),
+                Err(err) => PluginResponse::Failed(Box::new(err.into())),
+            }
+        }
+    }
+
+    pub trait ViaIntoResponse {
+        fn take_response(&self) -> PluginResponse;
+    }
+
+    impl<R: IntoPluginResponse> ViaIntoResponse for &Returned<R> {
+        fn take_response(&self) -> PluginResponse {
+            self.take().into_response()
+        }
+    }
+}
+
+pub async fn respond(
+    ctx: &PluginContext,
+    response: impl IntoPluginResponse,
+) -> Result<(), SendError> {
+    // Failures are reported even from plugins with no chat to answer in, like cron
+    // jobs and inline queries; only sending needs one.
+    let result = match (response.into_response(), ctx.chat_id()) {
+        (PluginResponse::Nothing, _) => Ok(()),
+        (PluginResponse::Failed(err), chat_id) => fail(ctx, chat_id, err).await,
+        (PluginResponse::Message(params), _) if ctx.plugin.is_some_and(|plugin| plugin.outbox) => {
+            if let Err(err) = ctx.send_durable(params).await {
+                reporting::report(ctx, ErrorKind::Send, &err).await;
+            }
+            Ok(())
+        }
+        (_, None) => Ok(()),
+        (PluginResponse::Message(params), Some(chat_id)) => {
+            send_params(ctx, chat_id, params).await.map(|_| ())
+        }
+        (PluginResponse::Stream(reply), Some(chat_id)) => send_stream(ctx, chat_id, reply).await,
+    };
+    if let Err(err) = &result {
+        reporting::report(ctx, ErrorKind::Send, err).await;
+    }
+    result
+}
+
+async fn fail(
+    ctx: &PluginContext,
+    chat_id: Option<ChatId>,
+    err: BoxError,
+) -> Result<(), SendError> {
+    let Some(handler) = err.downcast_ref::<PluginError>() else {
+        reporting::report(ctx, ErrorKind::Handler, err.as_ref()).await;
+        return Ok(());
+    };
+    if handler.is_reported() {
+        reporting::report(ctx, ErrorKind::Handler, err.as_ref()).await;
+    }
+    match (handler.user_message_in(&ctx.framework_texts()), chat_id) {
+        (Some(text), Some(chat_id)) => {
+            send_params(ctx, chat_id, SendMessageParams::new(text).reply())
+                .await
+                .map(|_| ())
+        }
+        _ => Ok(()),
+    }
+}
+
+fn check_send_budget(ctx: &PluginContext) -> Result<(), SendError> {
+    let Some(plugin) = ctx.plugin else {
+        return Ok(());
+    };
+    budget::record_send(plugin).map_err(|retry_in| SendError::OverBudget {
+        plugin: plugin.name,
+        retry_in,
+    })
+}
+
+pub(crate) async fn send_params(
+    ctx: &PluginContext,
+    chat_id: ChatId,
+    params: SendMessageParams,
+) -> Result<Message, SendError> {
+    check_send_budget(ctx)?;
+    let target = params
+        .reply
+        .as_ref()
+        .and_then(|r| r.deliver_to)
+        .unwrap_or(chat_id);
+    slowmode::wait(&ctx.bot, target).await;
+    let mut request = match ctx.business_connection_id() {
+        Some(id) => ctx
+            .bot
+            .send_message(target, params.text)
+            .business_connection_id(id.clone()),
+        None => ctx.bot.send_message(target, params.text),
+    };
+    if let Some(mode) = params.parse_mode {
+        request = request.parse_mode(mode);
+    }
+    let keyboard = matches!(params.reply_markup, Some(ReplyMarkup::InlineKeyboard(_)));
+    if let Some(markup) = params.reply_markup {
+        request = request.reply_markup(markup);
+    }
+    if params.disable_notification {
+        request = request.disable_notification(true);
+    }
+    if let Some(reply) = &params.reply {
+        let trigger = ctx.message.as_ref().map(|m| m.id);
+        if let Some(parameters) = reply.parameters(chat_id, trigger) {
+            request = request.reply_parameters(parameters);
+        }
+    }
+
+    let sent = ctx.call(request).await?;
+    if let (true, Some(owner)) = (keyboard, ctx.sender()) {
+        callbacks::remember_owner(sent.chat.id, sent.id, owner.id);
+    }
+    Ok(sent)
+}
+
+impl PluginContext {
+    pub async fn reply(&self, text: impl Into<String>) -> Result<Message, SendError> {
+        self.send(SendMessageParams::new(text).reply()).await
+    }
+
+    pub async fn reply_with(
+        &self,
+        text: impl Into<String>,
+        options: ReplyOptions,
+    ) -> Result<Message, SendError> {
+        self.send(SendMessageParams::new(text).reply_with(options))
+            .await
+    }
+
+    pub async fn send(&self, params: SendMessageParams) -> Result<Message, SendError> {
+        let chat_id = self
+            .chat_id()
+            .or_else(|| params.reply.as_ref().and_then(|r| r.deliver_to))
+            .ok_or(SendError::Request(RequestError::Api(
+                teloxide::ApiError::ChatNotFound,
+            )))?;
+        send_params(self, chat_id, params).await
+    }
+}
+
+async fn send_stream(
+    ctx: &PluginContext,
+    chat_id: ChatId,
+    mut reply: StreamReply,
+) -> Result<(), SendError> {
+    let mut text = String::new();
+    let mut shown = String::new();
+    let mut sent = None;
+    let mut last_edit = Instant::now();
+
+    while let Some(item) = reply.stream.next().await {
+        match reply.mode {
+            StreamMode::Replace => text = truncated(item),
+            StreamMode::Append => text.push_str(&item),
+        }
+        // A full message is finished as it is, and the text goes on in a new one.
+        while reply.mode == StreamMode::Append && text_len(&text) > MAX_TEXT {
+            let rest = text.split_off(overflow_at(&text));
+            match sent.take() {
+                Some(id) if text != shown => edit_text(ctx, chat_id, id, text).await?,
+                None if !text.trim().is_empty() => {
+                    start_stream(ctx, chat_id, text).await?;
+                }
+                _ => {}
+            }
+            shown.clear();
+            text = rest;
+        }
+        if text.trim().is_empty() || text == shown {
+            continue;
+        }
+
+        match sent {
+            None => sent = Some(start_stream(ctx, chat_id, text.clone()).await?),
+            Some(id) if last_edit.elapsed() >= reply.throttle => {
+                edit_text(ctx, chat_id, id, text.clone()).await?;
+            }
+            Some(_) => continue,
+        }
+        shown.clone_from(&text);
+        last_edit = Instant::now();
+    }
+
+    if let Some(id) = sent {
+        if text != shown && !text.trim().is_empty() {
+            edit_text(ctx, chat_id, id, text).await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn start_stream(
+    ctx: &PluginContext,
+    chat_id: ChatId,
+    text: String,
+) -> Result<MessageId, SendError> {
+    check_send_budget(ctx)?;
+    slowmode::wait(&ctx.bot, chat_id).await;
+    let mut request = ctx.bot.send_message(chat_id, text);
+    if let Some(connection) = ctx.business_connection_id() {
+        request = request.business_connection_id(connection.clone());
+    }
+    // Later edits come from `ctx.bot`, so the first message has to as well.
+    Ok(pool::pinned(ctx.call(request)).await?.id)
+}
+
+// Telegram counts message length in UTF-16 code units.
+fn text_len(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+// The byte index where the first `limit` UTF-16 units of `text` end.
+fn boundary(text: &str, limit: usize) -> usize {
+    let mut units = 0;
+    for (at, c) in text.char_indices() {
+        units += c.len_utf16();
+        if units > limit {
+            return at;
+        }
+    }
+    text.len()
+}
+
+// Where to cut a text that doesn't fit into one message: after the last line break
+// that fits, or the last space, or right at the limit when there's neither.
+fn overflow_at(text: &str) -> usize {
+    let head = &text[..boundary(text, MAX_TEXT)];
+    head.rfind('\n')
+        .or_else(|| head.rfind(' '))
+        .map(|at| at + 1)
+        .filter(|&at| !head[..at].trim().is_empty())
+        .unwrap_or(head.len())
+}
+
+fn truncated(mut text: String) -> String {
+    if text_len(&text) > MAX_TEXT {
+        text.truncate(boundary(&text, MAX_TEXT - 1));
+        text.push('…');
+    }
+    text
+}
+
+async fn edit_text(
+    ctx: &PluginContext,
+    chat_id: ChatId,
+    id: MessageId,
+    text: String,
+) -> Result<(), RequestError> {
+    let mut request = ctx.bot.edit_message_text(chat_id, id, text);
+    if let Some(connection) = ctx.business_connection_id() {
+        request = request.business_connection_id(connection.clone());
+    }
+    ctx.call(request).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_streams_break_after_the_last_line_that_fits() {
+        let text = format!("{}\n{}", "a".repeat(4000), "b".repeat(200));
+        let at = overflow_at(&text);
+        assert_eq!(&text[..at], format!("{}\n", "a".repeat(4000)));
+
+        let text = "a".repeat(5000);
+        assert_eq!(overflow_at(&text), MAX_TEXT);
+    }
+
+    #[test]
+    fn the_limit_counts_utf16_units() {
+        // Each of these takes two units, so only half of them fit.
+        let text = "😀".repeat(3000);
+        let head = &text[..overflow_at(&text)];
+        assert_eq!(text_len(head), MAX_TEXT);
+        assert_eq!(head.chars().count(), 2048);
+    }
+
+    #[test]
+    fn replaced_text_is_cut_short_with_an_ellipsis() {
+        let text = truncated("a".repeat(5000));
+        assert_eq!(text_len(&text), MAX_TEXT);
+        assert!(text.ends_with('…'));
+        assert_eq!(truncated(String::from("short")), "short");
+    }
+}
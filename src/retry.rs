@@ -0,0 +1,50 @@
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::Duration;
+
+#[cfg(feature = "throttle")]
+use crate::context::PluginContext;
+#[cfg(feature = "throttle")]
+use crate::plugin_bot::PluginBot;
+#[cfg(feature = "throttle")]
+use once_cell::sync::Lazy;
+#[cfg(feature = "throttle")]
+use std::collections::HashMap;
+#[cfg(feature = "throttle")]
+use std::sync::Mutex;
+#[cfg(feature = "throttle")]
+use teloxide::adaptors::throttle::{Limits, Throttle};
+#[cfg(feature = "throttle")]
+use teloxide::prelude::*;
+
+static MAX_RETRIES: AtomicU32 = AtomicU32::new(3);
+static MAX_WAIT_SECS: AtomicU64 = AtomicU64::new(60);
+
+pub fn set_max_retries(retries: u32) {
+    MAX_RETRIES.store(retries, Ordering::Relaxed);
+}
+
+pub fn set_max_wait(wait: Duration) {
+    MAX_WAIT_SECS.store(wait.as_secs(), Ordering::Relaxed);
+}
+
+pub(crate) fn should_retry(attempt: u32, wait: Duration) -> bool {
+    attempt < MAX_RETRIES.load(Ordering::Relaxed)
+        && wait.as_secs() <= MAX_WAIT_SECS.load(Ordering::Relaxed)
+}
+
+#[cfg(feature = "throttle")]
+static THROTTLED: Lazy<Mutex<HashMap<String, Throttle<Bot>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[cfg(feature = "throttle")]
+impl PluginContext {
+    pub fn throttled(&self) -> PluginBot<Throttle<Bot>> {
+        let throttle = THROTTLED
+            .lock()
+            .unwrap()
+            .entry(self.bot.token().to_string())
+            .or_insert_with(|| Throttle::new_spawn(self.bot.clone(), Limits::default()))
+            .clone();
+        PluginBot::new(throttle, self.plugin)
+    }
+}
@@ -0,0 +1,208 @@
+use crate::access;
+use crate::context::PluginContext;
+use crate::storage::{self, StorageError};
+use std::collections::HashMap;
+use std::sync::RwLock;
+use teloxide::types::UserId;
+
+const PREFIX: &str = "role:";
+
+static HIERARCHY: RwLock<Vec<String>> = RwLock::new(Vec::new());
+static ASSIGNED: RwLock<Option<HashMap<UserId, String>>> = RwLock::new(None);
+
+const DEFAULT_ROLES: [&str; 4] = ["user", "admin", "sudo", "owner"];
+
+pub fn set_roles(lowest_to_highest: impl IntoIterator<Item = impl Into<String>>) {
+    *HIERARCHY.write().unwrap() = lowest_to_highest.into_iter().map(Into::into).collect();
+}
+
+pub fn roles() -> Vec<String> {
+    let hierarchy = HIERARCHY.read().unwrap();
+    if hierarchy.is_empty() {
+        DEFAULT_ROLES.iter().map(|r| r.to_string()).collect()
+    } else {
+        hierarchy.clone()
+    }
+}
+
+pub fn rank(role: &str) -> Option<usize> {
+    roles().iter().position(|r| r == role)
+}
+
+fn lowest() -> String {
+    roles().into_iter().next().unwrap_or_default()
+}
+
+fn highest() -> String {
+    roles().into_iter().last().unwrap_or_default()
+}
+
+pub(crate) fn invalidate() {
+    *ASSIGNED.write().unwrap() = None;
+}
+
+async fn ensure_loaded() -> Result<(), StorageError> {
+    if ASSIGNED.read().unwrap().is_some() {
+        return Ok(());
+    }
+    let storage = storage::storage();
+    let mut assigned = HashMap::new();
+    for key in storage.keys(PREFIX).await? {
+        let Some(user) = key.strip_prefix(PREFIX).and_then(|id| id.parse().ok()) else {
+            continue;
+        };
+        if let Some(role) = storage.get(&key).await? {
+            assigned.insert(UserId(user), role);
+        }
+    }
+    let mut cache = ASSIGNED.write().unwrap();
+    if cache.is_none() {
+        *cache = Some(assigned);
+    }
+    Ok(())
+}
+
+pub async fn role_of(user: UserId) -> Result<String, StorageError> {
+    if access::is_owner(user) {
+        return Ok(highest());
+    }
+    ensure_loaded().await?;
+    let assigned = ASSIGNED
+        .read()
+        .unwrap()
+        .as_ref()
+        .and_then(|roles| roles.get(&user).cloned());
+    Ok(assigned
+        .filter(|role| rank(role).is_some())
+        .unwrap_or_else(lowest))
+}
+
+pub async fn has_role(user: UserId, role: &str) -> Result<bool, StorageError> {
+    let Some(required) = rank(role) else {
+        return Ok(false);
+    };
+    Ok(rank(&role_of(user).await?).is_some_and(|held| held >= required))
+}
+
+pub async fn assign(user: UserId, role: &str) -> Result<(), StorageError> {
+    ensure_loaded().await?;
+    if role == lowest() {
+        return revoke(user).await;
+    }
+    storage::storage()
+        .set(&format!("{}{}", PREFIX, user), role.to_string())
+        .await?;
+    if let Some(roles) = ASSIGNED.write().unwrap().as_mut() {
+        roles.insert(user, role.to_string());
+    }
+    Ok(())
+}
+
+pub async fn revoke(user: UserId) -> Result<(), StorageError> {
+    ensure_loaded().await?;
+    storage::delete(&format!("{}{}", PREFIX, user)).await?;
+    if let Some(roles) = ASSIGNED.write().unwrap().as_mut() {
+        roles.remove(&user);
+    }
+    Ok(())
+}
+
+pub async fn members(role: &str) -> Result<Vec<UserId>, StorageError> {
+    ensure_loaded().await?;
+    Ok(ASSIGNED
+        .read()
+        .unwrap()
+        .iter()
+        .flatten()
+        .filter(|(_, assigned)| *assigned == role)
+        .map(|(user, _)| *user)
+        .collect())
+}
+
+impl PluginContext {
+    pub async fn role(&self) -> String {
+        match self.sender() {
+            Some(user) => role_of(user.id).await.unwrap_or_else(|_| lowest()),
+            None => lowest(),
+        }
+    }
+
+    pub async fn has_role(&self, role: &str) -> bool {
+        match self.sender() {
+            Some(user) => has_role(user.id, role).await.unwrap_or(false),
+            None => false,
+        }
+    }
+}
+
+#[cfg(feature = "role-commands")]
+fn role_target(ctx: &PluginContext) -> (Option<UserId>, Option<String>) {
+    let mut target = ctx.target_user().and_then(|t| t.id());
+    let mut role = None;
+    for arg in ctx.args() {
+        match arg.parse::<u64>() {
+            Ok(id) => target = Some(UserId(id)),
+            Err(_) if !arg.starts_with('@') => role = Some(arg.to_lowercase()),
+            Err(_) => {}
+        }
+    }
+    (target, role)
+}
+
+#[cfg(feature = "role-commands")]
+#[crate::TeloxidePlugin(commands = ["promote"], prefixes = ["/"], role = "admin")]
+async fn promote_command(ctx: PluginContext) -> Option<String> {
+    let caller = rank(&ctx.role().await)?;
+    let (Some(target), role) = role_target(&ctx) else {
+        return Some(String::from("Reply to the user or pass their id."));
+    };
+    let current = rank(&role_of(target).await.ok()?)?;
+    let role = match role {
+        Some(role) => role,
+        None => roles().get(current + 1)?.clone(),
+    };
+    let Some(wanted) = rank(&role) else {
+        return Some(format!("Unknown role. Available: {}", roles().join(", ")));
+    };
+    if wanted >= caller || current >= caller {
+        return Some(String::from("You can only grant roles below your own."));
+    }
+    if wanted <= current {
+        return Some(format!("{} already has that role or a higher one.", target));
+    }
+    Some(match assign(target, &role).await {
+        Ok(()) => format!("{} is now {}.", target, role),
+        Err(err) => format!("Couldn't save the role: {}", err),
+    })
+}
+
+#[cfg(feature = "role-commands")]
+#[crate::TeloxidePlugin(commands = ["demote"], prefixes = ["/"], role = "admin")]
+async fn demote_command(ctx: PluginContext) -> Option<String> {
+    let caller = rank(&ctx.role().await)?;
+    let (Some(target), role) = role_target(&ctx) else {
+        return Some(String::from("Reply to the user or pass their id."));
+    };
+    let current = rank(&role_of(target).await.ok()?)?;
+    if current >= caller {
+        return Some(String::from(
+            "You can only demote users below your own role.",
+        ));
+    }
+    if current == 0 {
+        return Some(format!("{} has no role to take away.", target));
+    }
+    let role = match role {
+        Some(role) => role,
+        None => roles().get(current - 1)?.clone(),
+    };
+    match rank(&role) {
+        Some(wanted) if wanted < current => {}
+        Some(_) => return Some(format!("That wouldn't demote {}.", target)),
+        None => return Some(format!("Unknown role. Available: {}", roles().join(", "))),
+    }
+    Some(match assign(target, &role).await {
+        Ok(()) => format!("{} is now {}.", target, role),
+        Err(err) => format!("Couldn't save the role: {}", err),
+    })
+}
@@ -0,0 +1,380 @@
+use crate::context::PluginContext;
+use crate::registry::{self, PluginMeta};
+use crate::reporting::{self, ErrorKind};
+use crate::storage::{self, StorageError};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use croner::Cron;
+use futures::future::BoxFuture;
+use once_cell::sync::{Lazy, OnceCell};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use teloxide::prelude::*;
+use teloxide::RequestError;
+use tokio::task::JoinHandle;
+
+const JOB_PREFIX: &str = "job:";
+const MAX_DELAY: Duration = Duration::from_secs(10 * 366 * 86400);
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(600);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Job {
+    pub id: String,
+    pub chat_id: ChatId,
+    pub text: String,
+    pub run_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<UserId>,
+}
+
+pub trait JobStore: Send + Sync {
+    fn save<'a>(&'a self, job: &'a Job) -> BoxFuture<'a, Result<(), StorageError>>;
+
+    fn remove<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<(), StorageError>>;
+
+    fn load_all(&self) -> BoxFuture<'_, Result<Vec<Job>, StorageError>>;
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StorageJobStore;
+
+impl JobStore for StorageJobStore {
+    fn save<'a>(&'a self, job: &'a Job) -> BoxFuture<'a, Result<(), StorageError>> {
+        Box::pin(async move { storage::set_json(&format!("{}{}", JOB_PREFIX, job.id), job).await })
+    }
+
+    fn remove<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<(), StorageError>> {
+        Box::pin(async move { storage::delete(&format!("{}{}", JOB_PREFIX, id)).await })
+    }
+
+    fn load_all(&self) -> BoxFuture<'_, Result<Vec<Job>, StorageError>> {
+        Box::pin(async move {
+            let mut jobs = Vec::new();
+            for key in storage::storage().keys(JOB_PREFIX).await? {
+                if let Some(job) = storage::get_json::<Job>(&key).await? {
+                    jobs.push(job);
+                }
+            }
+            Ok(jobs)
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum SchedulerError {
+    Storage(StorageError),
+    InvalidCron {
+        plugin: &'static str,
+        reason: String,
+    },
+    // Further out than ten years, or than the calendar goes.
+    DelayTooLong(Duration),
+}
+
+impl fmt::Display for SchedulerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchedulerError::Storage(err) => write!(f, "{}", err),
+            SchedulerError::InvalidCron { plugin, reason } => {
+                write!(f, "invalid schedule for plugin `{}`: {}", plugin, reason)
+            }
+            SchedulerError::DelayTooLong(delay) => {
+                write!(f, "a delay of {}s is too long to schedule", delay.as_secs())
+            }
+        }
+    }
+}
+
+impl std::error::Error for SchedulerError {}
+
+impl From<StorageError> for SchedulerError {
+    fn from(err: StorageError) -> Self {
+        SchedulerError::Storage(err)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum CatchUp {
+    #[default]
+    FireAll,
+    Within(Duration),
+    Skip,
+}
+
+static STORE: Lazy<RwLock<Arc<dyn JobStore>>> =
+    Lazy::new(|| RwLock::new(Arc::new(StorageJobStore)));
+static CATCH_UP: RwLock<CatchUp> = RwLock::new(CatchUp::FireAll);
+static BOT: OnceCell<Bot> = OnceCell::new();
+static TIMERS: Lazy<Mutex<HashMap<String, JoinHandle<()>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+pub fn set_job_store(store: impl JobStore + 'static) {
+    *STORE.write().unwrap() = Arc::new(store);
+}
+
+pub fn set_catch_up(policy: CatchUp) {
+    *CATCH_UP.write().unwrap() = policy;
+}
+
+fn store() -> Arc<dyn JobStore> {
+    STORE.read().unwrap().clone()
+}
+
+pub(crate) fn new_id() -> String {
+    format!(
+        "{:x}-{:x}",
+        Utc::now().timestamp_millis(),
+        NEXT_ID.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+pub async fn start(bot: Bot) -> Result<(), SchedulerError> {
+    if BOT.set(bot.clone()).is_err() {
+        return Ok(());
+    }
+
+    let mut schedules = Vec::new();
+    for plugin in registry::plugins() {
+        if let Some(pattern) = plugin.cron {
+            schedules.push((plugin, parse_schedule(plugin, pattern)?));
+        }
+    }
+    for (plugin, (cron, tz)) in schedules {
+        tokio::spawn(run_schedule(bot.clone(), plugin, cron, tz));
+    }
+
+    arm_stored().await?;
+    Ok(())
+}
+
+// Arms every stored job that doesn't have a timer yet, after the catch-up policy has
+// dropped the missed ones. `start` does this once; `storage::import` again, for jobs
+// that came with the archive. Nothing happens before `start`.
+pub async fn arm_stored() -> Result<(), StorageError> {
+    if BOT.get().is_none() {
+        return Ok(());
+    }
+    let now = Utc::now();
+    let policy = *CATCH_UP.read().unwrap();
+    for job in store().load_all().await? {
+        if TIMERS.lock().unwrap().contains_key(&job.id) {
+            continue;
+        }
+        let missed_by = (now - job.run_at).to_std().unwrap_or_default();
+        let keep = match policy {
+            _ if job.run_at > now => true,
+            CatchUp::FireAll => true,
+            CatchUp::Within(limit) => missed_by <= limit,
+            CatchUp::Skip => false,
+        };
+        if keep {
+            arm(job);
+        } else {
+            store().remove(&job.id).await?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn parse_schedule(
+    plugin: &'static PluginMeta,
+    pattern: &str,
+) -> Result<(Cron, Tz), SchedulerError> {
+    let invalid = |reason: String| SchedulerError::InvalidCron {
+        plugin: plugin.name,
+        reason,
+    };
+    let cron = pattern
+        .parse::<Cron>()
+        .map_err(|err| invalid(err.to_string()))?;
+    let tz = match plugin.tz {
+        Some(name) => name
+            .parse::<Tz>()
+            .map_err(|_| invalid(format!("unknown time zone `{}`", name)))?,
+        None => Tz::UTC,
+    };
+    Ok((cron, tz))
+}
+
+pub fn next_fire(cron: &Cron, tz: Tz, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    cron.find_next_occurrence(&after.with_timezone(&tz), false)
+        .ok()
+        .map(|next| next.with_timezone(&Utc))
+}
+
+async fn run_schedule(bot: Bot, plugin: &'static PluginMeta, cron: Cron, tz: Tz) {
+    let running = Arc::new(tokio::sync::Mutex::new(()));
+    let queued = Arc::new(AtomicBool::new(false));
+    let mut after = Utc::now();
+    while let Some(next) = next_fire(&cron, tz, after) {
+        let wait = (next - Utc::now()).to_std().unwrap_or_default() + jitter(plugin.jitter);
+        tokio::time::sleep(wait).await;
+        after = next;
+        if !registry::is_enabled(plugin.name) {
+            continue;
+        }
+
+        let ctx = PluginContext::new(bot.clone(), None, None);
+        match plugin.overlap {
+            "parallel" => {
+                tokio::spawn(async move { registry::run_plugin(plugin, &ctx).await });
+            }
+            "queue" => {
+                if queued.swap(true, Ordering::SeqCst) {
+                    continue;
+                }
+                let running = running.clone();
+                let queued = queued.clone();
+                tokio::spawn(async move {
+                    let _guard = running.lock().await;
+                    queued.store(false, Ordering::SeqCst);
+                    registry::run_plugin(plugin, &ctx).await;
+                });
+            }
+            _ => {
+                let Ok(guard) = running.clone().try_lock_owned() else {
+                    continue;
+                };
+                tokio::spawn(async move {
+                    let _guard = guard;
+                    registry::run_plugin(plugin, &ctx).await;
+                });
+            }
+        }
+    }
+}
+
+fn jitter(max_secs: u64) -> Duration {
+    if max_secs == 0 {
+        return Duration::ZERO;
+    }
+    let seed = RandomState::new().build_hasher().finish();
+    Duration::from_millis(seed % (max_secs * 1000))
+}
+
+fn arm(job: Job) {
+    let Some(bot) = BOT.get().cloned() else {
+        return;
+    };
+    let id = job.id.clone();
+    let handle = tokio::spawn(async move {
+        let wait = (job.run_at - Utc::now()).to_std().unwrap_or_default();
+        tokio::time::sleep(wait).await;
+        fire(&bot, job.clone()).await;
+        TIMERS.lock().unwrap().remove(&job.id);
+    });
+    let mut timers = TIMERS.lock().unwrap();
+    if !handle.is_finished() {
+        timers.insert(id, handle);
+    }
+}
+
+// Sends the job's message, retrying like the outbox does: after Telegram's wait on a
+// flood limit, with backoff on network errors. The job stays stored until it's sent,
+// so a restart picks it up again. Only a request Telegram refuses outright drops it.
+async fn fire(bot: &Bot, mut job: Job) {
+    let mut attempts = 0u32;
+    loop {
+        let error = match bot.send_message(job.chat_id, job.text.clone()).await {
+            Ok(_) => {
+                let _ = store().remove(&job.id).await;
+                return;
+            }
+            Err(err) => err,
+        };
+        let wait = match &error {
+            RequestError::MigrateToChatId(chat) => {
+                job.chat_id = *chat;
+                let _ = store().save(&job).await;
+                Duration::ZERO
+            }
+            RequestError::RetryAfter(wait) => wait.duration(),
+            RequestError::Network(_) | RequestError::Io(_) | RequestError::InvalidJson { .. } => {
+                (FIRST_BACKOFF * 2u32.saturating_pow(attempts)).min(MAX_BACKOFF)
+            }
+            RequestError::Api(_) => {
+                let _ = store().remove(&job.id).await;
+                reporting::report_background(None, ErrorKind::Send, Some(job.chat_id), &error)
+                    .await;
+                return;
+            }
+        };
+        attempts += 1;
+        tokio::time::sleep(wait).await;
+    }
+}
+
+pub async fn send_at(
+    chat_id: ChatId,
+    text: impl Into<String>,
+    run_at: DateTime<Utc>,
+) -> Result<String, StorageError> {
+    let job = Job {
+        id: new_id(),
+        chat_id,
+        text: text.into(),
+        run_at,
+        owner: None,
+    };
+    schedule(job).await
+}
+
+pub(crate) async fn schedule(job: Job) -> Result<String, StorageError> {
+    store().save(&job).await?;
+    let id = job.id.clone();
+    arm(job);
+    Ok(id)
+}
+
+pub async fn send_after(
+    chat_id: ChatId,
+    text: impl Into<String>,
+    delay: Duration,
+) -> Result<String, SchedulerError> {
+    let run_at = run_at_after(delay)?;
+    Ok(send_at(chat_id, text, run_at).await?)
+}
+
+pub(crate) fn run_at_after(delay: Duration) -> Result<DateTime<Utc>, SchedulerError> {
+    Some(delay)
+        .filter(|delay| *delay <= MAX_DELAY)
+        .and_then(|delay| chrono::Duration::from_std(delay).ok())
+        .and_then(|delay| Utc::now().checked_add_signed(delay))
+        .ok_or(SchedulerError::DelayTooLong(delay))
+}
+
+pub async fn cancel(id: &str) -> Result<bool, StorageError> {
+    let timer = TIMERS.lock().unwrap().remove(id);
+    if let Some(timer) = &timer {
+        timer.abort();
+    }
+    store().remove(id).await?;
+    Ok(timer.is_some())
+}
+
+pub async fn pending() -> Result<Vec<Job>, StorageError> {
+    let mut jobs = store().load_all().await?;
+    jobs.sort_by_key(|job| job.run_at);
+    Ok(jobs)
+}
+
+impl PluginContext {
+    pub async fn send_later(
+        &self,
+        delay: Duration,
+        text: impl Into<String>,
+    ) -> Result<Option<String>, SchedulerError> {
+        let Some(chat_id) = self.chat_id() else {
+            return Ok(None);
+        };
+        send_after(chat_id, text, delay).await.map(Some)
+    }
+}
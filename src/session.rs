@@ -0,0 +1,346 @@
+use crate::context::PluginContext;
+use crate::storage::{self, StorageError};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::any::Any;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, RwLock};
+use tokio::sync::{Mutex, MutexGuard};
+
+const MERGE_ATTEMPTS: usize = 5;
+
+type Encoder = fn(&(dyn Any + Send)) -> Option<Result<Value, serde_json::Error>>;
+
+pub type MergeFn = fn(base: &Value, ours: &Value, theirs: &Value) -> Value;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub enum ConflictStrategy {
+    #[default]
+    LastWriteWins,
+    RetryWithReload {
+        attempts: usize,
+    },
+    Merge(MergeFn),
+}
+
+static STRATEGY: RwLock<ConflictStrategy> = RwLock::new(ConflictStrategy::LastWriteWins);
+
+pub fn set_conflict_strategy(strategy: ConflictStrategy) {
+    *STRATEGY.write().unwrap() = strategy;
+}
+
+pub(crate) fn conflict_strategy() -> ConflictStrategy {
+    *STRATEGY.read().unwrap()
+}
+
+#[derive(Serialize, Deserialize)]
+struct Stored {
+    version: u64,
+    data: Value,
+}
+
+impl Stored {
+    fn parse(raw: &str) -> Result<Self, serde_json::Error> {
+        Ok(match serde_json::from_str::<Stored>(raw) {
+            Ok(stored) => stored,
+            Err(_) => Stored {
+                version: 0,
+                data: serde_json::from_str(raw)?,
+            },
+        })
+    }
+}
+
+#[derive(Default)]
+struct Loaded {
+    key: Option<String>,
+    raw: Option<String>,
+    version: u64,
+    base: Value,
+    value: Option<Box<dyn Any + Send>>,
+    encode: Option<Encoder>,
+    dirty: bool,
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct SessionSlot(Arc<Mutex<Loaded>>);
+
+pub struct SessionGuard<'a, T> {
+    guard: MutexGuard<'a, Loaded>,
+    marker: std::marker::PhantomData<T>,
+}
+
+impl<T: 'static> Deref for SessionGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.guard
+            .value
+            .as_ref()
+            .and_then(|v| v.downcast_ref())
+            .expect("session type changed while borrowed")
+    }
+}
+
+impl<T: 'static> DerefMut for SessionGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.guard.dirty = true;
+        self.guard
+            .value
+            .as_mut()
+            .and_then(|v| v.downcast_mut())
+            .expect("session type changed while borrowed")
+    }
+}
+
+impl<T> SessionGuard<'_, T> {
+    pub fn version(&self) -> u64 {
+        self.guard.version
+    }
+}
+
+fn encode<T: Serialize + 'static>(
+    value: &(dyn Any + Send),
+) -> Option<Result<Value, serde_json::Error>> {
+    value.downcast_ref::<T>().map(serde_json::to_value)
+}
+
+pub(crate) fn session_key(ctx: &PluginContext) -> Option<String> {
+    let chat_id = ctx.chat_id()?;
+    let user_id = ctx.sender()?.id;
+    Some(format!("session:{}:{}", chat_id, user_id))
+}
+
+impl PluginContext {
+    pub async fn session_mut<T>(&self) -> Result<SessionGuard<'_, T>, StorageError>
+    where
+        T: Serialize + DeserializeOwned + Default + Send + 'static,
+    {
+        let mut guard = self.session.0.lock().await;
+        let loaded = guard.value.as_ref().is_some_and(|v| v.is::<T>());
+        if !loaded {
+            let key = session_key(self);
+            let raw = match &key {
+                Some(key) => storage::storage().get(key).await?,
+                None => None,
+            };
+            let stored = raw.as_deref().map(Stored::parse).transpose()?;
+            let (version, base) = stored.map_or((0, Value::Null), |s| (s.version, s.data));
+            let value: T = match &base {
+                Value::Null => T::default(),
+                data => serde_json::from_value(data.clone())?,
+            };
+            *guard = Loaded {
+                key,
+                raw,
+                version,
+                base,
+                value: Some(Box::new(value)),
+                encode: Some(encode::<T>),
+                dirty: false,
+            };
+        }
+        Ok(SessionGuard {
+            guard,
+            marker: std::marker::PhantomData,
+        })
+    }
+
+    pub async fn session<T>(&self) -> Result<T, StorageError>
+    where
+        T: Serialize + DeserializeOwned + Default + Clone + Send + 'static,
+    {
+        Ok(self.session_mut::<T>().await?.clone())
+    }
+
+    pub async fn clear_session(&self) -> Result<(), StorageError> {
+        *self.session.0.lock().await = Loaded::default();
+        match session_key(self) {
+            Some(key) => storage::delete(&key).await,
+            None => Ok(()),
+        }
+    }
+}
+
+pub(crate) enum WriteBack {
+    Done,
+    Conflict,
+}
+
+pub(crate) async fn write_back(ctx: &PluginContext) -> Result<WriteBack, StorageError> {
+    let guard = ctx.session.0.lock().await;
+    let (Some(key), Some(value), Some(encode), true) =
+        (&guard.key, &guard.value, guard.encode, guard.dirty)
+    else {
+        return Ok(WriteBack::Done);
+    };
+    let Some(ours) = encode(value.as_ref()) else {
+        return Ok(WriteBack::Done);
+    };
+    let ours = ours?;
+    let backend = storage::storage();
+
+    let mut expected = guard.raw.clone();
+    let mut version = guard.version;
+    let mut data = ours.clone();
+    for _ in 0..MERGE_ATTEMPTS {
+        let next = serde_json::to_string(&Stored {
+            version: version + 1,
+            data: data.clone(),
+        })?;
+        if backend
+            .compare_and_swap(key, expected.as_deref(), next)
+            .await?
+        {
+            return Ok(WriteBack::Done);
+        }
+
+        let theirs_raw = backend.get(key).await?;
+        let theirs = theirs_raw.as_deref().map(Stored::parse).transpose()?;
+        let (theirs_version, theirs_data) =
+            theirs.map_or((0, Value::Null), |s| (s.version, s.data));
+        match conflict_strategy() {
+            ConflictStrategy::LastWriteWins => data = ours.clone(),
+            ConflictStrategy::RetryWithReload { .. } => return Ok(WriteBack::Conflict),
+            ConflictStrategy::Merge(merge) => data = merge(&guard.base, &ours, &theirs_data),
+        }
+        expected = theirs_raw;
+        version = theirs_version;
+    }
+    Ok(WriteBack::Conflict)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::BTreeMap;
+    use teloxide::types::Message;
+    use teloxide::Bot;
+
+    // The conflict strategy is global, so tests that change it take turns.
+    static STRATEGY_LOCK: Mutex<()> = Mutex::const_new(());
+
+    type Counters = BTreeMap<String, u32>;
+
+    fn ctx(chat: i64) -> PluginContext {
+        let message: Message = serde_json::from_value(json!({
+            "message_id": 1,
+            "date": 0,
+            "chat": {"id": chat, "type": "private", "first_name": "Ann"},
+            "from": {"id": 7, "is_bot": false, "first_name": "Ann"},
+            "text": "hi",
+        }))
+        .unwrap();
+        PluginContext::new(Bot::new("1:test"), Some(message), None)
+    }
+
+    async fn bump(ctx: &PluginContext, counter: &str) {
+        *ctx.session_mut::<Counters>()
+            .await
+            .unwrap()
+            .entry(counter.to_string())
+            .or_default() += 1;
+    }
+
+    async fn stored(chat: i64) -> Stored {
+        let raw = storage::storage()
+            .get(&format!("session:{}:7", chat))
+            .await
+            .unwrap()
+            .unwrap();
+        Stored::parse(&raw).unwrap()
+    }
+
+    // Loads the same session in two contexts, saves `b` first and returns what saving
+    // `a` did.
+    async fn race(chat: i64, strategy: ConflictStrategy) -> WriteBack {
+        let (a, b) = (ctx(chat), ctx(chat));
+        a.session_mut::<Counters>().await.unwrap();
+        bump(&b, "b").await;
+        assert!(matches!(write_back(&b).await, Ok(WriteBack::Done)));
+        bump(&a, "a").await;
+        set_conflict_strategy(strategy);
+        let result = write_back(&a).await.unwrap();
+        set_conflict_strategy(ConflictStrategy::LastWriteWins);
+        result
+    }
+
+    // Keeps every counter from both sides, adding up what each side added.
+    fn add_counters(base: &Value, ours: &Value, theirs: &Value) -> Value {
+        let read =
+            |value: &Value| serde_json::from_value::<Counters>(value.clone()).unwrap_or_default();
+        let (base, ours, mut merged) = (read(base), read(ours), read(theirs));
+        for (name, count) in ours {
+            let added = count - base.get(&name).copied().unwrap_or(0);
+            *merged.entry(name).or_default() += added;
+        }
+        serde_json::to_value(merged).unwrap()
+    }
+
+    #[tokio::test]
+    async fn unchanged_sessions_are_not_written() {
+        let ctx = ctx(1);
+        ctx.session_mut::<Counters>().await.unwrap();
+        assert!(matches!(write_back(&ctx).await, Ok(WriteBack::Done)));
+        let raw = storage::storage().get("session:1:7").await.unwrap();
+        assert_eq!(raw, None);
+    }
+
+    #[tokio::test]
+    async fn every_write_bumps_the_version() {
+        let first = ctx(2);
+        bump(&first, "a").await;
+        write_back(&first).await.unwrap();
+        let second = ctx(2);
+        bump(&second, "a").await;
+        assert_eq!(second.session_mut::<Counters>().await.unwrap().version(), 1);
+        write_back(&second).await.unwrap();
+        let saved = stored(2).await;
+        assert_eq!(saved.version, 2);
+        assert_eq!(saved.data, json!({"a": 2}));
+    }
+
+    #[tokio::test]
+    async fn sessions_saved_before_versioning_still_load() {
+        storage::storage()
+            .set("session:3:7", String::from(r#"{"old":4}"#))
+            .await
+            .unwrap();
+        let ctx = ctx(3);
+        bump(&ctx, "old").await;
+        write_back(&ctx).await.unwrap();
+        let saved = stored(3).await;
+        assert_eq!(saved.version, 1);
+        assert_eq!(saved.data, json!({"old": 5}));
+    }
+
+    #[tokio::test]
+    async fn last_write_wins_overwrites() {
+        let _turn = STRATEGY_LOCK.lock().await;
+        let result = race(4, ConflictStrategy::LastWriteWins).await;
+        assert!(matches!(result, WriteBack::Done));
+        let saved = stored(4).await;
+        assert_eq!(saved.data, json!({"a": 1}));
+        assert_eq!(saved.version, 2);
+    }
+
+    #[tokio::test]
+    async fn retry_with_reload_reports_the_conflict() {
+        let _turn = STRATEGY_LOCK.lock().await;
+        let result = race(5, ConflictStrategy::RetryWithReload { attempts: 3 }).await;
+        assert!(matches!(result, WriteBack::Conflict));
+        assert_eq!(stored(5).await.data, json!({"b": 1}));
+    }
+
+    #[tokio::test]
+    async fn merge_combines_both_writes() {
+        let _turn = STRATEGY_LOCK.lock().await;
+        let result = race(6, ConflictStrategy::Merge(add_counters)).await;
+        assert!(matches!(result, WriteBack::Done));
+        let saved = stored(6).await;
+        assert_eq!(saved.data, json!({"a": 1, "b": 1}));
+        assert_eq!(saved.version, 2);
+    }
+}
@@ -0,0 +1,76 @@
+use crate::context::PluginContext;
+use crate::storage::{self, StorageError};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use teloxide::types::ChatId;
+
+const PREFIX: &str = "settings:";
+
+fn key(chat: ChatId, name: &str) -> String {
+    format!("{}{}:{}", PREFIX, chat, name)
+}
+
+fn chat_prefix(chat: ChatId) -> String {
+    format!("{}{}:", PREFIX, chat)
+}
+
+pub async fn get<T: DeserializeOwned>(chat: ChatId, name: &str) -> Result<Option<T>, StorageError> {
+    storage::get_json(&key(chat, name)).await
+}
+
+pub async fn set<T: Serialize>(chat: ChatId, name: &str, value: &T) -> Result<(), StorageError> {
+    storage::set_json(&key(chat, name), value).await
+}
+
+pub async fn remove(chat: ChatId, name: &str) -> Result<(), StorageError> {
+    storage::delete(&key(chat, name)).await
+}
+
+pub async fn names(chat: ChatId) -> Result<Vec<String>, StorageError> {
+    let prefix = chat_prefix(chat);
+    let keys = storage::storage().keys(&prefix).await?;
+    Ok(keys
+        .into_iter()
+        .filter_map(|key| key.strip_prefix(&prefix).map(str::to_string))
+        .collect())
+}
+
+pub async fn clear(chat: ChatId) -> Result<(), StorageError> {
+    let storage = storage::storage();
+    for key in storage.keys(&chat_prefix(chat)).await? {
+        storage.delete(&key).await?;
+    }
+    Ok(())
+}
+
+pub(crate) async fn migrate(from: ChatId, to: ChatId) -> Result<(), StorageError> {
+    let storage = storage::storage();
+    let prefix = chat_prefix(from);
+    for old in storage.keys(&prefix).await? {
+        let Some(name) = old.strip_prefix(&prefix) else {
+            continue;
+        };
+        if let Some(value) = storage.get(&old).await? {
+            storage.set(&key(to, name), value).await?;
+        }
+        storage.delete(&old).await?;
+    }
+    Ok(())
+}
+
+impl PluginContext {
+    pub async fn setting<T: DeserializeOwned>(&self, name: &str) -> Option<T> {
+        get(self.chat_id()?, name).await.ok().flatten()
+    }
+
+    pub async fn set_setting<T: Serialize>(
+        &self,
+        name: &str,
+        value: &T,
+    ) -> Result<(), StorageError> {
+        match self.chat_id() {
+            Some(chat) => set(chat, name, value).await,
+            None => Ok(()),
+        }
+    }
+}
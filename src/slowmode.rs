@@ -0,0 +1,72 @@
+use crate::chat_info;
+use crate::permissions;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use teloxide::prelude::*;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static NEXT: Lazy<Mutex<HashMap<ChatId, Instant>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+// Off by default: most bots are admins, whom slow mode doesn't apply to.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// The chat's slow mode delay, from the `chat_info` cache. Admins can change it at
+// any time without an update, so it's only as fresh as that cache's TTL. `None` when
+// slow mode is off or the chat couldn't be looked up.
+pub async fn delay(bot: &Bot, chat: ChatId) -> Option<Duration> {
+    if !chat.is_channel_or_supergroup() {
+        return None;
+    }
+    chat_info::get(bot, chat)
+        .await
+        .ok()?
+        .slow_mode_delay()
+        .map(|delay| delay.duration())
+        .filter(|delay| !delay.is_zero())
+}
+
+pub fn invalidate(chat: ChatId) {
+    NEXT.lock().unwrap().remove(&chat);
+}
+
+async fn is_exempt(bot: &Bot, chat: ChatId) -> bool {
+    permissions::bot_member(bot, chat)
+        .await
+        .is_ok_and(|member| member.status().is_privileged())
+}
+
+// Holds a new message back until the chat's slow mode lets the bot post again.
+// Sends to the same chat queue up behind each other, each taking the next free slot.
+pub(crate) async fn wait(bot: &Bot, chat: ChatId) {
+    if !is_enabled() {
+        return;
+    }
+    let Some(delay) = delay(bot, chat).await else {
+        return;
+    };
+    if is_exempt(bot, chat).await {
+        return;
+    }
+    let at = {
+        let mut next = NEXT.lock().unwrap();
+        let now = Instant::now();
+        let at = next
+            .get(&chat)
+            .copied()
+            .filter(|at| *at > now)
+            .unwrap_or(now);
+        next.insert(chat, at + delay);
+        at
+    };
+    tokio::time::sleep_until(at.into()).await;
+}
@@ -0,0 +1,139 @@
+use crate::context::PluginContext;
+use crate::dispatcher::context_of;
+use crate::external::ExternalEvent;
+use crate::queue::DispatchQueue;
+use crate::registry;
+use futures::future::BoxFuture;
+use serde_json::json;
+use std::sync::atomic::{AtomicI32, Ordering};
+use teloxide::prelude::*;
+use teloxide::types::{ChatId, UserId};
+use tokio::sync::mpsc;
+
+static NEXT_ID: AtomicI32 = AtomicI32::new(1);
+
+pub enum IncomingEvent {
+    Update(Box<Update>),
+    Context(Box<PluginContext>),
+    External(ExternalEvent),
+}
+
+impl IncomingEvent {
+    pub fn from_json(update: serde_json::Value) -> Result<Self, serde_json::Error> {
+        Ok(IncomingEvent::Update(Box::new(serde_json::from_str(
+            &update.to_string(),
+        )?)))
+    }
+
+    pub fn text(chat_id: ChatId, user_id: UserId, text: impl Into<String>) -> Self {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let mut message = synthetic_message(id, chat_id, user_id);
+        message["text"] = json!(text.into());
+        Self::synthetic(json!({ "update_id": id, "message": message }))
+    }
+
+    pub fn callback(chat_id: ChatId, user_id: UserId, data: impl Into<String>) -> Self {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let mut message = synthetic_message(id, chat_id, user_id);
+        message["text"] = json!("");
+        Self::synthetic(json!({
+            "update_id": id,
+            "callback_query": {
+                "id": format!("synthetic-{}", id),
+                "from": synthetic_user(user_id),
+                "chat_instance": chat_id.to_string(),
+                "data": data.into(),
+                "message": message,
+            },
+        }))
+    }
+
+    pub fn external(name: impl Into<String>, payload: serde_json::Value) -> Self {
+        IncomingEvent::External(ExternalEvent::new(name, payload))
+    }
+
+    fn synthetic(update: serde_json::Value) -> Self {
+        Self::from_json(update).expect("synthetic updates are valid")
+    }
+
+    pub fn into_context(self, bot: Bot) -> Option<PluginContext> {
+        match self {
+            IncomingEvent::Update(update) => context_of(bot, *update),
+            IncomingEvent::Context(ctx) => Some(*ctx),
+            IncomingEvent::External(event) => Some(PluginContext::from_external(bot, event)),
+        }
+    }
+}
+
+impl From<Update> for IncomingEvent {
+    fn from(update: Update) -> Self {
+        IncomingEvent::Update(Box::new(update))
+    }
+}
+
+impl From<ExternalEvent> for IncomingEvent {
+    fn from(event: ExternalEvent) -> Self {
+        IncomingEvent::External(event)
+    }
+}
+
+impl From<PluginContext> for IncomingEvent {
+    fn from(ctx: PluginContext) -> Self {
+        IncomingEvent::Context(Box::new(ctx))
+    }
+}
+
+fn synthetic_user(user_id: UserId) -> serde_json::Value {
+    json!({ "id": user_id.0, "is_bot": false, "first_name": "User" })
+}
+
+fn synthetic_message(id: i32, chat_id: ChatId, user_id: UserId) -> serde_json::Value {
+    let chat = if chat_id.is_user() {
+        json!({ "id": chat_id.0, "type": "private", "first_name": "User" })
+    } else {
+        json!({ "id": chat_id.0, "type": "supergroup", "title": "Chat" })
+    };
+    json!({
+        "message_id": id,
+        "date": chrono::Utc::now().timestamp(),
+        "chat": chat,
+        "from": synthetic_user(user_id),
+    })
+}
+
+pub trait EventSource: Send {
+    fn next(&mut self) -> BoxFuture<'_, Option<IncomingEvent>>;
+}
+
+impl<S: EventSource + ?Sized> EventSource for Box<S> {
+    fn next(&mut self) -> BoxFuture<'_, Option<IncomingEvent>> {
+        (**self).next()
+    }
+}
+
+impl EventSource for mpsc::Receiver<IncomingEvent> {
+    fn next(&mut self) -> BoxFuture<'_, Option<IncomingEvent>> {
+        Box::pin(self.recv())
+    }
+}
+
+impl EventSource for mpsc::UnboundedReceiver<IncomingEvent> {
+    fn next(&mut self) -> BoxFuture<'_, Option<IncomingEvent>> {
+        Box::pin(self.recv())
+    }
+}
+
+pub async fn run_source(bot: Bot, mut source: impl EventSource, queue: DispatchQueue) {
+    while let Some(event) = source.next().await {
+        if let Some(ctx) = event.into_context(bot.clone()) {
+            queue.push(ctx);
+        }
+    }
+}
+
+pub async fn dispatch_event(bot: Bot, event: IncomingEvent) -> Result<(), teloxide::RequestError> {
+    match event.into_context(bot) {
+        Some(ctx) => registry::dispatch(ctx).await,
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,284 @@
+use crate::context::PluginContext;
+use crate::extract::FromContext;
+use chrono::{DateTime, Duration, Utc};
+use futures::future::{join_all, BoxFuture};
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, RwLock};
+use teloxide::types::{ChatId, ChatMemberUpdated, Message, MessageEntityKind, UserId};
+
+const REMEMBERED_MEMBERS: usize = 50_000;
+const REMEMBERED_JOINS: usize = 50_000;
+const LINK_WEIGHT: f32 = 0.3;
+const MAX_LINK_SCORE: f32 = 0.6;
+const NEW_MEMBER_WEIGHT: f32 = 0.3;
+const FLOOD_WEIGHT: f32 = 0.5;
+
+static CLASSIFIERS: Lazy<RwLock<Vec<Arc<dyn SpamClassifier>>>> =
+    Lazy::new(|| RwLock::new(Vec::new()));
+// When members joined, from `new_chat_members` messages and `chat_member` updates.
+static JOINS: Lazy<Mutex<Recent<DateTime<Utc>>>> =
+    Lazy::new(|| Mutex::new(Recent::new(REMEMBERED_JOINS)));
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SpamScore {
+    pub score: f32,
+    pub reasons: Vec<String>,
+}
+
+impl SpamScore {
+    pub fn new(score: f32) -> Self {
+        Self {
+            score: score.clamp(0.0, 1.0),
+            reasons: Vec::new(),
+        }
+    }
+
+    pub fn reason(mut self, reason: impl Into<String>) -> Self {
+        self.reasons.push(reason.into());
+        self
+    }
+
+    pub fn is_at_least(&self, threshold: f32) -> bool {
+        self.score >= threshold
+    }
+
+    // Takes the highest score and keeps every classifier's reasons, so one confident
+    // classifier isn't diluted by the others abstaining low.
+    fn merge(mut self, other: SpamScore) -> Self {
+        self.score = self.score.max(other.score);
+        self.reasons.extend(other.reasons);
+        self
+    }
+}
+
+pub trait SpamClassifier: Send + Sync {
+    fn classify<'a>(&'a self, ctx: &'a PluginContext) -> BoxFuture<'a, Option<SpamScore>>;
+}
+
+pub fn add_classifier(classifier: impl SpamClassifier + 'static) {
+    CLASSIFIERS.write().unwrap().push(Arc::new(classifier));
+}
+
+pub fn clear_classifiers() {
+    CLASSIFIERS.write().unwrap().clear();
+}
+
+pub(crate) async fn score(ctx: &PluginContext) {
+    let classifiers = CLASSIFIERS.read().unwrap().clone();
+    if classifiers.is_empty() || ctx.sender().is_none() {
+        return;
+    }
+    let Some(msg) = &ctx.message else {
+        return;
+    };
+    for user in msg.new_chat_members().unwrap_or_default() {
+        record_join(msg.chat.id, user.id, msg.date);
+    }
+    let scores = join_all(classifiers.iter().map(|c| c.classify(ctx))).await;
+    if let Some(score) = scores.into_iter().flatten().reduce(SpamScore::merge) {
+        ctx.extensions.insert(score);
+    }
+}
+
+// Notes when a user joined a chat, for `HeuristicClassifier`'s new member check. The
+// dispatcher records `new_chat_members` messages and `chat_member` updates on its
+// own; call this for joins the bot learns about some other way.
+pub fn record_join(chat_id: ChatId, user_id: UserId, at: DateTime<Utc>) {
+    JOINS.lock().unwrap().insert((chat_id, user_id), at);
+}
+
+pub fn joined_at(chat_id: ChatId, user_id: UserId) -> Option<DateTime<Utc>> {
+    JOINS.lock().unwrap().get(&(chat_id, user_id)).copied()
+}
+
+// A `chat_member` update where the user went from not being in the chat to being in
+// it. Telegram only sends these to admins that asked for them in `allowed_updates`.
+pub(crate) fn record_member_update(update: &ChatMemberUpdated) {
+    if update.new_chat_member.kind.is_present() && !update.old_chat_member.kind.is_present() {
+        record_join(update.chat.id, update.new_chat_member.user.id, update.date);
+    }
+}
+
+// Per chat member state that forgets the least recently used members past its
+// capacity. Every use queues the key with a new stamp. Older stamps for the same key
+// are skipped when evicting, and cleared out once the queue is twice the capacity.
+struct Recent<V> {
+    capacity: usize,
+    entries: HashMap<(ChatId, UserId), (u64, V)>,
+    order: VecDeque<((ChatId, UserId), u64)>,
+    stamp: u64,
+}
+
+impl<V> Recent<V> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            stamp: 0,
+        }
+    }
+
+    fn get(&self, key: &(ChatId, UserId)) -> Option<&V> {
+        self.entries.get(key).map(|(_, value)| value)
+    }
+
+    fn insert(&mut self, key: (ChatId, UserId), value: V) {
+        if !self.entries.contains_key(&key) {
+            self.make_room();
+        }
+        let stamp = self.next_stamp(key);
+        self.entries.insert(key, (stamp, value));
+        self.compact();
+    }
+
+    // The entry for `key`, made with `make` if it's missing, and now the most recent.
+    fn touch(&mut self, key: (ChatId, UserId), make: impl FnOnce() -> V) -> &mut V {
+        if !self.entries.contains_key(&key) {
+            self.make_room();
+        }
+        let stamp = self.next_stamp(key);
+        self.entries.entry(key).or_insert_with(|| (stamp, make())).0 = stamp;
+        self.compact();
+        &mut self
+            .entries
+            .get_mut(&key)
+            .expect("the entry was just added")
+            .1
+    }
+
+    fn next_stamp(&mut self, key: (ChatId, UserId)) -> u64 {
+        self.stamp += 1;
+        self.order.push_back((key, self.stamp));
+        self.stamp
+    }
+
+    fn is_current(&self, key: &(ChatId, UserId), stamp: u64) -> bool {
+        self.entries.get(key).is_some_and(|(at, _)| *at == stamp)
+    }
+
+    // Drops the least recently used entries until one more fits.
+    fn make_room(&mut self) {
+        while self.entries.len() >= self.capacity {
+            let Some((key, stamp)) = self.order.pop_front() else {
+                break;
+            };
+            if self.is_current(&key, stamp) {
+                self.entries.remove(&key);
+            }
+        }
+    }
+
+    fn compact(&mut self) {
+        if self.order.len() > self.capacity * 2 {
+            let entries = &self.entries;
+            self.order
+                .retain(|(key, stamp)| entries.get(key).is_some_and(|(at, _)| at == stamp));
+        }
+    }
+}
+
+pub struct HeuristicClassifier {
+    new_member_window: Duration,
+    flood_limit: usize,
+    flood_window: Duration,
+    activity: Mutex<Recent<VecDeque<DateTime<Utc>>>>,
+}
+
+impl Default for HeuristicClassifier {
+    fn default() -> Self {
+        Self {
+            new_member_window: Duration::hours(24),
+            flood_limit: 5,
+            flood_window: Duration::seconds(10),
+            activity: Mutex::new(Recent::new(REMEMBERED_MEMBERS)),
+        }
+    }
+}
+
+impl HeuristicClassifier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn new_member_window(mut self, window: Duration) -> Self {
+        self.new_member_window = window;
+        self
+    }
+
+    pub fn flood(mut self, messages: usize, window: Duration) -> Self {
+        self.flood_limit = messages.max(1);
+        self.flood_window = window;
+        self
+    }
+
+    // Returns whether the sender joined the chat within the new member window and how
+    // many messages they sent within the flood window, counting this one. Members the
+    // bot never saw join aren't new: after a restart, everyone's first message would
+    // look like a newcomer's otherwise.
+    fn observe(&self, msg: &Message, user: UserId) -> (bool, usize) {
+        let key = (msg.chat.id, user);
+        let is_new = joined_at(msg.chat.id, user)
+            .is_some_and(|joined| msg.date - joined < self.new_member_window);
+        let mut activity = self.activity.lock().unwrap();
+        let recent = activity.touch(key, VecDeque::new);
+        let since = msg.date - self.flood_window;
+        recent.retain(|at| *at > since);
+        recent.push_back(msg.date);
+        (is_new, recent.len())
+    }
+}
+
+fn link_count(msg: &Message) -> usize {
+    msg.entities()
+        .or_else(|| msg.caption_entities())
+        .unwrap_or_default()
+        .iter()
+        .filter(|entity| {
+            matches!(
+                entity.kind,
+                MessageEntityKind::Url | MessageEntityKind::TextLink { .. }
+            )
+        })
+        .count()
+}
+
+impl SpamClassifier for HeuristicClassifier {
+    fn classify<'a>(&'a self, ctx: &'a PluginContext) -> BoxFuture<'a, Option<SpamScore>> {
+        Box::pin(async move {
+            let msg = ctx.message.as_ref().filter(|msg| !msg.chat.is_private())?;
+            let user = ctx.sender()?.id;
+            let (is_new, recent) = self.observe(msg, user);
+            let links = link_count(msg);
+
+            let mut score = SpamScore::default();
+            if links > 0 {
+                score.score += (links as f32 * LINK_WEIGHT).min(MAX_LINK_SCORE);
+                score.reasons.push("links".to_string());
+                if is_new {
+                    score.score += NEW_MEMBER_WEIGHT;
+                    score.reasons.push("new_member".to_string());
+                }
+            }
+            if recent > self.flood_limit {
+                score.score += FLOOD_WEIGHT;
+                score.reasons.push("flood".to_string());
+            }
+            score.score = score.score.min(1.0);
+            Some(score)
+        })
+    }
+}
+
+impl FromContext for SpamScore {
+    fn from_context(ctx: &PluginContext) -> Option<Self> {
+        ctx.spam_score()
+    }
+}
+
+impl PluginContext {
+    pub fn spam_score(&self) -> Option<SpamScore> {
+        self.extensions.get()
+    }
+}
@@ -0,0 +1,349 @@
+use crate::context::PluginContext;
+use crate::pool;
+use crate::traffic;
+use std::fmt;
+use teloxide::prelude::*;
+use teloxide::types::{InputFile, InputSticker, Sticker, StickerFormat, StickerSet};
+use teloxide::ApiError;
+
+// Regular sets hold up to 120 stickers; `kang` moves on to the next pack after that.
+pub const PACK_LIMIT: usize = 120;
+
+const NAME_LIMIT: usize = 64;
+const DEFAULT_EMOJI: &str = "🙂";
+
+#[derive(Debug)]
+pub enum StickerError {
+    InvalidName(String),
+    UnknownFormat,
+    TooLarge { size: usize, limit: usize },
+    NoEmoji,
+    NoSender,
+    Request(teloxide::RequestError),
+}
+
+impl fmt::Display for StickerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StickerError::InvalidName(name) => {
+                write!(f, "`{}` isn't a valid sticker set name", name)
+            }
+            StickerError::UnknownFormat => {
+                write!(
+                    f,
+                    "stickers must be PNG or WebP images, TGS animations or WebM videos"
+                )
+            }
+            StickerError::TooLarge { size, limit } => write!(
+                f,
+                "the sticker is {} KB, over the {} KB limit",
+                size.div_ceil(1024),
+                limit / 1024
+            ),
+            StickerError::NoEmoji => write!(f, "a sticker needs at least one emoji"),
+            StickerError::NoSender => write!(f, "the update has no user to own the pack"),
+            StickerError::Request(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for StickerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StickerError::Request(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<teloxide::RequestError> for StickerError {
+    fn from(err: teloxide::RequestError) -> Self {
+        StickerError::Request(err)
+    }
+}
+
+// Sniffs the format and checks Telegram's size limit for it.
+pub fn detect_format(bytes: &[u8]) -> Result<StickerFormat, StickerError> {
+    let (format, limit) = if bytes.starts_with(b"\x89PNG\r\n\x1a\n")
+        || (bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP")
+    {
+        (StickerFormat::Static, 512 * 1024)
+    } else if bytes.starts_with(&[0x1F, 0x8B]) {
+        (StickerFormat::Animated, 64 * 1024)
+    } else if bytes.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+        (StickerFormat::Video, 256 * 1024)
+    } else {
+        return Err(StickerError::UnknownFormat);
+    };
+    if bytes.len() > limit {
+        return Err(StickerError::TooLarge {
+            size: bytes.len(),
+            limit,
+        });
+    }
+    Ok(format)
+}
+
+#[derive(Clone, Debug)]
+pub struct NewSticker {
+    pub file: InputFile,
+    pub format: StickerFormat,
+    pub emojis: Vec<String>,
+    pub keywords: Vec<String>,
+}
+
+impl NewSticker {
+    // An upload from memory, with the format read from the file itself. Pictures still
+    // have to be 512px on their longer side; `media_utils::make_sticker` does that.
+    pub fn from_bytes(bytes: Vec<u8>, emoji: impl Into<String>) -> Result<Self, StickerError> {
+        let format = detect_format(&bytes)?;
+        Ok(Self {
+            file: InputFile::memory(bytes).file_name("sticker"),
+            format,
+            emojis: vec![emoji.into()],
+            keywords: Vec::new(),
+        })
+    }
+
+    // Reuses a sticker someone sent, without downloading it, keeping its emoji.
+    pub fn from_sticker(sticker: &Sticker) -> Self {
+        Self {
+            file: InputFile::file_id(sticker.file.id.clone()),
+            format: sticker.format(),
+            emojis: vec![sticker
+                .emoji
+                .clone()
+                .unwrap_or_else(|| DEFAULT_EMOJI.to_string())],
+            keywords: Vec::new(),
+        }
+    }
+
+    pub fn emojis(mut self, emojis: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.emojis = emojis.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn keywords(mut self, keywords: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.keywords = keywords.into_iter().map(Into::into).collect();
+        self
+    }
+
+    fn into_input(self) -> Result<InputSticker, StickerError> {
+        if self.emojis.is_empty() {
+            return Err(StickerError::NoEmoji);
+        }
+        Ok(InputSticker {
+            sticker: self.file,
+            format: self.format,
+            emoji_list: self.emojis,
+            mask_position: None,
+            keywords: self.keywords,
+        })
+    }
+}
+
+pub async fn bot_username(bot: &Bot) -> Result<String, StickerError> {
+    Ok(crate::permissions::bot_username(bot).await?)
+}
+
+// Telegram wants set names of latin letters, digits and single underscores, starting
+// with a letter and ending in `_by_<bot username>`, 64 characters at most.
+pub fn pack_name(base: &str, bot_username: &str) -> Result<String, StickerError> {
+    let suffix = format!("_by_{}", bot_username.trim_start_matches('@'));
+    let mut name = String::new();
+    for c in base.chars() {
+        let c = c.to_ascii_lowercase();
+        if c.is_ascii_alphanumeric() {
+            name.push(c);
+        } else if !name.is_empty() && !name.ends_with('_') {
+            name.push('_');
+        }
+    }
+    let name = name.trim_start_matches(|c: char| c.is_ascii_digit() || c == '_');
+    let room = NAME_LIMIT.saturating_sub(suffix.len());
+    let name = name[..name.len().min(room)].trim_end_matches('_');
+    if name.is_empty() || bot_username.is_empty() {
+        return Err(StickerError::InvalidName(format!("{}{}", base, suffix)));
+    }
+    Ok(format!("{}{}", name, suffix))
+}
+
+pub fn pack_link(name: &str) -> String {
+    format!("https://t.me/addstickers/{}", name)
+}
+
+pub async fn get_pack(bot: &Bot, name: &str) -> Result<Option<StickerSet>, StickerError> {
+    match traffic::send(bot.get_sticker_set(name)).await {
+        Ok(set) => Ok(Some(set)),
+        Err(teloxide::RequestError::Api(ApiError::InvalidStickersSet)) => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+pub async fn create_pack(
+    bot: &Bot,
+    owner: UserId,
+    name: &str,
+    title: &str,
+    stickers: Vec<NewSticker>,
+) -> Result<(), StickerError> {
+    let stickers = stickers
+        .into_iter()
+        .map(NewSticker::into_input)
+        .collect::<Result<Vec<_>, _>>()?;
+    traffic::send(bot.create_new_sticker_set(owner, name, title, stickers)).await?;
+    Ok(())
+}
+
+pub async fn add_sticker(
+    bot: &Bot,
+    owner: UserId,
+    name: &str,
+    sticker: NewSticker,
+) -> Result<(), StickerError> {
+    traffic::send(bot.add_sticker_to_set(owner, name, sticker.into_input()?)).await?;
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Progress {
+    pub done: usize,
+    pub total: usize,
+}
+
+// Adds stickers one at a time, creating the pack with the first one if it doesn't
+// exist yet, and calls `on_progress` after each. A failure stops there, so whatever
+// was added before stays in the pack.
+pub async fn upload(
+    bot: &Bot,
+    owner: UserId,
+    name: &str,
+    title: &str,
+    stickers: Vec<NewSticker>,
+    mut on_progress: impl FnMut(Progress),
+) -> Result<(), StickerError> {
+    let total = stickers.len();
+    let mut stickers = stickers.into_iter();
+    let mut done = 0;
+    if get_pack(bot, name).await?.is_none() {
+        let Some(first) = stickers.next() else {
+            return Ok(());
+        };
+        create_pack(bot, owner, name, title, vec![first]).await?;
+        done += 1;
+        on_progress(Progress { done, total });
+    }
+    for sticker in stickers {
+        add_sticker(bot, owner, name, sticker).await?;
+        done += 1;
+        on_progress(Progress { done, total });
+    }
+    Ok(())
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Kanged {
+    pub name: String,
+    pub link: String,
+    pub created: bool,
+}
+
+// Adds a sticker to the user's own pack, `u<id>_<n>_by_<bot>`, starting a new one
+// once a pack is full.
+pub async fn kang(
+    bot: &Bot,
+    user: &teloxide::types::User,
+    sticker: NewSticker,
+) -> Result<Kanged, StickerError> {
+    let username = bot_username(bot).await?;
+    let owner = user
+        .username
+        .as_deref()
+        .map_or(user.first_name.clone(), |name| format!("@{}", name));
+    for n in 1.. {
+        let name = pack_name(&format!("u{}_{}", user.id, n), &username)?;
+        let pack = get_pack(bot, &name).await?;
+        if pack
+            .as_ref()
+            .is_some_and(|pack| pack.stickers.len() >= PACK_LIMIT)
+        {
+            continue;
+        }
+        let created = pack.is_none();
+        match pack {
+            Some(_) => add_sticker(bot, user.id, &name, sticker).await?,
+            None => {
+                let title = match n {
+                    1 => format!("{}'s stickers", owner),
+                    n => format!("{}'s stickers {}", owner, n),
+                };
+                create_pack(bot, user.id, &name, &title, vec![sticker]).await?;
+            }
+        }
+        return Ok(Kanged {
+            link: pack_link(&name),
+            name,
+            created,
+        });
+    }
+    unreachable!("packs are numbered without end")
+}
+
+impl PluginContext {
+    pub async fn kang(&self, sticker: NewSticker) -> Result<Kanged, StickerError> {
+        kang(
+            &self.bot,
+            self.sender().ok_or(StickerError::NoSender)?,
+            sticker,
+        )
+        .await
+    }
+
+    // Like `upload`, with a message in the chat showing how far along it is.
+    pub async fn upload_stickers(
+        &self,
+        name: &str,
+        title: &str,
+        stickers: Vec<NewSticker>,
+    ) -> Result<(), StickerError> {
+        let owner = self.sender().ok_or(StickerError::NoSender)?.id;
+        let chat = self.chat_id();
+        let total = stickers.len();
+        let status = match chat {
+            Some(chat) => Some(
+                pool::pinned(
+                    self.call(
+                        self.bot
+                            .send_message(chat, format!("Adding stickers… 0/{}", total)),
+                    ),
+                )
+                .await?
+                .id,
+            ),
+            None => None,
+        };
+        // Edits can't be awaited inside the callback, so they run next to the upload and
+        // stop when it's done and drops the sender.
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<Progress>();
+        let uploaded = upload(&self.bot, owner, name, title, stickers, move |progress| {
+            let _ = sender.send(progress);
+        });
+        let edits = async {
+            while let Some(progress) = receiver.recv().await {
+                if let (Some(chat), Some(id)) = (chat, status) {
+                    let text = format!("Adding stickers… {}/{}", progress.done, progress.total);
+                    let _ = self.call(self.bot.edit_message_text(chat, id, text)).await;
+                }
+            }
+        };
+        let (result, _) = tokio::join!(uploaded, edits);
+        if let (Some(chat), Some(id)) = (chat, status) {
+            let text = match &result {
+                Ok(()) => format!("Done: {}", pack_link(name)),
+                Err(err) => format!("Stopped: {}", err),
+            };
+            let _ = self.call(self.bot.edit_message_text(chat, id, text)).await;
+        }
+        result
+    }
+}
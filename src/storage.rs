@@ -0,0 +1,297 @@
+use crate::access;
+use crate::chats;
+use crate::conversation::{self, ConversationRecord};
+use crate::federation;
+use crate::roles;
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use once_cell::sync::Lazy;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+const ARCHIVE_VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum StorageError {
+    Backend(Box<dyn std::error::Error + Send + Sync>),
+    Serde(serde_json::Error),
+    Io(io::Error),
+    UnsupportedArchive(u32),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Backend(err) => write!(f, "storage backend error: {}", err),
+            StorageError::Serde(err) => write!(f, "couldn't (de)serialize stored value: {}", err),
+            StorageError::Io(err) => write!(f, "couldn't access the archive: {}", err),
+            StorageError::UnsupportedArchive(version) => {
+                write!(f, "unsupported archive version {}", version)
+            }
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<serde_json::Error> for StorageError {
+    fn from(err: serde_json::Error) -> Self {
+        StorageError::Serde(err)
+    }
+}
+
+impl From<io::Error> for StorageError {
+    fn from(err: io::Error) -> Self {
+        StorageError::Io(err)
+    }
+}
+
+pub trait Storage: Send + Sync {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<String>, StorageError>>;
+
+    fn set<'a>(&'a self, key: &'a str, value: String) -> BoxFuture<'a, Result<(), StorageError>>;
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), StorageError>>;
+
+    fn keys<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>, StorageError>>;
+
+    // Backends without an atomic primitive get a best-effort check-then-set.
+    fn compare_and_swap<'a>(
+        &'a self,
+        key: &'a str,
+        expected: Option<&'a str>,
+        value: String,
+    ) -> BoxFuture<'a, Result<bool, StorageError>> {
+        Box::pin(async move {
+            if self.get(key).await?.as_deref() != expected {
+                return Ok(false);
+            }
+            self.set(key, value).await?;
+            Ok(true)
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryStorage {
+    entries: Mutex<BTreeMap<String, String>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<String>, StorageError>> {
+        let value = self.entries.lock().unwrap().get(key).cloned();
+        Box::pin(async move { Ok(value) })
+    }
+
+    fn set<'a>(&'a self, key: &'a str, value: String) -> BoxFuture<'a, Result<(), StorageError>> {
+        self.entries.lock().unwrap().insert(key.to_string(), value);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), StorageError>> {
+        self.entries.lock().unwrap().remove(key);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn compare_and_swap<'a>(
+        &'a self,
+        key: &'a str,
+        expected: Option<&'a str>,
+        value: String,
+    ) -> BoxFuture<'a, Result<bool, StorageError>> {
+        let mut entries = self.entries.lock().unwrap();
+        let swapped = entries.get(key).map(String::as_str) == expected;
+        if swapped {
+            entries.insert(key.to_string(), value);
+        }
+        Box::pin(async move { Ok(swapped) })
+    }
+
+    fn keys<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>, StorageError>> {
+        let keys = self
+            .entries
+            .lock()
+            .unwrap()
+            .range(prefix.to_string()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, _)| key.clone())
+            .collect();
+        Box::pin(async move { Ok(keys) })
+    }
+}
+
+static STORAGE: Lazy<RwLock<Arc<dyn Storage>>> =
+    Lazy::new(|| RwLock::new(Arc::new(MemoryStorage::new())));
+
+static CONFIGURED: AtomicBool = AtomicBool::new(false);
+
+pub fn set_storage(storage: impl Storage + 'static) {
+    *STORAGE.write().unwrap() = Arc::new(storage);
+    CONFIGURED.store(true, Ordering::Relaxed);
+    invalidate_caches();
+}
+
+fn invalidate_caches() {
+    access::invalidate();
+    roles::invalidate();
+    chats::invalidate();
+    federation::invalidate();
+    #[cfg(feature = "filters")]
+    crate::filters::invalidate();
+    #[cfg(feature = "afk")]
+    crate::afk::invalidate();
+}
+
+// Whether `set_storage` was called, rather than everything living in memory.
+pub fn is_configured() -> bool {
+    CONFIGURED.load(Ordering::Relaxed)
+}
+
+pub fn storage() -> Arc<dyn Storage> {
+    STORAGE.read().unwrap().clone()
+}
+
+pub async fn get_json<T: DeserializeOwned>(key: &str) -> Result<Option<T>, StorageError> {
+    match storage().get(key).await? {
+        Some(raw) => Ok(Some(serde_json::from_str(&raw)?)),
+        None => Ok(None),
+    }
+}
+
+pub async fn set_json<T: Serialize>(key: &str, value: &T) -> Result<(), StorageError> {
+    let raw = serde_json::to_string(value)?;
+    storage().set(key, raw).await
+}
+
+pub async fn delete(key: &str) -> Result<(), StorageError> {
+    storage().delete(key).await
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Archive {
+    pub version: u32,
+    pub exported_at: DateTime<Utc>,
+    pub entries: BTreeMap<String, String>,
+    #[serde(default)]
+    pub conversations: Vec<ConversationRecord>,
+}
+
+pub async fn snapshot() -> Result<Archive, StorageError> {
+    let storage = storage();
+    let mut entries = BTreeMap::new();
+    for key in storage.keys("").await? {
+        if let Some(value) = storage.get(&key).await? {
+            entries.insert(key, value);
+        }
+    }
+    Ok(Archive {
+        version: ARCHIVE_VERSION,
+        exported_at: Utc::now(),
+        entries,
+        conversations: conversation::snapshot(),
+    })
+}
+
+pub async fn restore(archive: Archive) -> Result<usize, StorageError> {
+    if archive.version > ARCHIVE_VERSION {
+        return Err(StorageError::UnsupportedArchive(archive.version));
+    }
+    let storage = storage();
+    let restored = archive.entries.len();
+    for (key, value) in archive.entries {
+        storage.set(&key, value).await?;
+    }
+    invalidate_caches();
+    // Jobs in the archive need timers, which the scheduler only sets up at start.
+    crate::scheduler::arm_stored().await?;
+    Ok(restored + conversation::restore(archive.conversations))
+}
+
+pub async fn export(path: impl AsRef<Path>) -> Result<usize, StorageError> {
+    let archive = snapshot().await?;
+    let exported = archive.entries.len() + archive.conversations.len();
+    tokio::fs::write(path, serde_json::to_vec_pretty(&archive)?).await?;
+    Ok(exported)
+}
+
+pub async fn import(path: impl AsRef<Path>) -> Result<usize, StorageError> {
+    let raw = tokio::fs::read(path).await?;
+    restore(serde_json::from_slice(&raw)?).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Leaves `compare_and_swap` to the trait's check-then-set.
+    #[derive(Default)]
+    struct Plain(MemoryStorage);
+
+    impl Storage for Plain {
+        fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<String>, StorageError>> {
+            self.0.get(key)
+        }
+
+        fn set<'a>(
+            &'a self,
+            key: &'a str,
+            value: String,
+        ) -> BoxFuture<'a, Result<(), StorageError>> {
+            self.0.set(key, value)
+        }
+
+        fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), StorageError>> {
+            self.0.delete(key)
+        }
+
+        fn keys<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>, StorageError>> {
+            self.0.keys(prefix)
+        }
+    }
+
+    async fn swaps(storage: &dyn Storage) {
+        let cas = |expected: Option<&'static str>, value: &str| {
+            storage.compare_and_swap("key", expected, value.to_string())
+        };
+        assert!(!cas(Some("a"), "b").await.unwrap());
+        assert!(cas(None, "a").await.unwrap());
+        assert!(!cas(None, "b").await.unwrap());
+        assert!(!cas(Some("b"), "c").await.unwrap());
+        assert!(cas(Some("a"), "c").await.unwrap());
+        assert_eq!(storage.get("key").await.unwrap().as_deref(), Some("c"));
+    }
+
+    #[tokio::test]
+    async fn memory_storage_swaps_only_on_a_match() {
+        swaps(&MemoryStorage::new()).await;
+    }
+
+    #[tokio::test]
+    async fn default_compare_and_swap_swaps_only_on_a_match() {
+        swaps(&Plain::default()).await;
+    }
+
+    #[tokio::test]
+    async fn keys_are_listed_by_prefix() {
+        let storage = MemoryStorage::new();
+        for key in ["note:1", "note:2", "notes", "warn:1"] {
+            storage.set(key, String::new()).await.unwrap();
+        }
+        assert_eq!(
+            storage.keys("note:").await.unwrap(),
+            vec!["note:1", "note:2"]
+        );
+    }
+}
@@ -0,0 +1,365 @@
+use crate::dispatcher::context_of;
+use crate::registry;
+use crate::reporting;
+use futures::FutureExt;
+use redis::aio::{ConnectionManager, ConnectionManagerConfig};
+use redis::streams::{
+    StreamAutoClaimOptions, StreamAutoClaimReply, StreamId, StreamMaxlen, StreamReadOptions,
+    StreamReadReply,
+};
+use redis::AsyncCommands;
+use std::convert::Infallible;
+use std::fmt;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use teloxide::dispatching::UpdateHandler;
+use teloxide::prelude::*;
+use tokio::sync::OnceCell;
+use tokio::task::{AbortHandle, JoinHandle};
+
+const FIELD: &str = "update";
+const BLOCK_MS: u64 = 5_000;
+// How long past a blocking read's own timeout a reply may take before the
+// connection counts as dead.
+const RESPONSE_MARGIN: Duration = Duration::from_secs(5);
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub enum StreamError {
+    InvalidUrl(String),
+    Redis(redis::RedisError),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StreamError::InvalidUrl(url) => write!(f, "`{}` isn't a redis:// URL", url),
+            StreamError::Redis(err) => write!(f, "redis error: {}", err),
+            StreamError::Json(err) => write!(f, "update isn't valid JSON: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for StreamError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StreamError::Redis(err) => Some(err),
+            StreamError::Json(err) => Some(err),
+            StreamError::InvalidUrl(_) => None,
+        }
+    }
+}
+
+impl From<redis::RedisError> for StreamError {
+    fn from(err: redis::RedisError) -> Self {
+        StreamError::Redis(err)
+    }
+}
+
+impl From<serde_json::Error> for StreamError {
+    fn from(err: serde_json::Error) -> Self {
+        StreamError::Json(err)
+    }
+}
+
+// The update in a stream entry, if it has one that parses.
+fn update_of(entry: &StreamId) -> Option<Update> {
+    serde_json::from_str(&entry.get::<String>(FIELD)?).ok()
+}
+
+#[derive(Clone)]
+pub struct RedisStream {
+    url: String,
+    stream: String,
+    group: String,
+    consumer: String,
+    consumers: usize,
+    max_len: Option<usize>,
+    claim_after: Duration,
+    publisher: Arc<OnceCell<ConnectionManager>>,
+}
+
+impl RedisStream {
+    // `url` is `redis://[user:password@]host[:port][/db]`, or `rediss://` for TLS,
+    // with the user and password percent-encoded.
+    pub fn new(url: impl Into<String>, stream: impl Into<String>) -> Self {
+        let host = std::env::var("HOSTNAME").unwrap_or_else(|_| String::from("worker"));
+        Self {
+            url: url.into(),
+            stream: stream.into(),
+            group: String::from("teloxide-plugins"),
+            consumer: format!("{}-{}", host, std::process::id()),
+            consumers: 4,
+            max_len: None,
+            claim_after: Duration::from_secs(60),
+            publisher: Arc::new(OnceCell::new()),
+        }
+    }
+
+    pub fn group(mut self, group: impl Into<String>) -> Self {
+        self.group = group.into();
+        self
+    }
+
+    // Must be unique per process and stable across its restarts, so a restarted
+    // worker picks up what it was holding. Defaults to `$HOSTNAME-pid`.
+    pub fn consumer(mut self, consumer: impl Into<String>) -> Self {
+        self.consumer = consumer.into();
+        self
+    }
+
+    // Updates handled at the same time by each worker process. Defaults to 4.
+    pub fn consumers(mut self, consumers: usize) -> Self {
+        self.consumers = consumers.max(1);
+        self
+    }
+
+    // Trims the stream to about this many entries as updates are added.
+    pub fn max_len(mut self, max_len: usize) -> Self {
+        self.max_len = Some(max_len);
+        self
+    }
+
+    // Updates a worker took but didn't acknowledge within this time, because it
+    // crashed or was killed, go to another worker. Defaults to a minute; keep it
+    // above your slowest handler.
+    pub fn claim_after(mut self, claim_after: Duration) -> Self {
+        self.claim_after = claim_after;
+        self
+    }
+
+    // A connection that reconnects on its own. Each consumer gets one of its own,
+    // since a blocking read holds up everything else sent over the connection.
+    async fn connect(&self) -> Result<ConnectionManager, StreamError> {
+        let client = redis::Client::open(self.url.as_str())
+            .map_err(|_| StreamError::InvalidUrl(self.url.clone()))?;
+        let config = ConnectionManagerConfig::new()
+            .set_response_timeout(Duration::from_millis(BLOCK_MS) + RESPONSE_MARGIN);
+        Ok(ConnectionManager::new_with_config(client, config).await?)
+    }
+
+    // Adds an update to the stream and returns its entry id.
+    pub async fn publish(&self, update: &Update) -> Result<String, StreamError> {
+        let json = serde_json::to_string(update)?;
+        let mut conn = self
+            .publisher
+            .get_or_try_init(|| self.connect())
+            .await?
+            .clone();
+        let items = [(FIELD, json)];
+        let id = match self.max_len {
+            Some(len) => {
+                conn.xadd_maxlen(&self.stream, StreamMaxlen::Approx(len), "*", &items)
+                    .await?
+            }
+            None => conn.xadd(&self.stream, "*", &items).await?,
+        };
+        Ok(id)
+    }
+
+    // Keeps trying until Redis takes the update, so nothing Telegram handed over is
+    // dropped; polling waits meanwhile and the backlog stays with Telegram.
+    async fn publish_until_done(&self, update: &Update) {
+        let mut backoff = FIRST_BACKOFF;
+        while let Err(err) = self.publish(update).await {
+            if matches!(err, StreamError::Json(_) | StreamError::InvalidUrl(_)) {
+                return;
+            }
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    // Creates the group, and the stream with it, unless they're already there.
+    async fn ensure_group(&self, conn: &mut ConnectionManager) -> Result<(), StreamError> {
+        let created: redis::RedisResult<()> = conn
+            .xgroup_create_mkstream(&self.stream, &self.group, "0")
+            .await;
+        match created {
+            Err(err) if err.code() == Some("BUSYGROUP") => Ok(()),
+            other => Ok(other?),
+        }
+    }
+
+    async fn next_batch(
+        &self,
+        conn: &mut ConnectionManager,
+        consumer: &str,
+        last_claim: &mut Instant,
+    ) -> Result<Vec<StreamId>, StreamError> {
+        let check_every = self.claim_after / 4;
+        if last_claim.elapsed() >= check_every {
+            *last_claim = Instant::now();
+            let reply: StreamAutoClaimReply = conn
+                .xautoclaim_options(
+                    &self.stream,
+                    &self.group,
+                    consumer,
+                    self.claim_after.as_millis() as u64,
+                    "0-0",
+                    StreamAutoClaimOptions::default().count(1),
+                )
+                .await?;
+            if !reply.claimed.is_empty() {
+                return Ok(reply.claimed);
+            }
+        }
+        // Waking up for the next claim check in time, too.
+        let block = (check_every.as_millis() as u64).clamp(1, BLOCK_MS);
+        let options = StreamReadOptions::default()
+            .group(&self.group, consumer)
+            .count(1)
+            .block(block as usize);
+        let reply: Option<StreamReadReply> = conn
+            .xread_options(&[&self.stream], &[">"], &options)
+            .await?;
+        Ok(reply
+            .into_iter()
+            .flat_map(|reply| reply.keys)
+            .flat_map(|key| key.ids)
+            .collect())
+    }
+
+    async fn consume_one(
+        &self,
+        bot: &Bot,
+        consumer: &str,
+        conn: &mut ConnectionManager,
+    ) -> Result<Infallible, StreamError> {
+        self.ensure_group(conn).await?;
+        let mut last_claim = Instant::now() - self.claim_after;
+        loop {
+            for entry in self.next_batch(conn, consumer, &mut last_claim).await? {
+                if let Some(ctx) =
+                    update_of(&entry).and_then(|update| context_of(bot.clone(), update))
+                {
+                    let run = AssertUnwindSafe(registry::dispatch(ctx))
+                        .catch_unwind()
+                        .await;
+                    if let Err(payload) = run {
+                        reporting::report_task_panic("streams", payload).await;
+                    }
+                }
+                let _: () = conn.xack(&self.stream, &self.group, &[&entry.id]).await?;
+            }
+        }
+    }
+
+    async fn run_consumer(self, bot: Bot, consumer: String) {
+        let mut backoff = FIRST_BACKOFF;
+        let mut conn = None;
+        loop {
+            let started = Instant::now();
+            let err = match &mut conn {
+                Some(conn) => {
+                    let Err(err) = self.consume_one(&bot, &consumer, conn).await;
+                    err
+                }
+                None => match self.connect().await {
+                    Ok(connected) => {
+                        conn = Some(connected);
+                        continue;
+                    }
+                    Err(err) => err,
+                },
+            };
+            if matches!(err, StreamError::InvalidUrl(_)) {
+                return;
+            }
+            if started.elapsed() > MAX_BACKOFF {
+                backoff = FIRST_BACKOFF;
+            }
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+}
+
+// The dispatcher endpoint of the receiving process: every update goes to the stream
+// instead of the local queue.
+pub fn handler(stream: RedisStream) -> UpdateHandler<teloxide::RequestError> {
+    dptree::endpoint(move |update: Update| {
+        let stream = stream.clone();
+        async move {
+            #[cfg(feature = "health")]
+            crate::health::record_update();
+            crate::transport::mirror(&update);
+            stream.publish_until_done(&update).await;
+            Ok(())
+        }
+    })
+}
+
+// Runs plugins for updates from the stream, `consumers` at a time, until the future
+// is dropped. An update is acknowledged once its plugins are done, so one that was
+// in flight when a worker died is handled again by another worker: handlers should
+// cope with seeing an update twice (see `ctx.once`).
+pub async fn consume(bot: Bot, stream: RedisStream) {
+    let tasks: Vec<JoinHandle<()>> = (0..stream.consumers)
+        .map(|n| {
+            let consumer = format!("{}-{}", stream.consumer, n);
+            tokio::spawn(stream.clone().run_consumer(bot.clone(), consumer))
+        })
+        .collect();
+    let _stop = AbortOnDrop(tasks.iter().map(JoinHandle::abort_handle).collect());
+    futures::future::join_all(tasks).await;
+}
+
+struct AbortOnDrop(Vec<AbortHandle>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        for task in &self.0 {
+            task.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redis::Value;
+    use std::collections::HashMap;
+
+    fn entry(fields: &[(&str, &str)]) -> StreamId {
+        StreamId {
+            id: String::from("1-0"),
+            map: fields
+                .iter()
+                .map(|(name, value)| {
+                    (
+                        name.to_string(),
+                        Value::BulkString(value.as_bytes().to_vec()),
+                    )
+                })
+                .collect::<HashMap<_, _>>(),
+        }
+    }
+
+    #[test]
+    fn the_update_comes_from_its_field() {
+        let json = r#"{"update_id": 7, "message": {"message_id": 1, "date": 0, "chat": {"id": 5, "type": "private", "first_name": "Ann"}, "text": "hi"}}"#;
+        let update = update_of(&entry(&[("other", "x"), (FIELD, json)])).unwrap();
+        assert_eq!(update.id.0, 7);
+        assert!(matches!(
+            update.kind,
+            teloxide::types::UpdateKind::Message(_)
+        ));
+        assert!(update_of(&entry(&[("other", json)])).is_none());
+        assert!(update_of(&entry(&[(FIELD, "not json")])).is_none());
+    }
+
+    #[tokio::test]
+    async fn bad_urls_are_refused_without_retrying() {
+        for url in ["http://localhost", "not a url"] {
+            let stream = RedisStream::new(url, "updates");
+            assert!(matches!(
+                stream.connect().await,
+                Err(StreamError::InvalidUrl(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use crate::reporting;
+use crate::traffic;
+use futures::FutureExt;
+use once_cell::sync::Lazy;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::{AbortHandle, JoinHandle};
+
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+static TASKS: Lazy<Mutex<Vec<Task>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+struct Task {
+    id: u64,
+    plugin: &'static str,
+    restarts: Arc<AtomicU32>,
+    handle: JoinHandle<()>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SupervisedTask {
+    pub id: u64,
+    pub plugin: &'static str,
+    pub restarts: u32,
+}
+
+// Runs `task()` in the background for a plugin. When it panics, the panic goes to the
+// error reporter and a fresh `task()` starts after a backoff that doubles from 1s up
+// to 5 minutes, and resets once a run lasts longer than that. A task that returns is
+// done and isn't restarted. Requests it makes count as the plugin's traffic.
+pub fn spawn_supervised<F, Fut>(plugin: &'static str, task: F) -> AbortHandle
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let restarts = Arc::new(AtomicU32::new(0));
+    let counter = restarts.clone();
+    let handle = tokio::spawn(async move {
+        let mut backoff = FIRST_BACKOFF;
+        loop {
+            let started = Instant::now();
+            let run = traffic::scope(plugin, AssertUnwindSafe(task()).catch_unwind()).await;
+            let Err(payload) = run else {
+                break;
+            };
+            reporting::report_task_panic(plugin, payload).await;
+            if started.elapsed() > MAX_BACKOFF {
+                backoff = FIRST_BACKOFF;
+            }
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+        TASKS.lock().unwrap().retain(|task| task.id != id);
+    });
+    let abort = handle.abort_handle();
+    let mut tasks = TASKS.lock().unwrap();
+    // A task that finished before getting here already tried to remove itself.
+    if !handle.is_finished() {
+        tasks.push(Task {
+            id,
+            plugin,
+            restarts,
+            handle,
+        });
+    }
+    abort
+}
+
+pub fn tasks() -> Vec<SupervisedTask> {
+    TASKS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|task| SupervisedTask {
+            id: task.id,
+            plugin: task.plugin,
+            restarts: task.restarts.load(Ordering::Relaxed),
+        })
+        .collect()
+}
+
+// Stops every supervised task of a plugin, for when it's disabled or reloaded.
+pub fn stop(plugin: &str) {
+    let mut tasks = TASKS.lock().unwrap();
+    tasks.retain(|task| {
+        let stopping = task.plugin == plugin;
+        if stopping {
+            task.handle.abort();
+        }
+        !stopping
+    });
+}
+
+// Cancels every supervised task and waits for them to unwind. The dispatcher calls
+// this when it stops, before plugins shut down.
+pub async fn shutdown() {
+    let tasks = std::mem::take(&mut *TASKS.lock().unwrap());
+    for task in &tasks {
+        task.handle.abort();
+    }
+    for task in tasks {
+        let _ = task.handle.await;
+    }
+}
@@ -0,0 +1,266 @@
+use crate::context::PluginContext;
+use crate::response::SendMessageParams;
+use crate::settings;
+use crate::storage::StorageError;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::RwLock;
+use teloxide::types::{ChatId, InlineKeyboardButton, InlineKeyboardMarkup, ParseMode};
+use teloxide::utils::{html, markdown};
+
+const OVERRIDE_PREFIX: &str = "template:";
+
+static TEMPLATES: Lazy<RwLock<HashMap<String, Template>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Template {
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parse_mode: Option<ParseMode>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub buttons: Vec<Vec<TemplateButton>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TemplateButton {
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub callback: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum TemplateError {
+    NotFound(String),
+    InvalidUrl(String),
+    Storage(StorageError),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::NotFound(name) => write!(f, "no template named `{}`", name),
+            TemplateError::InvalidUrl(url) => write!(f, "invalid button url `{}`", url),
+            TemplateError::Storage(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+impl From<StorageError> for TemplateError {
+    fn from(err: StorageError) -> Self {
+        TemplateError::Storage(err)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TemplateConfig {
+    Text(String),
+    Full(Template),
+}
+
+impl Template {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn parse_mode(mut self, mode: ParseMode) -> Self {
+        self.parse_mode = Some(mode);
+        self
+    }
+
+    pub fn row(mut self, buttons: impl IntoIterator<Item = TemplateButton>) -> Self {
+        self.buttons.push(buttons.into_iter().collect());
+        self
+    }
+
+    pub fn render<K, V>(
+        &self,
+        vars: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<SendMessageParams, TemplateError>
+    where
+        K: AsRef<str>,
+        V: ToString,
+    {
+        let vars: Vec<(String, String)> = vars
+            .into_iter()
+            .map(|(key, value)| (key.as_ref().to_string(), value.to_string()))
+            .collect();
+        let escaped: Vec<(String, String)> = vars
+            .iter()
+            .map(|(key, value)| {
+                let value = match self.parse_mode {
+                    Some(ParseMode::Html) => html::escape(value),
+                    Some(ParseMode::MarkdownV2) => markdown::escape(value),
+                    _ => value.clone(),
+                };
+                (key.clone(), value)
+            })
+            .collect();
+
+        let mut params = SendMessageParams::new(fill(&self.text, &escaped));
+        if let Some(mode) = self.parse_mode {
+            params = params.parse_mode(mode);
+        }
+        if !self.buttons.is_empty() {
+            let mut rows = Vec::new();
+            for row in &self.buttons {
+                let mut buttons = Vec::new();
+                for button in row {
+                    buttons.push(button.render(&vars)?);
+                }
+                rows.push(buttons);
+            }
+            params = params.reply_markup(InlineKeyboardMarkup::new(rows));
+        }
+        Ok(params)
+    }
+}
+
+impl From<&str> for Template {
+    fn from(text: &str) -> Self {
+        Template::new(text)
+    }
+}
+
+impl From<String> for Template {
+    fn from(text: String) -> Self {
+        Template::new(text)
+    }
+}
+
+impl TemplateButton {
+    pub fn callback(text: impl Into<String>, data: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            callback: Some(data.into()),
+            url: None,
+        }
+    }
+
+    pub fn url(text: impl Into<String>, url: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            callback: None,
+            url: Some(url.into()),
+        }
+    }
+
+    fn render(&self, vars: &[(String, String)]) -> Result<InlineKeyboardButton, TemplateError> {
+        let text = fill(&self.text, vars);
+        match (&self.url, &self.callback) {
+            (Some(url), _) => {
+                let url = fill(url, vars);
+                match url.parse() {
+                    Ok(parsed) => Ok(InlineKeyboardButton::url(text, parsed)),
+                    Err(_) => Err(TemplateError::InvalidUrl(url)),
+                }
+            }
+            (None, Some(data)) => Ok(InlineKeyboardButton::callback(text, fill(data, vars))),
+            (None, None) => Ok(InlineKeyboardButton::callback(text.clone(), text)),
+        }
+    }
+}
+
+fn fill(text: &str, vars: &[(String, String)]) -> String {
+    let mut filled = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        filled.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let value = after.find('}').and_then(|end| {
+            let (_, value) = vars.iter().find(|(key, _)| key == &after[..end])?;
+            Some((end, value))
+        });
+        match value {
+            Some((end, value)) => {
+                filled.push_str(value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                filled.push('{');
+                rest = after;
+            }
+        }
+    }
+    filled.push_str(rest);
+    filled
+}
+
+pub fn define(name: impl Into<String>, template: impl Into<Template>) {
+    TEMPLATES
+        .write()
+        .unwrap()
+        .insert(name.into(), template.into());
+}
+
+pub fn load(json: &str) -> Result<(), serde_json::Error> {
+    let config: HashMap<String, TemplateConfig> = serde_json::from_str(json)?;
+    let mut templates = TEMPLATES.write().unwrap();
+    for (name, template) in config {
+        let template = match template {
+            TemplateConfig::Text(text) => Template::new(text),
+            TemplateConfig::Full(template) => template,
+        };
+        templates.insert(name, template);
+    }
+    Ok(())
+}
+
+pub fn template(name: &str) -> Option<Template> {
+    TEMPLATES.read().unwrap().get(name).cloned()
+}
+
+pub fn names() -> Vec<String> {
+    let mut names: Vec<_> = TEMPLATES.read().unwrap().keys().cloned().collect();
+    names.sort();
+    names
+}
+
+fn override_name(name: &str) -> String {
+    format!("{}{}", OVERRIDE_PREFIX, name)
+}
+
+pub async fn set_override(
+    chat: ChatId,
+    name: &str,
+    template: &Template,
+) -> Result<(), StorageError> {
+    settings::set(chat, &override_name(name), template).await
+}
+
+pub async fn remove_override(chat: ChatId, name: &str) -> Result<(), StorageError> {
+    settings::remove(chat, &override_name(name)).await
+}
+
+pub async fn resolve(chat: Option<ChatId>, name: &str) -> Result<Template, TemplateError> {
+    if let Some(chat) = chat {
+        if let Some(template) = settings::get(chat, &override_name(name)).await? {
+            return Ok(template);
+        }
+    }
+    template(name).ok_or_else(|| TemplateError::NotFound(name.to_string()))
+}
+
+impl PluginContext {
+    pub async fn render_template<K, V>(
+        &self,
+        name: &str,
+        vars: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<SendMessageParams, TemplateError>
+    where
+        K: AsRef<str>,
+        V: ToString,
+    {
+        resolve(self.chat_id(), name).await?.render(vars)
+    }
+}
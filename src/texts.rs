@@ -0,0 +1,121 @@
+use crate::context::PluginContext;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+static DEFAULT: Lazy<RwLock<Arc<FrameworkTexts>>> =
+    Lazy::new(|| RwLock::new(Arc::new(FrameworkTexts::default())));
+static BY_LANG: Lazy<RwLock<HashMap<String, Arc<FrameworkTexts>>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+// Everything the framework says on its own. Placeholders in braces are filled in
+// when the text is sent.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FrameworkTexts {
+    pub groups_only: String,
+    pub anonymous_admin: String,
+    pub on_behalf_of_channel: String,
+    pub admins_only: String,
+    // `{rights}`
+    pub missing_rights: String,
+    // `{rights}`
+    pub bot_missing_rights: String,
+    pub requires_reply: String,
+    // `{role}`
+    pub missing_role: String,
+    // `{remaining}`, in seconds. A plugin's own `cooldown_message` wins over this.
+    pub cooldown: String,
+    pub rate_limited: String,
+    pub permission_denied: String,
+    pub not_your_button: String,
+    pub button_expired: String,
+    pub button_invalid: String,
+    pub conversation_expired: String,
+    // Sent to the buyer when a checkout's invoice payload doesn't decode.
+    pub invalid_checkout: String,
+    pub cancelled: String,
+    pub nothing_to_cancel: String,
+    // `{command}` and `{subcommands}`, the latter joined with `|`.
+    pub usage: String,
+    // Replaces the text passed to `registry::set_maintenance` when set, with the same
+    // `{name}`, `{since}` and `{minutes}` placeholders.
+    pub maintenance: Option<String>,
+}
+
+impl Default for FrameworkTexts {
+    fn default() -> Self {
+        Self {
+            groups_only: String::from("This command can only be used in groups."),
+            anonymous_admin: String::from(
+                "Anonymous admins can't use this command. Turn off \"Remain anonymous\" and try again.",
+            ),
+            on_behalf_of_channel: String::from(
+                "This command can't be used on behalf of a channel.",
+            ),
+            admins_only: String::from("Only chat admins can use this command."),
+            missing_rights: String::from("You need these admin rights: {rights}"),
+            bot_missing_rights: String::from(
+                "I can't do that here. Promote me with these rights first: {rights}",
+            ),
+            requires_reply: String::from("Reply to a message to use this command."),
+            missing_role: String::from("You need the {role} role to use this."),
+            cooldown: String::from("Slow down! Try again in {remaining}s."),
+            rate_limited: String::from("Slow down! Try again later."),
+            permission_denied: String::from("You're not allowed to do that."),
+            not_your_button: String::from("This button isn't for you."),
+            button_expired: String::from("This button has expired."),
+            button_invalid: String::from("This button is no longer valid."),
+            conversation_expired: String::from(
+                "This conversation timed out. Start again whenever you're ready.",
+            ),
+            invalid_checkout: String::from(
+                "This order can't be processed anymore. Please start a new one.",
+            ),
+            cancelled: String::from("Cancelled."),
+            nothing_to_cancel: String::from("There's nothing to cancel."),
+            usage: String::from("Usage: {command} <{subcommands}>"),
+            maintenance: None,
+        }
+    }
+}
+
+pub fn fill(template: &str, values: &[(&str, &str)]) -> String {
+    values
+        .iter()
+        .fold(template.to_string(), |text, (key, value)| {
+            text.replace(&format!("{{{}}}", key), value)
+        })
+}
+
+pub fn set_texts(texts: FrameworkTexts) {
+    *DEFAULT.write().unwrap() = Arc::new(texts);
+}
+
+// Used for users whose Telegram app is set to `lang`; only the primary tag counts, so
+// `pt` covers `pt-br` too.
+pub fn set_texts_for(lang: &str, texts: FrameworkTexts) {
+    BY_LANG
+        .write()
+        .unwrap()
+        .insert(lang.to_ascii_lowercase(), Arc::new(texts));
+}
+
+pub fn texts() -> Arc<FrameworkTexts> {
+    DEFAULT.read().unwrap().clone()
+}
+
+pub fn texts_for(lang: Option<&str>) -> Arc<FrameworkTexts> {
+    let primary = lang
+        .and_then(|code| code.split(['-', '_']).next())
+        .map(str::to_ascii_lowercase);
+    primary
+        .and_then(|code| BY_LANG.read().unwrap().get(&code).cloned())
+        .unwrap_or_else(texts)
+}
+
+impl PluginContext {
+    // The texts for the sender's language.
+    pub fn framework_texts(&self) -> Arc<FrameworkTexts> {
+        texts_for(self.sender().and_then(|u| u.language_code.as_deref()))
+    }
+}
@@ -0,0 +1,293 @@
+use crate::context::PluginContext;
+use crate::traffic;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use teloxide::prelude::*;
+use teloxide::types::{
+    ChatKind, ChatPublic, CustomEmojiId, ForumTopic, PublicChatKind, PublicChatSupergroup, Rgb,
+    ThreadId,
+};
+
+// Messages outside any topic of a forum belong to "General", which has no thread id.
+pub const GENERAL: &str = "General";
+
+static TOPICS: Lazy<RwLock<HashMap<ChatId, HashMap<ThreadId, Topic>>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Topic {
+    pub thread_id: ThreadId,
+    pub name: String,
+    pub icon_color: Option<Rgb>,
+    pub icon_custom_emoji_id: Option<CustomEmojiId>,
+    pub closed: bool,
+}
+
+impl From<ForumTopic> for Topic {
+    fn from(topic: ForumTopic) -> Self {
+        Self {
+            thread_id: topic.thread_id,
+            name: topic.name,
+            icon_color: Some(topic.icon_color),
+            icon_custom_emoji_id: topic.icon_custom_emoji_id,
+            closed: false,
+        }
+    }
+}
+
+fn learn(chat: ChatId, topic: Topic) {
+    TOPICS
+        .write()
+        .unwrap()
+        .entry(chat)
+        .or_default()
+        .insert(topic.thread_id, topic);
+}
+
+fn update(chat: ChatId, thread: ThreadId, change: impl FnOnce(&mut Topic)) {
+    if let Some(topic) = TOPICS
+        .write()
+        .unwrap()
+        .get_mut(&chat)
+        .and_then(|topics| topics.get_mut(&thread))
+    {
+        change(topic);
+    }
+}
+
+// Telegram only tells bots a topic's name when it's created or renamed, but every
+// message in a topic quotes the creation message, so names are picked up from there.
+pub(crate) fn track(msg: &Message) {
+    let Some(thread) = msg.thread_id.filter(|_| msg.is_topic_message) else {
+        return;
+    };
+    let chat = msg.chat.id;
+    let created = msg.forum_topic_created().or_else(|| {
+        msg.reply_to_message()
+            .filter(|root| root.id == thread.0)
+            .and_then(|root| root.forum_topic_created())
+    });
+    if let Some(created) = created {
+        let known = topic(chat, thread).is_some_and(|known| known.name == created.name);
+        if !known {
+            learn(
+                chat,
+                Topic {
+                    thread_id: thread,
+                    name: created.name.clone(),
+                    icon_color: Some(created.icon_color),
+                    icon_custom_emoji_id: created.icon_custom_emoji_id.clone(),
+                    closed: false,
+                },
+            );
+        }
+    }
+    if let Some(edited) = msg.forum_topic_edited() {
+        update(chat, thread, |topic| {
+            if let Some(name) = &edited.name {
+                topic.name = name.clone();
+            }
+            if edited.icon_custom_emoji_id.is_some() {
+                topic.icon_custom_emoji_id = edited.icon_custom_emoji_id.clone();
+            }
+        });
+    }
+    if msg.forum_topic_closed().is_some() {
+        update(chat, thread, |topic| topic.closed = true);
+    }
+    if msg.forum_topic_reopened().is_some() {
+        update(chat, thread, |topic| topic.closed = false);
+    }
+}
+
+pub(crate) fn migrate(from: ChatId, to: ChatId) {
+    let mut chats = TOPICS.write().unwrap();
+    if let Some(topics) = chats.remove(&from) {
+        chats.insert(to, topics);
+    }
+}
+
+pub fn topic(chat: ChatId, thread: ThreadId) -> Option<Topic> {
+    TOPICS.read().unwrap().get(&chat)?.get(&thread).cloned()
+}
+
+pub fn topics(chat: ChatId) -> Vec<Topic> {
+    let mut topics: Vec<Topic> = TOPICS
+        .read()
+        .unwrap()
+        .get(&chat)
+        .map(|topics| topics.values().cloned().collect())
+        .unwrap_or_default();
+    topics.sort_by_key(|topic| topic.thread_id.0 .0);
+    topics
+}
+
+// Names are compared ignoring case. If several topics share a name, the oldest wins.
+pub fn find(chat: ChatId, name: &str) -> Option<Topic> {
+    topics(chat)
+        .into_iter()
+        .find(|topic| topic.name.eq_ignore_ascii_case(name))
+}
+
+pub fn is_forum(chat: &teloxide::types::Chat) -> bool {
+    matches!(
+        &chat.kind,
+        ChatKind::Public(ChatPublic {
+            kind: PublicChatKind::Supergroup(PublicChatSupergroup { is_forum: true, .. }),
+            ..
+        })
+    )
+}
+
+// The name of the topic a message was sent in: `General` for forum messages outside
+// any topic, `None` outside forums or when the topic hasn't been seen yet.
+pub fn name_of(msg: &Message) -> Option<String> {
+    if !is_forum(&msg.chat) {
+        return None;
+    }
+    match msg.thread_id.filter(|_| msg.is_topic_message) {
+        Some(thread) => topic(msg.chat.id, thread).map(|topic| topic.name),
+        None => Some(GENERAL.to_string()),
+    }
+}
+
+pub async fn create(
+    bot: &Bot,
+    chat: ChatId,
+    name: impl Into<String>,
+) -> Result<Topic, teloxide::RequestError> {
+    let topic: Topic = traffic::send(bot.create_forum_topic(chat, name))
+        .await?
+        .into();
+    learn(chat, topic.clone());
+    Ok(topic)
+}
+
+// The topic with this name, creating it when there's none yet; handy for a topic
+// per customer or per ticket.
+pub async fn ensure(bot: &Bot, chat: ChatId, name: &str) -> Result<Topic, teloxide::RequestError> {
+    match find(chat, name) {
+        Some(topic) => Ok(topic),
+        None => create(bot, chat, name).await,
+    }
+}
+
+pub async fn rename(
+    bot: &Bot,
+    chat: ChatId,
+    thread: ThreadId,
+    name: impl Into<String>,
+) -> Result<(), teloxide::RequestError> {
+    let name = name.into();
+    traffic::send(bot.edit_forum_topic(chat, thread).name(name.clone())).await?;
+    update(chat, thread, |topic| topic.name = name);
+    Ok(())
+}
+
+pub async fn set_icon(
+    bot: &Bot,
+    chat: ChatId,
+    thread: ThreadId,
+    emoji: CustomEmojiId,
+) -> Result<(), teloxide::RequestError> {
+    traffic::send(
+        bot.edit_forum_topic(chat, thread)
+            .icon_custom_emoji_id(emoji.clone()),
+    )
+    .await?;
+    update(chat, thread, |topic| {
+        topic.icon_custom_emoji_id = Some(emoji)
+    });
+    Ok(())
+}
+
+pub async fn close(
+    bot: &Bot,
+    chat: ChatId,
+    thread: ThreadId,
+) -> Result<(), teloxide::RequestError> {
+    traffic::send(bot.close_forum_topic(chat, thread)).await?;
+    update(chat, thread, |topic| topic.closed = true);
+    Ok(())
+}
+
+pub async fn reopen(
+    bot: &Bot,
+    chat: ChatId,
+    thread: ThreadId,
+) -> Result<(), teloxide::RequestError> {
+    traffic::send(bot.reopen_forum_topic(chat, thread)).await?;
+    update(chat, thread, |topic| topic.closed = false);
+    Ok(())
+}
+
+pub async fn delete(
+    bot: &Bot,
+    chat: ChatId,
+    thread: ThreadId,
+) -> Result<(), teloxide::RequestError> {
+    traffic::send(bot.delete_forum_topic(chat, thread)).await?;
+    if let Some(topics) = TOPICS.write().unwrap().get_mut(&chat) {
+        topics.remove(&thread);
+    }
+    Ok(())
+}
+
+pub async fn send(
+    bot: &Bot,
+    chat: ChatId,
+    thread: ThreadId,
+    text: impl Into<String>,
+) -> Result<Message, teloxide::RequestError> {
+    traffic::send(bot.send_message(chat, text).message_thread_id(thread)).await
+}
+
+// When the topic filter runs on a button press, the topic is the one the button's
+// message is in.
+pub(crate) fn message_of(ctx: &PluginContext) -> Option<&Message> {
+    ctx.message.as_ref().or_else(|| {
+        ctx.callback_query
+            .as_ref()
+            .and_then(|query| query.regular_message())
+    })
+}
+
+impl PluginContext {
+    pub fn thread_id(&self) -> Option<ThreadId> {
+        let msg = message_of(self)?;
+        msg.thread_id.filter(|_| msg.is_topic_message)
+    }
+
+    pub fn topic(&self) -> Option<Topic> {
+        topic(self.chat_id()?, self.thread_id()?)
+    }
+
+    pub fn topic_name(&self) -> Option<String> {
+        name_of(message_of(self)?)
+    }
+
+    // Sends into the topic the update came from, or the chat itself outside topics.
+    pub async fn send_to_topic(
+        &self,
+        text: impl Into<String>,
+    ) -> Result<Message, teloxide::RequestError> {
+        let chat = self.chat_id().ok_or(teloxide::RequestError::Api(
+            teloxide::ApiError::ChatNotFound,
+        ))?;
+        let request = self.bot.send_message(chat, text);
+        match self.thread_id() {
+            Some(thread) => self.call(request.message_thread_id(thread)).await,
+            None => self.call(request).await,
+        }
+    }
+
+    pub async fn close_topic(&self) -> Result<(), teloxide::RequestError> {
+        let (Some(chat), Some(thread)) = (self.chat_id(), self.thread_id()) else {
+            return Ok(());
+        };
+        self.call(self.bot.close_forum_topic(chat, thread)).await?;
+        update(chat, thread, |topic| topic.closed = true);
+        Ok(())
+    }
+}
@@ -0,0 +1,144 @@
+use crate::context::PluginContext;
+use crate::pool::{self, Poolable};
+#[cfg(feature = "retry")]
+use crate::retry;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::{Arc, Mutex, RwLock};
+use teloxide::requests::{HasPayload, Output, Payload};
+use teloxide::RequestError;
+
+tokio::task_local! {
+    static PLUGIN: &'static str;
+}
+
+type RequestHook = Arc<dyn Fn(Option<&'static str>, &'static str) + Send + Sync>;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TrafficStats {
+    pub requests: u64,
+    pub by_method: BTreeMap<&'static str, u64>,
+}
+
+static STATS: Lazy<Mutex<HashMap<Option<&'static str>, TrafficStats>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+static HOOK: RwLock<Option<RequestHook>> = RwLock::new(None);
+
+pub fn current_plugin() -> Option<&'static str> {
+    PLUGIN.try_with(|plugin| *plugin).ok()
+}
+
+pub(crate) async fn scope<F: Future>(plugin: &'static str, future: F) -> F::Output {
+    PLUGIN.scope(plugin, future).await
+}
+
+pub fn set_request_hook(hook: impl Fn(Option<&'static str>, &'static str) + Send + Sync + 'static) {
+    *HOOK.write().unwrap() = Some(Arc::new(hook));
+}
+
+pub fn record(method: &'static str) {
+    record_for(current_plugin(), method);
+}
+
+pub(crate) fn record_for(plugin: Option<&'static str>, method: &'static str) {
+    {
+        let mut stats = STATS.lock().unwrap();
+        let entry = stats.entry(plugin).or_default();
+        entry.requests += 1;
+        *entry.by_method.entry(method).or_default() += 1;
+    }
+    if let Some(hook) = HOOK.read().unwrap().clone() {
+        hook(plugin, method);
+    }
+}
+
+pub fn stats(plugin: &'static str) -> TrafficStats {
+    STATS
+        .lock()
+        .unwrap()
+        .get(&Some(plugin))
+        .cloned()
+        .unwrap_or_default()
+}
+
+pub fn unattributed() -> TrafficStats {
+    STATS
+        .lock()
+        .unwrap()
+        .get(&None)
+        .cloned()
+        .unwrap_or_default()
+}
+
+pub fn all() -> Vec<(&'static str, TrafficStats)> {
+    let mut all: Vec<_> = STATS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|(plugin, stats)| Some(((*plugin)?, stats.clone())))
+        .collect();
+    all.sort_by_key(|(plugin, _)| *plugin);
+    all
+}
+
+pub fn reset() {
+    STATS.lock().unwrap().clear();
+}
+
+pub(crate) async fn send<R>(request: R) -> Result<Output<R>, RequestError>
+where
+    R: Poolable,
+    R::Payload: Serialize,
+{
+    execute(current_plugin(), request).await
+}
+
+#[cfg(not(feature = "retry"))]
+async fn execute<R>(plugin: Option<&'static str>, request: R) -> Result<Output<R>, RequestError>
+where
+    R: Poolable,
+    R::Payload: Serialize,
+{
+    record_for(plugin, <<R as HasPayload>::Payload as Payload>::NAME);
+    match pool::route(&request) {
+        Some(pooled) => pooled.send().await,
+        None => request.send().await,
+    }
+}
+
+#[cfg(feature = "retry")]
+async fn execute<R>(plugin: Option<&'static str>, request: R) -> Result<Output<R>, RequestError>
+where
+    R: Poolable,
+    R::Payload: Serialize,
+{
+    let mut attempt = 0;
+    loop {
+        record_for(plugin, <<R as HasPayload>::Payload as Payload>::NAME);
+        let sent = match pool::route(&request) {
+            Some(pooled) => pooled.send().await,
+            None => request.send_ref().await,
+        };
+        match sent {
+            Err(RequestError::RetryAfter(wait))
+                if retry::should_retry(attempt, wait.duration()) =>
+            {
+                attempt += 1;
+                tokio::time::sleep(wait.duration()).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+impl PluginContext {
+    pub async fn call<R>(&self, request: R) -> Result<Output<R>, RequestError>
+    where
+        R: Poolable,
+        R::Payload: Serialize,
+    {
+        execute(current_plugin().or(self.plugin.map(|p| p.name)), request).await
+    }
+}
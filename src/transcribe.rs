@@ -0,0 +1,155 @@
+use crate::context::PluginContext;
+use crate::extract::FromContext;
+use crate::files::{self, FileError};
+use crate::reporting::BoxError;
+use futures::future::BoxFuture;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use teloxide::types::{FileMeta, Message};
+
+static TRANSCRIBER: RwLock<Option<Arc<dyn Transcriber>>> = RwLock::new(None);
+static MAX_DURATION: RwLock<Option<Duration>> = RwLock::new(None);
+
+// A downloaded voice note, audio file or video note.
+#[derive(Clone, Debug)]
+pub struct Audio {
+    pub bytes: Vec<u8>,
+    pub mime_type: Option<String>,
+    pub duration: Duration,
+}
+
+pub trait Transcriber: Send + Sync {
+    fn transcribe(&self, audio: Audio) -> BoxFuture<'static, Result<String, BoxError>>;
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Transcript {
+    pub text: String,
+}
+
+#[derive(Debug)]
+pub enum TranscribeError {
+    NoTranscriber,
+    NoAudio,
+    TooLong(Duration),
+    Download(FileError),
+    Failed(BoxError),
+}
+
+impl fmt::Display for TranscribeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TranscribeError::NoTranscriber => write!(f, "no transcriber is configured"),
+            TranscribeError::NoAudio => write!(f, "the message has nothing to transcribe"),
+            TranscribeError::TooLong(duration) => {
+                write!(
+                    f,
+                    "{}s of audio is over the transcription limit",
+                    duration.as_secs()
+                )
+            }
+            TranscribeError::Download(err) => write!(f, "{}", err),
+            TranscribeError::Failed(err) => write!(f, "transcription failed: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for TranscribeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TranscribeError::Download(err) => Some(err),
+            TranscribeError::Failed(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<FileError> for TranscribeError {
+    fn from(err: FileError) -> Self {
+        TranscribeError::Download(err)
+    }
+}
+
+pub fn set_transcriber(transcriber: impl Transcriber + 'static) {
+    *TRANSCRIBER.write().unwrap() = Some(Arc::new(transcriber));
+}
+
+pub(crate) fn has_transcriber() -> bool {
+    TRANSCRIBER.read().unwrap().is_some()
+}
+
+// Longer recordings aren't downloaded or transcribed. No limit by default.
+pub fn set_max_duration(max: Option<Duration>) {
+    *MAX_DURATION.write().unwrap() = max;
+}
+
+fn spoken(msg: &Message) -> Option<(&FileMeta, Option<String>, Duration)> {
+    if let Some(voice) = msg.voice() {
+        let mime = voice.mime_type.as_ref().map(ToString::to_string);
+        return Some((&voice.file, mime, voice.duration.duration()));
+    }
+    if let Some(audio) = msg.audio() {
+        let mime = audio.mime_type.as_ref().map(ToString::to_string);
+        return Some((&audio.file, mime, audio.duration.duration()));
+    }
+    let note = msg.video_note()?;
+    Some((
+        &note.file,
+        Some(String::from("video/mp4")),
+        note.duration.duration(),
+    ))
+}
+
+pub async fn transcribe(ctx: &PluginContext) -> Result<Transcript, TranscribeError> {
+    let transcriber = TRANSCRIBER
+        .read()
+        .unwrap()
+        .clone()
+        .ok_or(TranscribeError::NoTranscriber)?;
+    let msg = ctx.message.as_ref().ok_or(TranscribeError::NoAudio)?;
+    let (file, mime_type, duration) = spoken(msg).ok_or(TranscribeError::NoAudio)?;
+    if MAX_DURATION
+        .read()
+        .unwrap()
+        .is_some_and(|max| duration > max)
+    {
+        return Err(TranscribeError::TooLong(duration));
+    }
+    let bytes = files::download(&ctx.bot, file.id.clone()).await?;
+    let audio = Audio {
+        bytes,
+        mime_type,
+        duration,
+    };
+    let text = transcriber
+        .transcribe(audio)
+        .await
+        .map_err(TranscribeError::Failed)?;
+    Ok(Transcript {
+        text: text.trim().to_string(),
+    })
+}
+
+// Runs before handlers of `transcribe = true` plugins. On failure the handler still
+// runs, just without a transcript, and the error goes to the error reporter.
+pub(crate) async fn prepare(ctx: &PluginContext) -> Result<(), TranscribeError> {
+    if ctx.extensions.contains::<Transcript>() {
+        return Ok(());
+    }
+    let transcript = transcribe(ctx).await?;
+    ctx.extensions.insert(transcript);
+    Ok(())
+}
+
+impl FromContext for Transcript {
+    fn from_context(ctx: &PluginContext) -> Option<Self> {
+        ctx.transcript()
+    }
+}
+
+impl PluginContext {
+    pub fn transcript(&self) -> Option<Transcript> {
+        self.extensions.get()
+    }
+}
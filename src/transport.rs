@@ -0,0 +1,175 @@
+use crate::dispatcher::context_of;
+use crate::registry;
+use crate::reporting;
+use crate::source::{EventSource, IncomingEvent};
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+use futures::{FutureExt, StreamExt};
+use std::fmt;
+use std::io;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use teloxide::prelude::*;
+use tokio::sync::mpsc;
+
+const MIRROR_BUFFER: usize = 1024;
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+static MIRRORS: RwLock<Vec<mpsc::Sender<Update>>> = RwLock::new(Vec::new());
+
+#[derive(Debug)]
+pub enum TransportError {
+    Io(io::Error),
+    Http(reqwest::Error),
+    InvalidUrl(String),
+    // The broker or proxy turned the request down.
+    Remote(String),
+    Protocol(&'static str),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransportError::Io(err) => write!(f, "transport connection failed: {}", err),
+            TransportError::Http(err) => write!(f, "transport request failed: {}", err),
+            TransportError::InvalidUrl(url) => write!(f, "`{}` isn't a valid transport URL", url),
+            TransportError::Remote(message) => write!(f, "transport error: {}", message),
+            TransportError::Protocol(what) => write!(f, "unexpected reply: {}", what),
+            TransportError::Json(err) => write!(f, "update isn't valid JSON: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for TransportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TransportError::Io(err) => Some(err),
+            TransportError::Http(err) => Some(err),
+            TransportError::Json(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for TransportError {
+    fn from(err: io::Error) -> Self {
+        TransportError::Io(err)
+    }
+}
+
+impl From<reqwest::Error> for TransportError {
+    fn from(err: reqwest::Error) -> Self {
+        TransportError::Http(err)
+    }
+}
+
+impl From<serde_json::Error> for TransportError {
+    fn from(err: serde_json::Error) -> Self {
+        TransportError::Json(err)
+    }
+}
+
+// Somewhere updates can be sent to and read back from: a message broker, a log, a
+// queue. Implementations reconnect on their own; `subscribe` keeps yielding after an
+// error.
+pub trait UpdateTransport: Send + Sync {
+    fn publish<'a>(&'a self, update: &'a Update) -> BoxFuture<'a, Result<(), TransportError>>;
+
+    // Updates as they're published, until the stream is dropped. Where the transport
+    // keeps a log, like Kafka, a subscriber picks up where its group left off.
+    fn subscribe(&self) -> BoxStream<'static, Result<Update, TransportError>>;
+}
+
+impl<T: UpdateTransport + ?Sized> UpdateTransport for Arc<T> {
+    fn publish<'a>(&'a self, update: &'a Update) -> BoxFuture<'a, Result<(), TransportError>> {
+        (**self).publish(update)
+    }
+
+    fn subscribe(&self) -> BoxStream<'static, Result<Update, TransportError>> {
+        (**self).subscribe()
+    }
+}
+
+// Copies every update the dispatcher receives from Telegram to the transport, in
+// order, next to handling it here. A slow or unreachable transport doesn't hold
+// polling up: once 1024 updates are waiting for it, newer ones skip it.
+pub(crate) fn start_mirror(transport: Arc<dyn UpdateTransport>) {
+    let (tx, mut rx) = mpsc::channel::<Update>(MIRROR_BUFFER);
+    MIRRORS.write().unwrap().push(tx);
+    tokio::spawn(async move {
+        while let Some(update) = rx.recv().await {
+            let mut backoff = FIRST_BACKOFF;
+            while let Err(err) = transport.publish(&update).await {
+                if matches!(err, TransportError::Json(_) | TransportError::InvalidUrl(_)) {
+                    break;
+                }
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    });
+}
+
+pub(crate) fn mirror(update: &Update) {
+    let mirrors = MIRRORS.read().unwrap();
+    for mirror in mirrors.iter() {
+        let _ = mirror.try_send(update.clone());
+    }
+}
+
+// Feeds updates from the transport into the dispatcher, for `PluginDispatcher::source`.
+// Updates that fail to arrive or decode are skipped.
+pub fn source(transport: impl UpdateTransport) -> impl EventSource {
+    TransportSource(transport.subscribe())
+}
+
+struct TransportSource(BoxStream<'static, Result<Update, TransportError>>);
+
+impl EventSource for TransportSource {
+    fn next(&mut self) -> BoxFuture<'_, Option<IncomingEvent>> {
+        Box::pin(async move {
+            loop {
+                match self.0.next().await? {
+                    Ok(update) => return Some(IncomingEvent::from(update)),
+                    Err(_) => continue,
+                }
+            }
+        })
+    }
+}
+
+// Runs plugins for updates from the transport, one at a time and in order, without
+// polling Telegram; for processes that only replay or follow what another one
+// receives. Returns when the subscription ends.
+pub async fn consume(bot: Bot, transport: impl UpdateTransport) {
+    let mut updates = transport.subscribe();
+    while let Some(update) = updates.next().await {
+        let Some(ctx) = update
+            .ok()
+            .and_then(|update| context_of(bot.clone(), update))
+        else {
+            continue;
+        };
+        let run = AssertUnwindSafe(registry::dispatch(ctx))
+            .catch_unwind()
+            .await;
+        if let Err(payload) = run {
+            reporting::report_task_panic("transport", payload).await;
+        }
+    }
+}
+
+// Turns the receiving end of a channel into a subscription, for implementations that
+// read in a background task.
+#[cfg(any(feature = "nats", feature = "kafka"))]
+pub(crate) fn channel_stream(
+    rx: mpsc::Receiver<Result<Update, TransportError>>,
+) -> BoxStream<'static, Result<Update, TransportError>> {
+    futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|item| (item, rx))
+    })
+    .boxed()
+}
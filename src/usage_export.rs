@@ -0,0 +1,323 @@
+use crate::analytics;
+use crate::http;
+use crate::reporting::{self, ErrorKind};
+use crate::storage::StorageError;
+use crate::supervisor;
+use chrono::{Days, NaiveDate, Utc};
+use serde::Serialize;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::task::AbortHandle;
+use url::Url;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct UsageRow {
+    pub day: NaiveDate,
+    pub plugin: String,
+    pub runs: u64,
+    // Distinct chats and users that day.
+    pub chats: u64,
+    pub users: u64,
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    Storage(StorageError),
+    Io(io::Error),
+    Http(reqwest::Error),
+    Rejected(reqwest::StatusCode),
+    #[cfg(feature = "parquet")]
+    Parquet(parquet::errors::ParquetError),
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportError::Storage(err) => write!(f, "{}", err),
+            ExportError::Io(err) => write!(f, "couldn't write the export: {}", err),
+            ExportError::Http(err) => write!(f, "couldn't post the export: {}", err),
+            ExportError::Rejected(status) => write!(f, "the webhook answered {}", status),
+            #[cfg(feature = "parquet")]
+            ExportError::Parquet(err) => write!(f, "couldn't encode the export: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ExportError::Storage(err) => Some(err),
+            ExportError::Io(err) => Some(err),
+            ExportError::Http(err) => Some(err),
+            ExportError::Rejected(_) => None,
+            #[cfg(feature = "parquet")]
+            ExportError::Parquet(err) => Some(err),
+        }
+    }
+}
+
+impl From<StorageError> for ExportError {
+    fn from(err: StorageError) -> Self {
+        ExportError::Storage(err)
+    }
+}
+
+impl From<io::Error> for ExportError {
+    fn from(err: io::Error) -> Self {
+        ExportError::Io(err)
+    }
+}
+
+impl From<reqwest::Error> for ExportError {
+    fn from(err: reqwest::Error) -> Self {
+        ExportError::Http(err)
+    }
+}
+
+#[cfg(feature = "parquet")]
+impl From<parquet::errors::ParquetError> for ExportError {
+    fn from(err: parquet::errors::ParquetError) -> Self {
+        ExportError::Parquet(err)
+    }
+}
+
+// One row per plugin and day, oldest day first.
+pub async fn rows(days: u32) -> Result<Vec<UsageRow>, StorageError> {
+    let today = Utc::now().date_naive();
+    let mut rows = Vec::new();
+    for back in (0..days.max(1) as u64).rev() {
+        let Some(day) = today.checked_sub_days(Days::new(back)) else {
+            continue;
+        };
+        let mut plugins = analytics::commands(day).await?;
+        plugins.sort();
+        for plugin in plugins {
+            let usage = analytics::usage(day, &plugin).await?;
+            rows.push(UsageRow {
+                day,
+                plugin,
+                runs: usage.total,
+                chats: usage.chats.len() as u64,
+                users: usage.users.len() as u64,
+            });
+        }
+    }
+    Ok(rows)
+}
+
+pub fn to_csv(rows: &[UsageRow]) -> String {
+    let mut csv = String::from("day,plugin,runs,chats,users\n");
+    for row in rows {
+        csv.push_str(&format!(
+            "{},{},{},{},{}\n",
+            row.day,
+            csv_field(&row.plugin),
+            row.runs,
+            row.chats,
+            row.users
+        ));
+    }
+    csv
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// The same columns as the CSV, in one row group: `day` as a DATE, `plugin` as UTF-8
+// and the counts as INT64.
+#[cfg(feature = "parquet")]
+pub fn to_parquet(rows: &[UsageRow]) -> Result<Vec<u8>, parquet::errors::ParquetError> {
+    use parquet::data_type::{ByteArray, ByteArrayType, Int32Type, Int64Type};
+    use parquet::file::properties::WriterProperties;
+    use parquet::file::writer::SerializedFileWriter;
+    use std::sync::Arc;
+
+    let schema = parquet::schema::parser::parse_message_type(
+        "message usage {
+            REQUIRED INT32 day (DATE);
+            REQUIRED BYTE_ARRAY plugin (UTF8);
+            REQUIRED INT64 runs;
+            REQUIRED INT64 chats;
+            REQUIRED INT64 users;
+        }",
+    )?;
+    let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).expect("a valid date");
+    let days: Vec<i32> = rows
+        .iter()
+        .map(|row| (row.day - epoch).num_days() as i32)
+        .collect();
+    let plugins: Vec<ByteArray> = rows.iter().map(|row| row.plugin.as_str().into()).collect();
+    let count = |field: fn(&UsageRow) -> u64| -> Vec<i64> {
+        rows.iter()
+            .map(|row| field(row).min(i64::MAX as u64) as i64)
+            .collect()
+    };
+
+    let mut out = Vec::new();
+    let mut writer = SerializedFileWriter::new(
+        &mut out,
+        Arc::new(schema),
+        Arc::new(WriterProperties::builder().build()),
+    )?;
+    let mut group = writer.next_row_group()?;
+    if let Some(mut column) = group.next_column()? {
+        column.typed::<Int32Type>().write_batch(&days, None, None)?;
+        column.close()?;
+    }
+    if let Some(mut column) = group.next_column()? {
+        column
+            .typed::<ByteArrayType>()
+            .write_batch(&plugins, None, None)?;
+        column.close()?;
+    }
+    for values in [
+        count(|row| row.runs),
+        count(|row| row.chats),
+        count(|row| row.users),
+    ] {
+        if let Some(mut column) = group.next_column()? {
+            column
+                .typed::<Int64Type>()
+                .write_batch(&values, None, None)?;
+            column.close()?;
+        }
+    }
+    group.close()?;
+    writer.close()?;
+    Ok(out)
+}
+
+#[derive(Clone, Debug)]
+pub enum ExportTarget {
+    // `usage-YYYY-MM-DD.csv` per day in this directory, rewritten while the day is
+    // still counting.
+    CsvDir(PathBuf),
+    // The same, as `usage-YYYY-MM-DD.parquet`.
+    #[cfg(feature = "parquet")]
+    ParquetDir(PathBuf),
+    // `{"rows": [..]}` as JSON.
+    Webhook(Url),
+}
+
+#[derive(Clone, Debug)]
+pub struct UsageExporter {
+    target: ExportTarget,
+    every: Duration,
+    days: u32,
+    headers: Vec<(String, String)>,
+}
+
+impl UsageExporter {
+    pub fn new(target: ExportTarget) -> Self {
+        Self {
+            target,
+            every: Duration::from_secs(3600),
+            days: 2,
+            headers: Vec::new(),
+        }
+    }
+
+    pub fn csv_dir(dir: impl Into<PathBuf>) -> Self {
+        Self::new(ExportTarget::CsvDir(dir.into()))
+    }
+
+    #[cfg(feature = "parquet")]
+    pub fn parquet_dir(dir: impl Into<PathBuf>) -> Self {
+        Self::new(ExportTarget::ParquetDir(dir.into()))
+    }
+
+    pub fn webhook(url: Url) -> Self {
+        Self::new(ExportTarget::Webhook(url))
+    }
+
+    // Defaults to an hour. Panics on zero, which would export in a busy loop.
+    pub fn every(mut self, interval: Duration) -> Self {
+        assert!(!interval.is_zero(), "the export interval can't be zero");
+        self.every = interval;
+        self
+    }
+
+    // Days sent on each run, today included. The default of 2 also settles yesterday,
+    // which kept counting after the last run before midnight. Rows for a day are sent
+    // again with growing numbers, so the receiving end should replace by `day` and
+    // `plugin` rather than add.
+    pub fn days(mut self, days: u32) -> Self {
+        self.days = days.max(1);
+        self
+    }
+
+    // Sent with every webhook request, for an `Authorization` token.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    // Exports once, now, and returns the number of rows.
+    pub async fn export(&self) -> Result<usize, ExportError> {
+        let rows = rows(self.days).await?;
+        match &self.target {
+            ExportTarget::CsvDir(dir) => {
+                write_days(dir, "csv", &rows, |day| Ok(to_csv(day).into_bytes())).await?;
+            }
+            #[cfg(feature = "parquet")]
+            ExportTarget::ParquetDir(dir) => {
+                write_days(dir, "parquet", &rows, |day| Ok(to_parquet(day)?)).await?;
+            }
+            ExportTarget::Webhook(url) => {
+                let mut request = http::client()
+                    .post(url.clone())
+                    .json(&serde_json::json!({ "rows": rows }));
+                for (name, value) in &self.headers {
+                    request = request.header(name.as_str(), value.as_str());
+                }
+                let response = request.send().await?;
+                if !response.status().is_success() {
+                    return Err(ExportError::Rejected(response.status()));
+                }
+            }
+        }
+        Ok(rows.len())
+    }
+
+    // Exports every `every` in the background, starting now, until the handle is
+    // aborted. A failed run goes to the error reporter and is tried again next time.
+    pub fn spawn(self) -> AbortHandle {
+        supervisor::spawn_supervised("usage-export", move || {
+            let exporter = self.clone();
+            async move {
+                let mut ticks = tokio::time::interval(exporter.every);
+                loop {
+                    ticks.tick().await;
+                    if let Err(err) = exporter.export().await {
+                        reporting::report_background(None, ErrorKind::Background, None, &err).await;
+                    }
+                }
+            }
+        })
+    }
+}
+
+// One file per day in `dir`, each written aside and renamed, so a reader never sees
+// half a file.
+async fn write_days(
+    dir: &std::path::Path,
+    extension: &str,
+    rows: &[UsageRow],
+    encode: impl Fn(&[UsageRow]) -> Result<Vec<u8>, ExportError>,
+) -> Result<(), ExportError> {
+    tokio::fs::create_dir_all(dir).await?;
+    for day in rows.chunk_by(|a, b| a.day == b.day) {
+        let path = dir.join(format!("usage-{}.{}", day[0].day, extension));
+        let partial = path.with_extension(format!("{}.part", extension));
+        tokio::fs::write(&partial, encode(day)?).await?;
+        tokio::fs::rename(&partial, &path).await?;
+    }
+    Ok(())
+}
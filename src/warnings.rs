@@ -0,0 +1,444 @@
+use crate::context::PluginContext;
+use crate::external::{self, ExternalEvent};
+use crate::moderation;
+use crate::settings;
+use crate::storage::{self, StorageError};
+use crate::traffic;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+use teloxide::prelude::*;
+use teloxide::types::ChatPermissions;
+
+const PREFIX: &str = "warns:";
+const CONFIG: &str = "warn_config";
+
+pub const WARNED: &str = "warned";
+pub const UNWARNED: &str = "unwarned";
+pub const LIMIT_REACHED: &str = "warn_limit_reached";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Warning {
+    pub reason: Option<String>,
+    pub by: Option<UserId>,
+    pub at: DateTime<Utc>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WarnAction {
+    Mute,
+    Kick,
+    Ban,
+}
+
+impl fmt::Display for WarnAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WarnAction::Mute => write!(f, "mute"),
+            WarnAction::Kick => write!(f, "kick"),
+            WarnAction::Ban => write!(f, "ban"),
+        }
+    }
+}
+
+impl std::str::FromStr for WarnAction {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "mute" => Ok(WarnAction::Mute),
+            "kick" => Ok(WarnAction::Kick),
+            "ban" => Ok(WarnAction::Ban),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WarnConfig {
+    pub limit: usize,
+    pub action: WarnAction,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_secs: Option<u64>,
+}
+
+impl Default for WarnConfig {
+    fn default() -> Self {
+        Self {
+            limit: 3,
+            action: WarnAction::Ban,
+            duration_secs: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WarnEvent {
+    pub chat_id: ChatId,
+    pub user_id: UserId,
+    pub count: usize,
+    pub limit: usize,
+    pub reason: Option<String>,
+    pub by: Option<UserId>,
+    pub action: Option<WarnAction>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WarnOutcome {
+    pub count: usize,
+    pub limit: usize,
+    pub action: Option<WarnAction>,
+}
+
+#[derive(Debug)]
+pub enum WarnError {
+    NoChat,
+    Storage(StorageError),
+    Request(teloxide::RequestError),
+}
+
+impl fmt::Display for WarnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WarnError::NoChat => write!(f, "the update has no chat to warn in"),
+            WarnError::Storage(err) => write!(f, "{}", err),
+            WarnError::Request(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for WarnError {}
+
+impl From<StorageError> for WarnError {
+    fn from(err: StorageError) -> Self {
+        WarnError::Storage(err)
+    }
+}
+
+impl From<teloxide::RequestError> for WarnError {
+    fn from(err: teloxide::RequestError) -> Self {
+        WarnError::Request(err)
+    }
+}
+
+fn key(chat: ChatId, user: UserId) -> String {
+    format!("{}{}:{}", PREFIX, chat, user)
+}
+
+fn chat_prefix(chat: ChatId) -> String {
+    format!("{}{}:", PREFIX, chat)
+}
+
+pub async fn config(chat: ChatId) -> Result<WarnConfig, StorageError> {
+    Ok(settings::get(chat, CONFIG).await?.unwrap_or_default())
+}
+
+pub async fn set_config(chat: ChatId, config: &WarnConfig) -> Result<(), StorageError> {
+    settings::set(chat, CONFIG, config).await
+}
+
+pub async fn warnings(chat: ChatId, user: UserId) -> Result<Vec<Warning>, StorageError> {
+    Ok(storage::get_json(&key(chat, user))
+        .await?
+        .unwrap_or_default())
+}
+
+async fn publish(bot: &Bot, name: &str, event: WarnEvent) {
+    if let Ok(payload) = serde_json::to_value(event) {
+        external::publish(bot, ExternalEvent::new(name, payload)).await;
+    }
+}
+
+async fn punish(
+    bot: &Bot,
+    chat: ChatId,
+    user: UserId,
+    config: &WarnConfig,
+) -> Result<(), WarnError> {
+    let until = moderation::until_date(config.duration_secs.map(Duration::from_secs));
+    match config.action {
+        WarnAction::Mute => {
+            let mut request = bot
+                .restrict_chat_member(chat, user, ChatPermissions::empty())
+                .use_independent_chat_permissions(true);
+            if let Some(until) = until {
+                request = request.until_date(until);
+            }
+            traffic::send(request).await?;
+        }
+        WarnAction::Kick => {
+            traffic::send(bot.ban_chat_member(chat, user)).await?;
+            traffic::send(bot.unban_chat_member(chat, user).only_if_banned(true)).await?;
+        }
+        WarnAction::Ban => {
+            let mut request = bot.ban_chat_member(chat, user);
+            if let Some(until) = until {
+                request = request.until_date(until);
+            }
+            traffic::send(request).await?;
+        }
+    }
+    Ok(())
+}
+
+// Reaching the limit runs the chat's action and starts the count over.
+pub async fn warn(
+    bot: &Bot,
+    chat: ChatId,
+    user: UserId,
+    reason: Option<String>,
+    by: Option<UserId>,
+) -> Result<WarnOutcome, WarnError> {
+    let config = config(chat).await?;
+    let mut warnings = warnings(chat, user).await?;
+    warnings.push(Warning {
+        reason: reason.clone(),
+        by,
+        at: Utc::now(),
+    });
+    let count = warnings.len();
+    let limit = config.limit.max(1);
+    let action = (count >= limit).then_some(config.action);
+    // Saved first, so a failed action is retried on the next warning.
+    storage::set_json(&key(chat, user), &warnings).await?;
+    if action.is_some() {
+        punish(bot, chat, user, &config).await?;
+        storage::delete(&key(chat, user)).await?;
+    }
+
+    let event = WarnEvent {
+        chat_id: chat,
+        user_id: user,
+        count,
+        limit,
+        reason,
+        by,
+        action,
+    };
+    publish(bot, WARNED, event.clone()).await;
+    if action.is_some() {
+        publish(bot, LIMIT_REACHED, event).await;
+    }
+    Ok(WarnOutcome {
+        count,
+        limit,
+        action,
+    })
+}
+
+pub async fn unwarn(
+    bot: &Bot,
+    chat: ChatId,
+    user: UserId,
+    by: Option<UserId>,
+) -> Result<Option<Warning>, WarnError> {
+    let mut warnings = warnings(chat, user).await?;
+    let Some(removed) = warnings.pop() else {
+        return Ok(None);
+    };
+    if warnings.is_empty() {
+        storage::delete(&key(chat, user)).await?;
+    } else {
+        storage::set_json(&key(chat, user), &warnings).await?;
+    }
+    let limit = config(chat).await?.limit;
+    let event = WarnEvent {
+        chat_id: chat,
+        user_id: user,
+        count: warnings.len(),
+        limit,
+        reason: removed.reason.clone(),
+        by,
+        action: None,
+    };
+    publish(bot, UNWARNED, event).await;
+    Ok(Some(removed))
+}
+
+pub async fn reset(chat: ChatId, user: UserId) -> Result<(), StorageError> {
+    storage::delete(&key(chat, user)).await
+}
+
+pub(crate) async fn migrate(from: ChatId, to: ChatId) -> Result<(), StorageError> {
+    let storage = storage::storage();
+    let prefix = chat_prefix(from);
+    for old in storage.keys(&prefix).await? {
+        let Some(user) = old.strip_prefix(&prefix) else {
+            continue;
+        };
+        if let Some(value) = storage.get(&old).await? {
+            storage
+                .set(&format!("{}{}", chat_prefix(to), user), value)
+                .await?;
+        }
+        storage.delete(&old).await?;
+    }
+    Ok(())
+}
+
+impl PluginContext {
+    pub async fn warn(
+        &self,
+        user: UserId,
+        reason: Option<String>,
+    ) -> Result<WarnOutcome, WarnError> {
+        let chat = self.chat_id().ok_or(WarnError::NoChat)?;
+        warn(&self.bot, chat, user, reason, self.sender().map(|u| u.id)).await
+    }
+}
+
+#[cfg(feature = "warn-commands")]
+fn warn_target(ctx: &PluginContext) -> (Option<UserId>, Option<String>) {
+    let args = ctx.args();
+    let (target, rest) = match args.first().and_then(|arg| arg.parse::<u64>().ok()) {
+        Some(id) if ctx.replied_message().is_none() => (Some(UserId(id)), &args[1..]),
+        _ => (ctx.target_user().and_then(|t| t.id()), &args[..]),
+    };
+    let rest: Vec<&str> = rest
+        .iter()
+        .copied()
+        .filter(|arg| !arg.starts_with('@'))
+        .collect();
+    (target, (!rest.is_empty()).then(|| rest.join(" ")))
+}
+
+#[cfg(feature = "warn-commands")]
+fn describe(outcome: &WarnOutcome, user: UserId) -> String {
+    match outcome.action {
+        Some(WarnAction::Mute) => {
+            format!("{} reached {} warnings and was muted.", user, outcome.limit)
+        }
+        Some(WarnAction::Kick) => format!(
+            "{} reached {} warnings and was kicked.",
+            user, outcome.limit
+        ),
+        Some(WarnAction::Ban) => format!(
+            "{} reached {} warnings and was banned.",
+            user, outcome.limit
+        ),
+        None => format!("Warned {} ({}/{}).", user, outcome.count, outcome.limit),
+    }
+}
+
+#[cfg(feature = "warn-commands")]
+#[crate::TeloxidePlugin(commands = ["warn"], prefixes = ["/"], admin_only = true)]
+async fn warn_command(ctx: PluginContext) -> Option<String> {
+    let (Some(user), reason) = warn_target(&ctx) else {
+        return Some(String::from(
+            "Reply to someone or give their id: /warn <user> [reason]",
+        ));
+    };
+    let privileged = crate::permissions::chat_member(&ctx.bot, ctx.chat_id()?, user)
+        .await
+        .is_ok_and(|member| member.status().is_privileged());
+    if privileged {
+        return Some(String::from("Admins can't be warned."));
+    }
+    Some(match ctx.warn(user, reason).await {
+        Ok(outcome) => describe(&outcome, user),
+        Err(err) => format!("Couldn't warn: {}", err),
+    })
+}
+
+#[cfg(feature = "warn-commands")]
+#[crate::TeloxidePlugin(commands = ["unwarn"], prefixes = ["/"], admin_only = true)]
+async fn unwarn_command(ctx: PluginContext) -> Option<String> {
+    let (Some(user), _) = warn_target(&ctx) else {
+        return Some(String::from(
+            "Reply to someone or give their id: /unwarn <user>",
+        ));
+    };
+    let by = ctx.sender().map(|u| u.id);
+    Some(match unwarn(&ctx.bot, ctx.chat_id()?, user, by).await {
+        Ok(Some(_)) => format!("Removed the last warning of {}.", user),
+        Ok(None) => format!("{} has no warnings.", user),
+        Err(err) => format!("Couldn't remove the warning: {}", err),
+    })
+}
+
+#[cfg(feature = "warn-commands")]
+#[crate::TeloxidePlugin(commands = ["resetwarns"], prefixes = ["/"], admin_only = true)]
+async fn reset_warns_command(ctx: PluginContext) -> Option<String> {
+    let (Some(user), _) = warn_target(&ctx) else {
+        return Some(String::from(
+            "Reply to someone or give their id: /resetwarns <user>",
+        ));
+    };
+    Some(match reset(ctx.chat_id()?, user).await {
+        Ok(()) => format!("Cleared the warnings of {}.", user),
+        Err(err) => format!("Couldn't clear the warnings: {}", err),
+    })
+}
+
+#[cfg(feature = "warn-commands")]
+#[crate::TeloxidePlugin(commands = ["warns"], prefixes = ["/"])]
+async fn warns_command(ctx: PluginContext) -> Option<String> {
+    let user = match warn_target(&ctx).0 {
+        Some(user) => user,
+        None => ctx.sender()?.id,
+    };
+    let chat = ctx.chat_id()?;
+    let limit = config(chat).await.ok()?.limit;
+    let warnings = warnings(chat, user).await.ok()?;
+    if warnings.is_empty() {
+        return Some(format!("{} has no warnings.", user));
+    }
+    let list: Vec<String> = warnings
+        .iter()
+        .map(|w| format!("- {}", w.reason.as_deref().unwrap_or("no reason")))
+        .collect();
+    Some(format!(
+        "{} has {}/{} warnings:\n{}",
+        user,
+        warnings.len(),
+        limit,
+        list.join("\n")
+    ))
+}
+
+#[cfg(feature = "warn-commands")]
+#[crate::TeloxidePlugin(commands = ["warnlimit"], prefixes = ["/"], admin_only = true)]
+async fn warn_limit_command(ctx: PluginContext) -> Option<String> {
+    let chat = ctx.chat_id()?;
+    let mut config = config(chat).await.ok()?;
+    let Some(limit) = ctx.args().first().and_then(|arg| arg.parse::<usize>().ok()) else {
+        return Some(format!(
+            "Users are punished after {} warnings. Usage: /warnlimit <n>",
+            config.limit
+        ));
+    };
+    if limit == 0 {
+        return Some(String::from("The limit has to be at least 1."));
+    }
+    config.limit = limit;
+    Some(match set_config(chat, &config).await {
+        Ok(()) => format!("Users are now punished after {} warnings.", limit),
+        Err(err) => format!("Couldn't save the limit: {}", err),
+    })
+}
+
+#[cfg(feature = "warn-commands")]
+#[crate::TeloxidePlugin(commands = ["warnaction"], prefixes = ["/"], admin_only = true)]
+async fn warn_action_command(ctx: PluginContext) -> Option<String> {
+    let chat = ctx.chat_id()?;
+    let args = ctx.args();
+    let Some(action) = args.first().and_then(|arg| arg.parse::<WarnAction>().ok()) else {
+        return Some(String::from("Usage: /warnaction mute|kick|ban [duration]"));
+    };
+    let duration = match args.get(1) {
+        Some(arg) => match arg.parse::<crate::args::HumanDuration>() {
+            Ok(duration) => Some(duration.0.as_secs()),
+            Err(err) => return Some(format!("Invalid duration: {}", err)),
+        },
+        None => None,
+    };
+    let mut config = config(chat).await.ok()?;
+    config.action = action;
+    config.duration_secs = duration;
+    Some(match set_config(chat, &config).await {
+        Ok(()) => format!("Reaching the warning limit now means a {}.", action),
+        Err(err) => format!("Couldn't save the action: {}", err),
+    })
+}
@@ -1,17 +1,128 @@
 #![allow(non_snake_case)]
 
 use proc_macro::TokenStream;
-use proc_macro2;
-use quote::quote;
+use quote::{format_ident, quote};
 use syn::{
     parse::Parse, parse::ParseStream, parse_macro_input, punctuated::Punctuated, Expr, ExprArray,
-    ExprLit, ItemFn, Lit, LitStr, Meta, MetaNameValue, Token,
+    ExprLit, FnArg, Item, ItemFn, ItemMod, ItemStruct, Lit, LitStr, Meta, MetaNameValue,
+    ReturnType, Token, Type, TypeParamBound,
 };
 
 const COMMANDS_IDENT: &str = "commands";
 const PREFIXES_IDENT: &str = "prefixes";
 const REGEX_IDENT: &str = "regex";
+const REGEX_MODE_IDENT: &str = "regex_mode";
+const NOT_REGEX_IDENT: &str = "not_regex";
+const EXCLUDE_COMMANDS_IDENT: &str = "exclude_commands";
 const CALLBACK_IDENT: &str = "callback";
+const STATE_IDENT: &str = "state";
+const REQUIRES_REPLY_IDENT: &str = "requires_reply";
+const ADMIN_ONLY_IDENT: &str = "admin_only";
+const ROLE_IDENT: &str = "role";
+const REQUIRES_IDENT: &str = "requires";
+const BOT_REQUIRES_IDENT: &str = "bot_requires";
+const ALLOW_ANONYMOUS_ADMIN_IDENT: &str = "allow_anonymous_admin";
+const IGNORE_BOTS_IDENT: &str = "ignore_bots";
+const FROM_BOT_ONLY_IDENT: &str = "from_bot_only";
+const ON_IDENT: &str = "on";
+const MATCH_CAPTIONS_IDENT: &str = "match_captions";
+const ENTITY_IDENT: &str = "entity";
+const MEDIA_IDENT: &str = "media";
+const TRANSCRIBE_IDENT: &str = "transcribe";
+const OUTBOX_IDENT: &str = "outbox";
+const LANG_IDENT: &str = "lang";
+const TOPIC_IDENT: &str = "topic";
+const INLINE_IDENT: &str = "inline";
+const ENABLED_IF_ENV_IDENT: &str = "enabled_if_env";
+const UPDATE_IDENT: &str = "update";
+const GAME_IDENT: &str = "game";
+const OWNER_ONLY_CALLBACK_IDENT: &str = "owner_only_callback";
+const SIGNED_IDENT: &str = "signed";
+const RAW_JSON_IDENT: &str = "raw_json";
+const CRON_IDENT: &str = "cron";
+const TZ_IDENT: &str = "tz";
+const OVERLAP_IDENT: &str = "overlap";
+const JITTER_IDENT: &str = "jitter";
+const EXTERNAL_IDENT: &str = "external";
+const CONVERSATION_IDENT: &str = "conversation";
+const STEP_IDENT: &str = "step";
+const PRIORITY_IDENT: &str = "priority";
+const COOLDOWN_IDENT: &str = "cooldown";
+const COOLDOWN_MESSAGE_IDENT: &str = "cooldown_message";
+const COOLDOWN_SILENT_IDENT: &str = "cooldown_silent";
+const PREFIX_IDENT: &str = "prefix";
+const SUB_IDENT: &str = "sub";
+const NAME_IDENT: &str = "name";
+const HELP_IDENT: &str = "help";
+
+const MESSAGE_KINDS: &[&str] = &["new", "edited", "business", "edited_business"];
+
+const OVERLAP_POLICIES: &[&str] = &["skip", "queue", "parallel"];
+
+const REGEX_MODES: &[&str] = &["search", "full", "word"];
+
+const UPDATE_KINDS: &[&str] = &[
+    "business_connection",
+    "chat_boost",
+    "removed_chat_boost",
+    "pre_checkout_query",
+    "my_chat_member",
+    "poll",
+    "poll_answer",
+    "successful_payment",
+    "refunded_payment",
+    "giveaway",
+    "giveaway_created",
+    "giveaway_winners",
+    "giveaway_completed",
+    "video_chat_scheduled",
+    "video_chat_started",
+    "video_chat_ended",
+    "video_chat_participants_invited",
+];
+
+const ENTITY_KINDS: &[&str] = &[
+    "mention",
+    "hashtag",
+    "cashtag",
+    "bot_command",
+    "url",
+    "email",
+    "phone_number",
+    "text_link",
+    "text_mention",
+    "custom_emoji",
+];
+
+const MEDIA_KINDS: &[&str] = &[
+    "photo",
+    "video",
+    "animation",
+    "audio",
+    "voice",
+    "video_note",
+    "document",
+    "sticker",
+];
+
+const SPOKEN_MEDIA: &[&str] = &["voice", "audio", "video_note"];
+
+const ADMIN_RIGHTS: &[&str] = &[
+    "can_manage_chat",
+    "can_change_info",
+    "can_post_messages",
+    "can_edit_messages",
+    "can_delete_messages",
+    "can_post_stories",
+    "can_edit_stories",
+    "can_delete_stories",
+    "can_manage_video_chats",
+    "can_invite_users",
+    "can_restrict_members",
+    "can_pin_messages",
+    "can_manage_topics",
+    "can_promote_members",
+];
 
 struct PluginArgs {
     metas: Punctuated<Meta, Token![,]>,
@@ -25,6 +136,52 @@ impl Parse for PluginArgs {
     }
 }
 
+#[derive(Default)]
+struct PluginConfig {
+    commands: Vec<String>,
+    prefixes: Vec<String>,
+    regex: Option<String>,
+    regex_mode: Option<String>,
+    not_regex: Option<String>,
+    exclude_commands: Vec<String>,
+    callback_filter: Option<String>,
+    games: Vec<String>,
+    owner_only_callback: bool,
+    signed: bool,
+    state: Option<Expr>,
+    requires_reply: bool,
+    admin_only: bool,
+    role: Option<String>,
+    requires: Vec<String>,
+    bot_requires: Vec<String>,
+    allow_anonymous_admin: bool,
+    ignore_bots: Option<bool>,
+    from_bot_only: bool,
+    on: Option<Vec<String>>,
+    match_captions: Option<bool>,
+    entities: Vec<String>,
+    media: Vec<String>,
+    transcribe: bool,
+    outbox: bool,
+    langs: Vec<String>,
+    topics: Vec<String>,
+    inline: Option<String>,
+    updates: Vec<String>,
+    raw_json: bool,
+    cron: Option<String>,
+    tz: Option<String>,
+    overlap: Option<String>,
+    jitter: u64,
+    externals: Vec<String>,
+    conversation: Option<String>,
+    step: Option<String>,
+    priority: i32,
+    cooldown: u64,
+    cooldown_message: Option<String>,
+    cooldown_silent: bool,
+    enabled_if_env: Option<String>,
+}
+
 fn extract_strings_from_array(expr: &Expr) -> syn::Result<Vec<String>> {
     match expr {
         Expr::Array(ExprArray { elems, .. }) => {
@@ -54,6 +211,75 @@ fn extract_strings_from_array(expr: &Expr) -> syn::Result<Vec<String>> {
     }
 }
 
+fn extract_rights(expr: &Expr) -> syn::Result<Vec<String>> {
+    let rights = extract_strings_from_array(expr)?;
+    if let Some(unknown) = rights.iter().find(|r| !ADMIN_RIGHTS.contains(&r.as_str())) {
+        return Err(syn::Error::new_spanned(
+            expr,
+            format!(
+                "unknown admin right `{}`, expected one of: {}",
+                unknown,
+                ADMIN_RIGHTS.join(", ")
+            ),
+        ));
+    }
+    Ok(rights)
+}
+
+fn extract_string(expr: &Expr) -> syn::Result<String> {
+    match expr {
+        Expr::Lit(ExprLit {
+            lit: Lit::Str(lit_str),
+            ..
+        }) => Ok(lit_str.value()),
+        _ => Err(syn::Error::new_spanned(expr, "expected string literal")),
+    }
+}
+
+fn extract_bool(expr: &Expr) -> syn::Result<bool> {
+    match expr {
+        Expr::Lit(ExprLit {
+            lit: Lit::Bool(lit_bool),
+            ..
+        }) => Ok(lit_bool.value),
+        _ => Err(syn::Error::new_spanned(expr, "expected `true` or `false`")),
+    }
+}
+
+fn extract_u64(expr: &Expr) -> syn::Result<u64> {
+    match expr {
+        Expr::Lit(ExprLit {
+            lit: Lit::Int(lit_int),
+            ..
+        }) => lit_int.base10_parse(),
+        _ => Err(syn::Error::new_spanned(expr, "expected an integer")),
+    }
+}
+
+fn extract_i32(expr: &Expr) -> syn::Result<i32> {
+    match expr {
+        Expr::Lit(ExprLit {
+            lit: Lit::Int(lit_int),
+            ..
+        }) => lit_int.base10_parse(),
+        Expr::Unary(syn::ExprUnary {
+            op: syn::UnOp::Neg(_),
+            expr: inner,
+            ..
+        }) => extract_i32(inner)?
+            .checked_neg()
+            .ok_or_else(|| syn::Error::new_spanned(expr, "integer out of range")),
+        _ => Err(syn::Error::new_spanned(expr, "expected an integer")),
+    }
+}
+
+fn create_optional_bool(value: Option<bool>) -> proc_macro2::TokenStream {
+    match value {
+        Some(value) => quote! { Some(#value) },
+        None => quote! { None },
+    }
+}
+
 fn create_optional_string_literal(value: Option<&String>) -> proc_macro2::TokenStream {
     value
         .map(|s| {
@@ -63,102 +289,473 @@ fn create_optional_string_literal(value: Option<&String>) -> proc_macro2::TokenS
         .unwrap_or_else(|| quote! { None })
 }
 
-fn parse_plugin_args(
-    args: TokenStream,
-) -> syn::Result<(Vec<String>, Vec<String>, Option<String>, Option<String>)> {
-    let mut commands = Vec::new();
-    let mut prefixes = Vec::new();
-    let mut regex = None;
-    let mut callback_filter = None;
+fn parse_plugin_args(args: TokenStream) -> syn::Result<PluginConfig> {
+    let mut config = PluginConfig::default();
 
     let plugin_args: PluginArgs = syn::parse(args)?;
 
     for meta in plugin_args.metas {
-        if let Meta::NameValue(MetaNameValue { path, value, .. }) = meta {
-            if let Some(ident) = path.get_ident() {
-                match ident.to_string().as_str() {
-                    COMMANDS_IDENT => {
-                        commands = extract_strings_from_array(&value)?;
-                    }
-                    PREFIXES_IDENT => {
-                        prefixes = extract_strings_from_array(&value)?;
-                    }
-                    REGEX_IDENT => {
-                        let patterns = extract_strings_from_array(&value)?;
-                        if !patterns.is_empty() {
-                            if patterns.len() == 1 {
-                                regex = Some(patterns[0].clone());
-                            } else {
-                                let combined_pattern = patterns.join("|");
-                                regex = Some(combined_pattern);
-                            }
-                        }
+        // A typo'd or value-less option must not compile: silently dropping
+        // `admin_ony = true` would leave the plugin open to everyone.
+        let (path, value) = match meta {
+            Meta::NameValue(MetaNameValue { path, value, .. }) => (path, value),
+            other => {
+                let name = other.path().get_ident().map(|ident| ident.to_string());
+                return Err(syn::Error::new_spanned(
+                    &other,
+                    match name {
+                        Some(name) => format!("expected `{} = ...`", name),
+                        None => String::from("expected `option = value`"),
+                    },
+                ));
+            }
+        };
+        let Some(ident) = path.get_ident() else {
+            return Err(syn::Error::new_spanned(&path, "expected an option name"));
+        };
+        match ident.to_string().as_str() {
+            COMMANDS_IDENT => {
+                config.commands = extract_strings_from_array(&value)?;
+            }
+            PREFIXES_IDENT => {
+                config.prefixes = extract_strings_from_array(&value)?;
+            }
+            REGEX_IDENT => {
+                let patterns = extract_strings_from_array(&value)?;
+                check_regexes(&value, &patterns)?;
+                if !patterns.is_empty() {
+                    if patterns.len() == 1 {
+                        config.regex = Some(patterns[0].clone());
+                    } else {
+                        let combined_pattern = patterns.join("|");
+                        config.regex = Some(combined_pattern);
                     }
-                    CALLBACK_IDENT => {
-                        let patterns = extract_strings_from_array(&value)?;
-                        if !patterns.is_empty() {
-                            if patterns.len() == 1 {
-                                callback_filter = Some(patterns[0].clone());
-                            } else {
-                                let combined_pattern = patterns.join("|");
-                                callback_filter = Some(combined_pattern);
-                            }
-                        }
+                }
+            }
+            REGEX_MODE_IDENT => {
+                let mode = extract_string(&value)?;
+                if !REGEX_MODES.contains(&mode.as_str()) {
+                    return Err(syn::Error::new_spanned(
+                        &value,
+                        format!(
+                            "unknown regex mode `{}`, expected one of: {}",
+                            mode,
+                            REGEX_MODES.join(", ")
+                        ),
+                    ));
+                }
+                config.regex_mode = Some(mode);
+            }
+            NOT_REGEX_IDENT => {
+                let patterns = extract_strings_from_array(&value)?;
+                check_regexes(&value, &patterns)?;
+                if !patterns.is_empty() {
+                    config.not_regex = Some(patterns.join("|"));
+                }
+            }
+            EXCLUDE_COMMANDS_IDENT => {
+                config.exclude_commands = extract_strings_from_array(&value)?;
+            }
+            CALLBACK_IDENT => {
+                let patterns = extract_strings_from_array(&value)?;
+                if !patterns.is_empty() {
+                    if patterns.len() == 1 {
+                        config.callback_filter = Some(patterns[0].clone());
+                    } else {
+                        let combined_pattern = patterns.join("|");
+                        config.callback_filter = Some(combined_pattern);
                     }
-                    _ => {}
                 }
             }
+            STATE_IDENT => {
+                config.state = Some(value);
+            }
+            REQUIRES_REPLY_IDENT => {
+                config.requires_reply = extract_bool(&value)?;
+            }
+            ADMIN_ONLY_IDENT => {
+                config.admin_only = extract_bool(&value)?;
+            }
+            ROLE_IDENT => {
+                let role = extract_string(&value)?;
+                if role.trim().is_empty() {
+                    return Err(syn::Error::new_spanned(&value, "role can't be empty"));
+                }
+                config.role = Some(role);
+            }
+            REQUIRES_IDENT => {
+                config.requires = extract_rights(&value)?;
+            }
+            BOT_REQUIRES_IDENT => {
+                config.bot_requires = extract_rights(&value)?;
+            }
+            ALLOW_ANONYMOUS_ADMIN_IDENT => {
+                config.allow_anonymous_admin = extract_bool(&value)?;
+            }
+            IGNORE_BOTS_IDENT => {
+                config.ignore_bots = Some(extract_bool(&value)?);
+            }
+            FROM_BOT_ONLY_IDENT => {
+                config.from_bot_only = extract_bool(&value)?;
+            }
+            ON_IDENT => {
+                let kinds = extract_strings_from_array(&value)?;
+                if let Some(kind) = kinds.iter().find(|k| !MESSAGE_KINDS.contains(&k.as_str())) {
+                    return Err(syn::Error::new_spanned(
+                        &value,
+                        format!(
+                            "unknown message kind `{}`, expected one of: {}",
+                            kind,
+                            MESSAGE_KINDS.join(", ")
+                        ),
+                    ));
+                }
+                config.on = Some(kinds);
+            }
+            ENTITY_IDENT => {
+                let kinds = extract_strings_from_array(&value)?;
+                if let Some(kind) = kinds.iter().find(|k| !ENTITY_KINDS.contains(&k.as_str())) {
+                    return Err(syn::Error::new_spanned(
+                        &value,
+                        format!(
+                            "unknown entity kind `{}`, expected one of: {}",
+                            kind,
+                            ENTITY_KINDS.join(", ")
+                        ),
+                    ));
+                }
+                config.entities = kinds;
+            }
+            MEDIA_IDENT => {
+                let kinds = extract_strings_from_array(&value)?;
+                if let Some(kind) = kinds.iter().find(|k| !MEDIA_KINDS.contains(&k.as_str())) {
+                    return Err(syn::Error::new_spanned(
+                        &value,
+                        format!(
+                            "unknown media kind `{}`, expected one of: {}",
+                            kind,
+                            MEDIA_KINDS.join(", ")
+                        ),
+                    ));
+                }
+                config.media = kinds;
+            }
+            TRANSCRIBE_IDENT => {
+                config.transcribe = extract_bool(&value)?;
+            }
+            OUTBOX_IDENT => {
+                config.outbox = extract_bool(&value)?;
+            }
+            INLINE_IDENT => {
+                let patterns = extract_strings_from_array(&value)?;
+                check_regexes(&value, &patterns)?;
+                if !patterns.is_empty() {
+                    config.inline = Some(patterns.join("|"));
+                }
+            }
+            CRON_IDENT => {
+                let pattern = extract_string(&value)?;
+                let fields = pattern.split_whitespace().count();
+                if !pattern.starts_with('@') && !(5..=7).contains(&fields) {
+                    return Err(syn::Error::new_spanned(
+                        &value,
+                        "cron expressions need 5 to 7 fields, e.g. \"0 9 * * *\"",
+                    ));
+                }
+                config.cron = Some(pattern);
+            }
+            TZ_IDENT => {
+                config.tz = Some(extract_string(&value)?);
+            }
+            OVERLAP_IDENT => {
+                let overlap = extract_string(&value)?;
+                if !OVERLAP_POLICIES.contains(&overlap.as_str()) {
+                    return Err(syn::Error::new_spanned(
+                        &value,
+                        format!("overlap must be one of: {}", OVERLAP_POLICIES.join(", ")),
+                    ));
+                }
+                config.overlap = Some(overlap);
+            }
+            JITTER_IDENT => {
+                config.jitter = extract_u64(&value)?;
+            }
+            EXTERNAL_IDENT => {
+                let names = extract_strings_from_array(&value)?;
+                if names.iter().any(|name| name.trim().is_empty()) {
+                    return Err(syn::Error::new_spanned(
+                        &value,
+                        "external event names can't be empty",
+                    ));
+                }
+                config.externals = names;
+            }
+            CONVERSATION_IDENT => {
+                config.conversation = Some(extract_string(&value)?);
+            }
+            STEP_IDENT => {
+                config.step = Some(extract_string(&value)?);
+            }
+            PRIORITY_IDENT => {
+                config.priority = extract_i32(&value)?;
+            }
+            COOLDOWN_IDENT => {
+                config.cooldown = extract_u64(&value)?;
+            }
+            COOLDOWN_MESSAGE_IDENT => {
+                config.cooldown_message = Some(extract_string(&value)?);
+            }
+            COOLDOWN_SILENT_IDENT => {
+                config.cooldown_silent = extract_bool(&value)?;
+            }
+            RAW_JSON_IDENT => {
+                config.raw_json = extract_bool(&value)?;
+            }
+            GAME_IDENT => {
+                config.games = extract_strings_from_array(&value)?;
+            }
+            OWNER_ONLY_CALLBACK_IDENT => {
+                config.owner_only_callback = extract_bool(&value)?;
+            }
+            SIGNED_IDENT => {
+                config.signed = extract_bool(&value)?;
+            }
+            UPDATE_IDENT => {
+                let kinds = extract_strings_from_array(&value)?;
+                if let Some(kind) = kinds.iter().find(|k| !UPDATE_KINDS.contains(&k.as_str())) {
+                    return Err(syn::Error::new_spanned(
+                        &value,
+                        format!(
+                            "unknown update kind `{}`, expected one of: {}",
+                            kind,
+                            UPDATE_KINDS.join(", ")
+                        ),
+                    ));
+                }
+                config.updates = kinds;
+            }
+            ENABLED_IF_ENV_IDENT => {
+                config.enabled_if_env = Some(extract_string(&value)?);
+            }
+            LANG_IDENT => {
+                config.langs = extract_strings_from_array(&value)?;
+            }
+            TOPIC_IDENT => {
+                config.topics = extract_strings_from_array(&value)?;
+            }
+            MATCH_CAPTIONS_IDENT => {
+                config.match_captions = Some(extract_bool(&value)?);
+            }
+            unknown => {
+                return Err(syn::Error::new_spanned(
+                    ident,
+                    format!("unknown `TeloxidePlugin` option `{}`", unknown),
+                ));
+            }
         }
     }
 
-    Ok((commands, prefixes, regex, callback_filter))
-}
+    if let Some(regex) = config.regex.take() {
+        config.regex = Some(match config.regex_mode.as_deref() {
+            Some("full") => format!("^(?:{})$", regex),
+            Some("word") => format!(r"\b(?:{})\b", regex),
+            _ => regex,
+        });
+    }
 
-fn determine_handler_type(
-    commands: &[String],
-    prefixes: &[String],
-    regex: &Option<String>,
-    callback_filter: &Option<String>,
-) -> syn::Result<bool> {
-    let has_message_triggers = !commands.is_empty() || !prefixes.is_empty() || regex.is_some();
-    let has_callback_triggers = callback_filter.is_some();
+    Ok(config)
+}
 
-    match (has_message_triggers, has_callback_triggers) {
-        (true, true) => {
-            Err(syn::Error::new(
-                proc_macro2::Span::call_site(),
-                "plugin cannot handle both message triggers (commands/prefixes/regex) and callback triggers simultaneously"
-            ))
-        }
-        (true, false) => Ok(false),
-        (false, true) => Ok(true),
-        (false, false) => {
-            Err(syn::Error::new(
-                proc_macro2::Span::call_site(),
-                "plugin must specify at least one trigger: commands, prefixes, regex, or callback"
-            ))
+// A pattern that doesn't compile would never match, so it's refused here rather
+// than found out about at runtime.
+fn check_regexes(value: &Expr, patterns: &[String]) -> syn::Result<()> {
+    for pattern in patterns {
+        if let Err(err) = regex::Regex::new(pattern) {
+            return Err(syn::Error::new_spanned(
+                value,
+                format!("invalid regex `{}`: {}", pattern, err),
+            ));
         }
     }
+    Ok(())
 }
 
-fn create_callback_handler(fn_name: &syn::Ident, is_callback: bool) -> proc_macro2::TokenStream {
-    if is_callback {
-        quote! {
-            |ctx| Box::pin(async move {
-                if let Some(cq) = ctx.callback_query {
-                    #fn_name(ctx.bot, cq).await;
-                }
-            })
-        }
-    } else {
-        quote! {
-            |ctx| Box::pin(async move {
-                if let Some(msg) = ctx.message {
-                    #fn_name(ctx.bot, msg).await;
-                }
-            })
+fn validate_triggers(config: &PluginConfig) -> syn::Result<()> {
+    if (config.overlap.is_some() || config.jitter > 0) && config.cron.is_none() {
+        return Err(syn::Error::new(
+            proc_macro2::Span::call_site(),
+            "`overlap` and `jitter` only apply to `cron` plugins",
+        ));
+    }
+
+    if config.regex_mode.is_some() && config.regex.is_none() {
+        return Err(syn::Error::new(
+            proc_macro2::Span::call_site(),
+            "`regex_mode` only applies to `regex` plugins",
+        ));
+    }
+
+    if config.step.is_some() != config.conversation.is_some() {
+        return Err(syn::Error::new(
+            proc_macro2::Span::call_site(),
+            "`conversation` and `step` must be used together",
+        ));
+    }
+
+    if config.transcribe
+        && !config
+            .media
+            .iter()
+            .any(|kind| SPOKEN_MEDIA.contains(&kind.as_str()))
+    {
+        return Err(syn::Error::new(
+            proc_macro2::Span::call_site(),
+            "`transcribe` needs `media` with voice, audio or video_note",
+        ));
+    }
+
+    let has_message_triggers = !config.commands.is_empty()
+        || !config.prefixes.is_empty()
+        || config.regex.is_some()
+        || !config.entities.is_empty()
+        || !config.media.is_empty();
+    let has_callback_triggers = config.callback_filter.is_some() || !config.games.is_empty();
+    if config.owner_only_callback && !has_callback_triggers {
+        return Err(syn::Error::new(
+            proc_macro2::Span::call_site(),
+            "`owner_only_callback` only applies to `callback` and `game` plugins",
+        ));
+    }
+    if config.signed && config.callback_filter.is_none() {
+        return Err(syn::Error::new(
+            proc_macro2::Span::call_site(),
+            "`signed` only applies to `callback` plugins",
+        ));
+    }
+    let has_inline_triggers = config.inline.is_some();
+    let has_update_triggers = !config.updates.is_empty() || config.raw_json;
+    let has_schedule_triggers = config.cron.is_some();
+    let has_external_triggers = !config.externals.is_empty();
+    let has_message_triggers = has_message_triggers
+        || (config.step.is_some() && !has_callback_triggers && !has_inline_triggers);
+    if (config.not_regex.is_some() || !config.exclude_commands.is_empty()) && !has_message_triggers
+    {
+        return Err(syn::Error::new(
+            proc_macro2::Span::call_site(),
+            "`not_regex` and `exclude_commands` only apply to plugins with message triggers",
+        ));
+    }
+
+    let kinds = [
+        has_message_triggers || has_callback_triggers,
+        has_inline_triggers,
+        has_update_triggers,
+        has_schedule_triggers,
+        has_external_triggers,
+    ]
+    .iter()
+    .filter(|present| **present)
+    .count();
+
+    match kinds {
+        0 => Err(syn::Error::new(
+            proc_macro2::Span::call_site(),
+            "plugin must specify at least one trigger: commands, prefixes, regex, entity, media, callback, game, inline, update, raw_json, cron, or external",
+        )),
+        1 => Ok(()),
+        _ => Err(syn::Error::new(
+            proc_macro2::Span::call_site(),
+            "plugin cannot mix message and callback triggers (commands/prefixes/regex/entity/media/callback/game) with inline, update/raw_json, cron or external triggers",
+        )),
+    }
+}
+
+fn find_state_type(input_fn: &ItemFn, config: &PluginConfig) -> syn::Result<Option<Type>> {
+    let state_ty = input_fn.sig.inputs.iter().find_map(|arg| match arg {
+        FnArg::Typed(pat_type) => match &*pat_type.ty {
+            Type::Reference(reference) => Some((*reference.elem).clone()),
+            _ => None,
+        },
+        FnArg::Receiver(_) => None,
+    });
+
+    match (&config.state, state_ty) {
+        (Some(_), Some(ty)) => Ok(Some(ty)),
+        (Some(expr), None) => Err(syn::Error::new_spanned(
+            expr,
+            "plugin with `state` must take a `&'static T` parameter for the state",
+        )),
+        (None, Some(ty)) => Err(syn::Error::new_spanned(
+            ty,
+            "reference parameters are only supported together with the `state` attribute",
+        )),
+        (None, None) => Ok(None),
+    }
+}
+
+fn returns_stream(input_fn: &ItemFn) -> bool {
+    let ReturnType::Type(_, ty) = &input_fn.sig.output else {
+        return false;
+    };
+    let Type::ImplTrait(impl_trait) = &**ty else {
+        return false;
+    };
+    impl_trait.bounds.iter().any(|bound| match bound {
+        TypeParamBound::Trait(trait_bound) => trait_bound
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "Stream"),
+        _ => false,
+    })
+}
+
+fn create_callback_handler(
+    input_fn: &ItemFn,
+    state_static: Option<&syn::Ident>,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let fn_name = &input_fn.sig.ident;
+    let mut bindings = Vec::new();
+    let mut call_args = Vec::new();
+
+    for (index, arg) in input_fn.sig.inputs.iter().enumerate() {
+        let FnArg::Typed(pat_type) = arg else {
+            return Err(syn::Error::new_spanned(
+                arg,
+                "plugin handlers must be free functions",
+            ));
+        };
+        match (&*pat_type.ty, state_static) {
+            (Type::Reference(_), Some(state_name)) => call_args.push(quote! { &*#state_name }),
+            (ty, _) => {
+                let binding = format_ident!("__arg{}", index);
+                bindings.push(quote! {
+                    let Some(#binding) = <#ty as teloxide_plugins::FromContext>::from_context(&ctx) else {
+                        return;
+                    };
+                });
+                call_args.push(quote! { #binding });
+            }
         }
     }
+
+    let wrap_stream = returns_stream(input_fn).then(|| {
+        quote! { let response = teloxide_plugins::response::StreamReply::new(response); }
+    });
+
+    Ok(quote! {
+        |ctx| Box::pin(async move {
+            #(#bindings)*
+            let response = #fn_name(#(#call_args),*).await;
+            #wrap_stream
+            let response = {
+                #[allow(unused_imports)]
+                use teloxide_plugins::response::__private::{ViaIntoResponse as _, ViaPluginError as _};
+                (&teloxide_plugins::response::__private::Returned::new(response)).take_response()
+            };
+            let _ = teloxide_plugins::response::respond(&ctx, response).await;
+        })
+    })
 }
 
 #[proc_macro_attribute]
@@ -173,47 +770,445 @@ pub fn TeloxidePlugin(args: TokenStream, input: TokenStream) -> TokenStream {
     let sig = &input_fn.sig;
     let block = &input_fn.block;
 
-    let (commands, prefixes, regex, callback_filter) = match parse_plugin_args(args) {
+    let config = match parse_plugin_args(args) {
         Ok(result) => result,
         Err(err) => return err.to_compile_error().into(),
     };
 
-    let is_callback_handler =
-        match determine_handler_type(&commands, &prefixes, &regex, &callback_filter) {
-            Ok(is_callback) => is_callback,
-            Err(err) => return err.to_compile_error().into(),
-        };
+    if let Err(err) = validate_triggers(&config) {
+        return err.to_compile_error().into();
+    }
+
+    let state_ty = match find_state_type(&input_fn, &config) {
+        Ok(ty) => ty,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    let state_name = syn::Ident::new(&format!("{}_state", fn_name_str), fn_name.span());
+    let state_static = config
+        .state
+        .as_ref()
+        .zip(state_ty.as_ref())
+        .map(|(expr, ty)| {
+            quote! {
+                #[allow(non_upper_case_globals)]
+                #[doc(hidden)]
+                static #state_name: teloxide_plugins::once_cell::sync::Lazy<#ty> =
+                    teloxide_plugins::once_cell::sync::Lazy::new(|| #expr);
+            }
+        });
 
-    let commands_lit = commands
+    let commands_lit = config
+        .commands
         .iter()
         .map(|c| LitStr::new(c, proc_macro2::Span::call_site()));
-    let prefixes_lit = prefixes
+    let prefixes_lit = config
+        .prefixes
         .iter()
         .map(|p| LitStr::new(p, proc_macro2::Span::call_site()));
-    let regex_lit = create_optional_string_literal(regex.as_ref());
-    let callback_filter_lit = create_optional_string_literal(callback_filter.as_ref());
+    let regex_lit = create_optional_string_literal(config.regex.as_ref());
+    let not_regex_lit = create_optional_string_literal(config.not_regex.as_ref());
+    let exclude_commands_lit = config
+        .exclude_commands
+        .iter()
+        .map(|c| LitStr::new(c, proc_macro2::Span::call_site()));
+    let callback_filter_lit = create_optional_string_literal(config.callback_filter.as_ref());
+    let owner_only_callback = config.owner_only_callback;
+    let signed = config.signed;
+    let requires_reply = config.requires_reply;
+    let admin_only = config.admin_only;
+    let role = create_optional_string_literal(config.role.as_ref());
+    let allow_anonymous_admin = config.allow_anonymous_admin;
+    let ignore_bots = create_optional_bool(config.ignore_bots);
+    let match_captions = create_optional_bool(config.match_captions);
+    let inline_lit = create_optional_string_literal(config.inline.as_ref());
+    let register = match &config.enabled_if_env {
+        Some(var) => quote! {
+            if teloxide_plugins::registry::env_flag_enabled(#var) {
+                teloxide_plugins::registry::register_plugin(#static_name);
+            }
+        },
+        None => quote! {
+            teloxide_plugins::registry::register_plugin(#static_name);
+        },
+    };
+    let langs_lit = config
+        .langs
+        .iter()
+        .map(|l| LitStr::new(l, proc_macro2::Span::call_site()));
+    let topics_lit = config
+        .topics
+        .iter()
+        .map(|t| LitStr::new(t, proc_macro2::Span::call_site()));
+    let entities_lit = config
+        .entities
+        .iter()
+        .map(|e| LitStr::new(e, proc_macro2::Span::call_site()));
+    let media_lit = config
+        .media
+        .iter()
+        .map(|m| LitStr::new(m, proc_macro2::Span::call_site()));
+    let transcribe = config.transcribe;
+    let outbox = config.outbox;
+    let from_bot_only = config.from_bot_only;
+    let on = config.on.unwrap_or_else(|| vec!["new".to_string()]);
+    let on_new = on.iter().any(|k| k == "new");
+    let on_edited = on.iter().any(|k| k == "edited");
+    let on_business = on.iter().any(|k| k == "business");
+    let on_edited_business = on.iter().any(|k| k == "edited_business");
+    let raw_json = config.raw_json;
+    let priority = config.priority;
+    let cooldown = config.cooldown;
+    let cron = create_optional_string_literal(config.cron.as_ref());
+    let tz = create_optional_string_literal(config.tz.as_ref());
+    let overlap = config.overlap.as_deref().unwrap_or("skip");
+    let jitter = config.jitter;
+    let externals_lit = config
+        .externals
+        .iter()
+        .map(|e| LitStr::new(e, proc_macro2::Span::call_site()));
+    let conversation = create_optional_string_literal(config.conversation.as_ref());
+    let step = create_optional_string_literal(config.step.as_ref());
+    let cooldown_message = create_optional_string_literal(config.cooldown_message.as_ref());
+    let cooldown_silent = config.cooldown_silent;
+    let games_lit = config
+        .games
+        .iter()
+        .map(|g| LitStr::new(g, proc_macro2::Span::call_site()));
+    let updates_lit = config
+        .updates
+        .iter()
+        .map(|u| LitStr::new(u, proc_macro2::Span::call_site()));
+    let requires_lit = config
+        .requires
+        .iter()
+        .map(|r| LitStr::new(r, proc_macro2::Span::call_site()));
+    let bot_requires_lit = config
+        .bot_requires
+        .iter()
+        .map(|r| LitStr::new(r, proc_macro2::Span::call_site()));
 
-    let callback_handler = create_callback_handler(fn_name, is_callback_handler);
+    let callback_handler =
+        match create_callback_handler(&input_fn, state_static.as_ref().map(|_| &state_name)) {
+            Ok(handler) => handler,
+            Err(err) => return err.to_compile_error().into(),
+        };
 
     let expanded = quote! {
         #vis #sig #block
 
+        #state_static
+
         #[allow(non_upper_case_globals)]
         #[doc(hidden)]
         static #static_name: &teloxide_plugins::registry::PluginMeta = &teloxide_plugins::registry::PluginMeta {
             name: #fn_name_str,
+            priority: #priority,
             commands: &[#(#commands_lit),*],
             prefixes: &[#(#prefixes_lit),*],
             regex: #regex_lit,
+            not_regex: #not_regex_lit,
+            exclude_commands: &[#(#exclude_commands_lit),*],
             callback_filter: #callback_filter_lit,
+            games: &[#(#games_lit),*],
+            owner_only_callback: #owner_only_callback,
+            signed: #signed,
+            requires_reply: #requires_reply,
+            admin_only: #admin_only,
+            role: #role,
+            requires: &[#(#requires_lit),*],
+            bot_requires: &[#(#bot_requires_lit),*],
+            allow_anonymous_admin: #allow_anonymous_admin,
+            ignore_bots: #ignore_bots,
+            from_bot_only: #from_bot_only,
+            on_new: #on_new,
+            on_edited: #on_edited,
+            on_business: #on_business,
+            on_edited_business: #on_edited_business,
+            match_captions: #match_captions,
+            entities: &[#(#entities_lit),*],
+            media: &[#(#media_lit),*],
+            transcribe: #transcribe,
+            outbox: #outbox,
+            langs: &[#(#langs_lit),*],
+            topics: &[#(#topics_lit),*],
+            inline: #inline_lit,
+            updates: &[#(#updates_lit),*],
+            raw_json: #raw_json,
+            cron: #cron,
+            tz: #tz,
+            overlap: #overlap,
+            jitter: #jitter,
+            external: &[#(#externals_lit),*],
+            conversation: #conversation,
+            step: #step,
+            cooldown: #cooldown,
+            cooldown_message: #cooldown_message,
+            cooldown_silent: #cooldown_silent,
             callback: #callback_handler,
         };
 
         #[ctor::ctor]
         fn #ctor_fn_name() {
-            teloxide_plugins::registry::register_plugin(#static_name);
+            #register
         }
     };
 
     TokenStream::from(expanded)
 }
+
+fn snake_case(ident: &str) -> String {
+    let mut name = String::with_capacity(ident.len() + 4);
+    for (index, c) in ident.chars().enumerate() {
+        if c.is_uppercase() {
+            if index > 0 {
+                name.push('_');
+            }
+            name.extend(c.to_lowercase());
+        } else {
+            name.push(c);
+        }
+    }
+    name
+}
+
+#[proc_macro_derive(Plugin)]
+pub fn derive_plugin(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as ItemStruct);
+    if !input.generics.params.is_empty() {
+        return syn::Error::new_spanned(
+            &input.generics,
+            "`#[derive(Plugin)]` doesn't support generic structs",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    let ident = &input.ident;
+    let name = LitStr::new(&snake_case(&ident.to_string()), ident.span());
+
+    TokenStream::from(quote! {
+        impl teloxide_plugins::plugin::PluginSlot for #ident {
+            const NAME: &'static str = #name;
+
+            fn slot() -> &'static teloxide_plugins::once_cell::sync::OnceCell<Self> {
+                static SLOT: teloxide_plugins::once_cell::sync::OnceCell<#ident> =
+                    teloxide_plugins::once_cell::sync::OnceCell::new();
+                &SLOT
+            }
+        }
+    })
+}
+
+struct SubCommand {
+    name: String,
+    help: String,
+    handler: proc_macro2::TokenStream,
+}
+
+fn take_sub_command(item_fn: &mut ItemFn) -> syn::Result<Option<SubCommand>> {
+    let Some(index) = item_fn
+        .attrs
+        .iter()
+        .position(|attr| attr.path().is_ident(SUB_IDENT))
+    else {
+        return Ok(None);
+    };
+    let attr = item_fn.attrs.remove(index);
+    let mut name = item_fn.sig.ident.to_string();
+    let mut help = String::new();
+    if let Meta::List(_) = attr.meta {
+        let metas = attr.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)?;
+        for meta in metas {
+            let Meta::NameValue(MetaNameValue { path, value, .. }) = meta else {
+                return Err(syn::Error::new_spanned(
+                    meta,
+                    "expected `name = \"...\"` or `help = \"...\"`",
+                ));
+            };
+            match path.get_ident().map(|ident| ident.to_string()).as_deref() {
+                Some(NAME_IDENT) => name = extract_string(&value)?.to_lowercase(),
+                Some(HELP_IDENT) => help = extract_string(&value)?,
+                _ => {
+                    return Err(syn::Error::new_spanned(
+                        path,
+                        "unknown `sub` option, expected `name` or `help`",
+                    ))
+                }
+            }
+        }
+    }
+    if item_fn.sig.asyncness.is_none() {
+        return Err(syn::Error::new_spanned(
+            &item_fn.sig,
+            "sub-commands must be async functions",
+        ));
+    }
+    let handler = create_callback_handler(item_fn, None)?;
+    Ok(Some(SubCommand {
+        name,
+        help,
+        handler,
+    }))
+}
+
+#[proc_macro_attribute]
+pub fn TeloxidePluginGroup(args: TokenStream, input: TokenStream) -> TokenStream {
+    let mut module = parse_macro_input!(input as ItemMod);
+    let group_args = parse_macro_input!(args as PluginArgs);
+
+    let mut prefix = None;
+    for meta in group_args.metas {
+        match meta {
+            Meta::NameValue(MetaNameValue { path, value, .. }) if path.is_ident(PREFIX_IDENT) => {
+                match extract_string(&value) {
+                    Ok(value) => prefix = Some((value, path)),
+                    Err(err) => return err.to_compile_error().into(),
+                }
+            }
+            other => {
+                return syn::Error::new_spanned(other, "expected `prefix = \"/command\"`")
+                    .to_compile_error()
+                    .into()
+            }
+        }
+    }
+    let Some((full_command, _)) = prefix else {
+        return syn::Error::new_spanned(
+            &module.ident,
+            "plugin group needs a `prefix`, like `prefix = \"/note\"`",
+        )
+        .to_compile_error()
+        .into();
+    };
+    let split = full_command
+        .find(char::is_alphanumeric)
+        .unwrap_or(full_command.len());
+    let (command_prefix, command) = full_command.split_at(split);
+    if command_prefix.is_empty() || command.is_empty() {
+        return syn::Error::new_spanned(
+            &module.ident,
+            "group `prefix` must be a command prefix followed by a name, like `/note`",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    let Some((_, items)) = module.content.as_mut() else {
+        return syn::Error::new_spanned(
+            &module,
+            "`TeloxidePluginGroup` needs an inline module with a body",
+        )
+        .to_compile_error()
+        .into();
+    };
+    let mut subs = Vec::new();
+    for item in items.iter_mut() {
+        if let Item::Fn(item_fn) = item {
+            match take_sub_command(item_fn) {
+                Ok(Some(sub)) => subs.push(sub),
+                Ok(None) => {}
+                Err(err) => return err.to_compile_error().into(),
+            }
+        }
+    }
+    if subs.is_empty() {
+        return syn::Error::new_spanned(
+            &module.ident,
+            "plugin group needs at least one `#[sub]` function",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    let group_name = module.ident.to_string();
+    let static_name = format_ident!("{}_group_meta", group_name);
+    let ctor_fn_name = format_ident!("{}_group_ctor", group_name);
+    let sub_names: Vec<_> = subs
+        .iter()
+        .map(|sub| LitStr::new(&sub.name, proc_macro2::Span::call_site()))
+        .collect();
+    let sub_helps = subs
+        .iter()
+        .map(|sub| LitStr::new(&sub.help, proc_macro2::Span::call_site()));
+    let handlers = subs.iter().map(|sub| &sub.handler);
+
+    let generated: Item = syn::parse_quote! {
+        const _: () = {
+            #[allow(non_upper_case_globals)]
+            static #static_name: &teloxide_plugins::registry::PluginMeta = &teloxide_plugins::registry::PluginMeta {
+                name: #group_name,
+                priority: 0,
+                commands: &[#command],
+                prefixes: &[#command_prefix],
+                regex: None,
+                not_regex: None,
+                exclude_commands: &[],
+                callback_filter: None,
+                games: &[],
+                owner_only_callback: false,
+                signed: false,
+                requires_reply: false,
+                admin_only: false,
+                role: None,
+                requires: &[],
+                bot_requires: &[],
+                allow_anonymous_admin: false,
+                ignore_bots: None,
+                from_bot_only: false,
+                on_new: true,
+                on_edited: false,
+                on_business: false,
+                on_edited_business: false,
+                match_captions: None,
+                entities: &[],
+                media: &[],
+                transcribe: false,
+                outbox: false,
+                langs: &[],
+                topics: &[],
+                inline: None,
+                updates: &[],
+                raw_json: false,
+                cron: None,
+                tz: None,
+                overlap: "skip",
+                jitter: 0,
+                external: &[],
+                conversation: None,
+                step: None,
+                cooldown: 0,
+                cooldown_message: None,
+                cooldown_silent: false,
+                callback: |ctx| Box::pin(async move {
+                    let sub = ctx.subcommand().map(str::to_lowercase);
+                    match sub.as_deref() {
+                        #(
+                            Some(#sub_names) => {
+                                let handler: fn(
+                                    teloxide_plugins::PluginContext,
+                                ) -> ::std::pin::Pin<Box<dyn ::std::future::Future<Output = ()> + Send>> = #handlers;
+                                handler(ctx).await;
+                            }
+                        )*
+                        _ => {
+                            let help = teloxide_plugins::groups::help_in(
+                                &ctx.framework_texts(),
+                                #full_command,
+                                &[#((#sub_names, #sub_helps)),*],
+                            );
+                            let _ = teloxide_plugins::response::respond(&ctx, help).await;
+                        }
+                    }
+                }),
+            };
+
+            #[ctor::ctor]
+            fn #ctor_fn_name() {
+                teloxide_plugins::registry::register_plugin(#static_name);
+            }
+        };
+    };
+    items.push(generated);
+
+    TokenStream::from(quote! { #module })
+}
@@ -0,0 +1,112 @@
+use futures::future::BoxFuture;
+use serde_json::json;
+use std::sync::atomic::{AtomicU32, Ordering};
+use teloxide::types::Message;
+use teloxide::Bot;
+use teloxide_plugins::plugin::{self, InstallError, PluginSlot};
+use teloxide_plugins::{
+    registry, IntoPluginResponse, Plugin, PluginContext, PluginResponse, Triggers,
+};
+
+#[derive(Plugin)]
+struct HitCounter {
+    hits: AtomicU32,
+}
+
+impl Plugin for HitCounter {
+    fn triggers(&self) -> Triggers {
+        Triggers::new().commands(["hits"]).prefixes(["/"])
+    }
+
+    fn handle(&self, _ctx: PluginContext) -> BoxFuture<'_, PluginResponse> {
+        Box::pin(async move {
+            self.hits.fetch_add(1, Ordering::SeqCst);
+            ().into_response()
+        })
+    }
+}
+
+#[derive(Plugin)]
+struct UptimeCheck;
+
+impl Plugin for UptimeCheck {
+    fn triggers(&self) -> Triggers {
+        Triggers::new()
+            .name("status")
+            .commands(["status"])
+            .prefixes(["/"])
+    }
+
+    fn handle(&self, _ctx: PluginContext) -> BoxFuture<'_, PluginResponse> {
+        Box::pin(async { ().into_response() })
+    }
+}
+
+#[derive(teloxide_plugins::derive::TeloxidePlugin)]
+struct EchoBack;
+
+impl Plugin for EchoBack {
+    fn triggers(&self) -> Triggers {
+        Triggers::new().commands(["echo"]).prefixes(["/"])
+    }
+
+    fn handle(&self, _ctx: PluginContext) -> BoxFuture<'_, PluginResponse> {
+        Box::pin(async { ().into_response() })
+    }
+}
+
+fn ctx(text: &str) -> PluginContext {
+    let message: Message = serde_json::from_value(json!({
+        "message_id": 1,
+        "date": 0,
+        "chat": {"id": 7, "type": "private", "first_name": "Ann"},
+        "from": {"id": 7, "is_bot": false, "first_name": "Ann"},
+        "text": text,
+    }))
+    .unwrap();
+    PluginContext::new(Bot::new("1:test"), Some(message), None)
+}
+
+#[test]
+fn names_the_plugin_after_the_struct_in_snake_case() {
+    assert_eq!(HitCounter::NAME, "hit_counter");
+    assert_eq!(UptimeCheck::NAME, "uptime_check");
+}
+
+#[test]
+fn derives_under_the_attribute_name_too() {
+    assert_eq!(EchoBack::NAME, "echo_back");
+}
+
+#[tokio::test]
+async fn installs_into_the_slot_and_dispatches_to_it() {
+    plugin::install(HitCounter {
+        hits: AtomicU32::new(0),
+    })
+    .await
+    .unwrap();
+    assert!(registry::plugins()
+        .iter()
+        .any(|meta| meta.name == "hit_counter" && meta.commands == ["hits"]));
+
+    teloxide_plugins::dispatch(ctx("/hits")).await.unwrap();
+    teloxide_plugins::dispatch(ctx("/other")).await.unwrap();
+    let installed = HitCounter::slot().get().unwrap();
+    assert_eq!(installed.hits.load(Ordering::SeqCst), 1);
+
+    let again = plugin::install(HitCounter {
+        hits: AtomicU32::new(0),
+    })
+    .await;
+    assert!(matches!(
+        again,
+        Err(InstallError::AlreadyInstalled("hit_counter"))
+    ));
+}
+
+#[tokio::test]
+async fn triggers_can_override_the_derived_name() {
+    plugin::install(UptimeCheck).await.unwrap();
+    assert!(registry::plugins().iter().any(|meta| meta.name == "status"));
+    assert!(UptimeCheck::slot().get().is_some());
+}
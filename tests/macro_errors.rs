@@ -0,0 +1,7 @@
+// Attribute mistakes that must stop the build rather than quietly change what a
+// plugin does. Regenerate the expected output with `TRYBUILD=overwrite`.
+#[test]
+fn rejected_attributes() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,88 @@
+use serde_json::json;
+use std::sync::Mutex;
+use teloxide::types::Message;
+use teloxide::Bot;
+use teloxide_plugins::{registry, PluginContext, TeloxidePluginGroup};
+
+// What the sub-commands saw, in order.
+static CALLS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+// Dispatching tests share `CALLS`, so they take turns.
+static DISPATCH_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+fn record(call: String) {
+    CALLS.lock().unwrap().push(call);
+}
+
+#[TeloxidePluginGroup(prefix = "/note")]
+mod note {
+    use super::*;
+
+    #[sub(help = "Save a note")]
+    async fn add(ctx: PluginContext) {
+        record(format!("add {}", ctx.subcommand_args().join(" ")));
+    }
+
+    #[sub(name = "del", help = "Delete a note by number")]
+    async fn delete(ctx: PluginContext) {
+        record(format!("del {}", ctx.subcommand_args().join(" ")));
+    }
+
+    #[sub]
+    async fn list() {
+        record(label("list"));
+    }
+
+    // Not a sub-command, just a helper the module keeps.
+    fn label(name: &str) -> String {
+        name.to_string()
+    }
+}
+
+fn ctx(text: &str) -> PluginContext {
+    let message: Message = serde_json::from_value(json!({
+        "message_id": 1,
+        "date": 0,
+        "chat": {"id": 7, "type": "private", "first_name": "Ann"},
+        "from": {"id": 7, "is_bot": false, "first_name": "Ann"},
+        "text": text,
+    }))
+    .unwrap();
+    PluginContext::new(Bot::new("1:test"), Some(message), None)
+}
+
+async fn dispatch(text: &str) -> Vec<String> {
+    CALLS.lock().unwrap().clear();
+    teloxide_plugins::dispatch(ctx(text)).await.unwrap();
+    std::mem::take(&mut *CALLS.lock().unwrap())
+}
+
+#[test]
+fn registers_one_plugin_named_after_the_module() {
+    let groups: Vec<_> = registry::plugins()
+        .into_iter()
+        .filter(|meta| meta.name == "note")
+        .collect();
+    assert_eq!(groups.len(), 1);
+    assert_eq!(groups[0].commands, ["note"]);
+    assert_eq!(groups[0].prefixes, ["/"]);
+}
+
+#[tokio::test]
+async fn routes_the_first_word_to_its_sub_command() {
+    let _guard = DISPATCH_LOCK.lock().await;
+    assert_eq!(dispatch("/note add buy milk").await, ["add buy milk"]);
+    assert_eq!(dispatch("/note list").await, ["list"]);
+}
+
+#[tokio::test]
+async fn matches_sub_commands_by_name_ignoring_case() {
+    let _guard = DISPATCH_LOCK.lock().await;
+    assert_eq!(dispatch("/note DEL 2").await, ["del 2"]);
+}
+
+#[tokio::test]
+async fn leaves_other_commands_alone() {
+    let _guard = DISPATCH_LOCK.lock().await;
+    assert!(dispatch("/notes add x").await.is_empty());
+    assert!(dispatch("note add x").await.is_empty());
+}
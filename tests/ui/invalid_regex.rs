@@ -0,0 +1,6 @@
+use teloxide_plugins::TeloxidePlugin;
+
+#[TeloxidePlugin(regex = ["(unclosed"])]
+async fn greet(_ctx: teloxide_plugins::PluginContext) {}
+
+fn main() {}
@@ -0,0 +1,6 @@
+use teloxide_plugins::TeloxidePlugin;
+
+#[TeloxidePlugin(commands = ["pin"], prefixes = ["/"], requires(can_pin_messages))]
+async fn pin(_ctx: teloxide_plugins::PluginContext) {}
+
+fn main() {}
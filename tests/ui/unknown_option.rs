@@ -0,0 +1,6 @@
+use teloxide_plugins::TeloxidePlugin;
+
+#[TeloxidePlugin(commands = ["ban"], prefixes = ["/"], admin_ony = true)]
+async fn ban(_ctx: teloxide_plugins::PluginContext) {}
+
+fn main() {}